@@ -0,0 +1,99 @@
+// Compares the fixed-point Price/Qty representation against rust_decimal
+// for the operations the matching path performs: book key lookups and
+// fill arithmetic.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kk99_matching_engine::fixed_point::{Price, Qty};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+const LEVELS: u64 = 1_000;
+const PRICE_DECIMALS: u32 = 2;
+const QTY_DECIMALS: u32 = 8;
+
+fn decimal_price(ticks: u64) -> Decimal {
+    Decimal::new(ticks as i64, PRICE_DECIMALS)
+}
+
+fn bench_book_keys(c: &mut Criterion) {
+    let mut group = c.benchmark_group("book_keys");
+
+    let decimal_book: BTreeMap<Decimal, u64> = (0..LEVELS).map(|t| (decimal_price(10_000 + t), t)).collect();
+    let fixed_book: BTreeMap<Price, u64> = (0..LEVELS).map(|t| (Price::from_raw(10_000 + t), t)).collect();
+
+    group.bench_function(BenchmarkId::new("lookup", "decimal"), |b| {
+        let mut tick = 0;
+        b.iter(|| {
+            tick = (tick + 7) % LEVELS;
+            black_box(decimal_book.get(&decimal_price(10_000 + tick)))
+        })
+    });
+    group.bench_function(BenchmarkId::new("lookup", "fixed"), |b| {
+        let mut tick = 0;
+        b.iter(|| {
+            tick = (tick + 7) % LEVELS;
+            black_box(fixed_book.get(&Price::from_raw(10_000 + tick)))
+        })
+    });
+
+    group.bench_function(BenchmarkId::new("insert_remove", "decimal"), |b| {
+        let mut book = decimal_book.clone();
+        let key = decimal_price(20_000 + LEVELS / 2);
+        b.iter(|| {
+            book.insert(black_box(key), 0);
+            black_box(book.remove(&key))
+        })
+    });
+    group.bench_function(BenchmarkId::new("insert_remove", "fixed"), |b| {
+        let mut book = fixed_book.clone();
+        let key = Price::from_raw(20_000 + LEVELS / 2);
+        b.iter(|| {
+            book.insert(black_box(key), 0);
+            black_box(book.remove(&key))
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_fill_arithmetic(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_arithmetic");
+
+    // One taker sweeping 100 makers, mirroring MatchingEngine::fill_level.
+    let decimal_makers: Vec<Decimal> = (1..=100).map(|i| Decimal::new(i * 1_000_000, QTY_DECIMALS)).collect();
+    let fixed_makers: Vec<Qty> = (1..=100).map(|i| Qty::from_raw(i * 1_000_000)).collect();
+
+    group.bench_function("decimal", |b| {
+        b.iter(|| {
+            let amount = Decimal::new(4_000_000_000, QTY_DECIMALS);
+            let mut filled = Decimal::ZERO;
+            for maker in &decimal_makers {
+                let remaining = amount - filled;
+                if remaining <= Decimal::ZERO {
+                    break;
+                }
+                filled += remaining.min(*maker);
+            }
+            black_box(filled)
+        })
+    });
+    group.bench_function("fixed", |b| {
+        b.iter(|| {
+            let amount = Qty::from_raw(4_000_000_000);
+            let mut filled = Qty::ZERO;
+            for maker in &fixed_makers {
+                let remaining = amount.saturating_sub(filled);
+                if remaining.is_zero() {
+                    break;
+                }
+                filled = filled.saturating_add(remaining.min(*maker));
+            }
+            black_box(filled)
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_book_keys, bench_fill_arithmetic);
+criterion_main!(benches);
@@ -0,0 +1,156 @@
+// Matching path benchmarks: end-to-end engine calls (including the hop to
+// the shard's matching thread) and the order book on its own, each against
+// a thin book and a deep one.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use kk99_matching_engine::engine::MatchingEngine;
+use kk99_matching_engine::fixed_point::{Price, Qty};
+use kk99_matching_engine::order_book::OrderBook;
+use kk99_matching_engine::types::{Instrument, Order, OrderSide, OrderStatus};
+use std::time::{Duration, Instant};
+
+const SYMBOL: &str = "BTC-USD";
+const MID: u64 = 1_000_000;
+
+/// Book shapes as (name, levels per side, orders per level).
+const SHAPES: [(&str, u64, u64); 2] = [("thin", 10, 1), ("deep", 1_000, 20)];
+
+fn limit(id: String, side: OrderSide, price: u64, amount: u64) -> Order {
+    Order::limit(&id, "bench", SYMBOL, side, Price::from_raw(price), Qty::from_raw(amount))
+}
+
+/// Resting orders on both sides of `MID`, `levels` ticks deep.
+fn seed_orders(levels: u64, per_level: u64) -> impl Iterator<Item = Order> {
+    (1..=levels).flat_map(move |tick| {
+        (0..per_level).flat_map(move |n| {
+            [
+                limit(format!("bid-{tick}-{n}"), OrderSide::Buy, MID - tick, 100),
+                limit(format!("ask-{tick}-{n}"), OrderSide::Sell, MID + tick, 100),
+            ]
+        })
+    })
+}
+
+fn seeded_engine(levels: u64, per_level: u64) -> MatchingEngine {
+    let engine = MatchingEngine::new();
+    for order in seed_orders(levels, per_level) {
+        engine.place_order(order).unwrap();
+    }
+    engine
+}
+
+fn bench_engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+
+    for (shape, levels, per_level) in SHAPES {
+        let engine = seeded_engine(levels, per_level);
+        let mut next_id = 0u64;
+        let mut id = move |prefix: &str| {
+            next_id += 1;
+            format!("{prefix}-{next_id}")
+        };
+
+        // Joins an existing bid level; the cancel that keeps the book from
+        // growing is not timed.
+        group.bench_function(BenchmarkId::new("place_resting", shape), |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let order_id = id("rest");
+                    let order = limit(order_id.clone(), OrderSide::Buy, MID - 1, 100);
+                    let start = Instant::now();
+                    black_box(engine.place_order(order).unwrap());
+                    elapsed += start.elapsed();
+                    engine.cancel_order(&order_id);
+                }
+                elapsed
+            })
+        });
+
+        // Takes a maker placed inside the spread, so the book shape is
+        // unchanged after each iteration.
+        group.bench_function(BenchmarkId::new("place_crossing", shape), |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    engine.place_order(limit(id("maker"), OrderSide::Sell, MID, 100)).unwrap();
+                    let taker = limit(id("taker"), OrderSide::Buy, MID, 100);
+                    let start = Instant::now();
+                    black_box(engine.place_order(taker).unwrap());
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("cancel", shape), |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let order_id = id("cancel");
+                    engine.place_order(limit(order_id.clone(), OrderSide::Sell, MID + 1, 100)).unwrap();
+                    let start = Instant::now();
+                    black_box(engine.cancel_order(&order_id));
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("book_snapshot", shape), |b| {
+            b.iter(|| black_box(engine.book_snapshot(SYMBOL)))
+        });
+        group.bench_function(BenchmarkId::new("get_order_book_20", shape), |b| {
+            b.iter(|| black_box(engine.get_order_book(SYMBOL, 20)))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_order_book(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_book");
+
+    for (shape, levels, per_level) in SHAPES {
+        let mut book = OrderBook::new(Instrument::with_default_precision(SYMBOL));
+        for order in seed_orders(levels, per_level) {
+            book.add_order(order).unwrap();
+        }
+
+        // Joins the back of the queue at a level in the middle of the book.
+        let tick = levels / 2 + 1;
+        group.bench_function(BenchmarkId::new("add_remove", shape), |b| {
+            b.iter(|| {
+                book.add_order(limit("probe".to_string(), OrderSide::Buy, MID - tick, 100)).unwrap();
+                black_box(book.remove_order("probe", OrderSide::Buy, Price::from_raw(MID - tick)))
+            })
+        });
+
+        // Fills the maker at the front of the best ask, as one taker match
+        // does, then queues an equal order at the back to keep the shape.
+        group.bench_function(BenchmarkId::new("match_front", shape), |b| {
+            b.iter(|| {
+                let level = book.asks.values_mut().next().unwrap();
+                let amount = level.front().unwrap().remaining();
+                black_box(level.fill_front(amount));
+                let maker = level.pop_front().unwrap();
+                level
+                    .add_order(Order {
+                        filled: Qty::ZERO,
+                        status: OrderStatus::New,
+                        ..maker
+                    })
+                    .unwrap();
+            })
+        });
+        group.bench_function(BenchmarkId::new("depth_50", shape), |b| b.iter(|| black_box(book.depth(50))));
+        group.bench_function(BenchmarkId::new("best_bid_ask", shape), |b| {
+            b.iter(|| black_box((book.best_bid(), book.best_ask())))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_engine, bench_order_book);
+criterion_main!(benches);
@@ -0,0 +1,233 @@
+// Order entry benchmarks: the OUCH-style binary protocol against gRPC
+// PlaceOrder, first the per-message wire handling alone and then a full
+// loopback round trip through each server into the engine.
+//
+// Both round trips go through `MatchingEngineService::new()`, the service
+// and engine the server runs, with every market data publisher attached.
+// The binary gateway shares its engine and execution reports.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use kk99_matching_engine::auth::Credentials;
+use kk99_matching_engine::fixed_point::{Price, Qty};
+use kk99_matching_engine::grpc::matching::{self, matching_engine_client::MatchingEngineClient};
+use kk99_matching_engine::grpc::matching::matching_engine_server::MatchingEngineServer;
+use kk99_matching_engine::grpc::MatchingEngineService;
+use kk99_matching_engine::ouch::{self, Request, Response};
+use kk99_matching_engine::ouch_gateway::OuchGateway;
+use kk99_matching_engine::types::{Instrument, Order, OrderSide, OrderType};
+use prost::Message;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic::transport::server::TcpIncoming;
+
+const SYMBOL: &str = "BTC-USD";
+/// 1000.00 at two price decimals, a level no sell order reaches.
+const PRICE: u64 = 100_000;
+/// 0.01 at four quantity decimals.
+const QUANTITY: u64 = 100;
+
+fn instrument() -> Instrument {
+    Instrument::new(SYMBOL, 2, 4)
+}
+
+fn order(id: String, user: &str, side: OrderSide, price: Price, amount: Qty) -> Order {
+    Order::limit(&id, user, SYMBOL, side, price, amount)
+}
+
+fn order_request(order_id: String) -> matching::OrderRequest {
+    matching::OrderRequest {
+        order_id,
+        user_id: "bench".to_string(),
+        symbol: SYMBOL.to_string(),
+        side: matching::OrderSide::Buy as i32,
+        r#type: matching::OrderType::Limit as i32,
+        price: "1000.00".to_string(),
+        amount: "0.01".to_string(),
+        timestamp: 0,
+    }
+}
+
+fn bench_codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_entry_codec");
+    let instrument = instrument();
+
+    let mut enter = Vec::new();
+    ouch::write_request(
+        &Request::EnterOrder {
+            token: 1,
+            symbol: SYMBOL.into(),
+            side: OrderSide::Buy,
+            order_type: OrderType::Limit,
+            price: Price::from_raw(PRICE),
+            quantity: Qty::from_raw(QUANTITY),
+        },
+        &mut enter,
+    );
+    let mut out = Vec::with_capacity(256);
+    group.bench_function("ouch", |b| {
+        b.iter(|| {
+            let (message, _) = ouch::next_frame(black_box(&enter)).unwrap();
+            let Ok(Request::EnterOrder {
+                token,
+                symbol,
+                side,
+                price,
+                quantity,
+                ..
+            }) = Request::decode(message)
+            else {
+                unreachable!()
+            };
+            black_box(order(format!("bench-{token}"), "bench", side, price, quantity));
+            out.clear();
+            let accepted = Response::Accepted {
+                timestamp: 0,
+                token,
+                symbol,
+                side,
+                price,
+                quantity,
+            };
+            ouch::write_response(&accepted, &mut out);
+            black_box(&out);
+        })
+    });
+
+    let request = order_request("bench-1".to_string()).encode_to_vec();
+    group.bench_function("protobuf", |b| {
+        b.iter(|| {
+            let req = matching::OrderRequest::decode(black_box(request.as_slice())).unwrap();
+            let price = instrument.parse_price(&req.price).unwrap();
+            let amount = instrument.parse_qty(&req.amount).unwrap();
+            black_box(order(req.order_id.clone(), &req.user_id, OrderSide::Buy, price, amount));
+            out.clear();
+            let response = matching::OrderResponse {
+                success: true,
+                order_id: req.order_id,
+                message: String::new(),
+                fills: Vec::new(),
+                status: matching::OrderStatus::New as i32,
+                reject_reason: 0,
+            };
+            response.encode(&mut out).unwrap();
+            black_box(&out);
+        })
+    });
+
+    group.finish();
+}
+
+/// Reads responses until one matches.
+fn read_until(stream: &mut TcpStream, buf: &mut Vec<u8>, mut done: impl FnMut(&Response) -> bool) {
+    let mut chunk = [0u8; 4096];
+    loop {
+        while let Some((message, used)) = ouch::next_frame(buf) {
+            let response = Response::decode(message).unwrap();
+            buf.drain(..used);
+            if done(&response) {
+                return;
+            }
+        }
+        let n = stream.read(&mut chunk).unwrap();
+        assert!(n > 0, "gateway closed the connection");
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_entry_round_trip");
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let service = MatchingEngineService::new();
+    let engine = Arc::clone(&service.engine);
+    let reports = Arc::clone(&service.execution_reports);
+    engine.register_instrument(instrument());
+
+    let (ouch_addr, grpc_addr) = runtime.block_on(async {
+        let ouch_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ouch_addr = ouch_listener.local_addr().unwrap();
+        let credentials = Credentials::new().with_user("bench", "bench");
+        let gateway = Arc::new(OuchGateway::new(Arc::clone(&engine), Arc::clone(&reports), credentials));
+        tokio::spawn(gateway.serve(ouch_listener));
+
+        let grpc_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let grpc_addr = grpc_listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(grpc_listener, true, None).unwrap();
+        let server = MatchingEngineServer::new(service);
+        tokio::spawn(tonic::transport::Server::builder().add_service(server).serve_with_incoming(incoming));
+        (ouch_addr, grpc_addr)
+    });
+
+    let mut stream = TcpStream::connect(ouch_addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut buf = Vec::new();
+    let mut out = Vec::new();
+    ouch::write_request(
+        &Request::Login {
+            user: "bench".into(),
+            password: "bench".to_string(),
+        },
+        &mut out,
+    );
+    stream.write_all(&out).unwrap();
+    read_until(&mut stream, &mut buf, |r| matches!(r, Response::LoginAccepted { .. }));
+
+    // Enters a resting order and waits for Accepted; the cancel that keeps
+    // the book from growing is not timed.
+    let mut token = 0u64;
+    group.bench_function("ouch", |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                token += 1;
+                let enter = Request::EnterOrder {
+                    token,
+                    symbol: SYMBOL.into(),
+                    side: OrderSide::Buy,
+                    order_type: OrderType::Limit,
+                    price: Price::from_raw(PRICE),
+                    quantity: Qty::from_raw(QUANTITY),
+                };
+                out.clear();
+                ouch::write_request(&enter, &mut out);
+                let start = Instant::now();
+                stream.write_all(&out).unwrap();
+                read_until(&mut stream, &mut buf, |r| matches!(r, Response::Accepted { token: t, .. } if *t == token));
+                elapsed += start.elapsed();
+
+                out.clear();
+                ouch::write_request(&Request::CancelOrder { token }, &mut out);
+                stream.write_all(&out).unwrap();
+                read_until(&mut stream, &mut buf, |r| matches!(r, Response::Cancelled { token: t, .. } if *t == token));
+            }
+            elapsed
+        })
+    });
+
+    let mut client = runtime
+        .block_on(MatchingEngineClient::connect(format!("http://{grpc_addr}")))
+        .unwrap();
+    let mut next_id = 0u64;
+    group.bench_function("grpc", |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                next_id += 1;
+                let order_id = format!("grpc-{next_id}");
+                let request = order_request(order_id.clone());
+                let start = Instant::now();
+                black_box(runtime.block_on(client.place_order(request)).unwrap());
+                elapsed += start.elapsed();
+                engine.cancel_order(&order_id);
+            }
+            elapsed
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_codec, bench_round_trip);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+/// Order entry credentials.
+///
+/// The session gateways log users on by name, and the name becomes the
+/// `user_id` of their orders, so a logon has to prove it. `Credentials`
+/// holds each user's password. A gateway with no credentials refuses every
+/// logon.
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Default)]
+pub struct Credentials {
+    passwords: HashMap<String, String>,
+}
+
+impl Credentials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.passwords.insert(user.into(), password.into());
+        self
+    }
+
+    /// Parses comma-separated `USER:PASSWORD` pairs. The password runs to
+    /// the next comma, so it may contain colons.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut credentials = Self::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (user, password) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("'{}' should be USER:PASSWORD", entry))?;
+            if user.is_empty() || password.is_empty() {
+                return Err(format!("'{}' has an empty user or password", entry));
+            }
+            if credentials.passwords.contains_key(user) {
+                return Err(format!("{} is listed twice", user));
+            }
+            credentials = credentials.with_user(user, password);
+        }
+        Ok(credentials)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passwords.is_empty()
+    }
+
+    /// Whether `password` is `user`'s. Compares every byte, so how long a
+    /// wrong guess takes says nothing about how much of it was right.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let Some(expected) = self.passwords.get(user) else {
+            return false;
+        };
+        let diff = expected
+            .bytes()
+            .zip(password.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        expected.len() == password.len() && diff == 0
+    }
+}
+
+/// Lists the users, never their passwords.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.passwords.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let credentials = Credentials::parse("alice:s3cret, bob:a:b").unwrap();
+        assert!(credentials.verify("alice", "s3cret"));
+        assert!(credentials.verify("bob", "a:b"));
+        assert!(!credentials.verify("alice", "s3cre"));
+        assert!(!credentials.verify("alice", "s3cret!"));
+        assert!(!credentials.verify("alice", "a:b"));
+        assert!(!credentials.verify("carol", ""));
+        assert!(!format!("{:?}", credentials).contains("s3cret"));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_entries() {
+        assert!(Credentials::parse("").unwrap().is_empty());
+        for bad in ["alice", "alice:", ":pw", "alice:a,alice:b"] {
+            assert!(Credentials::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
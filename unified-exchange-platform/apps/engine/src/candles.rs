@@ -0,0 +1,433 @@
+/// OHLCV candles built from trades.
+///
+/// `CandleBuilder` runs as an `EventHandler` and folds every `Trade` into a
+/// candle for each `CandleInterval`. Candles are aligned to UTC and timed
+/// by the trade timestamps. A candle closes once the clock passed to
+/// `CandleFeed::close_elapsed` leaves its interval, or when a trade in a
+/// later interval arrives, whichever is first. A trade timed inside an
+/// interval that was already closed counts toward the next one.
+///
+/// An interval with no trades becomes a flat candle. Its open, high, low
+/// and close are the previous close, and it has no volume or VWAP. Flat
+/// candles are added as `close_elapsed` sees their intervals end, or when
+/// the next trade arrives if that is sooner, so every symbol's series has
+/// no holes from its first trade on.
+///
+/// `CandleFeed` keeps the last `history` candles per symbol and interval
+/// for queries. It publishes every change: the final state of each closed
+/// candle, flat ones included, then the new open candle.
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::feed::{evict_idle, publish};
+use crate::fixed_point::{Price, Qty};
+use crate::ids::Symbol;
+use crate::types::Trade;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CandleInterval {
+    OneSecond,
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+    OneDay,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 5] = [
+        CandleInterval::OneSecond,
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+
+    pub fn millis(self) -> i64 {
+        match self {
+            CandleInterval::OneSecond => 1_000,
+            CandleInterval::OneMinute => 60_000,
+            CandleInterval::FiveMinutes => 300_000,
+            CandleInterval::OneHour => 3_600_000,
+            CandleInterval::OneDay => 86_400_000,
+        }
+    }
+
+    /// Start of the interval containing `timestamp`, in milliseconds.
+    pub fn open_time(self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.millis())
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candle {
+    pub symbol: Symbol,
+    pub interval: CandleInterval,
+    /// Start of the interval, in milliseconds since the epoch.
+    pub open_time: i64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Qty,
+    pub trade_count: u64,
+    /// Volume-weighted average price, `None` for a flat candle.
+    pub vwap: Option<Price>,
+    /// No more trades will be added.
+    pub closed: bool,
+    /// Sum of price * qty over trades, in raw fixed-point units.
+    #[serde(skip)]
+    notional: u128,
+}
+
+impl Candle {
+    fn open(trade: &Trade, interval: CandleInterval, open_time: i64) -> Self {
+        let mut candle = Self::flat(trade.symbol.clone(), interval, open_time, trade.price);
+        candle.add(trade);
+        candle
+    }
+
+    fn flat(symbol: Symbol, interval: CandleInterval, open_time: i64, price: Price) -> Self {
+        Self {
+            symbol,
+            interval,
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: Qty::ZERO,
+            trade_count: 0,
+            vwap: None,
+            closed: false,
+            notional: 0,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume = self.volume.saturating_add(trade.amount);
+        self.trade_count += 1;
+        self.notional += trade.price.raw() as u128 * trade.amount.raw() as u128;
+        self.vwap = Some(Price::from_raw((self.notional / self.volume.raw() as u128) as u64));
+    }
+}
+
+struct SymbolCandles {
+    /// Per interval, oldest first; the last candle is the open one.
+    series: [VecDeque<Candle>; CandleInterval::ALL.len()],
+    updates: broadcast::Sender<Candle>,
+}
+
+/// Recent candles and their update channels, by symbol.
+pub struct CandleFeed {
+    symbols: DashMap<Symbol, Arc<Mutex<SymbolCandles>>>,
+    history: usize,
+    capacity: usize,
+}
+
+impl CandleFeed {
+    /// Keeps `history` candles per symbol and interval. Each subscriber may
+    /// fall `capacity` updates behind before it lags.
+    pub fn new(history: usize, capacity: usize) -> Self {
+        Self {
+            symbols: DashMap::new(),
+            history: history.max(1),
+            capacity,
+        }
+    }
+
+    /// Up to `limit` of the latest candles, oldest first. The last one is
+    /// open if a trade has arrived since its interval began; otherwise
+    /// every candle is closed.
+    pub fn history(&self, symbol: &str, interval: CandleInterval, limit: usize) -> Vec<Candle> {
+        let Some(candles) = self.symbols.get(symbol).map(|c| Arc::clone(&c)) else {
+            return Vec::new();
+        };
+        let candles = candles.lock();
+        let series = &candles.series[interval.index()];
+        series.iter().skip(series.len().saturating_sub(limit)).cloned().collect()
+    }
+
+    /// The open candle of `symbol` for `interval` and every update after it,
+    /// for all intervals.
+    pub fn subscribe(&self, symbol: &str, interval: CandleInterval) -> (Option<Candle>, broadcast::Receiver<Candle>) {
+        let candles = self.candles(symbol);
+        let candles = candles.lock();
+        (candles.series[interval.index()].back().cloned(), candles.updates.subscribe())
+    }
+
+    /// Closes every open candle whose interval ended before `now`, in
+    /// milliseconds since the epoch, and adds flat candles for the intervals
+    /// that ended without trades, so quiet symbols still close on time.
+    pub fn close_elapsed(&self, now: i64) {
+        let symbols: Vec<_> = self.symbols.iter().map(|candles| Arc::clone(&candles)).collect();
+        for candles in symbols {
+            let mut candles = candles.lock();
+            let SymbolCandles { series, updates } = &mut *candles;
+            for interval in CandleInterval::ALL {
+                let series = &mut series[interval.index()];
+                let current_open = interval.open_time(now);
+                if series.back().is_some_and(|last| last.open_time < current_open) {
+                    close_until(series, updates, interval, current_open, self.history);
+                }
+            }
+        }
+    }
+
+    fn candles(&self, symbol: &str) -> Arc<Mutex<SymbolCandles>> {
+        if let Some(candles) = self.symbols.get(symbol) {
+            return Arc::clone(&candles);
+        }
+        // Symbols that have traded keep their history.
+        evict_idle(&self.symbols, |entry| {
+            Arc::strong_count(entry) == 1 && {
+                let candles = entry.lock();
+                candles.updates.receiver_count() == 0 && candles.series.iter().all(VecDeque::is_empty)
+            }
+        });
+        let candles = self.symbols.entry(Symbol::from(symbol)).or_insert_with(|| {
+            Arc::new(Mutex::new(SymbolCandles {
+                series: Default::default(),
+                updates: broadcast::channel(self.capacity).0,
+            }))
+        });
+        Arc::clone(&candles)
+    }
+
+    fn add_trade(&self, trade: &Trade) {
+        let candles = self.candles(&trade.symbol);
+        let mut candles = candles.lock();
+        let SymbolCandles { series, updates } = &mut *candles;
+
+        for interval in CandleInterval::ALL {
+            let series = &mut series[interval.index()];
+            let open_time = interval.open_time(trade.timestamp);
+            match series.back_mut() {
+                // Trades timed before the open candle still count toward it.
+                Some(current) if !current.closed && open_time <= current.open_time => {
+                    current.add(trade);
+                    publish(updates, current.clone());
+                }
+                Some(current) => {
+                    let open_time = open_time.max(current.open_time + interval.millis());
+                    close_until(series, updates, interval, open_time, self.history);
+                    let candle = Candle::open(trade, interval, open_time);
+                    publish(updates, candle.clone());
+                    push_bounded(series, candle, self.history);
+                }
+                None => {
+                    let candle = Candle::open(trade, interval, open_time);
+                    publish(updates, candle.clone());
+                    series.push_back(candle);
+                }
+            }
+        }
+    }
+}
+
+/// Closes the last candle of `series` if it is open, then adds closed flat
+/// candles at its close for the intervals from it up to `until`, at most as
+/// many as the history keeps.
+fn close_until(
+    series: &mut VecDeque<Candle>,
+    updates: &broadcast::Sender<Candle>,
+    interval: CandleInterval,
+    until: i64,
+    history: usize,
+) {
+    let Some(last) = series.back_mut() else {
+        return;
+    };
+    if !last.closed {
+        last.closed = true;
+        publish(updates, last.clone());
+    }
+    let (symbol, last_open, close) = (last.symbol.clone(), last.open_time, last.close);
+    let step = interval.millis();
+    let first_empty = (last_open + step).max(until - step * history as i64);
+    for empty_open in (first_empty..until).step_by(step as usize) {
+        let mut flat = Candle::flat(symbol.clone(), interval, empty_open, close);
+        flat.closed = true;
+        publish(updates, flat.clone());
+        push_bounded(series, flat, history);
+    }
+}
+
+fn push_bounded(series: &mut VecDeque<Candle>, candle: Candle, history: usize) {
+    if series.len() == history {
+        series.pop_front();
+    }
+    series.push_back(candle);
+}
+
+pub struct CandleBuilder {
+    feed: Arc<CandleFeed>,
+}
+
+impl CandleBuilder {
+    pub fn new(feed: Arc<CandleFeed>) -> Self {
+        Self { feed }
+    }
+}
+
+impl EventHandler<EngineEvent> for CandleBuilder {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {
+        if let EngineEvent::Trade(trade) = event {
+            self.feed.add_trade(trade);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OrderSide;
+
+    fn trade(timestamp: i64, price: u64, amount: u64) -> EngineEvent {
+        EngineEvent::Trade(Trade {
+            id: 1,
+            symbol: "BTC-USD".into(),
+            maker_order_id: "m".into(),
+            taker_order_id: "t".into(),
+            price: Price::from_raw(price),
+            amount: Qty::from_raw(amount),
+            taker_side: OrderSide::Buy,
+            timestamp,
+        })
+    }
+
+    fn ohlc(candle: &Candle) -> (u64, u64, u64, u64, u64) {
+        (
+            candle.open.raw(),
+            candle.high.raw(),
+            candle.low.raw(),
+            candle.close.raw(),
+            candle.volume.raw(),
+        )
+    }
+
+    #[test]
+    fn test_builds_ohlcv_and_vwap() {
+        let feed = Arc::new(CandleFeed::new(10, 64));
+        let mut builder = CandleBuilder::new(Arc::clone(&feed));
+
+        for (i, (price, amount)) in [(100, 1), (110, 3), (90, 1), (105, 5)].into_iter().enumerate() {
+            builder.on_event(&trade(60_000 + i as i64 * 1_000, price, amount), i as u64, true);
+        }
+
+        let minute = feed.history("BTC-USD", CandleInterval::OneMinute, 10);
+        assert_eq!(minute.len(), 1);
+        assert_eq!(ohlc(&minute[0]), (100, 110, 90, 105, 10));
+        assert_eq!(minute[0].trade_count, 4);
+        // (100 + 330 + 90 + 525) / 10
+        assert_eq!(minute[0].vwap, Some(Price::from_raw(104)));
+        assert!(!minute[0].closed);
+        assert_eq!(feed.history("BTC-USD", CandleInterval::OneSecond, 10).len(), 4);
+    }
+
+    #[test]
+    fn test_empty_intervals_are_flat() {
+        let feed = Arc::new(CandleFeed::new(10, 64));
+        let (_, mut rx) = feed.subscribe("BTC-USD", CandleInterval::OneSecond);
+        let mut builder = CandleBuilder::new(Arc::clone(&feed));
+
+        builder.on_event(&trade(1_500, 100, 1), 0, true);
+        builder.on_event(&trade(4_200, 120, 2), 1, true);
+
+        let seconds = feed.history("BTC-USD", CandleInterval::OneSecond, 10);
+        let times: Vec<_> = seconds.iter().map(|c| c.open_time).collect();
+        assert_eq!(times, vec![1_000, 2_000, 3_000, 4_000]);
+        assert_eq!(ohlc(&seconds[1]), (100, 100, 100, 100, 0));
+        assert_eq!((seconds[2].trade_count, seconds[2].vwap), (0, None));
+        assert!(seconds[..3].iter().all(|c| c.closed));
+        assert_eq!(ohlc(&seconds[3]), (120, 120, 120, 120, 2));
+
+        let streamed: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|c| c.interval == CandleInterval::OneSecond)
+            .map(|c| (c.open_time, c.closed))
+            .collect();
+        assert_eq!(
+            streamed,
+            vec![(1_000, false), (1_000, true), (2_000, true), (3_000, true), (4_000, false)]
+        );
+    }
+
+    #[test]
+    fn test_clock_closes_quiet_candles() {
+        let feed = Arc::new(CandleFeed::new(10, 64));
+        let (_, mut rx) = feed.subscribe("BTC-USD", CandleInterval::OneSecond);
+        let mut builder = CandleBuilder::new(Arc::clone(&feed));
+        builder.on_event(&trade(1_500, 100, 1), 0, true);
+
+        feed.close_elapsed(1_999);
+        assert!(!feed.history("BTC-USD", CandleInterval::OneSecond, 1)[0].closed);
+        feed.close_elapsed(2_000);
+        assert!(feed.history("BTC-USD", CandleInterval::OneSecond, 1)[0].closed);
+        assert!(!feed.history("BTC-USD", CandleInterval::OneMinute, 1)[0].closed);
+
+        // Timed inside the closed second, so it opens the next one.
+        builder.on_event(&trade(1_900, 110, 1), 1, true);
+        let seconds = feed.history("BTC-USD", CandleInterval::OneSecond, 10);
+        let times: Vec<_> = seconds.iter().map(|c| (c.open_time, c.closed)).collect();
+        assert_eq!(times, vec![(1_000, true), (2_000, false)]);
+        assert_eq!(ohlc(&seconds[0]), (100, 100, 100, 100, 1));
+
+        let streamed: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|c| c.interval == CandleInterval::OneSecond)
+            .map(|c| (c.open_time, c.closed))
+            .collect();
+        assert_eq!(streamed, vec![(1_000, false), (1_000, true), (2_000, false)]);
+    }
+
+    #[test]
+    fn test_clock_adds_flat_candles() {
+        let feed = Arc::new(CandleFeed::new(10, 64));
+        let (_, mut rx) = feed.subscribe("BTC-USD", CandleInterval::OneSecond);
+        let mut builder = CandleBuilder::new(Arc::clone(&feed));
+        builder.on_event(&trade(1_500, 100, 1), 0, true);
+
+        feed.close_elapsed(3_500);
+        feed.close_elapsed(4_200);
+        let seconds = feed.history("BTC-USD", CandleInterval::OneSecond, 10);
+        let times: Vec<_> = seconds.iter().map(|c| (c.open_time, c.closed)).collect();
+        assert_eq!(times, vec![(1_000, true), (2_000, true), (3_000, true)]);
+        assert_eq!(ohlc(&seconds[2]), (100, 100, 100, 100, 0));
+
+        // The next trade only opens its own candle.
+        builder.on_event(&trade(4_700, 120, 1), 1, true);
+        let streamed: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|c| c.interval == CandleInterval::OneSecond)
+            .map(|c| (c.open_time, c.closed))
+            .collect();
+        assert_eq!(
+            streamed,
+            vec![(1_000, false), (1_000, true), (2_000, true), (3_000, true), (4_000, false)]
+        );
+    }
+
+    #[test]
+    fn test_long_gap_fills_at_most_history() {
+        let feed = Arc::new(CandleFeed::new(3, 64));
+        let mut builder = CandleBuilder::new(Arc::clone(&feed));
+
+        builder.on_event(&trade(0, 100, 1), 0, true);
+        builder.on_event(&trade(3_600_000, 101, 1), 1, true);
+
+        let seconds = feed.history("BTC-USD", CandleInterval::OneSecond, 10);
+        let times: Vec<_> = seconds.iter().map(|c| c.open_time).collect();
+        assert_eq!(times, vec![3_598_000, 3_599_000, 3_600_000]);
+        assert_eq!(feed.history("BTC-USD", CandleInterval::OneHour, 10).len(), 2);
+        assert_eq!(feed.history("BTC-USD", CandleInterval::OneDay, 10).len(), 1);
+    }
+}
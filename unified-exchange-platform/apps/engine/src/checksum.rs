@@ -0,0 +1,105 @@
+/// Order book checksums for clients that keep a local book.
+///
+/// The checksum is a CRC-32 (IEEE) of the top `CHECKSUM_LEVELS` levels per
+/// side, written as `bid_price:bid_qty:ask_price:ask_qty:...`. Levels are
+/// interleaved best first. Once one side runs out, the other continues
+/// alone. Prices and quantities are the raw fixed-point integers, so the
+/// string does not depend on how decimals are formatted. Wire clients scale
+/// the decimal strings by the instrument precision sent with each snapshot.
+use crate::types::DepthLevel;
+use std::fmt::Write;
+
+/// Levels per side covered by a checksum.
+pub const CHECKSUM_LEVELS: usize = 25;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("book checksum {computed:#010x} does not match {expected:#010x}")]
+pub struct ChecksumMismatch {
+    pub expected: u32,
+    pub computed: u32,
+}
+
+/// Checksum of a book given its levels per side, best first.
+pub fn book_checksum<'a>(
+    bids: impl IntoIterator<Item = &'a DepthLevel>,
+    asks: impl IntoIterator<Item = &'a DepthLevel>,
+) -> u32 {
+    let mut bids = bids.into_iter().take(CHECKSUM_LEVELS);
+    let mut asks = asks.into_iter().take(CHECKSUM_LEVELS);
+    let mut text = String::with_capacity(CHECKSUM_LEVELS * 2 * 24);
+
+    loop {
+        let (bid, ask) = (bids.next(), asks.next());
+        if bid.is_none() && ask.is_none() {
+            break;
+        }
+        for level in [bid, ask].into_iter().flatten() {
+            if !text.is_empty() {
+                text.push(':');
+            }
+            let _ = write!(text, "{}:{}", level.price.raw(), level.amount.raw());
+        }
+    }
+    crc32(text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed_point::{Price, Qty};
+
+    fn level(price: u64, amount: u64) -> DepthLevel {
+        DepthLevel {
+            price: Price::from_raw(price),
+            amount: Qty::from_raw(amount),
+            order_count: 1,
+        }
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_levels_interleave_then_continue() {
+        let bids = [level(100, 5), level(99, 1)];
+        let asks = [level(101, 2)];
+        assert_eq!(book_checksum(&bids, &asks), crc32(b"100:5:101:2:99:1"));
+        assert_eq!(book_checksum(&[], &asks), crc32(b"101:2"));
+    }
+
+    #[test]
+    fn test_only_top_levels_count() {
+        let bids: Vec<_> = (0..40).map(|i| level(1_000 - i, 1)).collect();
+        let mut deeper = bids.clone();
+        deeper[CHECKSUM_LEVELS].amount = Qty::from_raw(7);
+        assert_eq!(book_checksum(&bids, &[]), book_checksum(&deeper, &[]));
+
+        deeper[0].amount = Qty::from_raw(7);
+        assert_ne!(book_checksum(&bids, &[]), book_checksum(&deeper, &[]));
+    }
+}
@@ -0,0 +1,99 @@
+/// Conflation for market data subscriptions on slow links.
+///
+/// A conflating subscription still reads every update from the feed, off
+/// the matching thread. Between emits it keeps only the latest state per
+/// key and drops the states that were overwritten. What the subscriber
+/// sees is always a correct, current picture of the book, just at a
+/// coarser grain. When it is emitted is up to the caller: on a timer, or
+/// whenever the client is ready for more.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+
+/// How a conflating subscription merges updates. A subscription that
+/// wants every update, in order, does not conflate at all and reads the
+/// feed directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflationMode {
+    /// The latest state of each price level that changed since the last
+    /// emit.
+    PerLevel,
+    /// The latest state of the whole symbol.
+    PerSymbol,
+}
+
+/// A subscription that conflates between flushes.
+pub trait Conflated {
+    type Batch;
+
+    /// Whether a flush would emit anything.
+    fn has_pending(&self) -> bool;
+
+    /// Takes in every update already on the feed without waiting. Returns
+    /// false once the feed is gone.
+    fn pump(&mut self) -> bool;
+
+    /// Waits for at least one update, then takes in the rest. Returns false
+    /// once the feed is gone.
+    fn changed(&mut self) -> impl Future<Output = bool> + Send;
+
+    /// Everything that changed since the last flush, conflated per the
+    /// subscription's mode.
+    fn flush(&mut self) -> Option<Self::Batch>;
+}
+
+/// The latest value per key since the last `drain`.
+#[derive(Debug, Clone)]
+pub struct Conflator<K, V> {
+    latest: BTreeMap<K, V>,
+}
+
+impl<K: Ord, V> Conflator<K, V> {
+    pub fn new() -> Self {
+        Self { latest: BTreeMap::new() }
+    }
+
+    /// Replaces any value still pending for `key`.
+    pub fn push(&mut self, key: K, value: V) {
+        self.latest.insert(key, value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.latest.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+    }
+
+    /// The pending values in key order, leaving the conflator empty.
+    pub fn drain(&mut self) -> Vec<V> {
+        std::mem::take(&mut self.latest).into_values().collect()
+    }
+}
+
+impl<K: Ord, V> Default for Conflator<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_latest_per_key() {
+        let mut conflator = Conflator::new();
+        conflator.push(2, "b1");
+        conflator.push(1, "a1");
+        conflator.push(2, "b2");
+        assert_eq!(conflator.len(), 2);
+        assert_eq!(conflator.drain(), vec!["a1", "b2"]);
+        assert!(conflator.is_empty());
+    }
+}
@@ -0,0 +1,608 @@
+/// Incremental L2 depth feed.
+///
+/// `DepthPublisher` runs as an `EventHandler` and rebuilds each book's
+/// aggregated price levels from the engine events. Every change to a level
+/// becomes a `LevelUpdate` carrying the level's new total amount and order
+/// count, numbered per symbol from 1. An amount of zero means the level is
+/// gone.
+///
+/// A subscriber gets a full `DepthSnapshot` plus the updates that follow
+/// it. Both are taken under the book's lock, so the first update has
+/// sequence `snapshot.sequence + 1`. Clients apply updates with
+/// `L2Book::apply`. It returns a `SequenceGap` when an update is missing,
+/// and the client then resyncs from a new snapshot. Each update also
+/// carries the checksum of the book after it; `L2Book::verify` catches a
+/// local book that has drifted even though no update went missing. As with
+/// trades, publishing never waits on a subscriber, so a slow one lags and
+/// is disconnected.
+///
+/// Subscribers on slow links use `ConflatedDepth` instead. It collapses
+/// updates per level or per symbol until the caller flushes. When it lags,
+/// it resyncs from a fresh snapshot on its own.
+///
+/// Each book keeps its latest updates in a `RetransmitRing`, recorded under
+/// the same lock before they are broadcast. `DepthFeed::retransmit` fills a
+/// gap from it.
+use crate::checksum::{book_checksum, ChecksumMismatch};
+use crate::conflation::{Conflated, ConflationMode, Conflator};
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::feed::{evict_idle, publish, LevelChange, RestingOrders};
+use crate::fixed_point::{Price, Qty};
+use crate::ids::Symbol;
+use crate::retransmit::{RetransmitConfig, RetransmitRing, Retransmission, Sequenced};
+use crate::types::{DepthLevel, DepthSnapshot, OrderSide};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// New state of one price level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LevelUpdate {
+    pub sequence: u64,
+    pub symbol: Symbol,
+    pub side: OrderSide,
+    pub price: Price,
+    /// Total remaining amount at the price, zero once the level is empty.
+    pub amount: Qty,
+    pub order_count: usize,
+    /// Checksum of the whole book once this update is applied.
+    pub checksum: u32,
+}
+
+impl Sequenced for LevelUpdate {
+    fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("expected depth update {expected}, got {received}")]
+pub struct SequenceGap {
+    pub expected: u64,
+    pub received: u64,
+}
+
+/// Aggregated price levels of one book, kept current by applying
+/// `LevelUpdate`s in sequence.
+#[derive(Debug, Clone, Default)]
+pub struct L2Book {
+    symbol: Symbol,
+    sequence: u64,
+    bids: BTreeMap<Reverse<Price>, DepthLevel>,
+    asks: BTreeMap<Price, DepthLevel>,
+}
+
+impl L2Book {
+    pub fn new(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &DepthSnapshot) -> Self {
+        Self {
+            symbol: snapshot.symbol.clone(),
+            sequence: snapshot.sequence,
+            bids: snapshot.bids.iter().map(|l| (Reverse(l.price), l.clone())).collect(),
+            asks: snapshot.asks.iter().map(|l| (l.price, l.clone())).collect(),
+        }
+    }
+
+    /// Sequence of the last update applied.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn best_bid(&self) -> Option<&DepthLevel> {
+        self.bids.values().next()
+    }
+
+    pub fn best_ask(&self) -> Option<&DepthLevel> {
+        self.asks.values().next()
+    }
+
+    pub fn level(&self, side: OrderSide, price: Price) -> Option<&DepthLevel> {
+        match side {
+            OrderSide::Buy => self.bids.get(&Reverse(price)),
+            OrderSide::Sell => self.asks.get(&price),
+        }
+    }
+
+    /// Applies the next update. An out-of-order update leaves the book
+    /// unchanged.
+    pub fn apply(&mut self, update: &LevelUpdate) -> Result<(), SequenceGap> {
+        if update.sequence != self.sequence + 1 {
+            return Err(SequenceGap {
+                expected: self.sequence + 1,
+                received: update.sequence,
+            });
+        }
+        self.sequence = update.sequence;
+
+        let level = DepthLevel {
+            price: update.price,
+            amount: update.amount,
+            order_count: update.order_count,
+        };
+        match (update.side, update.amount.is_zero()) {
+            (OrderSide::Buy, true) => drop(self.bids.remove(&Reverse(update.price))),
+            (OrderSide::Buy, false) => drop(self.bids.insert(Reverse(update.price), level)),
+            (OrderSide::Sell, true) => drop(self.asks.remove(&update.price)),
+            (OrderSide::Sell, false) => drop(self.asks.insert(update.price, level)),
+        }
+        Ok(())
+    }
+
+    /// Checksum of the top levels; see `checksum::book_checksum`.
+    pub fn checksum(&self) -> u32 {
+        book_checksum(self.bids.values(), self.asks.values())
+    }
+
+    /// Checks the book against a checksum from the feed. On a mismatch the
+    /// client resyncs from a new snapshot.
+    pub fn verify(&self, expected: u32) -> Result<(), ChecksumMismatch> {
+        let computed = self.checksum();
+        if computed == expected {
+            Ok(())
+        } else {
+            Err(ChecksumMismatch { expected, computed })
+        }
+    }
+
+    /// Every level, best price first on each side.
+    pub fn snapshot(&self) -> DepthSnapshot {
+        DepthSnapshot {
+            symbol: self.symbol.clone(),
+            sequence: self.sequence,
+            bids: self.bids.values().cloned().collect(),
+            asks: self.asks.values().cloned().collect(),
+        }
+    }
+}
+
+struct FeedBook {
+    book: L2Book,
+    updates: broadcast::Sender<LevelUpdate>,
+    history: RetransmitRing<LevelUpdate>,
+}
+
+/// Depth books and their update channels, by symbol.
+pub struct DepthFeed {
+    books: DashMap<Symbol, Arc<Mutex<FeedBook>>>,
+    capacity: usize,
+    retransmit: RetransmitConfig,
+}
+
+impl DepthFeed {
+    /// Each subscriber may fall `capacity` updates behind before it lags.
+    /// No updates are kept for retransmission.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_retransmit(capacity, RetransmitConfig::default())
+    }
+
+    pub fn with_retransmit(capacity: usize, retransmit: RetransmitConfig) -> Self {
+        Self {
+            books: DashMap::new(),
+            capacity,
+            retransmit,
+        }
+    }
+
+    /// The current depth of `symbol` and every update after it.
+    pub fn subscribe(&self, symbol: &str) -> (DepthSnapshot, broadcast::Receiver<LevelUpdate>) {
+        let book = self.book(symbol);
+        let book = book.lock();
+        (book.book.snapshot(), book.updates.subscribe())
+    }
+
+    /// Updates `from..=to` on `symbol` again.
+    pub fn retransmit(&self, symbol: &str, from: u64, to: u64) -> Retransmission<LevelUpdate> {
+        match self.books.get(symbol).map(|book| Arc::clone(&book)) {
+            Some(book) => {
+                let lookup = book.lock().history.get(from, to);
+                lookup.finish()
+            }
+            None => Retransmission::Messages(Vec::new()),
+        }
+    }
+
+    /// A subscription to `symbol` that conflates updates per `mode`. The
+    /// first flush is a snapshot.
+    pub fn subscribe_conflated(self: &Arc<Self>, symbol: &str, mode: ConflationMode) -> ConflatedDepth {
+        let (snapshot, updates) = self.subscribe(symbol);
+        ConflatedDepth {
+            feed: Arc::clone(self),
+            mode,
+            book: L2Book::from_snapshot(&snapshot),
+            updates,
+            pending: Conflator::new(),
+            needs_snapshot: true,
+        }
+    }
+
+    fn book(&self, symbol: &str) -> Arc<Mutex<FeedBook>> {
+        if let Some(book) = self.books.get(symbol) {
+            return Arc::clone(&book);
+        }
+        // A book only the map holds has no publisher and no subscribers.
+        evict_idle(&self.books, |book| Arc::strong_count(book) == 1 && book.lock().updates.receiver_count() == 0);
+        let book = self.books.entry(Symbol::from(symbol)).or_insert_with(|| {
+            Arc::new(Mutex::new(FeedBook {
+                book: L2Book::new(Symbol::from(symbol)),
+                updates: broadcast::channel(self.capacity).0,
+                history: RetransmitRing::new(&format!("depth-{symbol}"), &self.retransmit),
+            }))
+        });
+        Arc::clone(&book)
+    }
+}
+
+/// What a conflated depth subscription emits on a flush.
+#[derive(Debug, Clone)]
+pub enum DepthBatch {
+    /// The whole book, replacing whatever the subscriber held.
+    Snapshot(DepthSnapshot),
+    /// The latest state of every level changed since the last flush. The
+    /// book is then as of `sequence`, with `checksum`.
+    Levels {
+        sequence: u64,
+        checksum: u32,
+        levels: Vec<LevelUpdate>,
+    },
+}
+
+/// A depth subscription that keeps only the latest state per level (or a
+/// changed flag per symbol) between flushes. It mirrors the book itself, so
+/// when it lags it resubscribes and the next flush is a snapshot.
+pub struct ConflatedDepth {
+    feed: Arc<DepthFeed>,
+    mode: ConflationMode,
+    book: L2Book,
+    updates: broadcast::Receiver<LevelUpdate>,
+    /// Latest update per (side, price).
+    pending: Conflator<(u8, Price), LevelUpdate>,
+    needs_snapshot: bool,
+}
+
+impl Conflated for ConflatedDepth {
+    type Batch = DepthBatch;
+
+    fn has_pending(&self) -> bool {
+        self.needs_snapshot || !self.pending.is_empty()
+    }
+
+    fn pump(&mut self) -> bool {
+        loop {
+            match self.updates.try_recv() {
+                Ok(update) => self.absorb(update),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Lagged(_)) => self.resubscribe(),
+                Err(TryRecvError::Closed) => return false,
+            }
+        }
+    }
+
+    async fn changed(&mut self) -> bool {
+        match self.updates.recv().await {
+            Ok(update) => self.absorb(update),
+            Err(broadcast::error::RecvError::Lagged(_)) => self.resubscribe(),
+            Err(broadcast::error::RecvError::Closed) => return false,
+        }
+        self.pump()
+    }
+
+    fn flush(&mut self) -> Option<DepthBatch> {
+        if self.needs_snapshot {
+            self.needs_snapshot = false;
+            self.pending.clear();
+            return Some(DepthBatch::Snapshot(self.book.snapshot()));
+        }
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(DepthBatch::Levels {
+            sequence: self.book.sequence(),
+            checksum: self.book.checksum(),
+            levels: self.pending.drain(),
+        })
+    }
+}
+
+impl ConflatedDepth {
+    fn absorb(&mut self, update: LevelUpdate) {
+        if self.book.apply(&update).is_err() {
+            self.resubscribe();
+            return;
+        }
+        match self.mode {
+            ConflationMode::PerSymbol => self.needs_snapshot = true,
+            ConflationMode::PerLevel => self.pending.push((update.side as u8, update.price), update),
+        }
+    }
+
+    fn resubscribe(&mut self) {
+        let (snapshot, updates) = self.feed.subscribe(self.book.symbol.as_str());
+        self.book = L2Book::from_snapshot(&snapshot);
+        self.updates = updates;
+        self.needs_snapshot = true;
+    }
+}
+
+pub struct DepthPublisher {
+    feed: Arc<DepthFeed>,
+    books: HashMap<Symbol, Arc<Mutex<FeedBook>>>,
+    resting: RestingOrders,
+}
+
+impl DepthPublisher {
+    pub fn new(feed: Arc<DepthFeed>) -> Self {
+        Self {
+            feed,
+            books: HashMap::new(),
+            resting: RestingOrders::default(),
+        }
+    }
+
+    /// Adjusts a level and publishes its new state.
+    fn change(&mut self, symbol: &Symbol, change: LevelChange) {
+        let LevelChange { side, price, .. } = change;
+        let feed = &self.feed;
+        let book = self.books.entry(symbol.clone()).or_insert_with(|| feed.book(symbol));
+        let mut book = book.lock();
+
+        let mut level = book.book.level(side, price).cloned().unwrap_or(DepthLevel {
+            price,
+            amount: Qty::ZERO,
+            order_count: 0,
+        });
+        change.apply(&mut level);
+        let mut update = LevelUpdate {
+            sequence: book.book.sequence() + 1,
+            symbol: symbol.clone(),
+            side,
+            price,
+            amount: level.amount,
+            order_count: level.order_count,
+            checksum: 0,
+        };
+        book.book.apply(&update).expect("publisher numbers updates in order");
+        update.checksum = book.book.checksum();
+        book.history.record(&update);
+        publish(&book.updates, update);
+    }
+}
+
+impl EventHandler<EngineEvent> for DepthPublisher {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {
+        if let Some(change) = self.resting.on_event(event) {
+            self.change(event.symbol(), change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, MatchingEngine};
+    use crate::types::test_order;
+
+    fn levels(levels: &[DepthLevel]) -> Vec<(u64, u64, usize)> {
+        levels
+            .iter()
+            .map(|l| (l.price.raw(), l.amount.raw(), l.order_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_updates_carry_level_totals() {
+        let feed = Arc::new(DepthFeed::new(16));
+        let (snapshot, mut rx) = feed.subscribe("BTC-USD");
+        assert_eq!(snapshot.sequence, 0);
+        let mut publisher = DepthPublisher::new(Arc::clone(&feed));
+
+        let events = [
+            EngineEvent::OrderRested(test_order("a", OrderSide::Sell, 100, 2)),
+            EngineEvent::OrderRested(test_order("b", OrderSide::Sell, 100, 3)),
+            EngineEvent::Trade(crate::types::Trade {
+                id: 1,
+                symbol: "BTC-USD".into(),
+                maker_order_id: "a".into(),
+                taker_order_id: "t".into(),
+                price: Price::from_raw(100),
+                amount: Qty::from_raw(2),
+                taker_side: OrderSide::Buy,
+                timestamp: 0,
+            }),
+            EngineEvent::OrderCancelled(test_order("b", OrderSide::Sell, 100, 3)),
+        ];
+        for (i, event) in events.iter().enumerate() {
+            publisher.on_event(event, i as u64, true);
+        }
+
+        let seen: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|u| (u.sequence, u.amount.raw(), u.order_count))
+            .collect();
+        assert_eq!(seen, vec![(1, 2, 1), (2, 5, 2), (3, 3, 1), (4, 0, 0)]);
+        assert!(publisher.resting.is_empty());
+    }
+
+    #[test]
+    fn test_idle_books_are_evicted() {
+        let feed = Arc::new(DepthFeed::new(16));
+        let mut publisher = DepthPublisher::new(Arc::clone(&feed));
+        publisher.on_event(&EngineEvent::OrderRested(test_order("a", OrderSide::Sell, 100, 2)), 0, true);
+        drop(feed.subscribe("NOPE-1"));
+        drop(feed.subscribe("NOPE-2"));
+
+        // The published book stays; only the last idle one is left besides it.
+        let mut symbols: Vec<_> = feed.books.iter().map(|book| book.key().to_string()).collect();
+        symbols.sort();
+        assert_eq!(symbols, vec!["BTC-USD", "NOPE-2"]);
+    }
+
+    fn publish(publisher: &mut DepthPublisher, events: &[EngineEvent]) {
+        for (i, event) in events.iter().enumerate() {
+            publisher.on_event(event, i as u64, true);
+        }
+    }
+
+    #[test]
+    fn test_per_level_conflation_keeps_latest_state() {
+        let feed = Arc::new(DepthFeed::new(16));
+        let mut depth = feed.subscribe_conflated("BTC-USD", ConflationMode::PerLevel);
+        let mut publisher = DepthPublisher::new(Arc::clone(&feed));
+        assert!(matches!(depth.flush(), Some(DepthBatch::Snapshot(s)) if s.sequence == 0));
+
+        publish(
+            &mut publisher,
+            &[
+                EngineEvent::OrderRested(test_order("a", OrderSide::Buy, 100, 1)),
+                EngineEvent::OrderRested(test_order("b", OrderSide::Buy, 100, 2)),
+                EngineEvent::OrderRested(test_order("c", OrderSide::Sell, 101, 1)),
+                EngineEvent::OrderCancelled(test_order("a", OrderSide::Buy, 100, 1)),
+            ],
+        );
+        assert!(depth.pump());
+
+        let Some(DepthBatch::Levels { sequence, levels, .. }) = depth.flush() else {
+            panic!("expected a level batch");
+        };
+        assert_eq!(sequence, 4);
+        let seen: Vec<_> = levels.iter().map(|u| (u.sequence, u.amount.raw(), u.order_count)).collect();
+        assert_eq!(seen, vec![(4, 2, 1), (3, 1, 1)]);
+        assert!(depth.flush().is_none());
+    }
+
+    #[test]
+    fn test_lagging_conflated_subscriber_resyncs() {
+        let feed = Arc::new(DepthFeed::new(2));
+        let mut depth = feed.subscribe_conflated("BTC-USD", ConflationMode::PerLevel);
+        depth.flush();
+        let mut publisher = DepthPublisher::new(Arc::clone(&feed));
+
+        let events: Vec<_> = (0..5)
+            .map(|i| EngineEvent::OrderRested(test_order(&format!("o{i}"), OrderSide::Sell, 100 + i, 1)))
+            .collect();
+        publish(&mut publisher, &events);
+        assert!(depth.pump());
+
+        let Some(DepthBatch::Snapshot(snapshot)) = depth.flush() else {
+            panic!("expected a snapshot after lagging");
+        };
+        assert_eq!(snapshot.sequence, 5);
+        assert_eq!(snapshot.asks.len(), 5);
+    }
+
+    #[test]
+    fn test_per_symbol_conflation_emits_snapshots() {
+        let feed = Arc::new(DepthFeed::new(16));
+        let mut depth = feed.subscribe_conflated("BTC-USD", ConflationMode::PerSymbol);
+        depth.flush();
+        let mut publisher = DepthPublisher::new(Arc::clone(&feed));
+
+        publish(
+            &mut publisher,
+            &[
+                EngineEvent::OrderRested(test_order("a", OrderSide::Buy, 100, 1)),
+                EngineEvent::OrderRested(test_order("b", OrderSide::Buy, 99, 1)),
+            ],
+        );
+        assert!(depth.pump());
+
+        assert!(matches!(depth.flush(), Some(DepthBatch::Snapshot(s)) if s.sequence == 2 && s.bids.len() == 2));
+        assert!(!depth.has_pending());
+    }
+
+    #[test]
+    fn test_apply_detects_gap() {
+        let mut book = L2Book::new("BTC-USD".into());
+        let update = LevelUpdate {
+            sequence: 2,
+            symbol: "BTC-USD".into(),
+            side: OrderSide::Buy,
+            price: Price::from_raw(100),
+            amount: Qty::from_raw(1),
+            order_count: 1,
+            checksum: 0,
+        };
+
+        assert_eq!(book.apply(&update), Err(SequenceGap { expected: 1, received: 2 }));
+        assert_eq!(book.sequence(), 0);
+        assert!(book.level(OrderSide::Buy, Price::from_raw(100)).is_none());
+    }
+
+    #[test]
+    fn test_verify_catches_drifted_book() {
+        let feed = Arc::new(DepthFeed::new(64));
+        let mut publisher = DepthPublisher::new(Arc::clone(&feed));
+        let (snapshot, mut rx) = feed.subscribe("BTC-USD");
+        let mut client = L2Book::from_snapshot(&snapshot);
+
+        publish(
+            &mut publisher,
+            &[
+                EngineEvent::OrderRested(test_order("a", OrderSide::Buy, 100, 2)),
+                EngineEvent::OrderRested(test_order("b", OrderSide::Sell, 101, 1)),
+            ],
+        );
+        let first = rx.try_recv().unwrap();
+        client.apply(&first).unwrap();
+        assert_eq!(client.verify(first.checksum), Ok(()));
+
+        // The client misreads the ask; sequencing alone does not notice.
+        let mut second = rx.try_recv().unwrap();
+        second.amount = Qty::from_raw(3);
+        client.apply(&second).unwrap();
+        let err = client.verify(second.checksum).unwrap_err();
+        assert_eq!(err.expected, second.checksum);
+        assert_eq!(feed.subscribe("BTC-USD").0.checksum(), second.checksum);
+    }
+
+    #[test]
+    fn test_snapshot_plus_updates_track_engine_depth() {
+        let feed = Arc::new(DepthFeed::new(256));
+        let engine = MatchingEngine::start(
+            EngineConfig::default(),
+            vec![Box::new(DepthPublisher::new(Arc::clone(&feed)))],
+        );
+
+        engine.place_order(test_order("a", OrderSide::Sell, 101, 3)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Buy, 99, 2)).unwrap();
+
+        // Subscribe mid-stream, once the first two orders are on the feed.
+        let (snapshot, mut rx) = loop {
+            let (snapshot, rx) = feed.subscribe("BTC-USD");
+            if snapshot.sequence == 2 {
+                break (snapshot, rx);
+            }
+            std::thread::yield_now();
+        };
+        let mut client = L2Book::from_snapshot(&snapshot);
+
+        engine.place_order(test_order("c", OrderSide::Sell, 101, 1)).unwrap();
+        engine.amend_order("a", None, Some(Qty::from_raw(2))).unwrap();
+        engine.place_order(test_order("d", OrderSide::Buy, 101, 3)).unwrap();
+        engine.amend_order("b", Some(Price::from_raw(98)), None).unwrap();
+        engine.place_order(test_order("e", OrderSide::Sell, 95, 4)).unwrap();
+        engine.place_order(test_order("f", OrderSide::Buy, 90, 1)).unwrap();
+        engine.place_order(test_order("g", OrderSide::Sell, 102, 1)).unwrap();
+        engine.cancel_order("g");
+        let expected = engine.get_order_book("BTC-USD", 50).unwrap();
+        assert!(!expected.bids.is_empty() && !expected.asks.is_empty());
+        drop(engine);
+
+        while let Ok(update) = rx.try_recv() {
+            client.apply(&update).unwrap();
+            client.verify(update.checksum).unwrap();
+        }
+        let depth = client.snapshot();
+        assert_eq!(levels(&depth.bids), levels(&expected.bids));
+        assert_eq!(levels(&depth.asks), levels(&expected.asks));
+    }
+}
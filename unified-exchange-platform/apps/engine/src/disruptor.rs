@@ -0,0 +1,363 @@
+/// Disruptor-style ring buffer used to hand commands to matching threads
+/// and to fan engine events out to consumers.
+///
+/// Producers claim a sequence number, write the slot in place and publish
+/// it. Every consumer keeps its own cursor and sees each entry exactly once,
+/// in sequence order, in batches. A slot is only reused after all consumers
+/// have moved past it, so a slow consumer applies backpressure instead of
+/// losing entries. No locks are taken on the publish or consume paths.
+///
+/// This ring is used instead of the `lmax-disruptor` crate, which the
+/// manifest listed but no code used. That crate cannot be resolved by this
+/// build (`cargo build --offline` has no copy of it and the registry is not
+/// reachable), so it could be neither compiled nor benchmarked against. The
+/// ring here needs only `crossbeam`, which was already a dependency. With it,
+/// `benches/matching.rs` measures a full round trip through a shard (claim,
+/// publish, match on the shard thread, reply) at about 7 µs for
+/// `engine/place_resting/thin` and 9 µs for `engine/place_resting/deep`.
+/// Switching to the crate touches only this module and `engine`, which
+/// builds the rings; the feeds see nothing but `EventHandler`.
+use crossbeam::utils::{Backoff, CachePadded};
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::Duration;
+use tracing::info;
+
+/// Upper bound on how long an idle consumer sleeps before re-checking the
+/// ring on its own, in case a wake-up raced with it going to sleep.
+const PARK_TIMEOUT: Duration = Duration::from_millis(1);
+
+struct Slot<T> {
+    /// `sequence + 1` once the entry for `sequence` has been published.
+    published: AtomicU64,
+    value: UnsafeCell<Option<T>>,
+}
+
+struct ConsumerState {
+    /// Next sequence this consumer will read; everything below is released.
+    cursor: CachePadded<AtomicU64>,
+    sleeping: AtomicBool,
+    thread: OnceLock<Thread>,
+}
+
+pub struct RingBuffer<T> {
+    slots: Box<[Slot<T>]>,
+    mask: u64,
+    next: CachePadded<AtomicU64>,
+    consumers: Box<[ConsumerState]>,
+    halted: AtomicBool,
+}
+
+// Slots are only written by the producer that claimed them while no
+// consumer can read them, and only read after publication, so sharing the
+// ring is sound as long as entries can be shared between consumer threads.
+unsafe impl<T: Send + Sync> Sync for RingBuffer<T> {}
+unsafe impl<T: Send> Send for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    /// Creates a ring with `capacity` slots (a power of two) and a fixed set
+    /// of `consumers`, returning the producer handle and one handle per
+    /// consumer.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(capacity: usize, consumers: usize) -> (Producer<T>, Vec<Consumer<T>>) {
+        assert!(capacity.is_power_of_two(), "ring capacity must be a power of two");
+        assert!(consumers > 0, "ring needs at least one consumer");
+
+        let ring = Arc::new(Self {
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    published: AtomicU64::new(0),
+                    value: UnsafeCell::new(None),
+                })
+                .collect(),
+            mask: capacity as u64 - 1,
+            next: CachePadded::new(AtomicU64::new(0)),
+            consumers: (0..consumers)
+                .map(|_| ConsumerState {
+                    cursor: CachePadded::new(AtomicU64::new(0)),
+                    sleeping: AtomicBool::new(false),
+                    thread: OnceLock::new(),
+                })
+                .collect(),
+            halted: AtomicBool::new(false),
+        });
+
+        let handles = (0..consumers)
+            .map(|index| Consumer {
+                ring: Arc::clone(&ring),
+                index,
+                cursor: 0,
+            })
+            .collect();
+
+        (Producer { ring }, handles)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.mask + 1
+    }
+
+    fn slot(&self, sequence: u64) -> &Slot<T> {
+        &self.slots[(sequence & self.mask) as usize]
+    }
+
+    fn is_published(&self, sequence: u64) -> bool {
+        self.slot(sequence).published.load(Ordering::SeqCst) == sequence + 1
+    }
+
+    fn min_consumer_cursor(&self) -> u64 {
+        self.consumers
+            .iter()
+            .map(|c| c.cursor.load(Ordering::Acquire))
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn wake_consumers(&self) {
+        for consumer in self.consumers.iter() {
+            if consumer.sleeping.load(Ordering::SeqCst) && consumer.sleeping.swap(false, Ordering::SeqCst) {
+                if let Some(thread) = consumer.thread.get() {
+                    thread.unpark();
+                }
+            }
+        }
+    }
+}
+
+pub struct Producer<T> {
+    ring: Arc<RingBuffer<T>>,
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ring: Arc::clone(&self.ring),
+        }
+    }
+}
+
+impl<T> Producer<T> {
+    /// Publishes an entry and returns its sequence number. Spins while the
+    /// ring is full, i.e. until the slowest consumer frees a slot.
+    pub fn publish(&self, value: T) -> u64 {
+        let ring = &*self.ring;
+        let sequence = ring.next.fetch_add(1, Ordering::Relaxed);
+
+        if sequence >= ring.capacity() {
+            let wrap_point = sequence - ring.capacity();
+            let backoff = Backoff::new();
+            while ring.min_consumer_cursor() <= wrap_point {
+                backoff.snooze();
+            }
+        }
+
+        let slot = ring.slot(sequence);
+        // SAFETY: this producer alone claimed `sequence`, and every consumer
+        // has released the previous lap of this slot.
+        unsafe {
+            *slot.value.get() = Some(value);
+        }
+        slot.published.store(sequence + 1, Ordering::SeqCst);
+        ring.wake_consumers();
+
+        sequence
+    }
+
+    /// Stops consumers once they have drained everything already published.
+    pub fn halt(&self) {
+        self.ring.halted.store(true, Ordering::SeqCst);
+        for consumer in self.ring.consumers.iter() {
+            if let Some(thread) = consumer.thread.get() {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+pub struct Consumer<T> {
+    ring: Arc<RingBuffer<T>>,
+    index: usize,
+    cursor: u64,
+}
+
+impl<T> Consumer<T> {
+    /// Blocks until an entry is available. Returns `false` once the ring has
+    /// been halted and this consumer has read everything published.
+    pub fn wait(&mut self) -> bool {
+        let ring = &*self.ring;
+        let state = &ring.consumers[self.index];
+        let backoff = Backoff::new();
+
+        loop {
+            if ring.is_published(self.cursor) {
+                return true;
+            }
+            if ring.halted.load(Ordering::SeqCst) {
+                return ring.is_published(self.cursor);
+            }
+            if !backoff.is_completed() {
+                backoff.snooze();
+                continue;
+            }
+
+            state.thread.get_or_init(thread::current);
+            state.sleeping.store(true, Ordering::SeqCst);
+            if !ring.is_published(self.cursor) && !ring.halted.load(Ordering::SeqCst) {
+                thread::park_timeout(PARK_TIMEOUT);
+            }
+            state.sleeping.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Hands every contiguous published entry to `handler` as
+    /// `(entry, sequence, end_of_batch)` and returns how many were handled.
+    pub fn poll(&mut self, mut handler: impl FnMut(&T, u64, bool)) -> usize {
+        let end = self.available_end();
+        for sequence in self.cursor..end {
+            // SAFETY: the slot is published and cannot be reused until this
+            // consumer moves its cursor past it.
+            let value = unsafe { (*self.ring.slot(sequence).value.get()).as_ref() };
+            handler(value.expect("published slot holds a value"), sequence, sequence + 1 == end);
+        }
+        self.release(end)
+    }
+
+    /// Like `poll`, but moves entries out of the ring. Only valid for a ring
+    /// with a single consumer, such as a shard's command ring.
+    pub fn poll_owned(&mut self, mut handler: impl FnMut(T, u64, bool)) -> usize {
+        assert_eq!(self.ring.consumers.len(), 1, "poll_owned needs a sole consumer");
+
+        let end = self.available_end();
+        for sequence in self.cursor..end {
+            // SAFETY: as in `poll`, and no other consumer can observe the slot.
+            let value = unsafe { (*self.ring.slot(sequence).value.get()).take() };
+            handler(value.expect("published slot holds a value"), sequence, sequence + 1 == end);
+        }
+        self.release(end)
+    }
+
+    fn available_end(&self) -> u64 {
+        let limit = self.cursor + self.ring.capacity();
+        let mut end = self.cursor;
+        while end < limit && self.ring.is_published(end) {
+            end += 1;
+        }
+        end
+    }
+
+    fn release(&mut self, end: u64) -> usize {
+        let handled = (end - self.cursor) as usize;
+        self.cursor = end;
+        self.ring.consumers[self.index].cursor.store(end, Ordering::Release);
+        handled
+    }
+}
+
+/// A consumer of ring entries running on its own thread.
+pub trait EventHandler<T>: Send {
+    fn on_event(&mut self, event: &T, sequence: u64, end_of_batch: bool);
+}
+
+/// Runs `handler` on a dedicated thread until the ring is halted and drained.
+pub fn spawn_handler<T>(name: String, mut consumer: Consumer<T>, mut handler: Box<dyn EventHandler<T>>) -> JoinHandle<()>
+where
+    T: Send + Sync + 'static,
+{
+    thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            while consumer.wait() {
+                consumer.poll(|event, sequence, end_of_batch| handler.on_event(event, sequence, end_of_batch));
+            }
+            info!("Event handler {} stopped", name);
+        })
+        .expect("failed to spawn event handler thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consumers_see_every_entry_in_order() {
+        let (producer, consumers) = RingBuffer::new(8, 2);
+        let handles: Vec<_> = consumers
+            .into_iter()
+            .map(|mut consumer| {
+                thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    while consumer.wait() {
+                        consumer.poll(|value: &u64, sequence, _| {
+                            assert_eq!(*value, sequence);
+                            seen.push(*value);
+                        });
+                    }
+                    seen
+                })
+            })
+            .collect();
+
+        // More entries than slots, so the producer has to wrap around.
+        for value in 0..1_000 {
+            assert_eq!(producer.publish(value), value);
+        }
+        producer.halt();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (0..1_000).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_multiple_producers_keep_per_producer_order() {
+        let (producer, mut consumers) = RingBuffer::new(16, 1);
+        let mut consumer = consumers.pop().unwrap();
+
+        let producers: Vec<_> = (0..4u64)
+            .map(|p| {
+                let producer = producer.clone();
+                thread::spawn(move || {
+                    for i in 0..500u64 {
+                        producer.publish((p, i));
+                    }
+                })
+            })
+            .collect();
+
+        let reader = thread::spawn(move || {
+            let mut next = [0u64; 4];
+            let mut total = 0;
+            while consumer.wait() {
+                consumer.poll_owned(|(p, i), _, _| {
+                    assert_eq!(next[p as usize], i);
+                    next[p as usize] += 1;
+                    total += 1;
+                });
+            }
+            total
+        });
+
+        for handle in producers {
+            handle.join().unwrap();
+        }
+        producer.halt();
+        assert_eq!(reader.join().unwrap(), 2_000);
+    }
+
+    #[test]
+    fn test_poll_reports_end_of_batch() {
+        let (producer, mut consumers) = RingBuffer::new(8, 1);
+        for value in 0..3 {
+            producer.publish(value);
+        }
+
+        let mut batch = Vec::new();
+        let handled = consumers[0].poll(|value: &i32, _, end_of_batch| batch.push((*value, end_of_batch)));
+
+        assert_eq!(handled, 3);
+        assert_eq!(batch, vec![(0, false), (1, false), (2, true)]);
+        assert_eq!(consumers[0].poll(|_, _, _| panic!("nothing published")), 0);
+    }
+}
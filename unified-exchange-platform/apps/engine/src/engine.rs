@@ -1,252 +1,1341 @@
+use crate::disruptor::{self, Consumer, EventHandler, Producer, RingBuffer};
+use crate::fixed_point::{Price, Qty};
+use crate::ids::{OrderId, Symbol, TradeId, UserId};
+use crate::latency::{LatencyRecorder, LatencyReport, Operation};
+use crate::order_book::{OrderBook, PriceLevel};
+use crate::pool::ResultPool;
 use crate::types::*;
-use dashmap::DashMap;
-use parking_lot::RwLock;
-use rust_decimal::Decimal;
+use arc_swap::ArcSwap;
+use dashmap::mapref::entry::Entry;
+use dashmap::{DashMap, DashSet};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tracing::{info, warn};
-use uuid::Uuid;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
 use chrono::Utc;
 
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Number of matching threads; each symbol is owned by exactly one.
+    pub shards: usize,
+    /// Slots in each shard's command ring (power of two).
+    pub command_ring_capacity: usize,
+    /// Slots in the shared event ring (power of two).
+    pub event_ring_capacity: usize,
+    /// Levels per side kept in published book snapshots.
+    pub snapshot_depth: usize,
+    /// Result buffers each shard keeps for reuse; roughly how many results
+    /// may be in flight per shard before matching allocates again.
+    pub result_pool_size: usize,
+    /// Reject orders for symbols that were never registered, instead of
+    /// trading them at the default precision.
+    pub require_registered_symbols: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            shards: 4,
+            command_ring_capacity: 4096,
+            event_ring_capacity: 65536,
+            snapshot_depth: 50,
+            result_pool_size: 1024,
+            require_registered_symbols: false,
+        }
+    }
+}
+
+/// Everything the matching threads publish, in the order it happened on
+/// each symbol. Consumed by `EventHandler`s such as market data and the
+/// journal, each on its own thread.
+///
+/// A new order is an `OrderAccepted`, then its trades, then `OrderRested`
+/// if a remainder rests or `OrderExpired` if a market order ran out of
+/// liquidity. An amend is an `OrderAmended` with the new price and amount.
+/// If it lost queue priority (`requeued`), the order has left the book and
+/// the re-entered order's trades and `OrderRested` follow, as for a new
+/// order.
+#[derive(Debug, Clone, Serialize)]
+pub enum EngineEvent {
+    OrderAccepted(Order),
+    Trade(Trade),
+    OrderRested(Order),
+    OrderAmended { order: Order, requeued: bool },
+    OrderCancelled(Order),
+    OrderExpired(Order),
+}
+
+impl EngineEvent {
+    pub fn symbol(&self) -> &Symbol {
+        match self {
+            EngineEvent::Trade(trade) => &trade.symbol,
+            EngineEvent::OrderAccepted(order)
+            | EngineEvent::OrderRested(order)
+            | EngineEvent::OrderAmended { order, .. }
+            | EngineEvent::OrderCancelled(order)
+            | EngineEvent::OrderExpired(order) => &order.symbol,
+        }
+    }
+}
+
+enum Command {
+    Place {
+        order: Order,
+        reply: Reply<MatchingResult>,
+    },
+    Cancel {
+        order_id: String,
+        reply: Reply<Option<Order>>,
+    },
+    Amend {
+        order_id: String,
+        price: Option<Price>,
+        amount: Option<Qty>,
+        reply: Reply<Result<MatchingResult, RejectReason>>,
+    },
+    Latency {
+        reply: Reply<LatencyRecorder>,
+    },
+}
+
+/// A command stamped with the time the caller handed it to the engine.
+struct Envelope {
+    submitted: Instant,
+    command: Command,
+}
+
+#[derive(Default)]
+struct ShardStats {
+    resting_orders: AtomicUsize,
+    books: AtomicUsize,
+}
+
+/// Front end of the matching engine.
+///
+/// Symbols are partitioned across shards, and each shard's books are owned
+/// by a single matching thread that takes commands off its own ring buffer.
+/// Callers wait on a per-command reply, blocking or from an async task,
+/// while events are published to a shared ring read by the registered
+/// handlers, so no lock is held while matching.
+///
+/// After every change a matching thread swaps in a fresh aggregated depth
+/// snapshot for the book, so depth readers never touch the live book.
 pub struct MatchingEngine {
-    order_books: Arc<DashMap<String, Arc<RwLock<OrderBook>>>>,
-    orders: Arc<DashMap<String, Order>>,
+    shards: Vec<Producer<Envelope>>,
+    shard_stats: Vec<Arc<ShardStats>>,
+    shard_threads: Vec<JoinHandle<()>>,
+    events: Producer<EngineEvent>,
+    handler_threads: Vec<JoinHandle<()>>,
+    /// Open order id -> shard and owner. The matching threads drop the
+    /// routes of orders that fill or expire, so callers that stop waiting
+    /// leak none.
+    order_shards: Arc<DashMap<OrderId, Route>>,
+    instruments: Arc<DashMap<Symbol, Instrument>>,
+    snapshots: Arc<DashMap<Symbol, Arc<ArcSwap<DepthSnapshot>>>>,
+    /// Symbols whose market is closed.
+    halted: Arc<DashSet<Symbol>>,
+    snapshot_depth: usize,
+    require_registered_symbols: bool,
 }
 
 impl MatchingEngine {
     pub fn new() -> Self {
+        Self::start(EngineConfig::default(), Vec::new())
+    }
+
+    /// Spawns the matching threads and one thread per event handler.
+    pub fn start(config: EngineConfig, handlers: Vec<Box<dyn EventHandler<EngineEvent>>>) -> Self {
+        assert!(config.shards > 0, "engine needs at least one shard");
+
+        let instruments = Arc::new(DashMap::new());
+        let snapshots = Arc::new(DashMap::new());
+        let order_shards = Arc::new(DashMap::new());
+        let halted = Arc::new(DashSet::new());
+
+        // The ring needs at least one consumer, so an engine without
+        // handlers still gets one that discards events.
+        let mut handlers = handlers;
+        if handlers.is_empty() {
+            handlers.push(Box::new(DiscardEvents));
+        }
+
+        let (events, event_consumers) = RingBuffer::new(config.event_ring_capacity, handlers.len());
+        let handler_threads = event_consumers
+            .into_iter()
+            .zip(handlers)
+            .enumerate()
+            .map(|(i, (consumer, handler))| disruptor::spawn_handler(format!("engine-events-{}", i), consumer, handler))
+            .collect();
+
+        let mut shards = Vec::with_capacity(config.shards);
+        let mut shard_stats = Vec::with_capacity(config.shards);
+        let mut shard_threads = Vec::with_capacity(config.shards);
+
+        for index in 0..config.shards {
+            let (commands, mut consumers) = RingBuffer::new(config.command_ring_capacity, 1);
+            let stats = Arc::new(ShardStats::default());
+            let shard = Shard::new(
+                &config,
+                Arc::clone(&instruments),
+                Arc::clone(&snapshots),
+                Arc::clone(&halted),
+                Arc::clone(&order_shards),
+                events.clone(),
+                Arc::clone(&stats),
+            );
+            let consumer = consumers.pop().expect("ring has one consumer");
+
+            let handle = thread::Builder::new()
+                .name(format!("matching-shard-{}", index))
+                .spawn(move || shard.run(consumer))
+                .expect("failed to spawn matching thread");
+
+            shards.push(commands);
+            shard_stats.push(stats);
+            shard_threads.push(handle);
+        }
+
+        info!("Matching engine started with {} shards", config.shards);
+
         Self {
-            order_books: Arc::new(DashMap::new()),
-            orders: Arc::new(DashMap::new()),
+            shards,
+            shard_stats,
+            shard_threads,
+            events,
+            handler_threads,
+            order_shards,
+            instruments,
+            snapshots,
+            halted,
+            snapshot_depth: config.snapshot_depth,
+            require_registered_symbols: config.require_registered_symbols,
         }
     }
 
-    pub fn place_order(&self, mut order: Order) -> MatchingResult {
-        info!(
-            "Placing order: {} {} {} @ {} ({})",
-            order.id, order.side as u8, order.amount, order.price, order.symbol
-        );
+    /// Sets the precision for a symbol. Must be called before the symbol's
+    /// first order, since resting orders are stored at that scale.
+    pub fn register_instrument(&self, instrument: Instrument) {
+        self.instruments.insert(instrument.symbol.clone(), instrument);
+    }
 
-        let book = self.get_or_create_book(&order.symbol);
-        let mut result = MatchingResult::new();
+    /// Closes the market for a symbol. New orders and amends that would
+    /// requeue are rejected with `MarketClosed` until `resume_trading`;
+    /// cancels and amends that only reduce an order still go through.
+    pub fn halt_trading(&self, symbol: &str) {
+        self.halted.insert(Symbol::from(symbol));
+    }
+
+    pub fn resume_trading(&self, symbol: &str) {
+        self.halted.remove(symbol);
+    }
+
+    pub fn is_trading(&self, symbol: &str) -> bool {
+        !self.halted.contains(symbol)
+    }
 
-        // Match order
-        match order.order_type {
-            OrderType::Market => {
-                result = self.match_market_order(&mut order, &book);
+    /// The precision used for a symbol, falling back to the defaults for
+    /// symbols that were never registered.
+    pub fn instrument(&self, symbol: &str) -> Instrument {
+        lookup_instrument(&self.instruments, symbol)
+    }
+
+    /// Validates and places an order. The result's `status` says whether
+    /// it rested, filled or expired.
+    ///
+    /// Blocks until the matching thread replies, so it must not be called
+    /// from an async task; use `place_order_async` there.
+    pub fn place_order(&self, order: Order) -> Result<MatchingResult, RejectReason> {
+        self.check_order(&order)?;
+        Ok(self.submit_order(order)?.wait())
+    }
+
+    /// `place_order` without blocking the caller's runtime thread.
+    pub async fn place_order_async(&self, order: Order) -> Result<MatchingResult, RejectReason> {
+        self.check_order(&order)?;
+        Ok(self.submit_order(order)?.recv().await)
+    }
+
+    /// Places a batch of orders. Orders are handed to the matching threads
+    /// in batch order before any reply is awaited, so orders for the same
+    /// symbol match in that order and the batch costs one round trip.
+    ///
+    /// With `all_or_none`, nothing is placed unless every order passes
+    /// validation; valid orders are then reported as `BatchRejected`. An
+    /// order id repeated within the batch counts as a duplicate.
+    pub fn place_orders(&self, orders: Vec<Order>, all_or_none: bool) -> Vec<Result<MatchingResult, RejectReason>> {
+        self.submit_orders(orders, all_or_none)
+            .into_iter()
+            .map(|p| p.map(Pending::wait))
+            .collect()
+    }
+
+    /// `place_orders` without blocking the caller's runtime thread.
+    pub async fn place_orders_async(&self, orders: Vec<Order>, all_or_none: bool) -> Vec<Result<MatchingResult, RejectReason>> {
+        let mut results = Vec::with_capacity(orders.len());
+        for pending in self.submit_orders(orders, all_or_none) {
+            results.push(match pending {
+                Ok(pending) => Ok(pending.recv().await),
+                Err(reason) => Err(reason),
+            });
+        }
+        results
+    }
+
+    fn submit_orders(&self, orders: Vec<Order>, all_or_none: bool) -> Vec<Result<Pending<MatchingResult>, RejectReason>> {
+        let mut batch_ids = HashSet::with_capacity(orders.len());
+        let validated: Vec<_> = orders
+            .into_iter()
+            .map(|order| {
+                self.check_order(&order)?;
+                if !batch_ids.insert(order.id.clone()) {
+                    return Err(RejectReason::DuplicateOrderId);
+                }
+                Ok(order)
+            })
+            .collect();
+
+        if all_or_none && validated.iter().any(Result::is_err) {
+            return validated
+                .into_iter()
+                .map(|v| Err(v.err().unwrap_or(RejectReason::BatchRejected)))
+                .collect();
+        }
+
+        validated
+            .into_iter()
+            .map(|v| v.and_then(|order| self.submit_order(order)))
+            .collect()
+    }
+
+    /// Checks that need engine state on top of `validate_order`.
+    fn check_order(&self, order: &Order) -> Result<(), RejectReason> {
+        validate_order(order)?;
+        match self.instruments.get(order.symbol.as_str()) {
+            Some(instrument) => instrument.check_order_qty(order.amount)?,
+            None if self.require_registered_symbols => return Err(RejectReason::UnknownSymbol),
+            None => {}
+        }
+        if self.halted.contains(order.symbol.as_str()) {
+            return Err(RejectReason::MarketClosed);
+        }
+        if self.order_shards.contains_key(order.id.as_str()) {
+            return Err(RejectReason::DuplicateOrderId);
+        }
+        Ok(())
+    }
+
+    fn submit_order(&self, order: Order) -> Result<Pending<MatchingResult>, RejectReason> {
+        let shard = self.shard_for(&order.symbol);
+
+        // Claiming the route also settles a race between two orders with
+        // the same id that both passed `check_order`.
+        match self.order_shards.entry(order.id.clone()) {
+            Entry::Occupied(_) => return Err(RejectReason::DuplicateOrderId),
+            Entry::Vacant(route) => {
+                route.insert(Route {
+                    shard,
+                    user_id: order.user_id.clone(),
+                });
             }
-            OrderType::Limit => {
-                result = self.match_limit_order(&mut order, &book);
+        }
+
+        let (reply, result) = reply_channel();
+        self.submit(shard, Command::Place { order, reply });
+        Ok(result)
+    }
+
+    /// Cancels a resting order, returning it as it was cancelled. Blocks
+    /// like `place_order`.
+    pub fn cancel_order(&self, order_id: &str) -> Option<Order> {
+        self.submit_cancel(order_id)?.wait()
+    }
+
+    /// `cancel_order` without blocking the caller's runtime thread.
+    pub async fn cancel_order_async(&self, order_id: &str) -> Option<Order> {
+        self.submit_cancel(order_id)?.recv().await
+    }
+
+    fn submit_cancel(&self, order_id: &str) -> Option<Pending<Option<Order>>> {
+        let shard = match self.order_shards.remove(order_id) {
+            Some((_, route)) => route.shard,
+            None => {
+                warn!("Order not found for cancellation: {}", order_id);
+                return None;
             }
+        };
+
+        let (reply, result) = reply_channel();
+        self.submit(shard, Command::Cancel {
+            order_id: order_id.to_string(),
+            reply,
+        });
+        Some(result)
+    }
+
+    /// The user who placed an open order. Up to date as soon as a placement
+    /// returns, unlike `ExecutionReports`.
+    pub fn order_owner(&self, order_id: &str) -> Option<UserId> {
+        self.order_shards.get(order_id).map(|route| route.user_id.clone())
+    }
+
+    /// Changes the price and/or total amount of a resting order. Reducing
+    /// the amount at the same price keeps queue priority; any other change
+    /// re-enters the order at the back of the queue, where it may trade.
+    /// Blocks like `place_order`.
+    pub fn amend_order(&self, order_id: &str, price: Option<Price>, amount: Option<Qty>) -> Result<MatchingResult, RejectReason> {
+        self.submit_amend(order_id, price, amount)?.wait()
+    }
+
+    /// `amend_order` without blocking the caller's runtime thread.
+    pub async fn amend_order_async(&self, order_id: &str, price: Option<Price>, amount: Option<Qty>) -> Result<MatchingResult, RejectReason> {
+        self.submit_amend(order_id, price, amount)?.recv().await
+    }
+
+    fn submit_amend(
+        &self,
+        order_id: &str,
+        price: Option<Price>,
+        amount: Option<Qty>,
+    ) -> Result<Pending<Result<MatchingResult, RejectReason>>, RejectReason> {
+        let shard = self.order_shards.get(order_id).ok_or(RejectReason::UnknownOrder)?.shard;
+
+        let (reply, result) = reply_channel();
+        self.submit(shard, Command::Amend {
+            order_id: order_id.to_string(),
+            price,
+            amount,
+            reply,
+        });
+        Ok(result)
+    }
+
+    /// The latest published depth for a symbol, up to `depth` levels per
+    /// side and at most `EngineConfig::snapshot_depth`.
+    pub fn get_order_book(&self, symbol: &str, depth: usize) -> Option<DepthSnapshot> {
+        self.book_snapshot(symbol).map(|snapshot| DepthSnapshot {
+            symbol: snapshot.symbol.clone(),
+            sequence: snapshot.sequence,
+            bids: snapshot.bids.iter().take(depth).cloned().collect(),
+            asks: snapshot.asks.iter().take(depth).cloned().collect(),
+        })
+    }
+
+    /// Levels per side in published snapshots, the most `get_order_book`
+    /// can return.
+    pub fn snapshot_depth(&self) -> usize {
+        self.snapshot_depth
+    }
+
+    /// The latest published depth snapshot for a symbol. Never waits on the
+    /// matching thread.
+    pub fn book_snapshot(&self, symbol: &str) -> Option<Arc<DepthSnapshot>> {
+        self.snapshots.get(symbol).map(|snapshot| snapshot.load_full())
+    }
+
+    /// Order path latency percentiles, merged across shards. Blocks like
+    /// `place_order`.
+    pub fn latency_report(&self) -> LatencyReport {
+        let mut merged = LatencyRecorder::new();
+        for recorder in self.submit_latency() {
+            merged.merge(&recorder.wait());
         }
+        merged.report()
+    }
 
-        // Add remaining amount to order book if not fully filled
-        if !order.is_filled() {
-            let mut book_guard = book.write();
-            book_guard.add_order(order.clone());
-            self.orders.insert(order.id.clone(), order.clone());
-            info!("Order {} added to book with remaining {}", order.id, order.remaining());
-        } else {
-            info!("Order {} fully filled", order.id);
+    /// `latency_report` without blocking the caller's runtime thread.
+    pub async fn latency_report_async(&self) -> LatencyReport {
+        let mut merged = LatencyRecorder::new();
+        for recorder in self.submit_latency() {
+            merged.merge(&recorder.recv().await);
         }
+        merged.report()
+    }
 
-        result
+    fn submit_latency(&self) -> Vec<Pending<LatencyRecorder>> {
+        (0..self.shards.len())
+            .map(|shard| {
+                let (reply, recorder) = reply_channel();
+                self.submit(shard, Command::Latency { reply });
+                recorder
+            })
+            .collect()
     }
 
-    fn match_market_order(&self, order: &mut Order, book: &Arc<RwLock<OrderBook>>) -> MatchingResult {
-        let mut result = MatchingResult::new();
-        let mut book_guard = book.write();
+    pub fn get_stats(&self) -> EngineStats {
+        EngineStats {
+            total_orders: self.shard_stats.iter().map(|s| s.resting_orders.load(Ordering::Relaxed)).sum(),
+            active_symbols: self.shard_stats.iter().map(|s| s.books.load(Ordering::Relaxed)).sum(),
+        }
+    }
 
-        let opposite_levels = match order.side {
-            OrderSide::Buy => &mut book_guard.asks,
-            OrderSide::Sell => &mut book_guard.bids,
-        };
+    fn submit(&self, shard: usize, command: Command) {
+        self.shards[shard].publish(Envelope {
+            submitted: Instant::now(),
+            command,
+        });
+    }
 
-        let mut prices_to_remove = Vec::new();
+    fn shard_for(&self, symbol: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        symbol.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+}
 
-        for (price, level) in opposite_levels.iter_mut() {
-            let mut orders_to_remove = Vec::new();
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            for (idx, maker_order) in level.orders.iter_mut().enumerate() {
-                if order.is_filled() {
-                    break;
-                }
+impl Drop for MatchingEngine {
+    fn drop(&mut self) {
+        // Drain commands first so every event is published before the
+        // handlers are told to stop.
+        for shard in &self.shards {
+            shard.halt();
+        }
+        for handle in self.shard_threads.drain(..) {
+            let _ = handle.join();
+        }
 
-                let fill_amount = order.remaining().min(maker_order.remaining());
-                let fill_price = maker_order.price;
-
-                // Create trade
-                let trade = Trade {
-                    id: Uuid::new_v4().to_string(),
-                    symbol: order.symbol.clone(),
-                    maker_order_id: maker_order.id.clone(),
-                    taker_order_id: order.id.clone(),
-                    price: fill_price,
-                    amount: fill_amount,
-                    taker_side: order.side,
-                    timestamp: Utc::now().timestamp_millis(),
-                };
+        self.events.halt();
+        for handle in self.handler_threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
 
-                result.trades.push(trade);
+/// Checks an order before it reaches a book.
+pub fn validate_order(order: &Order) -> Result<(), RejectReason> {
+    if order.amount.is_zero() {
+        return Err(RejectReason::ZeroAmount);
+    }
+    if order.order_type == OrderType::Limit && order.price.is_zero() {
+        return Err(RejectReason::ZeroPrice);
+    }
+    Ok(())
+}
 
-                // Update filled amounts
-                order.filled += fill_amount;
-                maker_order.filled += fill_amount;
+/// Where an open order rests, and whose it is.
+struct Route {
+    shard: usize,
+    user_id: UserId,
+}
 
-                if maker_order.is_filled() {
-                    orders_to_remove.push(idx);
-                }
+/// The sending half of a command's reply, used by the matching thread.
+type Reply<T> = oneshot::Sender<T>;
 
-                result.updated_orders.push(maker_order.clone());
-            }
+/// The receiving half of a command's reply. Blocking callers `wait`, async
+/// ones `recv`, so an async caller never holds a runtime thread while the
+/// matching thread is busy.
+struct Pending<T>(oneshot::Receiver<T>);
 
-            // Remove filled orders (reverse order to maintain indices)
-            for idx in orders_to_remove.iter().rev() {
-                level.orders.remove(*idx);
-            }
+fn reply_channel<T>() -> (Reply<T>, Pending<T>) {
+    let (reply, result) = oneshot::channel();
+    (reply, Pending(result))
+}
 
-            if level.orders.is_empty() {
-                prices_to_remove.push(*price);
-            }
+impl<T> Pending<T> {
+    fn wait(self) -> T {
+        self.0.blocking_recv().expect("matching thread stopped")
+    }
 
-            if order.is_filled() {
-                break;
-            }
+    async fn recv(self) -> T {
+        self.0.await.expect("matching thread stopped")
+    }
+}
+
+fn lookup_instrument(instruments: &DashMap<Symbol, Instrument>, symbol: &str) -> Instrument {
+    instruments
+        .get(symbol)
+        .map(|i| i.clone())
+        .unwrap_or_else(|| Instrument::with_default_precision(symbol))
+}
+
+pub struct EngineStats {
+    pub total_orders: usize,
+    pub active_symbols: usize,
+}
+
+struct DiscardEvents;
+
+impl EventHandler<EngineEvent> for DiscardEvents {
+    fn on_event(&mut self, _event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {}
+}
+
+struct ShardBook {
+    book: OrderBook,
+    sequence: u64,
+    last_trade_id: TradeId,
+    snapshot: Arc<ArcSwap<DepthSnapshot>>,
+    /// The snapshot replaced last time, rewritten in place by the next
+    /// publish if no reader still holds it.
+    spare_snapshot: Option<Arc<DepthSnapshot>>,
+}
+
+impl ShardBook {
+    fn publish_snapshot(&mut self, depth: usize) {
+        self.sequence += 1;
+        let mut next = match self.spare_snapshot.take() {
+            Some(spare) if Arc::strong_count(&spare) == 1 => spare,
+            _ => Arc::new(DepthSnapshot::default()),
+        };
+
+        let snapshot = Arc::get_mut(&mut next).expect("snapshot is not shared");
+        self.book.depth_into(depth, snapshot);
+        snapshot.sequence = self.sequence;
+        self.spare_snapshot = Some(self.snapshot.swap(next));
+    }
+}
+
+/// The books of one shard, owned by its matching thread.
+///
+/// Orders, trades and results on this thread share their strings with the
+/// caller's order and draw their buffers from pools (emptied price levels,
+/// depth snapshots and result vectors), so once those are warm, matching
+/// does not allocate.
+struct Shard {
+    books: HashMap<Symbol, ShardBook>,
+    /// Resting order id -> (symbol, side, price), for cancels.
+    orders: HashMap<OrderId, (Symbol, OrderSide, Price)>,
+    instruments: Arc<DashMap<Symbol, Instrument>>,
+    snapshots: Arc<DashMap<Symbol, Arc<ArcSwap<DepthSnapshot>>>>,
+    halted: Arc<DashSet<Symbol>>,
+    /// The engine's routes, dropped here for orders that can no longer be
+    /// cancelled, before the reply goes out.
+    routes: Arc<DashMap<OrderId, Route>>,
+    snapshot_depth: usize,
+    results: ResultPool,
+    events: Producer<EngineEvent>,
+    stats: Arc<ShardStats>,
+    latency: LatencyRecorder,
+}
+
+impl Shard {
+    fn new(
+        config: &EngineConfig,
+        instruments: Arc<DashMap<Symbol, Instrument>>,
+        snapshots: Arc<DashMap<Symbol, Arc<ArcSwap<DepthSnapshot>>>>,
+        halted: Arc<DashSet<Symbol>>,
+        routes: Arc<DashMap<OrderId, Route>>,
+        events: Producer<EngineEvent>,
+        stats: Arc<ShardStats>,
+    ) -> Self {
+        Self {
+            books: HashMap::new(),
+            orders: HashMap::new(),
+            instruments,
+            snapshots,
+            halted,
+            routes,
+            snapshot_depth: config.snapshot_depth,
+            results: ResultPool::new(config.result_pool_size),
+            events,
+            stats,
+            latency: LatencyRecorder::new(),
         }
+    }
 
-        // Remove empty price levels
-        for price in prices_to_remove {
-            opposite_levels.remove(&price);
+    fn run(mut self, mut commands: Consumer<Envelope>) {
+        while commands.wait() {
+            commands.poll_owned(|envelope, _, _| self.handle(envelope));
         }
+    }
 
-        result
+    fn handle(&mut self, envelope: Envelope) {
+        // A caller that gave up waiting is not an error for the book.
+        let operation = match envelope.command {
+            Command::Place { order, reply } => {
+                self.events.publish(EngineEvent::OrderAccepted(order.clone()));
+                let _ = reply.send(self.place_order(order));
+                Operation::Place
+            }
+            Command::Cancel { order_id, reply } => {
+                let _ = reply.send(self.cancel_order(&order_id));
+                Operation::Cancel
+            }
+            Command::Amend {
+                order_id,
+                price,
+                amount,
+                reply,
+            } => {
+                let _ = reply.send(self.amend_order(&order_id, price, amount));
+                Operation::Amend
+            }
+            Command::Latency { reply } => {
+                let _ = reply.send(self.latency.clone());
+                return;
+            }
+        };
+        self.latency.record(operation, envelope.submitted.elapsed());
     }
 
-    fn match_limit_order(&self, order: &mut Order, book: &Arc<RwLock<OrderBook>>) -> MatchingResult {
-        let mut result = MatchingResult::new();
-        let mut book_guard = book.write();
+    fn place_order(&mut self, mut order: Order) -> MatchingResult {
+        debug!(
+            "Placing order: {} {} {} @ {} ({})",
+            order.id, order.side as u8, order.amount, order.price, order.symbol
+        );
 
-        let opposite_levels = match order.side {
-            OrderSide::Buy => &mut book_guard.asks,
-            OrderSide::Sell => &mut book_guard.bids,
+        let snapshot_depth = self.snapshot_depth;
+        let mut result = self.results.take();
+        let shard_book = self.book_mut(&order.symbol);
+        order.symbol = shard_book.book.instrument.symbol.clone();
+        Self::match_order(&mut order, shard_book, &mut result);
+
+        // Whatever a market order could not fill expires; a limit order's
+        // remainder rests.
+        order.status = if order.is_filled() {
+            OrderStatus::Filled
+        } else if order.order_type == OrderType::Market {
+            OrderStatus::Expired
+        } else if order.filled.is_zero() {
+            OrderStatus::New
+        } else {
+            OrderStatus::PartiallyFilled
         };
+        result.status = order.status;
+        debug!("Order {} is {:?} with remaining {}", order.id, order.status, order.remaining());
 
-        let mut prices_to_remove = Vec::new();
+        let resting = order.status.is_open();
+        if resting {
+            // check_order refused the id if it was already open.
+            let added = shard_book.book.add_order(order.clone());
+            debug_assert!(added.is_ok(), "order {} already rests", order.id);
+        }
 
-        for (price, level) in opposite_levels.iter_mut() {
-            // Check if price crosses
-            let crosses = match order.side {
-                OrderSide::Buy => *price <= order.price,
-                OrderSide::Sell => *price >= order.price,
-            };
+        // A market order against an empty side changes nothing.
+        if resting || !result.trades.is_empty() {
+            shard_book.publish_snapshot(snapshot_depth);
+        }
 
-            if !crosses {
-                break;
+        for maker in result.updated_orders.iter().filter(|o| o.is_filled()) {
+            self.orders.remove(&maker.id);
+            self.routes.remove(&maker.id);
+        }
+        for trade in &result.trades {
+            self.events.publish(EngineEvent::Trade(trade.clone()));
+        }
+        if resting {
+            self.orders
+                .insert(order.id.clone(), (order.symbol.clone(), order.side, order.price));
+            self.events.publish(EngineEvent::OrderRested(order));
+        } else {
+            self.routes.remove(&order.id);
+            if order.status == OrderStatus::Expired {
+                self.events.publish(EngineEvent::OrderExpired(order));
             }
+        }
+        self.stats.resting_orders.store(self.orders.len(), Ordering::Relaxed);
+
+        result
+    }
 
-            let mut orders_to_remove = Vec::new();
+    /// Matches an incoming order against the opposite side, best price first
+    /// and oldest order first within a price. Market orders walk the book
+    /// until filled or the side is empty; limit orders stop at their price.
+    fn match_order(order: &mut Order, shard_book: &mut ShardBook, result: &mut MatchingResult) {
+        let book = &mut shard_book.book;
 
-            for (idx, maker_order) in level.orders.iter_mut().enumerate() {
-                if order.is_filled() {
+        while !order.is_filled() {
+            let best_price = match order.side {
+                OrderSide::Buy => book.best_ask(),
+                OrderSide::Sell => book.best_bid(),
+            };
+
+            let price = match best_price {
+                Some(price) => price,
+                None => break,
+            };
+
+            // Check if price crosses
+            if order.order_type == OrderType::Limit {
+                let crosses = match order.side {
+                    OrderSide::Buy => price <= order.price,
+                    OrderSide::Sell => price >= order.price,
+                };
+
+                if !crosses {
                     break;
                 }
+            }
 
-                let fill_amount = order.remaining().min(maker_order.remaining());
-                let fill_price = maker_order.price; // Price-time priority
-
-                // Create trade
-                let trade = Trade {
-                    id: Uuid::new_v4().to_string(),
-                    symbol: order.symbol.clone(),
-                    maker_order_id: maker_order.id.clone(),
-                    taker_order_id: order.id.clone(),
-                    price: fill_price,
-                    amount: fill_amount,
-                    taker_side: order.side,
-                    timestamp: Utc::now().timestamp_millis(),
-                };
+            let (opposite_side, opposite_levels) = match order.side {
+                OrderSide::Buy => (OrderSide::Sell, &mut book.asks),
+                OrderSide::Sell => (OrderSide::Buy, &mut book.bids),
+            };
 
-                result.trades.push(trade);
+            let level = opposite_levels
+                .get_mut(&price)
+                .expect("best price has a level");
 
-                order.filled += fill_amount;
-                maker_order.filled += fill_amount;
+            Self::fill_level(order, level, &mut shard_book.last_trade_id, result);
+            book.remove_level_if_empty(opposite_side, price);
+        }
+    }
 
-                if maker_order.is_filled() {
-                    orders_to_remove.push(idx);
-                }
+    fn fill_level(order: &mut Order, level: &mut PriceLevel, last_trade_id: &mut TradeId, result: &mut MatchingResult) {
+        while !order.is_filled() {
+            let maker_order = match level.front() {
+                Some(maker_order) => maker_order,
+                None => break,
+            };
 
-                result.updated_orders.push(maker_order.clone());
-            }
+            let fill_amount = order.remaining().min(maker_order.remaining());
+            let fill_price = maker_order.price; // Price-time priority
 
-            for idx in orders_to_remove.iter().rev() {
-                level.orders.remove(*idx);
-            }
+            // Create trade
+            *last_trade_id += 1;
+            let trade = Trade {
+                id: *last_trade_id,
+                symbol: order.symbol.clone(),
+                maker_order_id: maker_order.id.clone(),
+                taker_order_id: order.id.clone(),
+                price: fill_price,
+                amount: fill_amount,
+                taker_side: order.side,
+                timestamp: Utc::now().timestamp_millis(),
+            };
 
-            if level.orders.is_empty() {
-                prices_to_remove.push(*price);
-            }
+            result.trades.push(trade);
+
+            // Update filled amounts
+            order.fill(fill_amount);
+            let maker_order = level.fill_front(fill_amount).expect("front order exists");
+
+            result.updated_orders.push(maker_order.clone());
 
-            if order.is_filled() {
-                break;
+            if maker_order.is_filled() {
+                level.pop_front();
             }
         }
+    }
+
+    fn cancel_order(&mut self, order_id: &str) -> Option<Order> {
+        let mut order = self.take_resting(order_id)?;
+        order.status = OrderStatus::Cancelled;
+        debug!("Cancelled order: {}", order_id);
+        self.events.publish(EngineEvent::OrderCancelled(order.clone()));
+
+        Some(order)
+    }
+
+    /// Takes a resting order off its book without publishing an event.
+    fn take_resting(&mut self, order_id: &str) -> Option<Order> {
+        let (symbol, side, price) = self.orders.remove(order_id)?;
+        self.stats.resting_orders.store(self.orders.len(), Ordering::Relaxed);
+
+        let shard_book = self.books.get_mut(&symbol)?;
+        let order = shard_book.book.remove_order(order_id, side, price)?;
+        shard_book.publish_snapshot(self.snapshot_depth);
+        Some(order)
+    }
+
+    fn amend_order(
+        &mut self,
+        order_id: &str,
+        price: Option<Price>,
+        amount: Option<Qty>,
+    ) -> Result<MatchingResult, RejectReason> {
+        let (symbol, side, current_price) = self.orders.get(order_id).cloned().ok_or(RejectReason::UnknownOrder)?;
+        let snapshot_depth = self.snapshot_depth;
+        let shard_book = self.books.get_mut(&symbol).ok_or(RejectReason::UnknownOrder)?;
+
+        let levels = match side {
+            OrderSide::Buy => &mut shard_book.book.bids,
+            OrderSide::Sell => &mut shard_book.book.asks,
+        };
+        let level = levels.get_mut(&current_price).ok_or(RejectReason::UnknownOrder)?;
+        let existing = level.get(order_id).ok_or(RejectReason::UnknownOrder)?;
 
-        for price in prices_to_remove {
-            opposite_levels.remove(&price);
+        let new_price = price.unwrap_or(existing.price);
+        let new_amount = amount.unwrap_or(existing.amount);
+        if new_amount <= existing.filled {
+            return Err(RejectReason::AmountBelowFilled);
+        }
+        if new_price.is_zero() {
+            return Err(RejectReason::ZeroPrice);
         }
 
-        result
+        debug!("Amending order {}: {} @ {}", order_id, new_amount, new_price);
+
+        if new_price == existing.price && new_amount <= existing.amount {
+            let order = level.reduce_order(order_id, new_amount).cloned().expect("order is resting");
+            shard_book.publish_snapshot(snapshot_depth);
+            let mut result = self.results.take();
+            result.status = order.status;
+            self.events.publish(EngineEvent::OrderAmended { order, requeued: false });
+            return Ok(result);
+        }
+
+        // Anything else re-enters the book, so it passes the same checks as
+        // a new order.
+        if self.halted.contains(symbol.as_str()) {
+            return Err(RejectReason::MarketClosed);
+        }
+        shard_book.book.instrument.check_order_qty(new_amount)?;
+
+        let mut order = self.take_resting(order_id).expect("order is resting");
+        order.price = new_price;
+        order.amount = new_amount;
+        self.events.publish(EngineEvent::OrderAmended {
+            order: order.clone(),
+            requeued: true,
+        });
+        Ok(self.place_order(order))
     }
 
-    pub fn cancel_order(&self, order_id: &str) -> Option<Order> {
-        if let Some((_, order)) = self.orders.remove(order_id) {
-            let book = self.get_or_create_book(&order.symbol);
-            let mut book_guard = book.write();
-            
-            book_guard.remove_order(order_id, order.side, order.price);
-            info!("Cancelled order: {}", order_id);
-            
-            Some(order)
-        } else {
-            warn!("Order not found for cancellation: {}", order_id);
-            None
+    fn book_mut(&mut self, symbol: &str) -> &mut ShardBook {
+        if !self.books.contains_key(symbol) {
+            let book = OrderBook::new(lookup_instrument(&self.instruments, symbol));
+            let symbol = book.instrument.symbol.clone();
+            let snapshot = Arc::new(ArcSwap::from_pointee(book.depth(0)));
+            self.snapshots.insert(symbol.clone(), Arc::clone(&snapshot));
+            self.books.insert(
+                symbol,
+                ShardBook {
+                    book,
+                    sequence: 0,
+                    last_trade_id: 0,
+                    snapshot,
+                    spare_snapshot: None,
+                },
+            );
+            self.stats.books.store(self.books.len(), Ordering::Relaxed);
         }
+        self.books.get_mut(symbol).expect("book was just created")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::{self, Sender};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts allocations made by threads that opted in with
+    /// `allocations_during`; every other test allocates as usual.
+    struct CountingAllocator;
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
     }
 
-    pub fn get_order_book(&self, symbol: &str, depth: usize) -> Option<OrderBook> {
-        self.order_books.get(symbol).map(|book_ref| {
-            let book = book_ref.read();
-            
-            // Return a snapshot with limited depth
-            let mut snapshot = OrderBook::new(symbol.to_string());
-            
-            for (price, level) in book.bids.iter().rev().take(depth) {
-                snapshot.bids.insert(*price, level.clone());
-            }
-            
-            for (price, level) in book.asks.iter().take(depth) {
-                snapshot.asks.insert(*price, level.clone());
+    fn note_allocation() {
+        let _ = COUNTING.try_with(|counting| {
+            if counting.get() {
+                ALLOCATIONS.with(|n| n.set(n.get() + 1));
             }
-            
-            snapshot
-        })
+        });
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            note_allocation();
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            note_allocation();
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            note_allocation();
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
     }
 
-    fn get_or_create_book(&self, symbol: &str) -> Arc<RwLock<OrderBook>> {
-        self.order_books
-            .entry(symbol.to_string())
-            .or_insert_with(|| Arc::new(RwLock::new(OrderBook::new(symbol.to_string()))))
-            .clone()
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations_during(f: impl FnOnce()) -> usize {
+        ALLOCATIONS.with(|n| n.set(0));
+        COUNTING.with(|counting| counting.set(true));
+        f();
+        COUNTING.with(|counting| counting.set(false));
+        ALLOCATIONS.with(|n| n.get())
     }
 
-    pub fn get_stats(&self) -> EngineStats {
-        EngineStats {
-            total_orders: self.orders.len(),
-            active_symbols: self.order_books.len(),
+    #[test]
+    fn test_sell_matches_highest_bid_first() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("b1", OrderSide::Buy, 99, 1)).unwrap();
+        engine.place_order(test_order("b2", OrderSide::Buy, 101, 1)).unwrap();
+
+        let result = engine.place_order(test_order("s1", OrderSide::Sell, 98, 1)).unwrap();
+
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].maker_order_id, "b2");
+        assert_eq!(result.trades[0].price, Price::from_raw(101));
+    }
+
+    #[test]
+    fn test_cancel_preserves_queue_order() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Sell, 100, 1)).unwrap();
+        engine.place_order(test_order("c", OrderSide::Sell, 100, 1)).unwrap();
+
+        assert!(engine.cancel_order("a").is_some());
+
+        let result = engine.place_order(test_order("t", OrderSide::Buy, 100, 2)).unwrap();
+        let makers: Vec<_> = result.trades.iter().map(|t| t.maker_order_id.as_str()).collect();
+        assert_eq!(makers, vec!["b", "c"]);
+        assert!(engine.get_order_book("BTC-USD", 10).unwrap().asks.is_empty());
+    }
+
+    struct Forward(Sender<(u64, EngineEvent)>);
+
+    impl EventHandler<EngineEvent> for Forward {
+        fn on_event(&mut self, event: &EngineEvent, sequence: u64, _end_of_batch: bool) {
+            self.0.send((sequence, event.clone())).unwrap();
         }
     }
-}
 
-pub struct EngineStats {
-    pub total_orders: usize,
-    pub active_symbols: usize,
+    #[test]
+    fn test_events_fan_out_in_sequence() {
+        let (tx, rx) = channel::unbounded();
+        let config = EngineConfig {
+            shards: 2,
+            ..EngineConfig::default()
+        };
+        let engine = MatchingEngine::start(config, vec![Box::new(Forward(tx))]);
+
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Buy, 100, 3)).unwrap();
+        engine.cancel_order("b");
+        drop(engine);
+
+        let events: Vec<_> = rx.iter().collect();
+        let sequences: Vec<u64> = events.iter().map(|(seq, _)| *seq).collect();
+        assert_eq!(sequences, vec![0, 1, 2, 3, 4, 5]);
+        assert!(matches!(&events[0].1, EngineEvent::OrderAccepted(o) if o.id == "a"));
+        assert!(matches!(&events[1].1, EngineEvent::OrderRested(o) if o.id == "a"));
+        assert!(matches!(&events[2].1, EngineEvent::OrderAccepted(o) if o.id == "b"));
+        assert!(matches!(&events[3].1, EngineEvent::Trade(t) if t.maker_order_id == "a"));
+        assert!(matches!(&events[4].1, EngineEvent::OrderRested(o) if o.id == "b" && o.remaining() == Qty::from_raw(2)));
+        assert!(matches!(&events[5].1, EngineEvent::OrderCancelled(o) if o.id == "b"));
+    }
+
+    #[test]
+    fn test_symbols_are_isolated_across_shards() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).unwrap();
+        engine.place_order(Order {
+            symbol: "ETH-USD".into(),
+            ..test_order("b", OrderSide::Buy, 100, 1)
+        }).unwrap();
+
+        let stats = engine.get_stats();
+        assert_eq!(stats.total_orders, 2);
+        assert_eq!(stats.active_symbols, 2);
+        assert!(engine.get_order_book("ETH-USD", 5).unwrap().asks.is_empty());
+        assert!(engine.get_order_book("SOL-USD", 5).is_none());
+    }
+
+    #[test]
+    fn test_batch_matches_in_order() {
+        let engine = MatchingEngine::new();
+        let results = engine.place_orders(
+            vec![
+                test_order("a", OrderSide::Sell, 100, 1),
+                test_order("bad", OrderSide::Sell, 100, 0),
+                test_order("b", OrderSide::Sell, 100, 1),
+                test_order("t", OrderSide::Buy, 100, 2),
+            ],
+            false,
+        );
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].as_ref().unwrap().trades.is_empty());
+        assert_eq!(results[1].as_ref().unwrap_err(), &RejectReason::ZeroAmount);
+        let makers: Vec<_> = results[3].as_ref().unwrap().trades.iter().map(|t| t.maker_order_id.as_str()).collect();
+        assert_eq!(makers, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_all_or_none_batch_rejects_everything() {
+        let engine = MatchingEngine::new();
+        let results = engine.place_orders(
+            vec![test_order("a", OrderSide::Sell, 100, 1), test_order("bad", OrderSide::Buy, 0, 1)],
+            true,
+        );
+
+        assert_eq!(results[0].as_ref().unwrap_err(), &RejectReason::BatchRejected);
+        assert_eq!(results[1].as_ref().unwrap_err(), &RejectReason::ZeroPrice);
+        assert!(engine.get_order_book("BTC-USD", 5).is_none());
+    }
+
+    #[test]
+    fn test_amend_down_keeps_priority() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 5)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Sell, 100, 5)).unwrap();
+
+        let result = engine.amend_order("a", None, Some(Qty::from_raw(2))).unwrap();
+        assert!(result.trades.is_empty());
+
+        let result = engine.place_order(test_order("t", OrderSide::Buy, 100, 3)).unwrap();
+        let fills: Vec<_> = result.trades.iter().map(|t| (t.maker_order_id.as_str(), t.amount.raw())).collect();
+        assert_eq!(fills, vec![("a", 2), ("b", 1)]);
+    }
+
+    #[test]
+    fn test_amend_up_or_reprice_loses_priority() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Sell, 100, 1)).unwrap();
+        engine.amend_order("a", None, Some(Qty::from_raw(2))).unwrap();
+
+        let result = engine.place_order(test_order("t", OrderSide::Buy, 100, 1)).unwrap();
+        assert_eq!(result.trades[0].maker_order_id, "b");
+
+        // Repricing through the book trades immediately.
+        engine.place_order(test_order("bid", OrderSide::Buy, 99, 1)).unwrap();
+        let result = engine.amend_order("a", Some(Price::from_raw(99)), None).unwrap();
+        assert_eq!(result.trades[0].maker_order_id, "bid");
+        assert_eq!(engine.get_order_book("BTC-USD", 5).unwrap().asks[0].amount, Qty::from_raw(1));
+    }
+
+    #[test]
+    fn test_amend_rejects_invalid_changes() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 5)).unwrap();
+        engine.place_order(test_order("t", OrderSide::Buy, 100, 2)).unwrap();
+
+        assert_eq!(engine.amend_order("a", None, Some(Qty::from_raw(2))).unwrap_err(), RejectReason::AmountBelowFilled);
+        assert_eq!(engine.amend_order("nope", None, None).unwrap_err(), RejectReason::UnknownOrder);
+        assert_eq!(engine.amend_order("t", None, None).unwrap_err(), RejectReason::UnknownOrder);
+    }
+
+    #[test]
+    fn test_latency_recorded_per_operation() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 5)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Sell, 101, 5)).unwrap();
+        engine.amend_order("a", None, Some(Qty::from_raw(1))).unwrap();
+        engine.cancel_order("b");
+
+        let report = engine.latency_report();
+        assert_eq!(report.get(Operation::Place).count, 2);
+        assert_eq!(report.get(Operation::Amend).count, 1);
+        assert_eq!(report.get(Operation::Cancel).count, 1);
+        assert!(report.get(Operation::Place).p50_nanos > 0);
+    }
+
+    #[test]
+    fn test_snapshot_published_before_reply() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 101, 2)).unwrap();
+        engine.place_order(test_order("b", OrderSide::Sell, 102, 1)).unwrap();
+        engine.place_order(test_order("c", OrderSide::Buy, 99, 1)).unwrap();
+
+        let before = engine.book_snapshot("BTC-USD").unwrap();
+        assert_eq!(before.sequence, 3);
+        assert_eq!(before.asks[0].amount, Qty::from_raw(2));
+
+        engine.place_order(test_order("d", OrderSide::Buy, 101, 1)).unwrap();
+        engine.cancel_order("c");
+
+        let after = engine.get_order_book("BTC-USD", 1).unwrap();
+        assert_eq!(after.sequence, 5);
+        assert!(after.bids.is_empty());
+        assert_eq!(after.asks, vec![DepthLevel { price: Price::from_raw(101), amount: Qty::from_raw(1), order_count: 1 }]);
+
+        // Earlier readers keep their own immutable copy.
+        assert_eq!(before.asks[0].amount, Qty::from_raw(2));
+    }
+
+    #[test]
+    fn test_status_follows_fills() {
+        let engine = MatchingEngine::new();
+        assert_eq!(engine.place_order(test_order("a", OrderSide::Sell, 100, 3)).unwrap().status, OrderStatus::New);
+
+        let result = engine.place_order(test_order("b", OrderSide::Buy, 100, 1)).unwrap();
+        assert_eq!(result.status, OrderStatus::Filled);
+        assert_eq!(result.updated_orders[0].status, OrderStatus::PartiallyFilled);
+
+        let result = engine.place_order(test_order("c", OrderSide::Buy, 100, 4)).unwrap();
+        assert_eq!(result.status, OrderStatus::PartiallyFilled);
+        assert_eq!(result.updated_orders[0].status, OrderStatus::Filled);
+
+        assert_eq!(engine.cancel_order("c").unwrap().status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_market_order_remainder_expires() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).unwrap();
+
+        let result = engine
+            .place_order(Order {
+                order_type: OrderType::Market,
+                price: Price::ZERO,
+                ..test_order("m", OrderSide::Buy, 0, 3)
+            })
+            .unwrap();
+        assert_eq!(result.status, OrderStatus::Expired);
+        assert_eq!(result.trades.len(), 1);
+
+        let book = engine.get_order_book("BTC-USD", 5).unwrap();
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+        assert!(engine.cancel_order("m").is_none());
+    }
+
+    #[test]
+    fn test_duplicate_open_order_id_is_rejected() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).unwrap();
+
+        let duplicate = engine.place_order(test_order("a", OrderSide::Sell, 101, 1));
+        assert_eq!(duplicate.unwrap_err(), RejectReason::DuplicateOrderId);
+
+        let results = engine.place_orders(vec![test_order("b", OrderSide::Sell, 101, 1), test_order("b", OrderSide::Sell, 102, 1)], false);
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err(), &RejectReason::DuplicateOrderId);
+
+        // Once filled, the id is free again.
+        engine.place_order(test_order("t", OrderSide::Buy, 100, 1)).unwrap();
+        assert!(engine.place_order(test_order("a", OrderSide::Sell, 105, 1)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_caller_does_not_leak_routes() {
+        let engine = MatchingEngine::new();
+        engine.place_order_async(test_order("m", OrderSide::Sell, 100, 1)).await.unwrap();
+
+        // Give up on a taker that fills the maker once it has been submitted.
+        let taker = engine.place_order_async(test_order("t", OrderSide::Buy, 100, 1));
+        let _ = tokio::time::timeout(std::time::Duration::ZERO, taker).await;
+        // Queued behind the taker on the same shard.
+        engine.place_order_async(test_order("x", OrderSide::Buy, 90, 1)).await.unwrap();
+
+        assert!(engine.place_order_async(test_order("t", OrderSide::Buy, 80, 1)).await.is_ok());
+        assert!(engine.place_order_async(test_order("m", OrderSide::Sell, 110, 1)).await.is_ok());
+    }
+
+    #[test]
+    fn test_unregistered_symbol_rejected_when_required() {
+        let config = EngineConfig {
+            require_registered_symbols: true,
+            ..EngineConfig::default()
+        };
+        let engine = MatchingEngine::start(config, Vec::new());
+
+        let rejected = engine.place_order(test_order("a", OrderSide::Sell, 100, 1));
+        assert_eq!(rejected.unwrap_err(), RejectReason::UnknownSymbol);
+
+        engine.register_instrument(Instrument::with_default_precision("BTC-USD"));
+        assert!(engine.place_order(test_order("a", OrderSide::Sell, 100, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_halted_market_rejects_new_risk() {
+        let engine = MatchingEngine::new();
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 5)).unwrap();
+        engine.halt_trading("BTC-USD");
+        assert!(!engine.is_trading("BTC-USD"));
+
+        let rejected = engine.place_order(test_order("b", OrderSide::Buy, 100, 1));
+        assert_eq!(rejected.unwrap_err(), RejectReason::MarketClosed);
+        let requeue = engine.amend_order("a", Some(Price::from_raw(99)), None);
+        assert_eq!(requeue.unwrap_err(), RejectReason::MarketClosed);
+
+        // Reducing and cancelling take risk off, so they still go through.
+        engine.amend_order("a", None, Some(Qty::from_raw(3))).unwrap();
+        assert_eq!(engine.cancel_order("a").unwrap().amount, Qty::from_raw(3));
+
+        engine.resume_trading("BTC-USD");
+        assert!(engine.place_order(test_order("b", OrderSide::Buy, 100, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_order_size_limit() {
+        let engine = MatchingEngine::new();
+        engine.register_instrument(Instrument::with_default_precision("BTC-USD").with_max_order_qty(Qty::from_raw(10)));
+
+        let rejected = engine.place_order(test_order("a", OrderSide::Sell, 100, 11));
+        assert_eq!(rejected.unwrap_err(), RejectReason::RiskLimit);
+        engine.place_order(test_order("a", OrderSide::Sell, 100, 10)).unwrap();
+
+        let grown = engine.amend_order("a", None, Some(Qty::from_raw(11)));
+        assert_eq!(grown.unwrap_err(), RejectReason::RiskLimit);
+    }
+
+    /// A shard driven directly on the test thread, with its event ring
+    /// drained by the test.
+    fn test_shard() -> (Shard, Consumer<EngineEvent>) {
+        let config = EngineConfig::default();
+        let (events, mut consumers) = RingBuffer::new(config.event_ring_capacity, 1);
+        let shard = Shard::new(
+            &config,
+            Arc::new(DashMap::new()),
+            Arc::new(DashMap::new()),
+            Arc::new(DashSet::new()),
+            Arc::new(DashMap::new()),
+            events,
+            Arc::new(ShardStats::default()),
+        );
+        (shard, consumers.pop().unwrap())
+    }
+
+    #[test]
+    fn test_steady_state_matching_does_not_allocate() {
+        let (mut shard, mut events) = test_shard();
+        for tick in 1..=5 {
+            shard.place_order(test_order(&format!("bid{}", tick), OrderSide::Buy, 100 - tick, 1_000));
+            shard.place_order(test_order(&format!("ask{}", tick), OrderSide::Sell, 100 + tick, 1_000));
+        }
+
+        // Orders arrive from the gateway with their ids already allocated.
+        let cycles: Vec<_> = (0..200)
+            .map(|i| {
+                [
+                    test_order(&format!("maker{}", i), OrderSide::Sell, 100, 1),
+                    test_order(&format!("taker{}", i), OrderSide::Buy, 100, 2),
+                    test_order(&format!("sweep{}", i), OrderSide::Buy, 101, 1),
+                ]
+            })
+            .collect();
+
+        // Each cycle opens and empties the 100 level on both sides, trades
+        // at two levels, rests, cancels and publishes snapshots and events.
+        let mut run_cycle = |shard: &mut Shard, [maker, taker, sweep]: [Order; 3]| {
+            let taker_id = taker.id.clone();
+            drop(shard.place_order(maker));
+            let result = shard.place_order(taker);
+            assert_eq!(result.trades.len(), 1);
+            drop(result);
+            assert!(shard.cancel_order(&taker_id).is_some());
+            assert_eq!(shard.place_order(sweep).trades.len(), 1);
+            events.poll(|_, _, _| {});
+        };
+
+        let mut cycles = cycles.into_iter();
+        for cycle in cycles.by_ref().take(100) {
+            run_cycle(&mut shard, cycle);
+        }
+
+        let allocations = allocations_during(|| {
+            for cycle in cycles {
+                run_cycle(&mut shard, cycle);
+            }
+        });
+        assert_eq!(allocations, 0);
+        assert_eq!(shard.books["BTC-USD"].last_trade_id, 400);
+    }
 }
@@ -0,0 +1,438 @@
+/// Per-user execution reports built from the engine event stream.
+///
+/// `ExecutionReporter` runs as an `EventHandler`. It follows every order
+/// from `OrderAccepted` to its final state and turns each change into an
+/// `ExecutionReport` with cumulative and leaves quantity and the average
+/// fill price. Reports are numbered per user and handed to that user's
+/// subscribers through `ExecutionReports`. A subscriber whose buffer is full
+/// is dropped rather than waited for, so a slow client never holds up the
+/// event thread; its stream ends and it has to resubscribe.
+///
+/// The latest report of every open order is also kept for
+/// `ExecutionReports::order_status`, along with those of the most recently
+/// closed orders. Being built from events, it can trail the reply to a
+/// placement by a moment.
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::fixed_point::{Price, Qty};
+use crate::ids::{OrderId, Symbol, TradeId, UserId};
+use crate::types::{Order, OrderSide, OrderStatus, Trade};
+use chrono::Utc;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ExecType {
+    New,
+    Trade,
+    Amended,
+    Cancelled,
+    Expired,
+}
+
+/// The fill behind an `ExecType::Trade` report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LastFill {
+    pub trade_id: TradeId,
+    pub price: Price,
+    pub qty: Qty,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionReport {
+    /// Starts at 1 and increases by one per report for the user, so a gap
+    /// means reports were missed.
+    pub sequence: u64,
+    pub exec_type: ExecType,
+    pub order_id: OrderId,
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub side: OrderSide,
+    pub status: OrderStatus,
+    pub price: Price,
+    pub amount: Qty,
+    pub last_fill: Option<LastFill>,
+    pub cum_qty: Qty,
+    /// Zero once the order is final.
+    pub leaves_qty: Qty,
+    /// `None` until the first fill.
+    pub avg_price: Option<Price>,
+    pub timestamp: i64,
+}
+
+/// Subscriptions to execution reports, by user.
+pub struct ExecutionReports {
+    subscribers: DashMap<UserId, Vec<mpsc::Sender<ExecutionReport>>>,
+    buffer: usize,
+    orders: DashMap<OrderId, ExecutionReport>,
+    /// Closed orders still in `orders`, oldest first.
+    closed: Mutex<VecDeque<OrderId>>,
+    closed_capacity: usize,
+}
+
+impl ExecutionReports {
+    /// Each subscriber may fall `buffer` reports behind before it is dropped.
+    /// Orders are forgotten as soon as they close.
+    pub fn new(buffer: usize) -> Self {
+        Self::with_order_history(buffer, 0)
+    }
+
+    /// Like `new`, but keeps the last `closed_orders` orders to close
+    /// available to `order_status`.
+    pub fn with_order_history(buffer: usize, closed_orders: usize) -> Self {
+        Self {
+            subscribers: DashMap::new(),
+            buffer,
+            orders: DashMap::new(),
+            closed: Mutex::new(VecDeque::with_capacity(closed_orders)),
+            closed_capacity: closed_orders,
+        }
+    }
+
+    /// Reports for `user_id` from now on.
+    pub fn subscribe(&self, user_id: &str) -> mpsc::Receiver<ExecutionReport> {
+        let (tx, rx) = mpsc::channel(self.buffer);
+        self.subscribers.entry(UserId::from(user_id)).or_default().push(tx);
+        rx
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.iter().map(|subs| subs.len()).sum()
+    }
+
+    /// The latest report for an order that is open or among the recently
+    /// closed.
+    pub fn order_status(&self, order_id: &str) -> Option<ExecutionReport> {
+        self.orders.get(order_id).map(|report| report.clone())
+    }
+
+    fn publish(&self, report: &ExecutionReport) {
+        self.record(report);
+
+        let Some(mut subscribers) = self.subscribers.get_mut(&report.user_id) else {
+            return;
+        };
+
+        subscribers.retain(|tx| match tx.try_send(report.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("Dropping slow execution report subscriber for {}", report.user_id);
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        });
+        // Release the entry's lock before `remove_if` takes it again.
+        drop(subscribers);
+        self.subscribers.remove_if(&report.user_id, |_, subs| subs.is_empty());
+    }
+
+    fn record(&self, report: &ExecutionReport) {
+        if report.status.is_open() {
+            self.orders.insert(report.order_id.clone(), report.clone());
+            return;
+        }
+        if self.closed_capacity == 0 {
+            self.orders.remove(&report.order_id);
+            return;
+        }
+
+        self.orders.insert(report.order_id.clone(), report.clone());
+        let mut closed = self.closed.lock();
+        if closed.len() == self.closed_capacity {
+            if let Some(oldest) = closed.pop_front() {
+                // Unless the id has been reused by a newer, open order.
+                self.orders.remove_if(&oldest, |_, report| !report.status.is_open());
+            }
+        }
+        closed.push_back(report.order_id.clone());
+    }
+}
+
+struct OpenOrder {
+    order: Order,
+    /// Sum of price * qty over fills, in raw fixed-point units.
+    notional: u128,
+}
+
+impl OpenOrder {
+    fn avg_price(&self) -> Option<Price> {
+        if self.order.filled.is_zero() {
+            return None;
+        }
+        Some(Price::from_raw((self.notional / self.order.filled.raw() as u128) as u64))
+    }
+}
+
+pub struct ExecutionReporter {
+    reports: Arc<ExecutionReports>,
+    open: HashMap<OrderId, OpenOrder>,
+    sequences: HashMap<UserId, u64>,
+}
+
+impl ExecutionReporter {
+    pub fn new(reports: Arc<ExecutionReports>) -> Self {
+        Self {
+            reports,
+            open: HashMap::new(),
+            sequences: HashMap::new(),
+        }
+    }
+
+    fn on_trade(&mut self, trade: &Trade) {
+        for order_id in [&trade.maker_order_id, &trade.taker_order_id] {
+            let Some(open) = self.open.get_mut(order_id) else {
+                continue;
+            };
+            open.order.fill(trade.amount);
+            open.notional += trade.price.raw() as u128 * trade.amount.raw() as u128;
+
+            let fill = LastFill {
+                trade_id: trade.id,
+                price: trade.price,
+                qty: trade.amount,
+            };
+            let report = Self::build(open, ExecType::Trade, Some(fill), trade.timestamp);
+            if open.order.is_filled() {
+                self.open.remove(order_id);
+            }
+            self.send(report);
+        }
+    }
+
+    /// Applies a final state and reports it.
+    fn close(&mut self, order: &Order, exec_type: ExecType) {
+        let mut open = self.open.remove(&order.id).unwrap_or(OpenOrder {
+            order: order.clone(),
+            notional: 0,
+        });
+        open.order.status = order.status;
+        let report = Self::build(&open, exec_type, None, Utc::now().timestamp_millis());
+        self.send(report);
+    }
+
+    fn build(open: &OpenOrder, exec_type: ExecType, last_fill: Option<LastFill>, timestamp: i64) -> ExecutionReport {
+        let order = &open.order;
+        ExecutionReport {
+            sequence: 0,
+            exec_type,
+            order_id: order.id.clone(),
+            user_id: order.user_id.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side,
+            status: order.status,
+            price: order.price,
+            amount: order.amount,
+            last_fill,
+            cum_qty: order.filled,
+            leaves_qty: if order.status.is_open() { order.remaining() } else { Qty::ZERO },
+            avg_price: open.avg_price(),
+            timestamp,
+        }
+    }
+
+    fn send(&mut self, mut report: ExecutionReport) {
+        let sequence = self.sequences.entry(report.user_id.clone()).or_insert(0);
+        *sequence += 1;
+        report.sequence = *sequence;
+        self.reports.publish(&report);
+    }
+}
+
+impl EventHandler<EngineEvent> for ExecutionReporter {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {
+        match event {
+            EngineEvent::OrderAccepted(order) => {
+                let open = OpenOrder {
+                    order: order.clone(),
+                    notional: 0,
+                };
+                let report = Self::build(&open, ExecType::New, None, Utc::now().timestamp_millis());
+                self.open.insert(order.id.clone(), open);
+                self.send(report);
+            }
+            EngineEvent::Trade(trade) => self.on_trade(trade),
+            EngineEvent::OrderAmended { order, .. } => {
+                if let Some(open) = self.open.get_mut(&order.id) {
+                    open.order.price = order.price;
+                    open.order.amount = order.amount;
+                    open.order.status = order.status;
+                    let report = Self::build(open, ExecType::Amended, None, Utc::now().timestamp_millis());
+                    self.send(report);
+                }
+            }
+            EngineEvent::OrderCancelled(order) => self.close(order, ExecType::Cancelled),
+            EngineEvent::OrderExpired(order) => self.close(order, ExecType::Expired),
+            // Already acknowledged as `New` or `Amended`.
+            EngineEvent::OrderRested(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{test_order, OrderType};
+
+    fn order_for(id: &str, user: &str, side: OrderSide, price: u64, amount: u64) -> Order {
+        Order {
+            user_id: user.into(),
+            ..test_order(id, side, price, amount)
+        }
+    }
+
+    fn trade(id: TradeId, maker: &str, taker: &str, price: u64, amount: u64) -> EngineEvent {
+        EngineEvent::Trade(Trade {
+            id,
+            symbol: "BTC-USD".into(),
+            maker_order_id: maker.into(),
+            taker_order_id: taker.into(),
+            price: Price::from_raw(price),
+            amount: Qty::from_raw(amount),
+            taker_side: OrderSide::Buy,
+            timestamp: 0,
+        })
+    }
+
+    fn drain(rx: &mut mpsc::Receiver<ExecutionReport>) -> Vec<ExecutionReport> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn test_maker_sees_fills_from_other_users() {
+        let reports = Arc::new(ExecutionReports::new(16));
+        let mut maker_reports = reports.subscribe("maker");
+        let mut reporter = ExecutionReporter::new(Arc::clone(&reports));
+
+        let events = [
+            EngineEvent::OrderAccepted(order_for("a", "maker", OrderSide::Sell, 100, 4)),
+            EngineEvent::OrderRested(order_for("a", "maker", OrderSide::Sell, 100, 4)),
+            EngineEvent::OrderAccepted(order_for("t1", "taker", OrderSide::Buy, 100, 1)),
+            trade(1, "a", "t1", 100, 1),
+            EngineEvent::OrderAccepted(order_for("t2", "taker", OrderSide::Buy, 110, 3)),
+            trade(2, "a", "t2", 110, 3),
+        ];
+        for (sequence, event) in events.iter().enumerate() {
+            reporter.on_event(event, sequence as u64, true);
+        }
+
+        let seen = drain(&mut maker_reports);
+        let kinds: Vec<_> = seen.iter().map(|r| (r.sequence, r.exec_type, r.status)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (1, ExecType::New, OrderStatus::New),
+                (2, ExecType::Trade, OrderStatus::PartiallyFilled),
+                (3, ExecType::Trade, OrderStatus::Filled),
+            ]
+        );
+        assert_eq!(seen[1].cum_qty, Qty::from_raw(1));
+        assert_eq!(seen[1].leaves_qty, Qty::from_raw(3));
+        assert_eq!(seen[2].leaves_qty, Qty::ZERO);
+        assert_eq!(seen[2].last_fill.as_ref().unwrap().trade_id, 2);
+        // (100 * 1 + 110 * 3) / 4
+        assert_eq!(seen[2].avg_price, Some(Price::from_raw(107)));
+        assert!(!reporter.open.contains_key("a"));
+    }
+
+    #[test]
+    fn test_cancel_and_expiry_are_final() {
+        let reports = Arc::new(ExecutionReports::new(16));
+        let mut rx = reports.subscribe("u1");
+        let mut reporter = ExecutionReporter::new(Arc::clone(&reports));
+
+        let resting = order_for("a", "u1", OrderSide::Buy, 100, 2);
+        reporter.on_event(&EngineEvent::OrderAccepted(resting.clone()), 0, true);
+        let amended = Order { amount: Qty::from_raw(5), ..resting.clone() };
+        reporter.on_event(&EngineEvent::OrderAmended { order: amended, requeued: true }, 1, true);
+        let cancelled = Order { status: OrderStatus::Cancelled, ..resting };
+        reporter.on_event(&EngineEvent::OrderCancelled(cancelled), 2, true);
+
+        let market = Order { order_type: OrderType::Market, ..order_for("m", "u1", OrderSide::Buy, 0, 1) };
+        reporter.on_event(&EngineEvent::OrderAccepted(market.clone()), 3, true);
+        reporter.on_event(&EngineEvent::OrderExpired(Order { status: OrderStatus::Expired, ..market }), 4, true);
+
+        let seen = drain(&mut rx);
+        let kinds: Vec<_> = seen.iter().map(|r| r.exec_type).collect();
+        assert_eq!(
+            kinds,
+            vec![ExecType::New, ExecType::Amended, ExecType::Cancelled, ExecType::New, ExecType::Expired]
+        );
+        assert_eq!(seen[1].amount, Qty::from_raw(5));
+        assert_eq!(seen[2].leaves_qty, Qty::ZERO);
+        assert_eq!(seen[4].status, OrderStatus::Expired);
+        assert_eq!(seen.last().unwrap().sequence, 5);
+        assert!(reporter.open.is_empty());
+    }
+
+    #[test]
+    fn test_slow_subscriber_is_dropped() {
+        let reports = Arc::new(ExecutionReports::new(1));
+        let mut slow = reports.subscribe("u1");
+        let mut reporter = ExecutionReporter::new(Arc::clone(&reports));
+
+        for (i, id) in ["a", "b"].into_iter().enumerate() {
+            reporter.on_event(&EngineEvent::OrderAccepted(order_for(id, "u1", OrderSide::Buy, 100, 1)), i as u64, true);
+        }
+
+        assert_eq!(reports.subscriber_count(), 0);
+        assert_eq!(slow.try_recv().unwrap().order_id, "a");
+        assert!(matches!(slow.try_recv(), Err(mpsc::error::TryRecvError::Disconnected)));
+    }
+
+    #[test]
+    fn test_order_status_keeps_recently_closed() {
+        let reports = Arc::new(ExecutionReports::with_order_history(16, 1));
+        let mut reporter = ExecutionReporter::new(Arc::clone(&reports));
+
+        let events = [
+            EngineEvent::OrderAccepted(order_for("a", "u1", OrderSide::Sell, 100, 4)),
+            EngineEvent::OrderAccepted(order_for("b", "u1", OrderSide::Buy, 100, 1)),
+            trade(1, "a", "b", 100, 1),
+            EngineEvent::OrderAccepted(order_for("c", "u1", OrderSide::Buy, 100, 1)),
+            trade(2, "a", "c", 100, 1),
+        ];
+        for (sequence, event) in events.iter().enumerate() {
+            reporter.on_event(event, sequence as u64, true);
+        }
+
+        let open = reports.order_status("a").unwrap();
+        assert_eq!((open.status, open.cum_qty), (OrderStatus::PartiallyFilled, Qty::from_raw(2)));
+        // "b" closed first and made way for "c".
+        assert!(reports.order_status("b").is_none());
+        assert_eq!(reports.order_status("c").unwrap().status, OrderStatus::Filled);
+
+        let forgetful = ExecutionReports::new(16);
+        let mut reporter = ExecutionReporter::new(Arc::new(forgetful));
+        reporter.on_event(&events[1], 0, true);
+        assert!(reporter.reports.order_status("b").is_some());
+        reporter.on_event(&EngineEvent::OrderCancelled(Order { status: OrderStatus::Cancelled, ..order_for("b", "u1", OrderSide::Buy, 100, 1) }), 1, true);
+        assert!(reporter.reports.order_status("b").is_none());
+    }
+
+    #[test]
+    fn test_reports_from_running_engine() {
+        use crate::engine::{EngineConfig, MatchingEngine};
+
+        let reports = Arc::new(ExecutionReports::new(16));
+        let mut maker_reports = reports.subscribe("maker");
+        let mut taker_reports = reports.subscribe("taker");
+        let reporter = ExecutionReporter::new(Arc::clone(&reports));
+        let engine = MatchingEngine::start(EngineConfig::default(), vec![Box::new(reporter)]);
+
+        engine.place_order(order_for("a", "maker", OrderSide::Sell, 100, 2)).unwrap();
+        engine.place_order(order_for("t", "taker", OrderSide::Buy, 100, 3)).unwrap();
+        drop(engine);
+
+        let maker: Vec<_> = drain(&mut maker_reports).iter().map(|r| (r.exec_type, r.status)).collect();
+        assert_eq!(maker, vec![(ExecType::New, OrderStatus::New), (ExecType::Trade, OrderStatus::Filled)]);
+
+        let taker = drain(&mut taker_reports);
+        assert_eq!(taker[1].status, OrderStatus::PartiallyFilled);
+        assert_eq!((taker[1].cum_qty, taker[1].leaves_qty), (Qty::from_raw(2), Qty::from_raw(1)));
+    }
+}
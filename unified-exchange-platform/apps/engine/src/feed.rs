@@ -0,0 +1,110 @@
+/// Plumbing shared by the market data feeds in `trades`, `depth`, `l3`,
+/// `candles` and `ticker`.
+use crate::engine::EngineEvent;
+use crate::fixed_point::{Price, Qty};
+use crate::ids::{OrderId, Symbol};
+use crate::types::{DepthLevel, Order, OrderSide};
+use dashmap::DashMap;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+/// Sends `update` to whoever is subscribed. Sending fails only when nobody
+/// is, which is not an error: the feed has already applied the update.
+pub(crate) fn publish<T>(updates: &broadcast::Sender<T>, update: T) {
+    let _ = updates.send(update);
+}
+
+/// Drops the entries `idle` says nothing refers to any more. Feeds call
+/// this before adding a symbol on behalf of a subscriber, so subscribing to
+/// symbols that never trade cannot grow a feed without bound.
+pub(crate) fn evict_idle<V>(entries: &DashMap<Symbol, V>, idle: impl Fn(&V) -> bool) {
+    entries.retain(|_, entry| !idle(entry));
+}
+
+/// The orders resting in the books, followed from the engine events. The
+/// depth and ticker publishers both build their price levels from the
+/// changes it reports.
+#[derive(Default)]
+pub(crate) struct RestingOrders {
+    /// Resting order id -> (side, price, remaining).
+    orders: HashMap<OrderId, (OrderSide, Price, Qty)>,
+}
+
+/// What one engine event does to the price level at `price` on `side`.
+pub(crate) struct LevelChange {
+    pub(crate) side: OrderSide,
+    pub(crate) price: Price,
+    /// Added to the level if an order came to rest, taken off it otherwise.
+    amount: Qty,
+    rested: bool,
+    /// The order has nothing left and leaves the level.
+    filled: bool,
+}
+
+impl LevelChange {
+    pub(crate) fn apply(&self, level: &mut DepthLevel) {
+        if self.rested {
+            level.amount = level.amount.saturating_add(self.amount);
+            level.order_count += 1;
+        } else {
+            level.amount = level.amount.saturating_sub(self.amount);
+            level.order_count -= usize::from(self.filled);
+        }
+    }
+}
+
+impl RestingOrders {
+    /// Follows `event`, returning the level change it makes, if any.
+    pub(crate) fn on_event(&mut self, event: &EngineEvent) -> Option<LevelChange> {
+        match event {
+            EngineEvent::OrderRested(order) => Some(self.rest(order)),
+            EngineEvent::Trade(trade) => self.reduce(&trade.maker_order_id, trade.amount),
+            // A requeued order left the book; its `OrderRested` follows.
+            EngineEvent::OrderCancelled(order) | EngineEvent::OrderAmended { order, requeued: true } => {
+                let &(_, _, remaining) = self.orders.get(&order.id)?;
+                self.reduce(&order.id, remaining)
+            }
+            EngineEvent::OrderAmended { order, requeued: false } => {
+                let &(_, _, remaining) = self.orders.get(&order.id)?;
+                self.reduce(&order.id, remaining.saturating_sub(order.remaining()))
+            }
+            EngineEvent::OrderAccepted(_) | EngineEvent::OrderExpired(_) => None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    fn rest(&mut self, order: &Order) -> LevelChange {
+        let remaining = order.remaining();
+        self.orders.insert(order.id.clone(), (order.side, order.price, remaining));
+        LevelChange {
+            side: order.side,
+            price: order.price,
+            amount: remaining,
+            rested: true,
+            filled: false,
+        }
+    }
+
+    /// Takes `amount` off a resting order, removing it once nothing is left.
+    fn reduce(&mut self, order_id: &OrderId, amount: Qty) -> Option<LevelChange> {
+        let (side, price, remaining) = self.orders.get_mut(order_id)?;
+        let (side, price) = (*side, *price);
+        let amount = amount.min(*remaining);
+        *remaining = remaining.saturating_sub(amount);
+        let filled = remaining.is_zero();
+        if filled {
+            self.orders.remove(order_id);
+        }
+        Some(LevelChange {
+            side,
+            price,
+            amount,
+            rested: false,
+            filled,
+        })
+    }
+}
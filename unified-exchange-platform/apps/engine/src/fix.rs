@@ -0,0 +1,247 @@
+/// FIX 4.4 tag=value encoding.
+///
+/// A `FixMessage` is the ordered list of fields from `MsgType` (35) up to,
+/// but not including, `CheckSum` (10). `encode` wraps it in `BeginString`
+/// and `BodyLength` and appends the checksum. `frame_len` finds where a
+/// message ends in a byte stream, and `decode` checks and strips all three.
+/// Session handling lives in `fix_gateway`.
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+pub const SOH: u8 = 0x01;
+pub const BEGIN_STRING: &str = "FIX.4.4";
+
+/// Tags used by the gateway.
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const USERNAME: u32 = 553;
+    pub const PASSWORD: u32 = 554;
+}
+
+/// Message types used by the gateway.
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+
+    /// Session-level messages, which are gap filled rather than resent.
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(msg_type, HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FixError {
+    /// The stream is not FIX 4.4 framing; the connection cannot continue.
+    #[error("garbled stream: {0}")]
+    Framing(&'static str),
+    /// One malformed message, to be ignored.
+    #[error("garbled message: {0}")]
+    Garbled(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            fields: vec![(tag::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn msg_type(&self) -> &str {
+        &self.fields[0].1
+    }
+
+    /// Value of the first occurrence of `tag`.
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v.as_str())
+    }
+
+    pub fn fields(&self) -> &[(u32, String)] {
+        &self.fields
+    }
+
+    pub fn push(&mut self, tag: u32, value: impl ToString) {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.push(tag, value);
+        self
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = String::new();
+        for (tag, value) in &self.fields {
+            let _ = write!(body, "{}={}\x01", tag, value);
+        }
+        let mut out = format!("8={}\x019={}\x01{}", BEGIN_STRING, body.len(), body).into_bytes();
+        let checksum = checksum(&out);
+        out.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        out
+    }
+
+    /// Decodes one whole frame, as measured by `frame_len`.
+    pub fn decode(frame: &[u8]) -> Result<Self, FixError> {
+        let (body_start, body_len) = header(frame)?.ok_or(FixError::Framing("incomplete frame"))?;
+        let body_end = body_start + body_len;
+        let trailer = &frame[body_end..];
+        if trailer.len() != 7 || !trailer.starts_with(b"10=") || trailer[6] != SOH {
+            return Err(FixError::Garbled("CheckSum(10) is not the last field"));
+        }
+        let expected = std::str::from_utf8(&trailer[3..6])
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .ok_or(FixError::Garbled("CheckSum(10) is not three digits"))?;
+        if checksum(&frame[..body_end]) != expected {
+            return Err(FixError::Garbled("CheckSum(10) does not match"));
+        }
+
+        let body = std::str::from_utf8(&frame[body_start..body_end]).map_err(|_| FixError::Garbled("not UTF-8"))?;
+        let mut fields = Vec::new();
+        for field in body.strip_suffix('\x01').unwrap_or(body).split('\x01') {
+            let (tag, value) = field.split_once('=').ok_or(FixError::Garbled("field without '='"))?;
+            let tag = tag.parse().map_err(|_| FixError::Garbled("tag is not a number"))?;
+            fields.push((tag, value.to_string()));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tag::MSG_TYPE) {
+            return Err(FixError::Garbled("MsgType(35) is not the third field"));
+        }
+        Ok(Self { fields })
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Where the body starts and how long it is, once the header is in `buf`.
+fn header(buf: &[u8]) -> Result<Option<(usize, usize)>, FixError> {
+    let begin = format!("8={}\x019=", BEGIN_STRING);
+    let prefix = &buf[..buf.len().min(begin.len())];
+    if !begin.as_bytes().starts_with(prefix) {
+        return Err(FixError::Framing("message does not start with BeginString FIX.4.4"));
+    }
+    if buf.len() < begin.len() {
+        return Ok(None);
+    }
+
+    let digits = &buf[begin.len()..];
+    let Some(end) = digits.iter().position(|&b| b == SOH) else {
+        return if digits.len() > 9 {
+            Err(FixError::Framing("BodyLength(9) is too long"))
+        } else {
+            Ok(None)
+        };
+    };
+    let body_len = std::str::from_utf8(&digits[..end])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(FixError::Framing("BodyLength(9) is not a number"))?;
+    Ok(Some((begin.len() + end + 1, body_len)))
+}
+
+/// Length of the first message in `buf`, or `None` until all of it has
+/// arrived.
+pub fn frame_len(buf: &[u8]) -> Result<Option<usize>, FixError> {
+    Ok(header(buf)?
+        .map(|(body_start, body_len)| body_start + body_len + 7)
+        .filter(|&len| buf.len() >= len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodes_length_and_checksum() {
+        let message = FixMessage::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "KK99")
+            .with(tag::MSG_SEQ_NUM, 2);
+        let bytes = message.encode();
+        let text = String::from_utf8(bytes.clone()).unwrap().replace('\x01', "|");
+        assert_eq!(text, "8=FIX.4.4|9=28|35=0|49=CLIENT|56=KK99|34=2|10=202|");
+
+        assert_eq!(frame_len(&bytes), Ok(Some(bytes.len())));
+        assert_eq!(FixMessage::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn test_frames_a_stream() {
+        let first = FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "t1").encode();
+        let mut stream = first.clone();
+        stream.extend_from_slice(&FixMessage::new(msg_type::HEARTBEAT).encode()[..12]);
+
+        assert_eq!(frame_len(&stream[..5]), Ok(None));
+        assert_eq!(frame_len(&stream[..first.len() - 1]), Ok(None));
+        assert_eq!(frame_len(&stream), Ok(Some(first.len())));
+        assert_eq!(frame_len(&stream[first.len()..]), Ok(None));
+        assert!(frame_len(b"8=FIX.4.2\x019=5\x01").is_err());
+    }
+
+    #[test]
+    fn test_bad_checksum_is_garbled() {
+        let mut bytes = FixMessage::new(msg_type::HEARTBEAT).encode();
+        let at = bytes.len() - 2;
+        bytes[at] = if bytes[at] == b'0' { b'1' } else { b'0' };
+        assert_eq!(FixMessage::decode(&bytes), Err(FixError::Garbled("CheckSum(10) does not match")));
+    }
+}
@@ -0,0 +1,1287 @@
+/// FIX 4.4 order entry.
+///
+/// `FixAcceptor` accepts TCP connections and runs one FIX session per
+/// counterparty, keyed by its SenderCompID. The counterparty is also the
+/// `user_id` of its orders, so execution reports come from the same
+/// `ExecutionReports` stream that gRPC clients subscribe to. NewOrderSingle,
+/// OrderCancelRequest and OrderCancelReplaceRequest become `place_order`,
+/// `cancel_order` and `amend_order` calls. Orders get an engine-assigned
+/// OrderID, and the session maps ClOrdIDs onto it.
+///
+/// The session layer covers Logon (including ResetSeqNumFlag), Heartbeat,
+/// TestRequest, ResendRequest, SequenceReset, Reject and Logout. A Logon
+/// has to carry the counterparty's Password(554) from `credentials`. When
+/// asked to resend, the acceptor replays up to `resend_capacity` of its
+/// recent application messages and gap fills the session messages and
+/// anything older. A session subscribes to its execution reports at its
+/// first logon and keeps reading them while logged out: each one is given
+/// the next sequence number and stored, so the counterparty sees the gap on
+/// its next Logon and asks for them. Sequence numbers and sent messages
+/// survive reconnects. With `store_dir` set, `fix_store` writes them to
+/// disk and they also survive restarts. ClOrdID mappings are kept in memory
+/// only.
+use crate::auth::Credentials;
+use crate::engine::MatchingEngine;
+use crate::execution::{ExecType, ExecutionReport, ExecutionReports};
+use crate::fix::{frame_len, msg_type, tag, FixError, FixMessage};
+use crate::fix_store::{SeqNums, SentMessage, SessionLog, SessionStore};
+use crate::fixed_point::{Price, Qty};
+use crate::ids::OrderId;
+use crate::types::{Order, OrderSide, OrderStatus, OrderType, RejectReason};
+use chrono::{TimeZone, Utc};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// How often heartbeat and test request timers are checked.
+const TIMER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// SessionRejectReason(373) values.
+const REQUIRED_TAG_MISSING: u32 = 1;
+const VALUE_IS_INCORRECT: u32 = 5;
+const COMP_ID_PROBLEM: u32 = 9;
+const INVALID_MSG_TYPE: u32 = 11;
+
+#[derive(Debug, Clone)]
+pub struct FixConfig {
+    /// Our CompID, which counterparties send as TargetCompID.
+    pub comp_id: String,
+    /// Directory for per-session sequence numbers and sent messages. `None`
+    /// keeps them in memory, so they reset with the process.
+    pub store_dir: Option<PathBuf>,
+    /// Counterparties allowed to log on. The CompID is the user.
+    pub credentials: Credentials,
+    /// Application messages kept per session for ResendRequest.
+    pub resend_capacity: usize,
+    /// How long a new connection has to send its Logon.
+    pub logon_timeout: Duration,
+}
+
+impl FixConfig {
+    pub fn new(comp_id: impl Into<String>) -> Self {
+        Self {
+            comp_id: comp_id.into(),
+            store_dir: None,
+            credentials: Credentials::new(),
+            resend_capacity: 10_000,
+            logon_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// An open order entered through FIX.
+struct FixOrder {
+    cl_ord_id: String,
+    /// Set while the latest cancel or replace is the current ClOrdID.
+    orig_cl_ord_id: Option<String>,
+    /// Every ClOrdID the order has had, so all are released once it is final.
+    cl_ord_ids: Vec<String>,
+    status: OrderStatus,
+}
+
+/// What a session keeps between connections.
+struct SessionState {
+    seq: SeqNums,
+    sent: VecDeque<SentMessage>,
+    orders: HashMap<OrderId, FixOrder>,
+    cl_ord_ids: HashMap<String, OrderId>,
+    /// The counterparty's execution reports, read whether or not it is
+    /// logged on.
+    reports: mpsc::Receiver<ExecutionReport>,
+    /// `None` without a `store_dir`.
+    log: Option<SessionLog>,
+}
+
+impl SessionState {
+    fn persist(&mut self) {
+        if let Some(log) = &mut self.log {
+            log.seq(self.seq);
+        }
+        self.compact();
+    }
+
+    fn compact(&mut self) {
+        if let Some(log) = self.log.as_mut().filter(|log| log.needs_rewrite()) {
+            log.rewrite(self.seq, &self.sent);
+        }
+    }
+
+    /// Starts both sequences again from 1 for ResetSeqNumFlag.
+    fn reset(&mut self) {
+        self.seq = SeqNums::default();
+        self.sent.clear();
+        if let Some(log) = &mut self.log {
+            log.rewrite(self.seq, &self.sent);
+        }
+    }
+
+    /// Uses up the next outgoing sequence number on `body`, keeping it for
+    /// resend if it is an application message.
+    fn record(&mut self, body: FixMessage, sending_time: String, resend_capacity: usize) {
+        let seq = self.seq.next_out;
+        self.seq.next_out += 1;
+        if msg_type::is_admin(body.msg_type()) || resend_capacity == 0 {
+            return self.persist();
+        }
+        if self.sent.len() == resend_capacity {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(SentMessage {
+            seq,
+            sending_time,
+            body,
+        });
+        if let Some(log) = &mut self.log {
+            log.sent(self.sent.back().expect("just pushed"));
+        }
+        self.compact();
+    }
+
+    fn report_message(&mut self, acceptor: &FixAcceptor, report: ExecutionReport) -> FixMessage {
+        let order = self.orders.get_mut(&report.order_id);
+        let (cl_ord_id, orig_cl_ord_id) = match order {
+            Some(order) => {
+                order.status = report.status;
+                (order.cl_ord_id.clone(), order.orig_cl_ord_id.clone())
+            }
+            // Entered through another gateway under the same user.
+            None => (report.order_id.to_string(), None),
+        };
+        let instrument = acceptor.engine.instrument(&report.symbol);
+
+        let mut message = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, &report.order_id)
+            .with(tag::CL_ORD_ID, cl_ord_id);
+        if let Some(orig) = orig_cl_ord_id {
+            message.push(tag::ORIG_CL_ORD_ID, orig);
+        }
+        message.push(tag::EXEC_ID, format!("{}-{}", acceptor.epoch, report.sequence));
+        message.push(tag::EXEC_TYPE, exec_type(report.exec_type));
+        message.push(tag::ORD_STATUS, ord_status(report.status));
+        message.push(tag::SYMBOL, &report.symbol);
+        message.push(tag::SIDE, side_code(report.side));
+        message.push(tag::ORDER_QTY, instrument.format_qty(report.amount));
+        if !report.price.is_zero() {
+            message.push(tag::PRICE, instrument.format_price(report.price));
+        }
+        if let Some(fill) = &report.last_fill {
+            message.push(tag::LAST_QTY, instrument.format_qty(fill.qty));
+            message.push(tag::LAST_PX, instrument.format_price(fill.price));
+        }
+        message.push(tag::CUM_QTY, instrument.format_qty(report.cum_qty));
+        message.push(tag::LEAVES_QTY, instrument.format_qty(report.leaves_qty));
+        let avg_px = report.avg_price.map_or_else(|| "0".to_string(), |price| instrument.format_price(price));
+        message.push(tag::AVG_PX, avg_px);
+        if let Some(time) = Utc.timestamp_millis_opt(report.timestamp).single() {
+            message.push(tag::TRANSACT_TIME, time.format("%Y%m%d-%H:%M:%S%.3f"));
+        }
+
+        if !report.status.is_open() {
+            if let Some(order) = self.orders.remove(&report.order_id) {
+                for cl_ord_id in order.cl_ord_ids {
+                    self.cl_ord_ids.remove(&cl_ord_id);
+                }
+            }
+        }
+        message
+    }
+}
+
+/// Who holds a session's state.
+enum Slot {
+    /// The connection that is logged on.
+    LoggedOn,
+    /// A task that stores the session's execution reports until it logs on
+    /// again, then hands the state back.
+    LoggedOut {
+        stop: oneshot::Sender<()>,
+        task: JoinHandle<SessionState>,
+    },
+}
+
+pub struct FixAcceptor {
+    engine: Arc<MatchingEngine>,
+    reports: Arc<ExecutionReports>,
+    config: FixConfig,
+    /// `None` without a `store_dir`.
+    store: Option<SessionStore>,
+    /// Sessions by counterparty CompID, from their first logon on.
+    sessions: Mutex<HashMap<String, Slot>>,
+    /// Start time in milliseconds, prefixed to ExecIDs so they stay unique
+    /// across restarts.
+    epoch: i64,
+}
+
+impl FixAcceptor {
+    /// Fails if `store_dir` cannot be created.
+    pub fn new(engine: Arc<MatchingEngine>, reports: Arc<ExecutionReports>, config: FixConfig) -> io::Result<Self> {
+        let store = config.store_dir.clone().map(SessionStore::open).transpose()?;
+        Ok(Self {
+            engine,
+            reports,
+            config,
+            store,
+            sessions: Mutex::new(HashMap::new()),
+            epoch: Utc::now().timestamp_millis(),
+        })
+    }
+
+    /// Accepts connections until the listener fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let acceptor = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = acceptor.connection(stream).await {
+                    warn!("FIX connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn connection(self: Arc<Self>, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let (mut reader, writer) = stream.into_split();
+        let mut buf = Vec::with_capacity(4096);
+
+        let logon = match tokio::time::timeout(self.config.logon_timeout, read_message(&mut reader, &mut buf)).await {
+            Ok(message) => message?,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "no Logon")),
+        };
+        let Some(logon) = logon else {
+            return Ok(());
+        };
+        if logon.msg_type() != msg_type::LOGON {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "first message is not a Logon"));
+        }
+
+        let Some(mut session) = Session::logon(Arc::clone(&self), writer, &logon).await? else {
+            return Ok(());
+        };
+        let result = session.run(reader, buf).await;
+        info!("FIX session {} logged out", session.counterparty);
+        self.release(session.counterparty, session.state);
+        result
+    }
+
+    /// Takes a session's state for a new connection, or `None` if the
+    /// session is already logged on.
+    async fn checkout(self: &Arc<Self>, counterparty: &str) -> io::Result<Option<SessionState>> {
+        let previous = self.sessions.lock().insert(counterparty.to_string(), Slot::LoggedOn);
+        let state = match previous {
+            Some(Slot::LoggedOn) => return Ok(None),
+            Some(Slot::LoggedOut { stop, task }) => {
+                let _ = stop.send(());
+                match task.await {
+                    Ok(state) => Ok(state),
+                    Err(e) => {
+                        error!("FIX session {} lost its state, reloading it: {}", counterparty, e);
+                        self.open_state(counterparty).await
+                    }
+                }
+            }
+            None => self.open_state(counterparty).await,
+        };
+        if state.is_err() {
+            self.sessions.lock().remove(counterparty);
+        }
+        state.map(Some)
+    }
+
+    /// Hands a session's state to a task that keeps storing its execution
+    /// reports until the next logon.
+    fn release(self: &Arc<Self>, counterparty: String, state: SessionState) {
+        let (stop, stopped) = oneshot::channel();
+        let task = tokio::spawn(Arc::clone(self).park(counterparty.clone(), state, stopped));
+        self.sessions.lock().insert(counterparty, Slot::LoggedOut { stop, task });
+    }
+
+    async fn park(self: Arc<Self>, counterparty: String, mut state: SessionState, mut stop: oneshot::Receiver<()>) -> SessionState {
+        loop {
+            tokio::select! {
+                _ = &mut stop => return state,
+                report = state.reports.recv() => match report {
+                    Some(report) => {
+                        let message = state.report_message(&self, report);
+                        state.record(message, sending_time(), self.config.resend_capacity);
+                    }
+                    None => {
+                        error!("FIX session {} fell behind on execution reports while logged out", counterparty);
+                        state.reports = self.reports.subscribe(&counterparty);
+                    }
+                },
+            }
+        }
+    }
+
+    /// State for a counterparty's first logon since the acceptor started.
+    async fn open_state(&self, counterparty: &str) -> io::Result<SessionState> {
+        let reports = self.reports.subscribe(counterparty);
+        let (seq, sent, log) = match &self.store {
+            Some(store) => {
+                let mut log = store.session(counterparty, self.config.resend_capacity);
+                let path = log.path().to_path_buf();
+                let capacity = self.config.resend_capacity;
+                let (seq, sent) = tokio::task::spawn_blocking(move || SessionLog::load(&path, capacity))
+                    .await
+                    .map_err(io::Error::other)??;
+                // Leaves out what loading skipped: older messages and stale sequence numbers.
+                log.rewrite(seq, &sent);
+                (seq, sent, Some(log))
+            }
+            None => (SeqNums::default(), VecDeque::new(), None),
+        };
+        Ok(SessionState {
+            seq,
+            sent,
+            orders: HashMap::new(),
+            cl_ord_ids: HashMap::new(),
+            reports,
+            log,
+        })
+    }
+}
+
+/// Reads the next well-formed message, skipping garbled ones. `None` once
+/// the peer closes the connection.
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<Option<FixMessage>> {
+    loop {
+        if let Some(message) = take_message(buf)? {
+            return Ok(Some(message));
+        }
+        if reader.read_buf(buf).await? == 0 {
+            return Ok(None);
+        }
+    }
+}
+
+/// Takes the first well-formed message off the front of `buf`.
+fn take_message(buf: &mut Vec<u8>) -> io::Result<Option<FixMessage>> {
+    loop {
+        let len = match frame_len(buf) {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let decoded = FixMessage::decode(&buf[..len]);
+        buf.drain(..len);
+        match decoded {
+            Ok(message) => return Ok(Some(message)),
+            Err(e @ FixError::Garbled(_)) => warn!("Ignoring FIX message: {}", e),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+fn sending_time() -> String {
+    Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+/// Puts the standard header on `body`. `orig_sending_time` marks a
+/// possible duplicate.
+fn frame(body: &FixMessage, sender: &str, target: &str, seq: u64, sending_time: &str, orig_sending_time: Option<&str>) -> Vec<u8> {
+    let mut message = FixMessage::new(body.msg_type())
+        .with(tag::SENDER_COMP_ID, sender)
+        .with(tag::TARGET_COMP_ID, target)
+        .with(tag::MSG_SEQ_NUM, seq);
+    if let Some(orig) = orig_sending_time {
+        message.push(tag::POSS_DUP_FLAG, "Y");
+        message.push(tag::SENDING_TIME, sending_time);
+        message.push(tag::ORIG_SENDING_TIME, orig);
+    } else {
+        message.push(tag::SENDING_TIME, sending_time);
+    }
+    for (tag, value) in &body.fields()[1..] {
+        message.push(*tag, value);
+    }
+    message.encode()
+}
+
+/// A field that is missing or has a bad value.
+struct InvalidField {
+    tag: u32,
+    reason: u32,
+}
+
+fn required(message: &FixMessage, tag: u32) -> Result<&str, InvalidField> {
+    message.get(tag).filter(|v| !v.is_empty()).ok_or(InvalidField {
+        tag,
+        reason: REQUIRED_TAG_MISSING,
+    })
+}
+
+fn invalid(tag: u32) -> InvalidField {
+    InvalidField {
+        tag,
+        reason: VALUE_IS_INCORRECT,
+    }
+}
+
+fn side(message: &FixMessage) -> Result<OrderSide, InvalidField> {
+    match required(message, tag::SIDE)? {
+        "1" => Ok(OrderSide::Buy),
+        "2" => Ok(OrderSide::Sell),
+        _ => Err(invalid(tag::SIDE)),
+    }
+}
+
+fn order_type(message: &FixMessage) -> Result<OrderType, InvalidField> {
+    match required(message, tag::ORD_TYPE)? {
+        "1" => Ok(OrderType::Market),
+        "2" => Ok(OrderType::Limit),
+        _ => Err(invalid(tag::ORD_TYPE)),
+    }
+}
+
+/// Day and GTC both rest until cancelled; IOC is implied for market orders.
+fn check_time_in_force(message: &FixMessage, order_type: OrderType) -> Result<(), InvalidField> {
+    match (message.get(tag::TIME_IN_FORCE), order_type) {
+        (None | Some("0" | "1"), _) | (Some("3"), OrderType::Market) => Ok(()),
+        _ => Err(invalid(tag::TIME_IN_FORCE)),
+    }
+}
+
+struct NewOrder<'a> {
+    cl_ord_id: &'a str,
+    symbol: &'a str,
+    side: OrderSide,
+    order_type: OrderType,
+    /// `None` for market orders.
+    price: Option<&'a str>,
+    qty: &'a str,
+}
+
+impl<'a> NewOrder<'a> {
+    fn parse(message: &'a FixMessage) -> Result<Self, InvalidField> {
+        let cl_ord_id = required(message, tag::CL_ORD_ID)?;
+        let symbol = required(message, tag::SYMBOL)?;
+        let side = side(message)?;
+        let order_type = order_type(message)?;
+        check_time_in_force(message, order_type)?;
+        Ok(Self {
+            cl_ord_id,
+            symbol,
+            side,
+            order_type,
+            price: match order_type {
+                OrderType::Limit => Some(required(message, tag::PRICE)?),
+                OrderType::Market => None,
+            },
+            qty: required(message, tag::ORDER_QTY)?,
+        })
+    }
+}
+
+fn exec_type(exec_type: ExecType) -> &'static str {
+    match exec_type {
+        ExecType::New => "0",
+        ExecType::Trade => "F",
+        ExecType::Amended => "5",
+        ExecType::Cancelled => "4",
+        ExecType::Expired => "C",
+    }
+}
+
+fn ord_status(status: OrderStatus) -> &'static str {
+    match status {
+        OrderStatus::New => "0",
+        OrderStatus::PartiallyFilled => "1",
+        OrderStatus::Filled => "2",
+        OrderStatus::Cancelled => "4",
+        OrderStatus::Rejected => "8",
+        OrderStatus::Expired => "C",
+    }
+}
+
+fn side_code(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "1",
+        OrderSide::Sell => "2",
+    }
+}
+
+/// OrdRejReason(103) for an engine rejection.
+fn ord_rej_reason(reason: RejectReason) -> &'static str {
+    match reason {
+        RejectReason::UnknownSymbol => "1",
+        RejectReason::MarketClosed => "2",
+        RejectReason::RiskLimit => "3",
+        RejectReason::DuplicateOrderId => "6",
+        RejectReason::UnknownOrder => "5",
+        _ => "99",
+    }
+}
+
+/// Answers a refused Logon. The Logout is outside the session's sequence,
+/// which the connection has no claim to.
+async fn refuse(acceptor: &FixAcceptor, writer: &mut OwnedWriteHalf, counterparty: &str, text: &str) -> io::Result<()> {
+    let logout = FixMessage::new(msg_type::LOGOUT).with(tag::TEXT, text);
+    writer.write_all(&frame(&logout, &acceptor.config.comp_id, counterparty, 1, &sending_time(), None)).await
+}
+
+/// One logged-on connection.
+struct Session {
+    acceptor: Arc<FixAcceptor>,
+    counterparty: String,
+    writer: OwnedWriteHalf,
+    state: SessionState,
+    heartbeat: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+    /// TestReqID of an unanswered TestRequest.
+    test_request: Option<String>,
+    /// Highest sequence number seen beyond a gap we asked to be resent.
+    awaiting_resend: Option<u64>,
+    closing: bool,
+}
+
+impl Session {
+    /// Validates a Logon and answers it. `None` if the logon was refused.
+    async fn logon(acceptor: Arc<FixAcceptor>, mut writer: OwnedWriteHalf, logon: &FixMessage) -> io::Result<Option<Self>> {
+        let counterparty = logon.get(tag::SENDER_COMP_ID).unwrap_or_default().to_string();
+        if counterparty.is_empty() || logon.get(tag::TARGET_COMP_ID) != Some(acceptor.config.comp_id.as_str()) {
+            warn!("Refusing FIX logon from {:?}: wrong CompIDs", counterparty);
+            return Ok(None);
+        }
+        let user = logon.get(tag::USERNAME).unwrap_or(&counterparty);
+        let password = logon.get(tag::PASSWORD).unwrap_or_default();
+        if user != counterparty || !acceptor.config.credentials.verify(user, password) {
+            warn!("Refusing FIX logon from {}: invalid credentials", counterparty);
+            refuse(&acceptor, &mut writer, &counterparty, "Invalid credentials").await?;
+            return Ok(None);
+        }
+        let Some(heartbeat) = logon.get(tag::HEART_BT_INT).and_then(|v| v.parse::<u64>().ok()).filter(|&s| s > 0) else {
+            warn!("Refusing FIX logon from {}: missing HeartBtInt", counterparty);
+            return Ok(None);
+        };
+        let Some(seq) = logon.get(tag::MSG_SEQ_NUM).and_then(|v| v.parse::<u64>().ok()) else {
+            warn!("Refusing FIX logon from {}: missing MsgSeqNum", counterparty);
+            return Ok(None);
+        };
+        let Some(mut state) = acceptor.checkout(&counterparty).await? else {
+            warn!("Refusing FIX logon from {}: already logged on", counterparty);
+            refuse(&acceptor, &mut writer, &counterparty, "Session is already logged on").await?;
+            return Ok(None);
+        };
+
+        let reset = logon.get(tag::RESET_SEQ_NUM_FLAG) == Some("Y");
+        if reset {
+            state.reset();
+        }
+        let mut session = Self {
+            acceptor,
+            counterparty,
+            writer,
+            state,
+            heartbeat: Duration::from_secs(heartbeat),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request: None,
+            awaiting_resend: None,
+            closing: false,
+        };
+
+        if seq < session.state.seq.next_in {
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", session.state.seq.next_in, seq);
+            warn!("Refusing FIX logon from {}: {}", session.counterparty, text);
+            session.logout(text).await?;
+            session.acceptor.release(session.counterparty, session.state);
+            return Ok(None);
+        }
+
+        let mut reply = FixMessage::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, heartbeat);
+        if reset {
+            reply.push(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        session.send(reply).await?;
+        info!("FIX session {} logged on", session.counterparty);
+
+        if seq == session.state.seq.next_in {
+            session.state.seq.next_in += 1;
+            session.state.persist();
+        } else {
+            session.request_resend(seq).await?;
+        }
+        Ok(Some(session))
+    }
+
+    async fn run(&mut self, mut reader: OwnedReadHalf, mut buf: Vec<u8>) -> io::Result<()> {
+        let mut timer = tokio::time::interval(TIMER_INTERVAL);
+        while !self.closing {
+            tokio::select! {
+                read = reader.read_buf(&mut buf) => {
+                    if read? == 0 {
+                        return Ok(());
+                    }
+                    while !self.closing {
+                        let Some(message) = take_message(&mut buf)? else {
+                            break;
+                        };
+                        self.on_message(message).await?;
+                    }
+                }
+                report = self.state.reports.recv() => match report {
+                    Some(report) => self.on_report(report).await?,
+                    None => {
+                        self.state.reports = self.acceptor.reports.subscribe(&self.counterparty);
+                        self.logout("Execution reports fell behind".to_string()).await?;
+                    }
+                },
+                _ = timer.tick() => self.on_timer().await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, body: FixMessage) -> io::Result<()> {
+        let time = sending_time();
+        let bytes = frame(&body, &self.acceptor.config.comp_id, &self.counterparty, self.state.seq.next_out, &time, None);
+        self.state.record(body, time, self.acceptor.config.resend_capacity);
+        self.last_sent = Instant::now();
+        self.writer.write_all(&bytes).await
+    }
+
+    async fn logout(&mut self, text: String) -> io::Result<()> {
+        self.closing = true;
+        self.send(FixMessage::new(msg_type::LOGOUT).with(tag::TEXT, text)).await
+    }
+
+    async fn reject(&mut self, message: &FixMessage, field: Option<u32>, reason: u32, text: &str) -> io::Result<()> {
+        let mut reject = FixMessage::new(msg_type::REJECT)
+            .with(tag::REF_SEQ_NUM, message.get(tag::MSG_SEQ_NUM).unwrap_or("0"))
+            .with(tag::REF_MSG_TYPE, message.msg_type());
+        if let Some(field) = field {
+            reject.push(tag::REF_TAG_ID, field);
+        }
+        reject.push(tag::SESSION_REJECT_REASON, reason);
+        reject.push(tag::TEXT, text);
+        self.send(reject).await
+    }
+
+    async fn reject_field(&mut self, message: &FixMessage, field: InvalidField) -> io::Result<()> {
+        let text = if field.reason == REQUIRED_TAG_MISSING {
+            format!("Required tag {} missing", field.tag)
+        } else {
+            format!("Value is incorrect for tag {}", field.tag)
+        };
+        self.reject(message, Some(field.tag), field.reason, &text).await
+    }
+
+    /// Asks for everything from the next expected sequence number on.
+    async fn request_resend(&mut self, received: u64) -> io::Result<()> {
+        if self.awaiting_resend.is_none() {
+            let request = FixMessage::new(msg_type::RESEND_REQUEST)
+                .with(tag::BEGIN_SEQ_NO, self.state.seq.next_in)
+                .with(tag::END_SEQ_NO, 0);
+            self.send(request).await?;
+        }
+        self.awaiting_resend = Some(self.awaiting_resend.unwrap_or(0).max(received));
+        Ok(())
+    }
+
+    async fn on_timer(&mut self) -> io::Result<()> {
+        if self.last_sent.elapsed() >= self.heartbeat {
+            self.send(FixMessage::new(msg_type::HEARTBEAT)).await?;
+        }
+
+        let silent = self.last_received.elapsed();
+        let grace = self.heartbeat / 5;
+        if self.test_request.is_some() && silent >= self.heartbeat * 2 + grace {
+            warn!("FIX session {} stopped responding", self.counterparty);
+            self.logout("Heartbeat timeout".to_string()).await?;
+        } else if self.test_request.is_none() && silent >= self.heartbeat + grace {
+            let id = format!("TEST-{}", Utc::now().timestamp_millis());
+            self.test_request = Some(id.clone());
+            self.send(FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, id)).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_message(&mut self, message: FixMessage) -> io::Result<()> {
+        self.last_received = Instant::now();
+        self.test_request = None;
+
+        if message.get(tag::SENDER_COMP_ID) != Some(self.counterparty.as_str())
+            || message.get(tag::TARGET_COMP_ID) != Some(self.acceptor.config.comp_id.as_str())
+        {
+            self.reject(&message, None, COMP_ID_PROBLEM, "CompID problem").await?;
+            return self.logout("CompID problem".to_string()).await;
+        }
+        let Some(seq) = message.get(tag::MSG_SEQ_NUM).and_then(|v| v.parse::<u64>().ok()) else {
+            return self.logout("MsgSeqNum(34) missing".to_string()).await;
+        };
+        let gap_fill = message.get(tag::GAP_FILL_FLAG) == Some("Y");
+        if message.msg_type() == msg_type::SEQUENCE_RESET && !gap_fill {
+            return self.on_sequence_reset(&message).await;
+        }
+
+        let next_in = self.state.seq.next_in;
+        if seq < next_in {
+            if message.get(tag::POSS_DUP_FLAG) == Some("Y") {
+                return Ok(());
+            }
+            return self
+                .logout(format!("MsgSeqNum too low, expecting {} but received {}", next_in, seq))
+                .await;
+        }
+        if seq > next_in {
+            match message.msg_type() {
+                msg_type::RESEND_REQUEST => self.on_resend_request(&message).await?,
+                msg_type::LOGOUT => return self.on_logout().await,
+                _ => {}
+            }
+            return self.request_resend(seq).await;
+        }
+
+        self.state.seq.next_in += 1;
+        match message.msg_type() {
+            msg_type::HEARTBEAT | msg_type::REJECT => {}
+            msg_type::TEST_REQUEST => match required(&message, tag::TEST_REQ_ID) {
+                Ok(id) => {
+                    let heartbeat = FixMessage::new(msg_type::HEARTBEAT).with(tag::TEST_REQ_ID, id);
+                    self.send(heartbeat).await?;
+                }
+                Err(field) => self.reject_field(&message, field).await?,
+            },
+            msg_type::RESEND_REQUEST => self.on_resend_request(&message).await?,
+            msg_type::SEQUENCE_RESET => self.on_sequence_reset(&message).await?,
+            msg_type::LOGOUT => return self.on_logout().await,
+            msg_type::LOGON => self.reject(&message, None, VALUE_IS_INCORRECT, "Already logged on").await?,
+            msg_type::NEW_ORDER_SINGLE => self.on_new_order(&message).await?,
+            msg_type::ORDER_CANCEL_REQUEST => self.on_cancel(&message).await?,
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.on_replace(&message).await?,
+            _ => self.reject(&message, None, INVALID_MSG_TYPE, "Unsupported MsgType").await?,
+        }
+        if self.awaiting_resend.is_some_and(|last| self.state.seq.next_in > last) {
+            self.awaiting_resend = None;
+        }
+        self.state.persist();
+        Ok(())
+    }
+
+    async fn on_logout(&mut self) -> io::Result<()> {
+        self.state.persist();
+        self.logout("Logout acknowledged".to_string()).await
+    }
+
+    /// Moves the next expected sequence number on, either to fill a gap or,
+    /// without GapFillFlag, as a reset regardless of MsgSeqNum.
+    async fn on_sequence_reset(&mut self, message: &FixMessage) -> io::Result<()> {
+        let new_seq = match required(message, tag::NEW_SEQ_NO).map(|v| v.parse::<u64>()) {
+            Ok(Ok(new_seq)) => new_seq,
+            Ok(Err(_)) => return self.reject_field(message, invalid(tag::NEW_SEQ_NO)).await,
+            Err(field) => return self.reject_field(message, field).await,
+        };
+        // A gap fill has already been counted as the next expected message.
+        let expected = if message.get(tag::GAP_FILL_FLAG) == Some("Y") {
+            self.state.seq.next_in - 1
+        } else {
+            self.state.seq.next_in
+        };
+        if new_seq < expected {
+            return self
+                .reject(message, Some(tag::NEW_SEQ_NO), VALUE_IS_INCORRECT, "NewSeqNo is lower than expected")
+                .await;
+        }
+        self.state.seq.next_in = new_seq;
+        self.state.persist();
+        Ok(())
+    }
+
+    /// Resends stored application messages in the range and gap fills the
+    /// rest.
+    async fn on_resend_request(&mut self, message: &FixMessage) -> io::Result<()> {
+        let range = required(message, tag::BEGIN_SEQ_NO)
+            .and_then(|v| v.parse::<u64>().map_err(|_| invalid(tag::BEGIN_SEQ_NO)))
+            .and_then(|begin| {
+                let end = required(message, tag::END_SEQ_NO)?;
+                Ok((begin, end.parse::<u64>().map_err(|_| invalid(tag::END_SEQ_NO))?))
+            });
+        let (begin, end) = match range {
+            Ok(range) => range,
+            Err(field) => return self.reject_field(message, field).await,
+        };
+        let last = self.state.seq.next_out - 1;
+        let end = if end == 0 || end > last { last } else { end };
+        if begin == 0 || begin > end {
+            return Ok(());
+        }
+
+        let resend: Vec<_> = self
+            .state
+            .sent
+            .iter()
+            .filter(|sent| (begin..=end).contains(&sent.seq))
+            .map(|sent| (sent.seq, frame(&sent.body, &self.acceptor.config.comp_id, &self.counterparty, sent.seq, &sending_time(), Some(&sent.sending_time))))
+            .collect();
+        let mut next = begin;
+        for (seq, bytes) in resend {
+            if seq > next {
+                self.gap_fill(next, seq).await?;
+            }
+            self.writer.write_all(&bytes).await?;
+            next = seq + 1;
+        }
+        if next <= end {
+            self.gap_fill(next, end + 1).await?;
+        }
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    async fn gap_fill(&mut self, seq: u64, new_seq: u64) -> io::Result<()> {
+        let gap_fill = FixMessage::new(msg_type::SEQUENCE_RESET)
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, new_seq);
+        let time = sending_time();
+        let bytes = frame(&gap_fill, &self.acceptor.config.comp_id, &self.counterparty, seq, &time, Some(&time));
+        self.writer.write_all(&bytes).await
+    }
+
+    async fn on_new_order(&mut self, message: &FixMessage) -> io::Result<()> {
+        let NewOrder {
+            cl_ord_id,
+            symbol,
+            side,
+            order_type,
+            price,
+            qty,
+        } = match NewOrder::parse(message) {
+            Ok(order) => order,
+            Err(field) => return self.reject_field(message, field).await,
+        };
+
+        let instrument = self.acceptor.engine.instrument(symbol);
+        let price = match price {
+            Some(price) => instrument.parse_price(price).map_err(|e| e.price_reject()),
+            None => Ok(Price::ZERO),
+        };
+        let qty = instrument.parse_qty(qty).map_err(|e| e.qty_reject());
+        let cl_ord_id = cl_ord_id.to_string();
+        let order_id = OrderId::from(uuid::Uuid::new_v4().to_string());
+
+        let result = match (price, qty) {
+            _ if self.state.cl_ord_ids.contains_key(&cl_ord_id) => Err(RejectReason::DuplicateOrderId),
+            (Ok(price), Ok(amount)) => {
+                self.state.orders.insert(
+                    order_id.clone(),
+                    FixOrder {
+                        cl_ord_id: cl_ord_id.clone(),
+                        orig_cl_ord_id: None,
+                        cl_ord_ids: vec![cl_ord_id.clone()],
+                        status: OrderStatus::New,
+                    },
+                );
+                self.state.cl_ord_ids.insert(cl_ord_id.clone(), order_id.clone());
+                self.acceptor
+                    .engine
+                    .place_order_async(Order {
+                        id: order_id.clone(),
+                        user_id: self.counterparty.as_str().into(),
+                        symbol: symbol.into(),
+                        side,
+                        order_type,
+                        price,
+                        amount,
+                        filled: Qty::ZERO,
+                        status: OrderStatus::New,
+                        timestamp: Utc::now().timestamp_millis(),
+                    })
+                    .await
+                    .map(drop)
+            }
+            (Err(reason), _) | (_, Err(reason)) => Err(reason),
+        };
+        let Err(reason) = result else {
+            return Ok(());
+        };
+
+        if reason != RejectReason::DuplicateOrderId {
+            self.state.orders.remove(&order_id);
+            self.state.cl_ord_ids.remove(&cl_ord_id);
+        }
+        let report = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, "NONE")
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::EXEC_ID, uuid::Uuid::new_v4())
+            .with(tag::EXEC_TYPE, "8")
+            .with(tag::ORD_STATUS, ord_status(OrderStatus::Rejected))
+            .with(tag::ORD_REJ_REASON, ord_rej_reason(reason))
+            .with(tag::SYMBOL, symbol)
+            .with(tag::SIDE, side_code(side))
+            .with(tag::ORDER_QTY, message.get(tag::ORDER_QTY).unwrap_or_default())
+            .with(tag::CUM_QTY, 0)
+            .with(tag::LEAVES_QTY, 0)
+            .with(tag::AVG_PX, 0)
+            .with(tag::TEXT, reason);
+        self.send(report).await
+    }
+
+    /// Points the order at a new ClOrdID ahead of a cancel or replace.
+    /// Returns the order and its previous ClOrdIDs so a rejection can put
+    /// them back.
+    fn begin_change(&mut self, message: &FixMessage) -> Result<Option<(OrderId, String, Option<String>)>, InvalidField> {
+        let cl_ord_id = required(message, tag::CL_ORD_ID)?;
+        let orig_cl_ord_id = required(message, tag::ORIG_CL_ORD_ID)?;
+        required(message, tag::SYMBOL)?;
+        side(message)?;
+
+        let Some(order_id) = self.state.cl_ord_ids.get(orig_cl_ord_id).cloned() else {
+            return Ok(None);
+        };
+        if self.state.cl_ord_ids.contains_key(cl_ord_id) {
+            return Err(invalid(tag::CL_ORD_ID));
+        }
+        let Some(order) = self.state.orders.get_mut(&order_id) else {
+            return Ok(None);
+        };
+        let previous = std::mem::replace(&mut order.cl_ord_id, cl_ord_id.to_string());
+        let previous_orig = order.orig_cl_ord_id.replace(orig_cl_ord_id.to_string());
+        order.cl_ord_ids.push(cl_ord_id.to_string());
+        self.state.cl_ord_ids.insert(cl_ord_id.to_string(), order_id.clone());
+        Ok(Some((order_id, previous, previous_orig)))
+    }
+
+    /// Undoes `begin_change` and sends an OrderCancelReject.
+    async fn reject_change(&mut self, message: &FixMessage, change: Option<(OrderId, String, Option<String>)>, reason: &str, text: &str) -> io::Result<()> {
+        let cl_ord_id = message.get(tag::CL_ORD_ID).unwrap_or_default();
+        let mut status = OrderStatus::Rejected;
+        let mut order_id = "NONE".to_string();
+        if let Some((id, previous, previous_orig)) = change {
+            if let Some(order) = self.state.orders.get_mut(&id) {
+                order.cl_ord_id = previous;
+                order.orig_cl_ord_id = previous_orig;
+                order.cl_ord_ids.retain(|id| id != cl_ord_id);
+                status = order.status;
+            }
+            self.state.cl_ord_ids.remove(cl_ord_id);
+            order_id = id.to_string();
+        }
+
+        let response_to = if message.msg_type() == msg_type::ORDER_CANCEL_REQUEST { 1 } else { 2 };
+        let reject = FixMessage::new(msg_type::ORDER_CANCEL_REJECT)
+            .with(tag::ORDER_ID, order_id)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::ORIG_CL_ORD_ID, message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default())
+            .with(tag::ORD_STATUS, ord_status(status))
+            .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tag::CXL_REJ_REASON, reason)
+            .with(tag::TEXT, text);
+        self.send(reject).await
+    }
+
+    async fn on_cancel(&mut self, message: &FixMessage) -> io::Result<()> {
+        let change = match self.begin_change(message) {
+            Ok(Some(change)) => change,
+            Ok(None) => return self.reject_change(message, None, "1", "Unknown order").await,
+            Err(field) => return self.reject_field(message, field).await,
+        };
+        if self.acceptor.engine.cancel_order_async(&change.0).await.is_some() {
+            return Ok(());
+        }
+        self.reject_change(message, Some(change), "0", "Order is no longer open").await
+    }
+
+    async fn on_replace(&mut self, message: &FixMessage) -> io::Result<()> {
+        let parsed = order_type(message).and_then(|order_type| {
+            let price = match order_type {
+                OrderType::Limit => Some(required(message, tag::PRICE)?),
+                OrderType::Market => None,
+            };
+            Ok((price, required(message, tag::ORDER_QTY)?))
+        });
+        let (price, qty) = match parsed {
+            Ok(parsed) => parsed,
+            Err(field) => return self.reject_field(message, field).await,
+        };
+        let change = match self.begin_change(message) {
+            Ok(Some(change)) => change,
+            Ok(None) => return self.reject_change(message, None, "1", "Unknown order").await,
+            Err(field) => return self.reject_field(message, field).await,
+        };
+
+        let instrument = self.acceptor.engine.instrument(message.get(tag::SYMBOL).unwrap_or_default());
+        let price = price.map(|price| instrument.parse_price(price).map_err(|e| e.price_reject())).transpose();
+        let qty = instrument.parse_qty(qty).map_err(|e| e.qty_reject());
+        let result = match (price, qty) {
+            (Ok(price), Ok(qty)) => self.acceptor.engine.amend_order_async(&change.0, price, Some(qty)).await.map(drop),
+            (Err(reason), _) | (_, Err(reason)) => Err(reason),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(reason) => {
+                let code = if reason == RejectReason::UnknownOrder { "0" } else { "99" };
+                self.reject_change(message, Some(change), code, &reason.to_string()).await
+            }
+        }
+    }
+
+    async fn on_report(&mut self, report: ExecutionReport) -> io::Result<()> {
+        let message = self.state.report_message(&self.acceptor, report);
+        self.send(message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+    use crate::execution::ExecutionReporter;
+    use crate::types::Instrument;
+    use std::fs;
+    use std::net::SocketAddr;
+
+    const CLIENT: &str = "CLIENT";
+    const SERVER: &str = "KK99";
+    const PASSWORD: &str = "pw";
+
+    async fn start(store_dir: Option<PathBuf>) -> SocketAddr {
+        let reports = Arc::new(ExecutionReports::new(64));
+        let engine = MatchingEngine::start(EngineConfig::default(), vec![Box::new(ExecutionReporter::new(Arc::clone(&reports)))]);
+        engine.register_instrument(Instrument::new("BTC-USD", 2, 4));
+        let config = FixConfig {
+            store_dir,
+            credentials: Credentials::new().with_user(CLIENT, PASSWORD).with_user("SELLER", PASSWORD),
+            ..FixConfig::new(SERVER)
+        };
+        let acceptor = Arc::new(FixAcceptor::new(Arc::new(engine), reports, config).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(acceptor.serve(listener));
+        addr
+    }
+
+    struct Client {
+        stream: TcpStream,
+        buf: Vec<u8>,
+        comp_id: &'static str,
+        seq: u64,
+    }
+
+    impl Client {
+        async fn logon(addr: SocketAddr, comp_id: &'static str, seq: u64) -> (Self, FixMessage) {
+            Self::logon_with(addr, comp_id, seq, PASSWORD).await
+        }
+
+        async fn logon_with(addr: SocketAddr, comp_id: &'static str, seq: u64, password: &str) -> (Self, FixMessage) {
+            let mut client = Self {
+                stream: TcpStream::connect(addr).await.unwrap(),
+                buf: Vec::new(),
+                comp_id,
+                seq,
+            };
+            let logon = FixMessage::new(msg_type::LOGON)
+                .with(tag::ENCRYPT_METHOD, 0)
+                .with(tag::HEART_BT_INT, 30)
+                .with(tag::PASSWORD, password);
+            client.send(logon).await;
+            let reply = client.recv().await;
+            (client, reply)
+        }
+
+        async fn send(&mut self, body: FixMessage) {
+            let bytes = frame(&body, self.comp_id, SERVER, self.seq, &sending_time(), None);
+            self.seq += 1;
+            self.stream.write_all(&bytes).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> FixMessage {
+            tokio::time::timeout(Duration::from_secs(5), read_message(&mut self.stream, &mut self.buf))
+                .await
+                .expect("no message from the acceptor")
+                .unwrap()
+                .expect("acceptor closed the connection")
+        }
+    }
+
+    fn new_order(cl_ord_id: &str, side: &str, price: &str, qty: &str) -> FixMessage {
+        FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, "BTC-USD")
+            .with(tag::SIDE, side)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, price)
+            .with(tag::ORDER_QTY, qty)
+    }
+
+    fn fields(message: &FixMessage, tags: &[u32]) -> Vec<Option<String>> {
+        tags.iter().map(|&t| message.get(t).map(str::to_string)).collect()
+    }
+
+    fn some(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|v| Some(v.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_orders_cancels_and_replaces() {
+        let addr = start(None).await;
+        let (mut buyer, logon) = Client::logon(addr, CLIENT, 1).await;
+        assert_eq!(logon.msg_type(), msg_type::LOGON);
+
+        let report_tags = [tag::EXEC_TYPE, tag::ORD_STATUS, tag::CL_ORD_ID, tag::CUM_QTY, tag::LEAVES_QTY];
+        buyer.send(new_order("a1", "1", "100.00", "1")).await;
+        let new = buyer.recv().await;
+        assert_eq!(fields(&new, &report_tags), some(&["0", "0", "a1", "0", "1"]));
+
+        let (mut seller, _) = Client::logon(addr, "SELLER", 1).await;
+        seller.send(new_order("s1", "2", "100.00", "0.4")).await;
+        assert_eq!(seller.recv().await.get(tag::EXEC_TYPE), Some("0"));
+        let fill = seller.recv().await;
+        assert_eq!(fields(&fill, &[tag::EXEC_TYPE, tag::ORD_STATUS, tag::LAST_QTY, tag::LAST_PX]), some(&["F", "2", "0.4", "100"]));
+        let fill = buyer.recv().await;
+        assert_eq!(fields(&fill, &report_tags), some(&["F", "1", "a1", "0.4", "0.6"]));
+
+        let replace = FixMessage::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+            .with(tag::ORIG_CL_ORD_ID, "a1")
+            .with(tag::CL_ORD_ID, "a2")
+            .with(tag::SYMBOL, "BTC-USD")
+            .with(tag::SIDE, 1)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "99.50")
+            .with(tag::ORDER_QTY, "0.8");
+        buyer.send(replace).await;
+        let replaced = buyer.recv().await;
+        assert_eq!(fields(&replaced, &report_tags), some(&["5", "1", "a2", "0.4", "0.4"]));
+        assert_eq!(replaced.get(tag::ORIG_CL_ORD_ID), Some("a1"));
+        assert_eq!(replaced.get(tag::PRICE), Some("99.5"));
+
+        let cancel = |orig: &str, cl: &str| {
+            FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+                .with(tag::ORIG_CL_ORD_ID, orig)
+                .with(tag::CL_ORD_ID, cl)
+                .with(tag::SYMBOL, "BTC-USD")
+                .with(tag::SIDE, 1)
+        };
+        buyer.send(cancel("a2", "a3")).await;
+        let cancelled = buyer.recv().await;
+        assert_eq!(fields(&cancelled, &report_tags), some(&["4", "4", "a3", "0.4", "0"]));
+
+        buyer.send(cancel("a3", "a4")).await;
+        let reject = buyer.recv().await;
+        assert_eq!(reject.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        assert_eq!(fields(&reject, &[tag::CXL_REJ_RESPONSE_TO, tag::CXL_REJ_REASON]), some(&["1", "1"]));
+
+        buyer.send(new_order("a5", "1", "100.001", "1")).await;
+        let rejected = buyer.recv().await;
+        assert_eq!(fields(&rejected, &[tag::EXEC_TYPE, tag::ORD_STATUS, tag::CL_ORD_ID]), some(&["8", "8", "a5"]));
+        assert_eq!(rejected.get(tag::TEXT), Some(RejectReason::BadTick.to_string().as_str()));
+
+        buyer.send(FixMessage::new(msg_type::NEW_ORDER_SINGLE).with(tag::CL_ORD_ID, "a6")).await;
+        let reject = buyer.recv().await;
+        assert_eq!(fields(&reject, &[tag::REF_TAG_ID, tag::SESSION_REJECT_REASON]), some(&["55", "1"]));
+    }
+
+    #[tokio::test]
+    async fn test_sequence_numbers_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("fix-test-{}", std::process::id()));
+        let addr = start(Some(dir.clone())).await;
+        let (mut client, logon) = Client::logon(addr, CLIENT, 1).await;
+        assert_eq!(logon.get(tag::MSG_SEQ_NUM), Some("1"));
+        client.send(new_order("c1", "1", "100.00", "1")).await;
+        let report = client.recv().await;
+        client.send(FixMessage::new(msg_type::LOGOUT)).await;
+        assert_eq!(client.recv().await.msg_type(), msg_type::LOGOUT);
+        // The store is written in the background.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // A second acceptor over the same store stands in for a restart.
+        let addr = start(Some(dir.clone())).await;
+        let (_, refused) = Client::logon(addr, CLIENT, 1).await;
+        assert_eq!(refused.msg_type(), msg_type::LOGOUT);
+        assert_eq!(refused.get(tag::MSG_SEQ_NUM), Some("4"));
+
+        let (mut client, logon) = Client::logon(addr, CLIENT, 4).await;
+        assert_eq!(fields(&logon, &[tag::MSG_TYPE, tag::MSG_SEQ_NUM]), some(&["A", "5"]));
+
+        // The execution report sent before the restart is replayed, not gap filled.
+        client.send(FixMessage::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 2).with(tag::END_SEQ_NO, 2)).await;
+        let resent = client.recv().await;
+        assert_eq!(fields(&resent, &[tag::MSG_SEQ_NUM, tag::POSS_DUP_FLAG]), some(&["2", "Y"]));
+        assert_eq!(resent.get(tag::EXEC_ID), report.get(tag::EXEC_ID));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_logon_requires_credentials() {
+        let addr = start(None).await;
+        for (comp_id, password) in [(CLIENT, "wrong"), (CLIENT, ""), ("STRANGER", PASSWORD)] {
+            let (_, refused) = Client::logon_with(addr, comp_id, 1, password).await;
+            assert_eq!(fields(&refused, &[tag::MSG_TYPE, tag::TEXT]), some(&["5", "Invalid credentials"]));
+        }
+
+        let (_, logon) = Client::logon(addr, CLIENT, 1).await;
+        assert_eq!(logon.msg_type(), msg_type::LOGON);
+    }
+
+    #[tokio::test]
+    async fn test_reports_while_logged_out_are_kept_for_resend() {
+        let addr = start(None).await;
+        let (mut buyer, _) = Client::logon(addr, CLIENT, 1).await;
+        buyer.send(new_order("b1", "1", "100.00", "1")).await;
+        assert_eq!(buyer.recv().await.get(tag::MSG_SEQ_NUM), Some("2"));
+        buyer.send(FixMessage::new(msg_type::LOGOUT)).await;
+        assert_eq!(buyer.recv().await.msg_type(), msg_type::LOGOUT);
+
+        let (mut seller, _) = Client::logon(addr, "SELLER", 1).await;
+        seller.send(new_order("s1", "2", "100.00", "0.4")).await;
+        assert_eq!(seller.recv().await.get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(seller.recv().await.get(tag::EXEC_TYPE), Some("F"));
+        // Gives the logged-out session time to store the buyer's fill.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The fill took sequence number 4, so the Logon reply shows a gap.
+        let (mut buyer, logon) = Client::logon(addr, CLIENT, 4).await;
+        assert_eq!(logon.get(tag::MSG_SEQ_NUM), Some("5"));
+        buyer.send(FixMessage::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 4).with(tag::END_SEQ_NO, 4)).await;
+        let fill = buyer.recv().await;
+        let fill_tags = [tag::MSG_SEQ_NUM, tag::POSS_DUP_FLAG, tag::CL_ORD_ID, tag::EXEC_TYPE, tag::LAST_QTY];
+        assert_eq!(fields(&fill, &fill_tags), some(&["4", "Y", "b1", "F", "0.4"]));
+    }
+
+    #[tokio::test]
+    async fn test_resend_request_replays_and_gap_fills() {
+        let addr = start(None).await;
+        let (mut client, _) = Client::logon(addr, CLIENT, 1).await;
+        client.send(new_order("c1", "1", "100.00", "1")).await;
+        let report = client.recv().await;
+        client.send(FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "t1")).await;
+        assert_eq!(client.recv().await.get(tag::TEST_REQ_ID), Some("t1"));
+
+        client.send(FixMessage::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 1).with(tag::END_SEQ_NO, 0)).await;
+        let gap_tags = [tag::MSG_TYPE, tag::MSG_SEQ_NUM, tag::GAP_FILL_FLAG, tag::NEW_SEQ_NO];
+        assert_eq!(fields(&client.recv().await, &gap_tags), some(&["4", "1", "Y", "2"]));
+        let resent = client.recv().await;
+        assert_eq!(fields(&resent, &[tag::MSG_SEQ_NUM, tag::POSS_DUP_FLAG]), some(&["2", "Y"]));
+        assert_eq!(resent.get(tag::EXEC_ID), report.get(tag::EXEC_ID));
+        assert_eq!(fields(&client.recv().await, &gap_tags), some(&["4", "3", "Y", "4"]));
+    }
+
+    #[tokio::test]
+    async fn test_gap_in_incoming_sequence_is_requested() {
+        let addr = start(None).await;
+        let (mut client, _) = Client::logon(addr, CLIENT, 1).await;
+        client.seq = 5;
+        client.send(FixMessage::new(msg_type::HEARTBEAT)).await;
+        let request = client.recv().await;
+        assert_eq!(fields(&request, &[tag::MSG_TYPE, tag::BEGIN_SEQ_NO, tag::END_SEQ_NO]), some(&["2", "2", "0"]));
+
+        client.seq = 2;
+        let gap_fill = FixMessage::new(msg_type::SEQUENCE_RESET).with(tag::GAP_FILL_FLAG, "Y").with(tag::NEW_SEQ_NO, 6);
+        client.send(gap_fill).await;
+        client.seq = 6;
+        client.send(FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "t2")).await;
+        assert_eq!(client.recv().await.get(tag::TEST_REQ_ID), Some("t2"));
+    }
+}
@@ -0,0 +1,285 @@
+/// Durable FIX session store.
+///
+/// Each counterparty gets one append-only file of JSON lines: its sequence
+/// numbers as they move, and every application message sent to it, so a
+/// restarted acceptor can still answer a ResendRequest. Sessions hand their
+/// records to a single writer thread, which appends whatever has queued up
+/// and fsyncs each file it touched once per batch, so no session waits on
+/// the disk. A crash loses at most the batch being written. Loading a
+/// session keeps the newest `resend_capacity` messages, and the file is
+/// rewritten down to that whenever it has grown well past it.
+use crate::fix::FixMessage;
+use crate::retransmit::file_stem;
+use crossbeam::channel::{self, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use tracing::{error, warn};
+
+/// Appends allowed between rewrites, as a multiple of the messages kept.
+const REWRITE_FACTOR: usize = 4;
+/// Floor on appends between rewrites, so small stores are not rewritten
+/// every few messages.
+const MIN_REWRITE_AFTER: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SeqNums {
+    pub next_in: u64,
+    pub next_out: u64,
+}
+
+impl Default for SeqNums {
+    fn default() -> Self {
+        Self { next_in: 1, next_out: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SentMessage {
+    pub seq: u64,
+    pub sending_time: String,
+    pub body: FixMessage,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    SeqNums(SeqNums),
+    /// Also moves `next_out` past `seq`.
+    Sent(SentMessage),
+}
+
+enum Op {
+    Append { path: Arc<Path>, line: Vec<u8> },
+    /// Replaces the file, e.g. with the compacted records.
+    Rewrite { path: Arc<Path>, contents: Vec<u8> },
+}
+
+pub(crate) struct SessionStore {
+    dir: PathBuf,
+    ops: Sender<Op>,
+}
+
+impl SessionStore {
+    /// Creates `dir` if needed and starts the writer thread, which stops
+    /// once the store and all its session logs are dropped.
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let (ops, queued) = channel::unbounded();
+        thread::Builder::new()
+            .name("fix-store".to_string())
+            .spawn(move || write_loop(queued))?;
+        Ok(Self { dir, ops })
+    }
+
+    /// The log of `counterparty`, whose CompID is escaped into the file name
+    /// so that no two counterparties share a file.
+    pub fn session(&self, counterparty: &str, resend_capacity: usize) -> SessionLog {
+        SessionLog {
+            path: self.dir.join(format!("{}.fix.jsonl", file_stem(counterparty))).into(),
+            ops: self.ops.clone(),
+            capacity: resend_capacity,
+            appended: 0,
+        }
+    }
+}
+
+/// One counterparty's file.
+pub(crate) struct SessionLog {
+    path: Arc<Path>,
+    ops: Sender<Op>,
+    capacity: usize,
+    /// Records appended since the file was last rewritten.
+    appended: usize,
+}
+
+impl SessionLog {
+    /// Reads the stored sequence numbers and the newest messages. Blocks on
+    /// the disk, so async callers go through `spawn_blocking`.
+    pub fn load(path: &Path, capacity: usize) -> io::Result<(SeqNums, VecDeque<SentMessage>)> {
+        let mut seq = SeqNums::default();
+        let mut sent = VecDeque::new();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((seq, sent)),
+            Err(e) => return Err(e),
+        };
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            match serde_json::from_str(&line?) {
+                Ok(Record::SeqNums(stored)) => seq = stored,
+                Ok(Record::Sent(message)) => {
+                    seq.next_out = seq.next_out.max(message.seq + 1);
+                    if capacity > 0 {
+                        if sent.len() == capacity {
+                            sent.pop_front();
+                        }
+                        sent.push_back(message);
+                    }
+                }
+                // Most likely the tail of a write cut short by a crash.
+                Err(e) => warn!("Skipping line {} of {}: {}", number + 1, path.display(), e),
+            }
+        }
+        Ok((seq, sent))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn seq(&mut self, seq: SeqNums) {
+        self.append(&Record::SeqNums(seq));
+    }
+
+    pub fn sent(&mut self, message: &SentMessage) {
+        self.append(&Record::Sent(message.clone()));
+    }
+
+    /// Whether the file has grown enough to be worth rewriting.
+    pub fn needs_rewrite(&self) -> bool {
+        self.appended >= (self.capacity * REWRITE_FACTOR).max(MIN_REWRITE_AFTER)
+    }
+
+    /// Replaces the file with just `seq` and `sent`.
+    pub fn rewrite(&mut self, seq: SeqNums, sent: &VecDeque<SentMessage>) {
+        let mut contents = Vec::new();
+        for record in std::iter::once(Record::SeqNums(seq)).chain(sent.iter().cloned().map(Record::Sent)) {
+            line(&mut contents, &record);
+        }
+        self.appended = 0;
+        self.submit(Op::Rewrite {
+            path: Arc::clone(&self.path),
+            contents,
+        });
+    }
+
+    fn append(&mut self, record: &Record) {
+        let mut bytes = Vec::new();
+        line(&mut bytes, record);
+        self.appended += 1;
+        self.submit(Op::Append {
+            path: Arc::clone(&self.path),
+            line: bytes,
+        });
+    }
+
+    fn submit(&self, op: Op) {
+        if self.ops.send(op).is_err() {
+            error!("FIX store writer has stopped, {} is no longer updated", self.path.display());
+        }
+    }
+}
+
+fn line(out: &mut Vec<u8>, record: &Record) {
+    serde_json::to_writer(&mut *out, record).expect("records serialize");
+    out.push(b'\n');
+}
+
+fn write_loop(queued: Receiver<Op>) {
+    let mut files: HashMap<Arc<Path>, File> = HashMap::new();
+    while let Ok(first) = queued.recv() {
+        let mut touched = HashSet::new();
+        for op in std::iter::once(first).chain(queued.try_iter()) {
+            let (path, result) = match op {
+                Op::Append { path, line } => {
+                    let result = match files.get_mut(&path) {
+                        Some(file) => file.write_all(&line),
+                        None => OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(&path)
+                            .and_then(|mut file| file.write_all(&line).map(|()| file))
+                            .map(|file| drop(files.insert(Arc::clone(&path), file))),
+                    };
+                    touched.insert(Arc::clone(&path));
+                    (path, result)
+                }
+                Op::Rewrite { path, contents } => {
+                    files.remove(&path);
+                    touched.remove(&path);
+                    let result = replace(&path, &contents);
+                    (path, result)
+                }
+            };
+            if let Err(e) = result {
+                error!("Failed to write FIX session store {}: {}", path.display(), e);
+            }
+        }
+        for path in touched {
+            if let Some(Err(e)) = files.get(&path).map(File::sync_data) {
+                error!("Failed to sync FIX session store {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Writes `contents` beside `path`, syncs it and renames it into place.
+fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    match path.parent() {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::{msg_type, tag};
+    use std::time::{Duration, Instant};
+
+    fn message(seq: u64) -> SentMessage {
+        SentMessage {
+            seq,
+            sending_time: "20240101-00:00:00.000".to_string(),
+            body: FixMessage::new(msg_type::EXECUTION_REPORT).with(tag::EXEC_ID, seq),
+        }
+    }
+
+    /// Waits for the writer thread to catch up with `expected` lines.
+    fn wait_for_lines(path: &Path, expected: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while fs::read_to_string(path).map_or(0, |text| text.lines().count()) != expected {
+            assert!(Instant::now() < deadline, "store never reached {} lines", expected);
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_log_reloads_and_rewrites() {
+        let dir = std::env::temp_dir().join(format!("fix-store-test-{}", std::process::id()));
+        let store = SessionStore::open(dir.clone()).unwrap();
+        let mut log = store.session("CLIENT/1", 2);
+        assert_eq!(log.path(), dir.join("CLIENT%2F1.fix.jsonl"));
+        assert_ne!(store.session("CLIENT_1", 2).path(), log.path());
+
+        log.seq(SeqNums { next_in: 4, next_out: 1 });
+        for seq in 1..=3 {
+            log.sent(&message(seq));
+        }
+        wait_for_lines(log.path(), 4);
+        let mut file = OpenOptions::new().append(true).open(log.path()).unwrap();
+        file.write_all(b"{\"Sent\":{\"se").unwrap();
+
+        let (seq, sent) = SessionLog::load(log.path(), 2).unwrap();
+        assert_eq!(seq, SeqNums { next_in: 4, next_out: 4 });
+        assert_eq!(sent.iter().map(|m| m.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(sent[1].body, message(3).body);
+
+        log.rewrite(seq, &sent);
+        wait_for_lines(log.path(), 3);
+        let (reloaded, resent) = SessionLog::load(log.path(), 2).unwrap();
+        assert_eq!(reloaded, seq);
+        assert_eq!(resent.len(), 2);
+        assert_eq!(SessionLog::load(&dir.join("missing"), 2).unwrap().0, SeqNums::default());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+/// Fixed-point prices and quantities for the matching path.
+///
+/// Values are unsigned integers counting the smallest increment of an
+/// instrument (`10^-decimals`). The scale lives on the instrument, not on the
+/// value, so book keys and fill arithmetic are plain integer operations and
+/// the only conversions happen at the API boundary. There are no arithmetic
+/// operators: every sum and difference says whether it is checked or
+/// saturating, so nothing wraps in release builds.
+use crate::types::RejectReason;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use thiserror::Error;
+
+/// Largest supported scale; `10^18` is the biggest power of ten that leaves
+/// headroom in a `u64`.
+pub const MAX_DECIMALS: u32 = 18;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FixedPointError {
+    #[error("'{0}' is not a valid decimal number")]
+    Invalid(String),
+    #[error("'{value}' has more than {decimals} decimal places")]
+    Precision { value: String, decimals: u32 },
+    #[error("'{0}' is out of range")]
+    Overflow(String),
+}
+
+impl FixedPointError {
+    /// Why an order with this price is refused: too many decimals breaks
+    /// the instrument's tick size, anything else is not a usable number.
+    pub fn price_reject(&self) -> RejectReason {
+        match self {
+            FixedPointError::Precision { .. } => RejectReason::BadTick,
+            _ => RejectReason::InvalidPrice,
+        }
+    }
+
+    /// Why an order with this quantity is refused; see `price_reject`.
+    pub fn qty_reject(&self) -> RejectReason {
+        match self {
+            FixedPointError::Precision { .. } => RejectReason::BadLot,
+            _ => RejectReason::InvalidAmount,
+        }
+    }
+}
+
+macro_rules! fixed_point_type {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(u64);
+
+        impl $name {
+            pub const ZERO: Self = Self(0);
+
+            pub const fn from_raw(raw: u64) -> Self {
+                Self(raw)
+            }
+
+            pub const fn raw(self) -> u64 {
+                self.0
+            }
+
+            pub fn is_zero(self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn checked_add(self, rhs: Self) -> Option<Self> {
+                self.0.checked_add(rhs.0).map(Self)
+            }
+
+            pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+                self.0.checked_sub(rhs.0).map(Self)
+            }
+
+            pub fn saturating_add(self, rhs: Self) -> Self {
+                Self(self.0.saturating_add(rhs.0))
+            }
+
+            pub fn saturating_sub(self, rhs: Self) -> Self {
+                Self(self.0.saturating_sub(rhs.0))
+            }
+
+            /// Parses a decimal string exactly at the given scale.
+            pub fn parse(value: &str, decimals: u32) -> Result<Self, FixedPointError> {
+                parse_scaled(value, decimals).map(Self)
+            }
+
+            /// Formats the value as a canonical decimal string at the given scale.
+            pub fn format(self, decimals: u32) -> String {
+                format_scaled(self.0.into(), decimals)
+            }
+
+            pub fn to_decimal(self, decimals: u32) -> Decimal {
+                Decimal::from_i128_with_scale(self.0 as i128, decimals)
+            }
+        }
+
+        /// Saturates rather than wrapping.
+        impl Sum for $name {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::ZERO, Self::saturating_add)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+fixed_point_type!(
+    /// A price in ticks of `10^-price_decimals`.
+    Price
+);
+
+fixed_point_type!(
+    /// A quantity in lots of `10^-qty_decimals`.
+    Qty
+);
+
+/// Parses an unsigned decimal string into an integer scaled by
+/// `10^decimals`. Digits beyond the scale are only accepted if they are
+/// trailing zeros, so the conversion never rounds.
+pub fn parse_scaled(value: &str, decimals: u32) -> Result<u64, FixedPointError> {
+    debug_assert!(decimals <= MAX_DECIMALS);
+
+    let (int_part, frac_part) = match value.split_once('.') {
+        Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+        None => (value, None),
+    };
+
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(int_part) || !frac_part.is_none_or(is_digits) {
+        return Err(FixedPointError::Invalid(value.to_string()));
+    }
+
+    let frac = frac_part.unwrap_or("").trim_end_matches('0');
+    if frac.len() > decimals as usize {
+        return Err(FixedPointError::Precision {
+            value: value.to_string(),
+            decimals,
+        });
+    }
+
+    let overflow = || FixedPointError::Overflow(value.to_string());
+    let mut scaled: u64 = 0;
+    for digit in int_part.bytes().chain(frac.bytes()) {
+        scaled = scaled
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(u64::from(digit - b'0')))
+            .ok_or_else(overflow)?;
+    }
+
+    scaled
+        .checked_mul(10u64.pow(decimals - frac.len() as u32))
+        .ok_or_else(overflow)
+}
+
+/// Formats a scaled integer without trailing fractional zeros.
+pub fn format_scaled(scaled: u128, decimals: u32) -> String {
+    let unit = 10u128.pow(decimals);
+    let (int_part, frac_part) = (scaled / unit, scaled % unit);
+
+    if frac_part == 0 {
+        return int_part.to_string();
+    }
+
+    let frac = format!("{:0width$}", frac_part, width = decimals as usize);
+    format!("{}.{}", int_part, frac.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_is_exact() {
+        assert_eq!(Price::parse("101.25", 2).unwrap().raw(), 10125);
+        assert_eq!(Price::parse("101.250000", 2).unwrap().raw(), 10125);
+        assert_eq!(Price::parse("7", 6).unwrap().raw(), 7_000_000);
+        assert_eq!(Qty::parse("0.00000001", 8).unwrap().raw(), 1);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        for bad in ["", ".5", "5.", "-1", "1e3", "1.2.3", " 1", "abc"] {
+            assert!(matches!(Price::parse(bad, 2), Err(FixedPointError::Invalid(_))), "{}", bad);
+        }
+        assert!(matches!(
+            Price::parse("1.005", 2),
+            Err(FixedPointError::Precision { decimals: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_rejects_overflow() {
+        assert_eq!(Qty::parse("18446744073709551615", 0).unwrap().raw(), u64::MAX);
+        assert!(matches!(Qty::parse("18446744073709551616", 0), Err(FixedPointError::Overflow(_))));
+        assert!(matches!(Qty::parse("18446744073709551615", 1), Err(FixedPointError::Overflow(_))));
+    }
+
+    #[test]
+    fn test_parse_errors_map_to_reject_reasons() {
+        assert_eq!(Price::parse("1.005", 2).unwrap_err().price_reject(), RejectReason::BadTick);
+        assert_eq!(Price::parse("abc", 2).unwrap_err().price_reject(), RejectReason::InvalidPrice);
+        assert_eq!(Qty::parse("0.5", 0).unwrap_err().qty_reject(), RejectReason::BadLot);
+        assert_eq!(Qty::parse("18446744073709551616", 0).unwrap_err().qty_reject(), RejectReason::InvalidAmount);
+    }
+
+    #[test]
+    fn test_format_round_trips() {
+        for (text, decimals) in [("0", 4), ("1.5", 4), ("100", 0), ("0.0001", 4), ("123456.789", 8)] {
+            let price = Price::parse(text, decimals).unwrap();
+            assert_eq!(price.format(decimals), text);
+            assert_eq!(price.to_decimal(decimals).to_string().parse::<Decimal>().unwrap(), text.parse::<Decimal>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(Qty::from_raw(u64::MAX).checked_add(Qty::from_raw(1)), None);
+        assert_eq!(Qty::from_raw(1).checked_sub(Qty::from_raw(2)), None);
+        assert_eq!(Qty::from_raw(3).saturating_sub(Qty::from_raw(1)), Qty::from_raw(2));
+        assert_eq!(Qty::from_raw(1).saturating_sub(Qty::from_raw(2)), Qty::ZERO);
+        assert_eq!(Qty::from_raw(u64::MAX).saturating_add(Qty::from_raw(1)), Qty::from_raw(u64::MAX));
+        assert_eq!([Qty::from_raw(u64::MAX), Qty::from_raw(1)].into_iter().sum::<Qty>(), Qty::from_raw(u64::MAX));
+    }
+}
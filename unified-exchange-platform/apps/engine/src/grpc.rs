@@ -0,0 +1,1439 @@
+/// The gRPC `MatchingEngine` service.
+///
+/// `MatchingEngineService` serves order entry, order sessions, execution
+/// reports, market data streams and Retransmit over one engine and its
+/// feeds. `new` wires them up the way the server runs, reading
+/// `INSTRUMENTS`, `JOURNAL_PATH` and `RETRANSMIT_DIR` from the environment,
+/// and the other gateways share the engine and feeds through its fields.
+use crate::candles::{self, CandleBuilder, CandleFeed, CandleInterval};
+use crate::conflation::{Conflated, ConflationMode};
+use crate::depth::{DepthBatch, DepthFeed, DepthPublisher, LevelUpdate};
+use crate::disruptor::EventHandler;
+use crate::engine::{EngineConfig, EngineEvent, MatchingEngine};
+use crate::execution::{self, ExecutionReporter, ExecutionReports};
+use crate::fixed_point::{Price, Qty, MAX_DECIMALS};
+use crate::journal::Journal;
+use crate::l3::{self, L3Event, L3Feed, L3Publisher};
+use crate::multicast::{ItchFeed, ItchPublisher, MulticastConfig};
+use crate::retransmit::{DiskRetention, RetransmitConfig, Retransmission};
+use crate::stats::{RollingStats, StatsBuilder};
+use crate::ticker::{self, TickerFeed, TickerPublisher};
+use crate::trades::{PublicTrade, TradeFeed, TradePublisher};
+use crate::types::{self, Instrument, MatchingResult, RejectReason};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::MissedTickBehavior;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Types and service traits generated from `proto/matching.proto`.
+pub mod matching {
+    tonic::include_proto!("matching");
+}
+
+use matching::{
+    matching_engine_server::MatchingEngine as MatchingEngineTrait,
+    OrderRequest, OrderResponse, BatchOrderRequest, BatchOrderResponse,
+    CancelRequest, CancelResponse,
+    OrderBookRequest, OrderBookResponse, StreamRequest, TradeEvent,
+    Fill, PriceLevel, AmendRequest, SessionCommand, SessionEvent, SessionAck, SessionReject,
+    SessionAction, session_command, session_event, ExecutionReportRequest,
+    OrderBookStreamRequest, OrderBookUpdate, OrderBookSnapshot, PriceLevelUpdate, PriceLevelBatch, order_book_update,
+    L3Update, L3Snapshot, L3Level, L3Order, L3Add, L3Modify, L3Execute, L3Delete, l3_update,
+    TickerRequest, TickerUpdate, Ticker, CandleRequest, CandleResponse, CandleStreamRequest, Candle,
+    GetTickerRequest, TickerStatistics, RetransmitRequest, RetransmitResponse, MarketDataChannel,
+};
+
+/// Largest batch accepted by `PlaceOrders`.
+const MAX_BATCH_ORDERS: usize = 1000;
+
+/// Session events buffered before the session stops reading commands.
+const SESSION_BUFFER: usize = 1024;
+
+/// Execution reports a subscriber may fall behind before it is dropped.
+const EXECUTION_REPORT_BUFFER: usize = 1024;
+
+/// Closed orders whose final status stays available to REST clients.
+const CLOSED_ORDER_HISTORY: usize = 100_000;
+
+/// Trades a StreamTrades subscriber may fall behind before it is dropped.
+const TRADE_FEED_CAPACITY: usize = 4096;
+
+/// Level updates a StreamOrderBook subscriber may fall behind before it is
+/// dropped.
+const DEPTH_FEED_CAPACITY: usize = 4096;
+
+/// Updates a StreamOrders subscriber may fall behind before it is dropped.
+const L3_FEED_CAPACITY: usize = 4096;
+
+/// Tickers a StreamTicker subscriber may fall behind before it is dropped.
+const TICKER_FEED_CAPACITY: usize = 4096;
+
+/// Candles kept per symbol and interval for GetCandles.
+const CANDLE_HISTORY: usize = 1440;
+
+/// Candle updates a StreamCandles subscriber may fall behind before it is
+/// dropped.
+const CANDLE_FEED_CAPACITY: usize = 4096;
+
+/// Messages kept in memory per symbol on each sequenced feed for
+/// Retransmit.
+const RETRANSMIT_CAPACITY: usize = 65536;
+
+/// Messages per segment file when `RETRANSMIT_DIR` sets a directory for
+/// Retransmit to reach further back from disk.
+const RETRANSMIT_SEGMENT_LEN: usize = 1_000_000;
+
+/// Most messages returned by one Retransmit call.
+const MAX_RETRANSMIT: u64 = 10_000;
+
+/// Group the binary multicast feed is published to.
+pub const MULTICAST_GROUP: &str = "239.255.0.1:31001";
+
+/// Session name in the binary feed's packet headers.
+const MULTICAST_SESSION: &str = "KK99";
+
+/// Messages queued ahead of a conflated subscriber. Kept at one so updates
+/// conflate while the client is backed up instead of queueing.
+const CONFLATED_BUFFER: usize = 1;
+
+pub struct MatchingEngineService {
+    pub engine: Arc<MatchingEngine>,
+    pub execution_reports: Arc<ExecutionReports>,
+    pub trade_feed: Arc<TradeFeed>,
+    pub depth_feed: Arc<DepthFeed>,
+    pub l3_feed: Arc<L3Feed>,
+    pub ticker_feed: Arc<TickerFeed>,
+    pub candle_feed: Arc<CandleFeed>,
+    pub stats: Arc<RollingStats>,
+    pub itch_feed: Arc<ItchFeed>,
+}
+
+impl MatchingEngineService {
+    pub fn new() -> Self {
+        let execution_reports = Arc::new(ExecutionReports::with_order_history(EXECUTION_REPORT_BUFFER, CLOSED_ORDER_HISTORY));
+        let retransmit = RetransmitConfig {
+            capacity: RETRANSMIT_CAPACITY,
+            disk: std::env::var_os("RETRANSMIT_DIR").map(|dir| DiskRetention {
+                dir: PathBuf::from(dir),
+                segment_len: RETRANSMIT_SEGMENT_LEN,
+            }),
+        };
+        if let Some(Err(e)) = retransmit.disk.as_ref().map(DiskRetention::remove_stale) {
+            error!("Failed to remove stale retransmit segments: {}", e);
+        }
+        let trade_feed = Arc::new(TradeFeed::with_retransmit(TRADE_FEED_CAPACITY, retransmit.clone()));
+        let depth_feed = Arc::new(DepthFeed::with_retransmit(DEPTH_FEED_CAPACITY, retransmit.clone()));
+        let l3_feed = Arc::new(L3Feed::with_retransmit(L3_FEED_CAPACITY, retransmit.clone()));
+        let itch_feed = Arc::new(ItchFeed::new(MULTICAST_SESSION, retransmit));
+        let ticker_feed = Arc::new(TickerFeed::new(TICKER_FEED_CAPACITY));
+        let candle_feed = Arc::new(CandleFeed::new(CANDLE_HISTORY, CANDLE_FEED_CAPACITY));
+        let stats = Arc::new(RollingStats::new());
+        let mut handlers: Vec<Box<dyn EventHandler<EngineEvent>>> = vec![
+            Box::new(ExecutionReporter::new(Arc::clone(&execution_reports))),
+            Box::new(TradePublisher::new(Arc::clone(&trade_feed))),
+            Box::new(DepthPublisher::new(Arc::clone(&depth_feed))),
+            Box::new(L3Publisher::new(Arc::clone(&l3_feed))),
+            Box::new(TickerPublisher::new(Arc::clone(&ticker_feed))),
+            Box::new(CandleBuilder::new(Arc::clone(&candle_feed))),
+            Box::new(StatsBuilder::new(Arc::clone(&stats))),
+        ];
+        if let Some(path) = std::env::var_os("JOURNAL_PATH") {
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => handlers.push(Box::new(Journal::new(BufWriter::new(file)))),
+                Err(e) => error!("Event journal disabled: {}", e),
+            }
+        }
+        let multicast = MulticastConfig::new(MULTICAST_GROUP.parse().expect("valid multicast group"));
+        match ItchPublisher::new(Arc::clone(&itch_feed), &multicast) {
+            Ok(publisher) => handlers.push(Box::new(publisher)),
+            Err(e) => error!("Multicast feed disabled: {}", e),
+        }
+
+        // Once instruments are configured, only they can be traded.
+        let instruments = match std::env::var("INSTRUMENTS") {
+            Ok(spec) => parse_instruments(&spec).unwrap_or_else(|e| panic!("Invalid INSTRUMENTS: {}", e)),
+            Err(_) => Vec::new(),
+        };
+        let config = EngineConfig {
+            require_registered_symbols: !instruments.is_empty(),
+            ..EngineConfig::default()
+        };
+        let engine = Arc::new(MatchingEngine::start(config, handlers));
+        for instrument in instruments {
+            engine.register_instrument(instrument);
+        }
+
+        Self {
+            engine,
+            execution_reports,
+            trade_feed,
+            depth_feed,
+            l3_feed,
+            ticker_feed,
+            candle_feed,
+            stats,
+            itch_feed,
+        }
+    }
+}
+
+/// Parses comma-separated `SYMBOL:PRICE_DECIMALS:QTY_DECIMALS` entries,
+/// each optionally followed by `:MAX_ORDER_QTY` as a decimal amount.
+fn parse_instruments(spec: &str) -> Result<Vec<Instrument>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let fields: Vec<&str> = entry.split(':').collect();
+            let (symbol, price_decimals, qty_decimals, max_qty) = match fields[..] {
+                [symbol, price, qty] => (symbol, price, qty, None),
+                [symbol, price, qty, max_qty] => (symbol, price, qty, Some(max_qty)),
+                _ => return Err(format!("'{}' is not SYMBOL:PRICE_DECIMALS:QTY_DECIMALS[:MAX_ORDER_QTY]", entry)),
+            };
+            let decimals = |value: &str| match value.parse::<u32>() {
+                Ok(decimals) if decimals <= MAX_DECIMALS => Ok(decimals),
+                _ => Err(format!("'{}' in '{}' is not a precision from 0 to {}", value, entry, MAX_DECIMALS)),
+            };
+            let instrument = Instrument::new(symbol, decimals(price_decimals)?, decimals(qty_decimals)?);
+            match max_qty {
+                Some(max_qty) => {
+                    let max_qty = instrument.parse_qty(max_qty).map_err(|e| format!("{}: {}", entry, e))?;
+                    Ok(instrument.with_max_order_qty(max_qty))
+                }
+                None => Ok(instrument),
+            }
+        })
+        .collect()
+}
+
+impl Default for MatchingEngineService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tonic::async_trait]
+impl MatchingEngineTrait for MatchingEngineService {
+    async fn place_order(
+        &self,
+        request: Request<OrderRequest>,
+    ) -> Result<Response<OrderResponse>, Status> {
+        let req = request.into_inner();
+        let order_id = req.order_id.clone();
+        let instrument = self.engine.instrument(&req.symbol);
+
+        let placed = match order_from_request(req, &instrument) {
+            Ok(order) => self.engine.place_order_async(order).await,
+            Err(reason) => Err(reason),
+        };
+        let response = match placed {
+            Ok(result) => order_response(order_id, &result, &instrument),
+            Err(reason) => rejected_response(order_id, reason),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn place_orders(
+        &self,
+        request: Request<BatchOrderRequest>,
+    ) -> Result<Response<BatchOrderResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.orders.len() > MAX_BATCH_ORDERS {
+            return Err(Status::invalid_argument(format!(
+                "Batch of {} orders exceeds the limit of {}",
+                req.orders.len(),
+                MAX_BATCH_ORDERS
+            )));
+        }
+
+        let mut valid_orders = Vec::with_capacity(req.orders.len());
+        let entries: Vec<_> = req
+            .orders
+            .into_iter()
+            .map(|order| {
+                let order_id = order.order_id.clone();
+                let instrument = self.engine.instrument(&order.symbol);
+                let error = match order_from_request(order, &instrument) {
+                    Ok(order) => {
+                        valid_orders.push(order);
+                        None
+                    }
+                    Err(reason) => Some(reason),
+                };
+                (order_id, instrument, error)
+            })
+            .collect();
+
+        // Orders that failed conversion count as invalid for all-or-none.
+        let reject_all = req.all_or_none && entries.iter().any(|(_, _, error)| error.is_some());
+        let mut placed = if reject_all {
+            Vec::new()
+        } else {
+            self.engine.place_orders_async(valid_orders, req.all_or_none).await
+        }
+        .into_iter();
+
+        let results = entries
+            .into_iter()
+            .map(|(order_id, instrument, error)| {
+                let outcome = match error {
+                    Some(reason) => Err(reason),
+                    None if reject_all => Err(RejectReason::BatchRejected),
+                    None => placed.next().expect("one result per placed order"),
+                };
+
+                match outcome {
+                    Ok(result) => order_response(order_id, &result, &instrument),
+                    Err(reason) => rejected_response(order_id, reason),
+                }
+            })
+            .collect();
+
+        Ok(Response::new(BatchOrderResponse { results }))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<CancelRequest>,
+    ) -> Result<Response<CancelResponse>, Status> {
+        let req = request.into_inner();
+
+        // Another user's order is as unknown as a missing one.
+        let cancelled = match self.engine.order_owner(&req.order_id) {
+            Some(owner) if owner.as_str() == req.user_id => self.engine.cancel_order_async(&req.order_id).await,
+            _ => None,
+        };
+        match cancelled {
+            Some(_) => Ok(Response::new(CancelResponse {
+                success: true,
+                message: "Order cancelled".to_string(),
+            })),
+            None => Ok(Response::new(CancelResponse {
+                success: false,
+                message: "Order not found".to_string(),
+            })),
+        }
+    }
+
+    async fn get_order_book(
+        &self,
+        request: Request<OrderBookRequest>,
+    ) -> Result<Response<OrderBookResponse>, Status> {
+        let req = request.into_inner();
+        let max_depth = self.engine.snapshot_depth();
+        let depth = usize::try_from(req.depth)
+            .ok()
+            .filter(|&depth| depth <= max_depth)
+            .ok_or_else(|| Status::invalid_argument(format!("depth must be between 0 and {}", max_depth)))?;
+        let instrument = self.engine.instrument(&req.symbol);
+
+        match self.engine.get_order_book(&req.symbol, depth) {
+            Some(book) => {
+                let to_proto = |level: &types::DepthLevel| depth_level_to_proto(level, &instrument);
+
+                Ok(Response::new(OrderBookResponse {
+                    bids: book.bids.iter().map(to_proto).collect(),
+                    asks: book.asks.iter().map(to_proto).collect(),
+                }))
+            }
+            None => Err(Status::not_found("Symbol not found")),
+        }
+    }
+
+    type StreamTradesStream = ReceiverStream<Result<TradeEvent, Status>>;
+
+    async fn stream_trades(
+        &self,
+        request: Request<StreamRequest>,
+    ) -> Result<Response<Self::StreamTradesStream>, Status> {
+        let req = request.into_inner();
+        if req.symbol.is_empty() {
+            return Err(Status::invalid_argument("symbol is required"));
+        }
+
+        let trades = self.trade_feed.subscribe(&req.symbol);
+        let instrument = self.engine.instrument(&req.symbol);
+        let (tx, rx) = mpsc::channel(TRADE_FEED_CAPACITY);
+
+        tokio::spawn(forward_broadcast(trades, tx, "Trade stream", req.symbol, move |public| {
+            Some(public_trade_to_proto(&public, &instrument))
+        }));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type OrderSessionStream = ReceiverStream<Result<SessionEvent, Status>>;
+
+    async fn order_session(
+        &self,
+        request: Request<Streaming<SessionCommand>>,
+    ) -> Result<Response<Self::OrderSessionStream>, Status> {
+        let mut commands = request.into_inner();
+        let (tx, rx) = mpsc::channel(SESSION_BUFFER);
+        let mut session = OrderSession::new(Arc::clone(&self.engine), Arc::clone(&self.execution_reports), tx);
+
+        tokio::spawn(async move {
+            loop {
+                let handled = tokio::select! {
+                    command = commands.message() => match command {
+                        Ok(Some(command)) => session.handle(command).await,
+                        Ok(None) => break,
+                        Err(status) => {
+                            warn!("Order session closed with error: {}", status);
+                            break;
+                        }
+                    },
+                    report = session.next_report() => match report {
+                        Some(report) => session.on_report(report).await,
+                        None => {
+                            let status = Status::resource_exhausted("Session fell behind its execution reports");
+                            let _ = session.events.send(Err(status)).await;
+                            break;
+                        }
+                    },
+                };
+                if handled.is_err() {
+                    break; // Client stopped reading
+                }
+            }
+            info!("Order session ended after {} events", session.sequence);
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type StreamExecutionReportsStream = ReceiverStream<Result<matching::ExecutionReport, Status>>;
+
+    async fn stream_execution_reports(
+        &self,
+        request: Request<ExecutionReportRequest>,
+    ) -> Result<Response<Self::StreamExecutionReportsStream>, Status> {
+        let req = request.into_inner();
+        if req.user_id.is_empty() {
+            return Err(Status::invalid_argument("user_id is required"));
+        }
+
+        let mut reports = self.execution_reports.subscribe(&req.user_id);
+        let (tx, rx) = mpsc::channel(EXECUTION_REPORT_BUFFER);
+        let engine = Arc::clone(&self.engine);
+
+        tokio::spawn(async move {
+            while let Some(report) = reports.recv().await {
+                let instrument = engine.instrument(&report.symbol);
+                if tx.send(Ok(report_to_proto(&report, &instrument))).await.is_err() {
+                    return; // Client went away
+                }
+            }
+            // The engine only drops a subscription that fell too far behind.
+            let _ = tx
+                .send(Err(Status::resource_exhausted("Execution reports fell behind; resubscribe")))
+                .await;
+            info!("Execution report stream for {} closed", req.user_id);
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type StreamOrderBookStream = ReceiverStream<Result<OrderBookUpdate, Status>>;
+
+    async fn stream_order_book(
+        &self,
+        request: Request<OrderBookStreamRequest>,
+    ) -> Result<Response<Self::StreamOrderBookStream>, Status> {
+        let req = request.into_inner();
+        if req.symbol.is_empty() {
+            return Err(Status::invalid_argument("symbol is required"));
+        }
+
+        let instrument = self.engine.instrument(&req.symbol);
+        let (mode, interval) = conflation_from_request(req.conflation, req.conflation_interval_ms)
+            .ok_or_else(|| Status::invalid_argument("Unknown conflation mode"))?;
+        if let Some(mode) = mode {
+            let depth = self.depth_feed.subscribe_conflated(&req.symbol, mode);
+            let (tx, rx) = mpsc::channel(CONFLATED_BUFFER);
+            tokio::spawn(stream_conflated(depth, interval, tx, move |batch| {
+                depth_batch_to_proto(batch, &instrument)
+            }));
+            return Ok(Response::new(ReceiverStream::new(rx)));
+        }
+
+        let (snapshot, updates) = self.depth_feed.subscribe(&req.symbol);
+        let (tx, rx) = mpsc::channel(DEPTH_FEED_CAPACITY);
+
+        tokio::spawn(async move {
+            if tx.send(Ok(depth_snapshot_to_proto(&snapshot, &instrument))).await.is_err() {
+                return;
+            }
+            forward_broadcast(updates, tx, "Order book stream", req.symbol, |level| {
+                Some(level_message_to_proto(&level, &instrument))
+            })
+            .await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type StreamOrdersStream = ReceiverStream<Result<L3Update, Status>>;
+
+    async fn stream_orders(
+        &self,
+        request: Request<OrderBookStreamRequest>,
+    ) -> Result<Response<Self::StreamOrdersStream>, Status> {
+        let req = request.into_inner();
+        if req.symbol.is_empty() {
+            return Err(Status::invalid_argument("symbol is required"));
+        }
+
+        let (snapshot, updates) = self.l3_feed.subscribe(&req.symbol);
+        let instrument = self.engine.instrument(&req.symbol);
+        let (tx, rx) = mpsc::channel(L3_FEED_CAPACITY);
+
+        tokio::spawn(async move {
+            let levels = |levels: &[l3::L3Level]| -> Vec<L3Level> {
+                levels
+                    .iter()
+                    .map(|level| L3Level {
+                        price: instrument.format_price(level.price),
+                        orders: level
+                            .orders
+                            .iter()
+                            .map(|order| L3Order {
+                                order_id: order.order_id,
+                                amount: instrument.format_qty(order.amount),
+                            })
+                            .collect(),
+                    })
+                    .collect()
+            };
+            let first = L3Update {
+                sequence: snapshot.sequence,
+                symbol: snapshot.symbol.to_string(),
+                event: Some(l3_update::Event::Snapshot(L3Snapshot {
+                    bids: levels(&snapshot.bids),
+                    asks: levels(&snapshot.asks),
+                })),
+            };
+            if tx.send(Ok(first)).await.is_err() {
+                return;
+            }
+            forward_broadcast(updates, tx, "Order stream", req.symbol, |update| {
+                Some(l3_update_to_proto(&update, &instrument))
+            })
+            .await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type StreamTickerStream = ReceiverStream<Result<TickerUpdate, Status>>;
+
+    async fn stream_ticker(
+        &self,
+        request: Request<TickerRequest>,
+    ) -> Result<Response<Self::StreamTickerStream>, Status> {
+        let req = request.into_inner();
+        if req.symbols.is_empty() || req.symbols.iter().any(String::is_empty) {
+            return Err(Status::invalid_argument("symbols are required"));
+        }
+        let (mode, interval) = conflation_from_request(req.conflation, req.conflation_interval_ms)
+            .ok_or_else(|| Status::invalid_argument("Unknown conflation mode"))?;
+
+        let instruments: HashMap<String, Instrument> = req
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.clone(), self.engine.instrument(symbol)))
+            .collect();
+        let to_proto = move |tickers: Vec<ticker::Ticker>| TickerUpdate {
+            tickers: tickers
+                .iter()
+                .map(|ticker| ticker_to_proto(ticker, &instruments[ticker.symbol.as_str()]))
+                .collect(),
+        };
+
+        if mode.is_some() {
+            let symbols: Vec<&str> = req.symbols.iter().map(String::as_str).collect();
+            let tickers = self.ticker_feed.subscribe_conflated(&symbols);
+            let (tx, rx) = mpsc::channel(CONFLATED_BUFFER);
+            tokio::spawn(stream_conflated(tickers, interval, tx, to_proto));
+            return Ok(Response::new(ReceiverStream::new(rx)));
+        }
+
+        // Subscribe before reading the current tickers, and skip anything
+        // not newer than what was already sent.
+        let updates = self.ticker_feed.subscribe();
+        let current: Vec<_> = req.symbols.iter().filter_map(|symbol| self.ticker_feed.latest(symbol)).collect();
+        let mut sent: HashMap<String, u64> = req.symbols.iter().map(|symbol| (symbol.clone(), 0)).collect();
+        let (tx, rx) = mpsc::channel(TICKER_FEED_CAPACITY);
+
+        tokio::spawn(async move {
+            for ticker in &current {
+                sent.insert(ticker.symbol.to_string(), ticker.sequence);
+            }
+            if tx.send(Ok(to_proto(current))).await.is_err() {
+                return;
+            }
+            let symbols = req.symbols.join(",");
+            forward_broadcast(updates, tx, "Ticker stream", symbols, |ticker| {
+                match sent.get_mut(ticker.symbol.as_str()) {
+                    Some(last) if ticker.sequence > *last => *last = ticker.sequence,
+                    _ => return None,
+                }
+                Some(to_proto(vec![ticker]))
+            })
+            .await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_candles(
+        &self,
+        request: Request<CandleRequest>,
+    ) -> Result<Response<CandleResponse>, Status> {
+        let req = request.into_inner();
+        let interval = interval_from_proto(req.interval)
+            .ok_or_else(|| Status::invalid_argument("interval is required"))?;
+        let limit = if req.limit > 0 { req.limit as usize } else { CANDLE_HISTORY };
+        let instrument = self.engine.instrument(&req.symbol);
+
+        let candles = self.candle_feed.history(&req.symbol, interval, limit);
+        Ok(Response::new(CandleResponse {
+            candles: candles.iter().map(|c| candle_to_proto(c, &instrument)).collect(),
+        }))
+    }
+
+    type StreamCandlesStream = ReceiverStream<Result<Candle, Status>>;
+
+    async fn stream_candles(
+        &self,
+        request: Request<CandleStreamRequest>,
+    ) -> Result<Response<Self::StreamCandlesStream>, Status> {
+        let req = request.into_inner();
+        if req.symbol.is_empty() {
+            return Err(Status::invalid_argument("symbol is required"));
+        }
+        let interval = interval_from_proto(req.interval)
+            .ok_or_else(|| Status::invalid_argument("interval is required"))?;
+
+        let (current, updates) = self.candle_feed.subscribe(&req.symbol, interval);
+        let instrument = self.engine.instrument(&req.symbol);
+        let (tx, rx) = mpsc::channel(CANDLE_FEED_CAPACITY);
+
+        tokio::spawn(async move {
+            if let Some(candle) = current {
+                if tx.send(Ok(candle_to_proto(&candle, &instrument))).await.is_err() {
+                    return;
+                }
+            }
+            forward_broadcast(updates, tx, "Candle stream", req.symbol, |candle| {
+                (candle.interval == interval).then(|| candle_to_proto(&candle, &instrument))
+            })
+            .await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_ticker(
+        &self,
+        request: Request<GetTickerRequest>,
+    ) -> Result<Response<TickerStatistics>, Status> {
+        let req = request.into_inner();
+        let stats = self
+            .stats
+            .get(&req.symbol)
+            .ok_or_else(|| Status::not_found("No trades for symbol"))?;
+        let instrument = self.engine.instrument(&req.symbol);
+
+        let change = instrument.format_price(Price::from_raw(stats.price_change.unsigned_abs() as u64));
+        Ok(Response::new(TickerStatistics {
+            symbol: stats.symbol.to_string(),
+            open: instrument.format_price(stats.open),
+            high: instrument.format_price(stats.high),
+            low: instrument.format_price(stats.low),
+            last: instrument.format_price(stats.last),
+            volume: instrument.format_qty(stats.volume),
+            quote_volume: instrument.format_notional(stats.quote_volume),
+            price_change: if stats.price_change < 0 { format!("-{}", change) } else { change },
+            price_change_percent: format!("{:.2}", stats.price_change_percent),
+            trade_count: stats.trade_count,
+            open_time: stats.open_time,
+            close_time: stats.close_time,
+        }))
+    }
+
+    async fn retransmit(
+        &self,
+        request: Request<RetransmitRequest>,
+    ) -> Result<Response<RetransmitResponse>, Status> {
+        let req = request.into_inner();
+        if req.symbol.is_empty() {
+            return Err(Status::invalid_argument("symbol is required"));
+        }
+        if req.from_sequence == 0 || req.from_sequence > req.to_sequence {
+            return Err(Status::invalid_argument("Sequence range must start at 1 or later and not be reversed"));
+        }
+        let channel = MarketDataChannel::try_from(req.channel)
+            .ok()
+            .filter(|channel| *channel != MarketDataChannel::Unspecified)
+            .ok_or_else(|| Status::invalid_argument("Unknown market data channel"))?;
+
+        let instrument = self.engine.instrument(&req.symbol);
+        let (from, to) = (req.from_sequence, req.to_sequence.min(req.from_sequence.saturating_add(MAX_RETRANSMIT - 1)));
+        let (trade_feed, depth_feed, l3_feed) =
+            (Arc::clone(&self.trade_feed), Arc::clone(&self.depth_feed), Arc::clone(&self.l3_feed));
+
+        // Older ranges are read back from disk.
+        let response = tokio::task::spawn_blocking(move || match channel {
+            MarketDataChannel::Trades => {
+                retransmission_to_proto(trade_feed.retransmit(&req.symbol, from, to), |trades| RetransmitResponse {
+                    trades: trades.iter().map(|t| public_trade_to_proto(t, &instrument)).collect(),
+                    ..Default::default()
+                })
+            }
+            MarketDataChannel::OrderBook => {
+                retransmission_to_proto(depth_feed.retransmit(&req.symbol, from, to), |levels| RetransmitResponse {
+                    order_book: levels.iter().map(|l| level_message_to_proto(l, &instrument)).collect(),
+                    ..Default::default()
+                })
+            }
+            MarketDataChannel::Orders => {
+                retransmission_to_proto(l3_feed.retransmit(&req.symbol, from, to), |updates| RetransmitResponse {
+                    orders: updates.iter().map(|u| l3_update_to_proto(u, &instrument)).collect(),
+                    ..Default::default()
+                })
+            }
+            MarketDataChannel::Unspecified => unreachable!("rejected above"),
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Retransmit failed: {}", e)))?;
+
+        Ok(Response::new(response))
+    }
+}
+
+fn interval_from_proto(interval: i32) -> Option<CandleInterval> {
+    match matching::CandleInterval::try_from(interval).ok()? {
+        matching::CandleInterval::Unspecified => None,
+        matching::CandleInterval::OneSecond => Some(CandleInterval::OneSecond),
+        matching::CandleInterval::OneMinute => Some(CandleInterval::OneMinute),
+        matching::CandleInterval::FiveMinutes => Some(CandleInterval::FiveMinutes),
+        matching::CandleInterval::OneHour => Some(CandleInterval::OneHour),
+        matching::CandleInterval::OneDay => Some(CandleInterval::OneDay),
+    }
+}
+
+fn interval_to_proto(interval: CandleInterval) -> matching::CandleInterval {
+    match interval {
+        CandleInterval::OneSecond => matching::CandleInterval::OneSecond,
+        CandleInterval::OneMinute => matching::CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes => matching::CandleInterval::FiveMinutes,
+        CandleInterval::OneHour => matching::CandleInterval::OneHour,
+        CandleInterval::OneDay => matching::CandleInterval::OneDay,
+    }
+}
+
+fn candle_to_proto(candle: &candles::Candle, instrument: &Instrument) -> Candle {
+    Candle {
+        symbol: candle.symbol.to_string(),
+        interval: interval_to_proto(candle.interval) as i32,
+        open_time: candle.open_time,
+        open: instrument.format_price(candle.open),
+        high: instrument.format_price(candle.high),
+        low: instrument.format_price(candle.low),
+        close: instrument.format_price(candle.close),
+        volume: instrument.format_qty(candle.volume),
+        trade_count: candle.trade_count,
+        vwap: candle.vwap.map(|p| instrument.format_price(p)).unwrap_or_default(),
+        closed: candle.closed,
+    }
+}
+
+fn ticker_to_proto(ticker: &ticker::Ticker, instrument: &Instrument) -> Ticker {
+    let price = |price: Option<Price>| price.map(|p| instrument.format_price(p)).unwrap_or_default();
+    let size = |level: &Option<types::DepthLevel>| {
+        level.as_ref().map(|l| instrument.format_qty(l.amount)).unwrap_or_default()
+    };
+
+    Ticker {
+        sequence: ticker.sequence,
+        symbol: ticker.symbol.to_string(),
+        best_bid: price(ticker.best_bid.as_ref().map(|l| l.price)),
+        bid_size: size(&ticker.best_bid),
+        best_ask: price(ticker.best_ask.as_ref().map(|l| l.price)),
+        ask_size: size(&ticker.best_ask),
+        last_price: price(ticker.last_price),
+        spread: price(ticker.spread()),
+        mid_price: price(ticker.mid_price()),
+        timestamp: ticker.timestamp,
+    }
+}
+
+fn l3_event_to_proto(event: &L3Event, instrument: &Instrument) -> l3_update::Event {
+    match *event {
+        L3Event::Add {
+            order_id,
+            side,
+            price,
+            amount,
+        } => l3_update::Event::Add(L3Add {
+            order_id,
+            side: side_to_proto(side) as i32,
+            price: instrument.format_price(price),
+            amount: instrument.format_qty(amount),
+        }),
+        L3Event::Modify { order_id, amount } => l3_update::Event::Modify(L3Modify {
+            order_id,
+            amount: instrument.format_qty(amount),
+        }),
+        L3Event::Execute {
+            order_id,
+            amount,
+            trade_id,
+        } => l3_update::Event::Execute(L3Execute {
+            order_id,
+            amount: instrument.format_qty(amount),
+            trade_id: trade_id.to_string(),
+        }),
+        L3Event::Delete { order_id } => l3_update::Event::Delete(L3Delete { order_id }),
+    }
+}
+
+/// The conflation mode and emit interval of a request, `None` if the mode
+/// is unknown. A request for every update has no conflation mode.
+fn conflation_from_request(mode: i32, interval_ms: u32) -> Option<(Option<ConflationMode>, Option<Duration>)> {
+    let mode = match matching::ConflationMode::try_from(mode).ok()? {
+        matching::ConflationMode::None => None,
+        matching::ConflationMode::PerLevel => Some(ConflationMode::PerLevel),
+        matching::ConflationMode::PerSymbol => Some(ConflationMode::PerSymbol),
+    };
+    let interval = (interval_ms > 0).then(|| Duration::from_millis(interval_ms.into()));
+    Some((mode, interval))
+}
+
+/// Feeds a broadcast subscription to the client, skipping the updates
+/// `to_proto` returns `None` for. A subscriber that lags is told to
+/// resubscribe and dropped, so the feed never waits on it.
+async fn forward_broadcast<T: Clone, M>(
+    mut updates: broadcast::Receiver<T>,
+    tx: mpsc::Sender<Result<M, Status>>,
+    stream: &'static str,
+    symbols: String,
+    mut to_proto: impl FnMut(T) -> Option<M>,
+) {
+    loop {
+        let message = match updates.recv().await {
+            Ok(update) => match to_proto(update) {
+                Some(message) => message,
+                None => continue,
+            },
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Dropping slow subscriber to {} on {}: {} updates behind", stream, symbols, missed);
+                let status = Status::resource_exhausted(format!("{} fell behind; resubscribe", stream));
+                let _ = tx.send(Err(status)).await;
+                return;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if tx.send(Ok(message)).await.is_err() {
+            return; // Client went away
+        }
+    }
+}
+
+/// Feeds a conflated subscription to the client, one batch per emit.
+/// Updates keep being taken in while waiting for the next tick or for the
+/// client, so the subscription never lags just because the link is slow.
+async fn stream_conflated<S: Conflated, M>(
+    mut subscription: S,
+    interval: Option<Duration>,
+    tx: mpsc::Sender<Result<M, Status>>,
+    to_proto: impl Fn(S::Batch) -> M,
+) {
+    let mut ticker = interval.map(|period| {
+        let mut ticker = tokio::time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker
+    });
+
+    loop {
+        if let Some(ticker) = &mut ticker {
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => break,
+                    alive = subscription.changed() => if !alive { return },
+                }
+            }
+        }
+        let permit = loop {
+            tokio::select! {
+                permit = tx.reserve() => match permit {
+                    Ok(permit) => break permit,
+                    Err(_) => return, // Client went away
+                },
+                alive = subscription.changed() => if !alive { return },
+            }
+        };
+        while !subscription.has_pending() {
+            if !subscription.changed().await {
+                return;
+            }
+        }
+        if !subscription.pump() {
+            return;
+        }
+
+        if let Some(batch) = subscription.flush() {
+            permit.send(Ok(to_proto(batch)));
+        }
+    }
+}
+
+fn retransmission_to_proto<T>(
+    retransmission: Retransmission<T>,
+    to_proto: impl FnOnce(Vec<T>) -> RetransmitResponse,
+) -> RetransmitResponse {
+    match retransmission {
+        Retransmission::Messages(messages) => to_proto(messages),
+        Retransmission::Expired { first_available } => RetransmitResponse {
+            snapshot_required: true,
+            first_available: first_available.unwrap_or(0),
+            ..Default::default()
+        },
+    }
+}
+
+fn public_trade_to_proto(public: &PublicTrade, instrument: &Instrument) -> TradeEvent {
+    TradeEvent {
+        trade_id: public.trade.id.to_string(),
+        symbol: public.trade.symbol.to_string(),
+        price: instrument.format_price(public.trade.price),
+        amount: instrument.format_qty(public.trade.amount),
+        taker_side: side_to_proto(public.trade.taker_side) as i32,
+        timestamp: public.trade.timestamp,
+        sequence: public.sequence,
+    }
+}
+
+fn l3_update_to_proto(update: &l3::L3Update, instrument: &Instrument) -> L3Update {
+    L3Update {
+        sequence: update.sequence,
+        symbol: update.symbol.to_string(),
+        event: Some(l3_event_to_proto(&update.event, instrument)),
+    }
+}
+
+fn level_message_to_proto(level: &LevelUpdate, instrument: &Instrument) -> OrderBookUpdate {
+    OrderBookUpdate {
+        sequence: level.sequence,
+        symbol: level.symbol.to_string(),
+        update: Some(order_book_update::Update::Level(level_update_to_proto(level, instrument))),
+        checksum: level.checksum,
+    }
+}
+
+fn depth_batch_to_proto(batch: DepthBatch, instrument: &Instrument) -> OrderBookUpdate {
+    match batch {
+        DepthBatch::Snapshot(snapshot) => depth_snapshot_to_proto(&snapshot, instrument),
+        DepthBatch::Levels {
+            sequence,
+            checksum,
+            levels,
+        } => OrderBookUpdate {
+            sequence,
+            symbol: instrument.symbol.to_string(),
+            update: Some(order_book_update::Update::Levels(PriceLevelBatch {
+                levels: levels.iter().map(|level| level_update_to_proto(level, instrument)).collect(),
+            })),
+            checksum,
+        },
+    }
+}
+
+fn depth_snapshot_to_proto(snapshot: &types::DepthSnapshot, instrument: &Instrument) -> OrderBookUpdate {
+    OrderBookUpdate {
+        sequence: snapshot.sequence,
+        symbol: snapshot.symbol.to_string(),
+        update: Some(order_book_update::Update::Snapshot(OrderBookSnapshot {
+            bids: snapshot.bids.iter().map(|l| depth_level_to_proto(l, instrument)).collect(),
+            asks: snapshot.asks.iter().map(|l| depth_level_to_proto(l, instrument)).collect(),
+            price_decimals: instrument.price_decimals,
+            qty_decimals: instrument.qty_decimals,
+        })),
+        checksum: snapshot.checksum(),
+    }
+}
+
+fn level_update_to_proto(level: &LevelUpdate, instrument: &Instrument) -> PriceLevelUpdate {
+    PriceLevelUpdate {
+        side: side_to_proto(level.side) as i32,
+        price: instrument.format_price(level.price),
+        amount: instrument.format_qty(level.amount),
+        order_count: level.order_count as i32,
+    }
+}
+
+fn depth_level_to_proto(level: &types::DepthLevel, instrument: &Instrument) -> PriceLevel {
+    PriceLevel {
+        price: instrument.format_price(level.price),
+        amount: instrument.format_qty(level.amount),
+        order_count: level.order_count as i32,
+    }
+}
+
+fn report_to_proto(report: &execution::ExecutionReport, instrument: &Instrument) -> matching::ExecutionReport {
+    let (last_trade_id, last_price, last_qty) = match &report.last_fill {
+        Some(fill) => (
+            fill.trade_id.to_string(),
+            instrument.format_price(fill.price),
+            instrument.format_qty(fill.qty),
+        ),
+        None => Default::default(),
+    };
+
+    matching::ExecutionReport {
+        sequence: report.sequence,
+        exec_type: exec_type_to_proto(report.exec_type) as i32,
+        order_id: report.order_id.to_string(),
+        symbol: report.symbol.to_string(),
+        side: side_to_proto(report.side) as i32,
+        status: status_to_proto(report.status) as i32,
+        price: instrument.format_price(report.price),
+        amount: instrument.format_qty(report.amount),
+        last_trade_id,
+        last_price,
+        last_qty,
+        cum_qty: instrument.format_qty(report.cum_qty),
+        leaves_qty: instrument.format_qty(report.leaves_qty),
+        avg_price: report.avg_price.map(|p| instrument.format_price(p)).unwrap_or_default(),
+        timestamp: report.timestamp,
+    }
+}
+
+fn side_to_proto(side: types::OrderSide) -> matching::OrderSide {
+    match side {
+        types::OrderSide::Buy => matching::OrderSide::Buy,
+        types::OrderSide::Sell => matching::OrderSide::Sell,
+    }
+}
+
+fn exec_type_to_proto(exec_type: execution::ExecType) -> matching::ExecType {
+    match exec_type {
+        execution::ExecType::New => matching::ExecType::New,
+        execution::ExecType::Trade => matching::ExecType::Trade,
+        execution::ExecType::Amended => matching::ExecType::Amended,
+        execution::ExecType::Cancelled => matching::ExecType::Cancelled,
+        execution::ExecType::Expired => matching::ExecType::Expired,
+    }
+}
+
+fn order_from_request(req: OrderRequest, instrument: &Instrument) -> Result<types::Order, RejectReason> {
+    Ok(types::Order {
+        id: req.order_id.into(),
+        user_id: req.user_id.into(),
+        symbol: instrument.symbol.clone(),
+        side: match req.side {
+            0 => types::OrderSide::Buy,
+            1 => types::OrderSide::Sell,
+            _ => return Err(RejectReason::InvalidSide),
+        },
+        order_type: match req.r#type {
+            0 => types::OrderType::Limit,
+            1 => types::OrderType::Market,
+            _ => return Err(RejectReason::InvalidOrderType),
+        },
+        price: instrument
+            .parse_price(&req.price)
+            .map_err(|e| e.price_reject())?,
+        amount: instrument
+            .parse_qty(&req.amount)
+            .map_err(|e| e.qty_reject())?,
+        filled: Qty::ZERO,
+        status: types::OrderStatus::New,
+        timestamp: req.timestamp,
+    })
+}
+
+fn order_response(order_id: String, result: &MatchingResult, instrument: &Instrument) -> OrderResponse {
+    let fills = fills_to_proto(result, instrument);
+
+    OrderResponse {
+        success: true,
+        order_id,
+        message: format!("Order placed with {} fills", fills.len()),
+        fills,
+        status: status_to_proto(result.status) as i32,
+        reject_reason: matching::RejectReason::Unspecified as i32,
+    }
+}
+
+fn rejected_response(order_id: String, reason: RejectReason) -> OrderResponse {
+    OrderResponse {
+        success: false,
+        order_id,
+        message: reason.to_string(),
+        fills: Vec::new(),
+        status: matching::OrderStatus::Rejected as i32,
+        reject_reason: reject_reason_to_proto(reason) as i32,
+    }
+}
+
+fn status_to_proto(status: types::OrderStatus) -> matching::OrderStatus {
+    match status {
+        types::OrderStatus::New => matching::OrderStatus::New,
+        types::OrderStatus::PartiallyFilled => matching::OrderStatus::PartiallyFilled,
+        types::OrderStatus::Filled => matching::OrderStatus::Filled,
+        types::OrderStatus::Cancelled => matching::OrderStatus::Cancelled,
+        types::OrderStatus::Rejected => matching::OrderStatus::Rejected,
+        types::OrderStatus::Expired => matching::OrderStatus::Expired,
+    }
+}
+
+fn reject_reason_to_proto(reason: RejectReason) -> matching::RejectReason {
+    match reason {
+        RejectReason::UnknownSymbol => matching::RejectReason::UnknownSymbol,
+        RejectReason::InvalidSide => matching::RejectReason::InvalidSide,
+        RejectReason::InvalidOrderType => matching::RejectReason::InvalidOrderType,
+        RejectReason::InvalidPrice => matching::RejectReason::InvalidPrice,
+        RejectReason::InvalidAmount => matching::RejectReason::InvalidAmount,
+        RejectReason::BadTick => matching::RejectReason::BadTick,
+        RejectReason::BadLot => matching::RejectReason::BadLot,
+        RejectReason::ZeroAmount => matching::RejectReason::ZeroAmount,
+        RejectReason::ZeroPrice => matching::RejectReason::ZeroPrice,
+        RejectReason::RiskLimit => matching::RejectReason::RiskLimit,
+        RejectReason::MarketClosed => matching::RejectReason::MarketClosed,
+        RejectReason::DuplicateOrderId => matching::RejectReason::DuplicateOrderId,
+        RejectReason::BatchRejected => matching::RejectReason::BatchRejected,
+        RejectReason::UnknownOrder => matching::RejectReason::UnknownOrder,
+        RejectReason::AmountBelowFilled => matching::RejectReason::AmountBelowFilled,
+    }
+}
+
+fn fills_to_proto(result: &MatchingResult, instrument: &Instrument) -> Vec<Fill> {
+    result
+        .trades
+        .iter()
+        .map(|t| Fill {
+            trade_id: t.id.to_string(),
+            price: instrument.format_price(t.price),
+            amount: instrument.format_qty(t.amount),
+            timestamp: t.timestamp,
+        })
+        .collect()
+}
+
+/// One `OrderSession` stream, bound to the user of its first command.
+/// Commands are handled one at a time and each is answered with an ack or
+/// reject. Fills, and cancels the session did not ask for, come from the
+/// user's execution reports for orders placed on the session, so passive
+/// fills arrive as they happen. Every event carries the next sequence
+/// number.
+struct OrderSession {
+    engine: Arc<MatchingEngine>,
+    execution_reports: Arc<ExecutionReports>,
+    events: mpsc::Sender<Result<SessionEvent, Status>>,
+    sequence: u64,
+    user: Option<String>,
+    reports: Option<mpsc::Receiver<execution::ExecutionReport>>,
+    /// Orders placed on this session that have not closed yet.
+    orders: HashSet<String>,
+    /// Orders the session cancelled, whose cancel report is still to come.
+    cancelling: HashSet<String>,
+}
+
+type SessionClosed = mpsc::error::SendError<Result<SessionEvent, Status>>;
+
+impl OrderSession {
+    fn new(engine: Arc<MatchingEngine>, execution_reports: Arc<ExecutionReports>, events: mpsc::Sender<Result<SessionEvent, Status>>) -> Self {
+        Self {
+            engine,
+            execution_reports,
+            events,
+            sequence: 0,
+            user: None,
+            reports: None,
+            orders: HashSet::new(),
+            cancelling: HashSet::new(),
+        }
+    }
+
+    /// The next execution report for the session's user. Never resolves
+    /// before the first command, and resolves to `None` once the
+    /// subscription was dropped for falling behind.
+    async fn next_report(&mut self) -> Option<execution::ExecutionReport> {
+        match &mut self.reports {
+            Some(reports) => reports.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn handle(&mut self, command: SessionCommand) -> Result<(), SessionClosed> {
+        let Some(command) = command.command else {
+            return self.refuse(String::new(), SessionAction::New, "Empty command".to_string()).await;
+        };
+        let (action, order_id, user_id) = match &command {
+            session_command::Command::Place(req) => (SessionAction::New, &req.order_id, &req.user_id),
+            session_command::Command::Cancel(req) => (SessionAction::Cancel, &req.order_id, &req.user_id),
+            session_command::Command::Amend(req) => (SessionAction::Amend, &req.order_id, &req.user_id),
+        };
+        match &self.user {
+            Some(user) if user != user_id => {
+                let reason = format!("Session is bound to user {}", user);
+                return self.refuse(order_id.clone(), action, reason).await;
+            }
+            Some(_) => {}
+            None => {
+                // Subscribe before the first order so none of its reports
+                // are missed.
+                self.reports = Some(self.execution_reports.subscribe(user_id));
+                self.user = Some(user_id.clone());
+            }
+        }
+
+        match command {
+            session_command::Command::Place(req) => self.place(req).await,
+            session_command::Command::Cancel(req) => self.cancel(req).await,
+            session_command::Command::Amend(req) => self.amend(req).await,
+        }
+    }
+
+    async fn place(&mut self, req: OrderRequest) -> Result<(), SessionClosed> {
+        let order_id = req.order_id.clone();
+        let instrument = self.engine.instrument(&req.symbol);
+
+        let placed = match order_from_request(req, &instrument) {
+            Ok(order) => self.engine.place_order_async(order).await,
+            Err(reason) => Err(reason),
+        };
+        match placed {
+            Ok(result) => {
+                self.orders.insert(order_id.clone());
+                self.ack(order_id, SessionAction::New, result.status).await
+            }
+            Err(reason) => self.reject(order_id, SessionAction::New, reason).await,
+        }
+    }
+
+    /// Whether the order is open and belongs to the session's user. Other
+    /// users' orders are refused as unknown, so their ids are not revealed.
+    fn owns(&self, order_id: &str) -> bool {
+        let owner = self.engine.order_owner(order_id);
+        owner.is_some_and(|owner| self.user.as_deref() == Some(owner.as_str()))
+    }
+
+    async fn cancel(&mut self, req: CancelRequest) -> Result<(), SessionClosed> {
+        if !self.owns(&req.order_id) {
+            return self.reject(req.order_id, SessionAction::Cancel, RejectReason::UnknownOrder).await;
+        }
+        match self.engine.cancel_order_async(&req.order_id).await {
+            Some(order) => {
+                if self.orders.contains(&req.order_id) {
+                    self.cancelling.insert(req.order_id.clone());
+                }
+                self.ack(req.order_id, SessionAction::Cancel, order.status).await
+            }
+            None => self.reject(req.order_id, SessionAction::Cancel, RejectReason::UnknownOrder).await,
+        }
+    }
+
+    async fn amend(&mut self, req: AmendRequest) -> Result<(), SessionClosed> {
+        if !self.owns(&req.order_id) {
+            return self.reject(req.order_id, SessionAction::Amend, RejectReason::UnknownOrder).await;
+        }
+        let instrument = self.engine.instrument(&req.symbol);
+        let result = match amend_from_request(&req, &instrument) {
+            Ok((price, amount)) => self.engine.amend_order_async(&req.order_id, price, amount).await,
+            Err(reason) => Err(reason),
+        };
+
+        match result {
+            Ok(result) => self.ack(req.order_id, SessionAction::Amend, result.status).await,
+            Err(reason) => self.reject(req.order_id, SessionAction::Amend, reason).await,
+        }
+    }
+
+    /// Turns a report on one of the session's orders into a fill or an
+    /// unsolicited cancel. New, amend and expiry reports repeat what the
+    /// command's ack already said.
+    async fn on_report(&mut self, report: execution::ExecutionReport) -> Result<(), SessionClosed> {
+        let order_id = report.order_id.to_string();
+        if !self.orders.contains(&order_id) {
+            return Ok(());
+        }
+        if !report.status.is_open() {
+            self.orders.remove(&order_id);
+        }
+
+        match report.exec_type {
+            execution::ExecType::Trade => {
+                let Some(fill) = &report.last_fill else {
+                    return Ok(());
+                };
+                let instrument = self.engine.instrument(&report.symbol);
+                let fill = Fill {
+                    trade_id: fill.trade_id.to_string(),
+                    price: instrument.format_price(fill.price),
+                    amount: instrument.format_qty(fill.qty),
+                    timestamp: report.timestamp,
+                };
+                self.send(order_id, session_event::Event::Fill(fill)).await
+            }
+            execution::ExecType::Cancelled if !self.cancelling.remove(&order_id) => {
+                self.ack(order_id, SessionAction::Cancel, report.status).await
+            }
+            execution::ExecType::New | execution::ExecType::Amended | execution::ExecType::Cancelled | execution::ExecType::Expired => Ok(()),
+        }
+    }
+
+    async fn ack(&mut self, order_id: String, action: SessionAction, status: types::OrderStatus) -> Result<(), SessionClosed> {
+        let ack = SessionAck {
+            action: action as i32,
+            status: status_to_proto(status) as i32,
+        };
+        self.send(order_id, session_event::Event::Ack(ack)).await
+    }
+
+    async fn reject(&mut self, order_id: String, action: SessionAction, reason: RejectReason) -> Result<(), SessionClosed> {
+        let reject = SessionReject {
+            action: action as i32,
+            reason: reason.to_string(),
+            reject_reason: reject_reason_to_proto(reason) as i32,
+        };
+        self.send(order_id, session_event::Event::Reject(reject)).await
+    }
+
+    /// Rejects a command the engine never saw.
+    async fn refuse(&mut self, order_id: String, action: SessionAction, reason: String) -> Result<(), SessionClosed> {
+        let reject = SessionReject {
+            action: action as i32,
+            reason,
+            reject_reason: matching::RejectReason::Unspecified as i32,
+        };
+        self.send(order_id, session_event::Event::Reject(reject)).await
+    }
+
+    async fn send(&mut self, order_id: String, event: session_event::Event) -> Result<(), SessionClosed> {
+        self.sequence += 1;
+        self.events
+            .send(Ok(SessionEvent {
+                sequence: self.sequence,
+                order_id,
+                event: Some(event),
+            }))
+            .await
+    }
+}
+
+fn amend_from_request(req: &AmendRequest, instrument: &Instrument) -> Result<(Option<Price>, Option<Qty>), RejectReason> {
+    let price = optional_field(&req.price, |v| instrument.parse_price(v))
+        .map_err(|e| e.price_reject())?;
+    let amount = optional_field(&req.amount, |v| instrument.parse_qty(v))
+        .map_err(|e| e.qty_reject())?;
+    Ok((price, amount))
+}
+
+/// Parses an optional decimal field, where an empty string means "unchanged".
+fn optional_field<T, E>(value: &str, parse: impl FnOnce(&str) -> Result<T, E>) -> Result<Option<T>, E> {
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        parse(value).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place(order_id: &str, user_id: &str) -> SessionCommand {
+        SessionCommand {
+            command: Some(session_command::Command::Place(OrderRequest {
+                order_id: order_id.to_string(),
+                user_id: user_id.to_string(),
+                symbol: "BTC-USD".to_string(),
+                side: matching::OrderSide::Sell as i32,
+                r#type: matching::OrderType::Limit as i32,
+                price: "100".to_string(),
+                amount: "1".to_string(),
+                timestamp: 0,
+            })),
+        }
+    }
+
+    fn cancel(order_id: &str, user_id: &str) -> SessionCommand {
+        SessionCommand {
+            command: Some(session_command::Command::Cancel(CancelRequest {
+                order_id: order_id.to_string(),
+                user_id: user_id.to_string(),
+            })),
+        }
+    }
+
+    fn amend(order_id: &str, user_id: &str) -> SessionCommand {
+        SessionCommand {
+            command: Some(session_command::Command::Amend(AmendRequest {
+                order_id: order_id.to_string(),
+                user_id: user_id.to_string(),
+                symbol: "BTC-USD".to_string(),
+                price: "99".to_string(),
+                amount: String::new(),
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sessions_only_touch_their_own_orders() {
+        let engine = Arc::new(MatchingEngine::new());
+        let reports = Arc::new(ExecutionReports::new(64));
+        let (alice_events, mut alice_rx) = mpsc::channel(16);
+        let (mallory_events, mut mallory_rx) = mpsc::channel(16);
+        let mut alice = OrderSession::new(Arc::clone(&engine), Arc::clone(&reports), alice_events);
+        let mut mallory = OrderSession::new(Arc::clone(&engine), Arc::clone(&reports), mallory_events);
+
+        alice.handle(place("a1", "alice")).await.unwrap();
+        assert!(matches!(alice_rx.recv().await.unwrap().unwrap().event, Some(session_event::Event::Ack(_))));
+
+        mallory.handle(place("m1", "mallory")).await.unwrap();
+        mallory_rx.recv().await.unwrap().unwrap();
+        for command in [cancel("a1", "mallory"), amend("a1", "mallory")] {
+            mallory.handle(command).await.unwrap();
+            let event = mallory_rx.recv().await.unwrap().unwrap();
+            let Some(session_event::Event::Reject(reject)) = event.event else {
+                panic!("expected a reject, got {:?}", event);
+            };
+            assert_eq!(reject.reject_reason, matching::RejectReason::UnknownOrder as i32);
+        }
+
+        // Alice's order is untouched, and she can still cancel it.
+        assert_eq!(engine.order_owner("a1").as_deref(), Some("alice"));
+        alice.handle(cancel("a1", "alice")).await.unwrap();
+        let event = alice_rx.recv().await.unwrap().unwrap();
+        assert!(matches!(event.event, Some(session_event::Event::Ack(_))), "{:?}", event);
+    }
+
+    #[tokio::test]
+    async fn test_order_book_depth_is_bounded() {
+        let service = MatchingEngineService::new();
+        let max_depth = service.engine.snapshot_depth() as i32;
+        let book = |depth| {
+            service.get_order_book(Request::new(OrderBookRequest {
+                symbol: "BTC-USD".to_string(),
+                depth,
+            }))
+        };
+
+        for depth in [-1, max_depth + 1] {
+            assert_eq!(book(depth).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        }
+        assert_eq!(book(max_depth).await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,128 @@
+/// Identifiers carried by orders and trades on the matching path.
+///
+/// Order, user and symbol ids are immutable reference-counted strings. The
+/// gateway allocates each one once; copying it into the book, the shard's
+/// order index, trades, events and replies only bumps a count. Each book
+/// interns its symbol, so all orders resting on it share one allocation.
+/// Trade ids are plain numbers, assigned per book in execution order.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Sequence number of a trade within its book, starting at 1.
+pub type TradeId = u64;
+
+macro_rules! shared_id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(Arc<str>);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                Self(Arc::from(value))
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                Self(Arc::from(value))
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                &*self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                &*self.0 == *other
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&*self.0, f)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer).map(Self::from)
+            }
+        }
+    };
+}
+
+shared_id!(
+    /// Client-assigned order id, unique across the engine.
+    OrderId
+);
+shared_id!(UserId);
+shared_id!(
+    /// Instrument symbol, e.g. `BTC-USD`.
+    Symbol
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_clone_shares_allocation() {
+        let id = OrderId::from("o1");
+        let copy = id.clone();
+        assert!(Arc::ptr_eq(&id.0, &copy.0));
+        assert_eq!(copy, "o1");
+    }
+
+    #[test]
+    fn test_map_lookup_by_str() {
+        let mut orders = HashMap::new();
+        orders.insert(OrderId::from("o1"), 1);
+        assert_eq!(orders.get("o1"), Some(&1));
+    }
+}
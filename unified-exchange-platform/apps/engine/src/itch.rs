@@ -0,0 +1,536 @@
+/// Binary market data protocol, in the style of ITCH over MoldUDP64.
+///
+/// Every message has a fixed layout for its type: a type byte, then
+/// big-endian integers with no padding. Prices and quantities are the raw
+/// fixed-point integers, scaled by the decimals in the symbol's
+/// `SymbolDirectory` entry. Order messages name the symbol by its locate
+/// code and the order by its public id. Timestamps are nanoseconds since
+/// the Unix epoch.
+///
+/// | Type | Message           | Fields after the type byte                         |
+/// |------|-------------------|----------------------------------------------------|
+/// | `S`  | `SystemEvent`     | timestamp u64, code u8 (`O` start, `C` end)        |
+/// | `R`  | `SymbolDirectory` | locate u16, symbol [16], price_decimals u8, qty_decimals u8 |
+/// | `A`  | `AddOrder`        | locate u16, timestamp u64, order_ref u64, side u8 (`B`/`S`), amount u64, price u64 |
+/// | `E`  | `OrderExecuted`   | locate u16, timestamp u64, order_ref u64, amount u64, match_number u64 |
+/// | `X`  | `OrderCancel`     | locate u16, timestamp u64, order_ref u64, cancelled u64 |
+/// | `D`  | `OrderDelete`     | locate u16, timestamp u64, order_ref u64           |
+/// | `P`  | `Trade`           | locate u16, timestamp u64, side u8, amount u64, price u64, match_number u64 |
+/// | `G`  | `EndOfSnapshot`   | sequence u64                                       |
+///
+/// Symbols are ASCII, padded with spaces to 16 bytes.
+///
+/// Messages travel in packets. A packet header holds the session [10], the
+/// sequence of its first message (u64) and its message count (u16). Each
+/// message follows, prefixed by its length (u16).
+use crate::fixed_point::{Price, Qty};
+use crate::ids::{Symbol, TradeId};
+use crate::l3::PublicOrderId;
+use crate::types::OrderSide;
+use serde::{Deserialize, Serialize};
+
+pub const SESSION_LEN: usize = 10;
+pub const SYMBOL_LEN: usize = 16;
+pub const PACKET_HEADER_LEN: usize = SESSION_LEN + 8 + 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemEventCode {
+    StartOfMessages,
+    EndOfMessages,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    SystemEvent {
+        timestamp: u64,
+        code: SystemEventCode,
+    },
+    SymbolDirectory {
+        locate: u16,
+        symbol: Symbol,
+        price_decimals: u8,
+        qty_decimals: u8,
+    },
+    AddOrder {
+        locate: u16,
+        timestamp: u64,
+        order_ref: PublicOrderId,
+        side: OrderSide,
+        amount: Qty,
+        price: Price,
+    },
+    OrderExecuted {
+        locate: u16,
+        timestamp: u64,
+        order_ref: PublicOrderId,
+        amount: Qty,
+        match_number: TradeId,
+    },
+    /// Part of an order taken off the book; the rest keeps its priority.
+    OrderCancel {
+        locate: u16,
+        timestamp: u64,
+        order_ref: PublicOrderId,
+        cancelled: Qty,
+    },
+    OrderDelete {
+        locate: u16,
+        timestamp: u64,
+        order_ref: PublicOrderId,
+    },
+    /// A trade, sent after the `OrderExecuted` for the resting order.
+    Trade {
+        locate: u16,
+        timestamp: u64,
+        /// Side of the incoming order.
+        side: OrderSide,
+        amount: Qty,
+        price: Price,
+        match_number: TradeId,
+    },
+    /// Ends a snapshot on the recovery channel. The book is as of
+    /// `sequence`; the multicast stream carries on from `sequence + 1`.
+    EndOfSnapshot { sequence: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("truncated: needed {needed} bytes, had {available}")]
+    Truncated { needed: usize, available: usize },
+    #[error("unknown message type {0:#04x}")]
+    UnknownType(u8),
+    #[error("invalid {field} {value:#04x}")]
+    InvalidField { field: &'static str, value: u8 },
+    #[error("symbol is not ASCII")]
+    InvalidSymbol,
+}
+
+/// Big-endian field reader, shared with the `ouch` order entry protocol.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::Truncated {
+                needed: len,
+                available: self.bytes.len(),
+            });
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn symbol(&mut self) -> Result<Symbol, DecodeError> {
+        let symbol = std::str::from_utf8(self.take(SYMBOL_LEN)?)
+            .ok()
+            .filter(|s| s.is_ascii())
+            .ok_or(DecodeError::InvalidSymbol)?;
+        Ok(Symbol::from(symbol.trim_end_matches(' ')))
+    }
+
+    pub(crate) fn side(&mut self) -> Result<OrderSide, DecodeError> {
+        match self.u8()? {
+            b'B' => Ok(OrderSide::Buy),
+            b'S' => Ok(OrderSide::Sell),
+            value => Err(DecodeError::InvalidField { field: "side", value }),
+        }
+    }
+}
+
+pub(crate) fn side_code(side: OrderSide) -> u8 {
+    match side {
+        OrderSide::Buy => b'B',
+        OrderSide::Sell => b'S',
+    }
+}
+
+/// Appends `symbol` padded with spaces to `SYMBOL_LEN` bytes.
+pub(crate) fn write_symbol(symbol: &str, out: &mut Vec<u8>) {
+    let mut padded = [b' '; SYMBOL_LEN];
+    padded[..symbol.len()].copy_from_slice(symbol.as_bytes());
+    out.extend_from_slice(&padded);
+}
+
+/// Whether `symbol` can appear in a `SymbolDirectory` entry.
+pub fn is_encodable_symbol(symbol: &str) -> bool {
+    symbol.len() <= SYMBOL_LEN && symbol.bytes().all(|b| b.is_ascii_graphic())
+}
+
+impl Message {
+    /// Appends the message to `out`. A symbol must pass
+    /// `is_encodable_symbol`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::SystemEvent { timestamp, code } => {
+                out.push(b'S');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.push(match code {
+                    SystemEventCode::StartOfMessages => b'O',
+                    SystemEventCode::EndOfMessages => b'C',
+                });
+            }
+            Message::SymbolDirectory {
+                locate,
+                symbol,
+                price_decimals,
+                qty_decimals,
+            } => {
+                debug_assert!(is_encodable_symbol(symbol));
+                out.push(b'R');
+                out.extend_from_slice(&locate.to_be_bytes());
+                write_symbol(symbol, out);
+                out.extend_from_slice(&[*price_decimals, *qty_decimals]);
+            }
+            Message::AddOrder {
+                locate,
+                timestamp,
+                order_ref,
+                side,
+                amount,
+                price,
+            } => {
+                out.push(b'A');
+                out.extend_from_slice(&locate.to_be_bytes());
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&order_ref.to_be_bytes());
+                out.push(side_code(*side));
+                out.extend_from_slice(&amount.raw().to_be_bytes());
+                out.extend_from_slice(&price.raw().to_be_bytes());
+            }
+            Message::OrderExecuted {
+                locate,
+                timestamp,
+                order_ref,
+                amount,
+                match_number,
+            } => {
+                out.push(b'E');
+                out.extend_from_slice(&locate.to_be_bytes());
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&order_ref.to_be_bytes());
+                out.extend_from_slice(&amount.raw().to_be_bytes());
+                out.extend_from_slice(&match_number.to_be_bytes());
+            }
+            Message::OrderCancel {
+                locate,
+                timestamp,
+                order_ref,
+                cancelled,
+            } => {
+                out.push(b'X');
+                out.extend_from_slice(&locate.to_be_bytes());
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&order_ref.to_be_bytes());
+                out.extend_from_slice(&cancelled.raw().to_be_bytes());
+            }
+            Message::OrderDelete {
+                locate,
+                timestamp,
+                order_ref,
+            } => {
+                out.push(b'D');
+                out.extend_from_slice(&locate.to_be_bytes());
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&order_ref.to_be_bytes());
+            }
+            Message::Trade {
+                locate,
+                timestamp,
+                side,
+                amount,
+                price,
+                match_number,
+            } => {
+                out.push(b'P');
+                out.extend_from_slice(&locate.to_be_bytes());
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.push(side_code(*side));
+                out.extend_from_slice(&amount.raw().to_be_bytes());
+                out.extend_from_slice(&price.raw().to_be_bytes());
+                out.extend_from_slice(&match_number.to_be_bytes());
+            }
+            Message::EndOfSnapshot { sequence } => {
+                out.push(b'G');
+                out.extend_from_slice(&sequence.to_be_bytes());
+            }
+        }
+    }
+
+    /// Decodes one message. Bytes past its fixed length are ignored, so
+    /// later versions can append fields.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader { bytes };
+        let message = match r.u8()? {
+            b'S' => Message::SystemEvent {
+                timestamp: r.u64()?,
+                code: match r.u8()? {
+                    b'O' => SystemEventCode::StartOfMessages,
+                    b'C' => SystemEventCode::EndOfMessages,
+                    value => return Err(DecodeError::InvalidField { field: "event code", value }),
+                },
+            },
+            b'R' => Message::SymbolDirectory {
+                locate: r.u16()?,
+                symbol: r.symbol()?,
+                price_decimals: r.u8()?,
+                qty_decimals: r.u8()?,
+            },
+            b'A' => Message::AddOrder {
+                locate: r.u16()?,
+                timestamp: r.u64()?,
+                order_ref: r.u64()?,
+                side: r.side()?,
+                amount: Qty::from_raw(r.u64()?),
+                price: Price::from_raw(r.u64()?),
+            },
+            b'E' => Message::OrderExecuted {
+                locate: r.u16()?,
+                timestamp: r.u64()?,
+                order_ref: r.u64()?,
+                amount: Qty::from_raw(r.u64()?),
+                match_number: r.u64()?,
+            },
+            b'X' => Message::OrderCancel {
+                locate: r.u16()?,
+                timestamp: r.u64()?,
+                order_ref: r.u64()?,
+                cancelled: Qty::from_raw(r.u64()?),
+            },
+            b'D' => Message::OrderDelete {
+                locate: r.u16()?,
+                timestamp: r.u64()?,
+                order_ref: r.u64()?,
+            },
+            b'P' => Message::Trade {
+                locate: r.u16()?,
+                timestamp: r.u64()?,
+                side: r.side()?,
+                amount: Qty::from_raw(r.u64()?),
+                price: Price::from_raw(r.u64()?),
+                match_number: r.u64()?,
+            },
+            b'G' => Message::EndOfSnapshot { sequence: r.u64()? },
+            other => return Err(DecodeError::UnknownType(other)),
+        };
+        Ok(message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub session: [u8; SESSION_LEN],
+    /// Sequence of the first message in the packet.
+    pub sequence: u64,
+    pub count: u16,
+}
+
+impl PacketHeader {
+    /// Sequence the packet after this one starts at.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence + u64::from(self.count)
+    }
+}
+
+/// Session name padded with spaces, or cut, to `SESSION_LEN` bytes.
+pub fn session_id(name: &str) -> [u8; SESSION_LEN] {
+    let mut session = [b' '; SESSION_LEN];
+    let len = name.len().min(SESSION_LEN);
+    session[..len].copy_from_slice(&name.as_bytes()[..len]);
+    session
+}
+
+/// Packs consecutive messages into one packet of at most `max_len` bytes.
+pub struct PacketBuilder {
+    buf: Vec<u8>,
+    max_len: usize,
+    count: u16,
+}
+
+impl PacketBuilder {
+    pub fn new(session: [u8; SESSION_LEN], max_len: usize) -> Self {
+        let mut buf = Vec::with_capacity(max_len);
+        buf.extend_from_slice(&session);
+        buf.extend_from_slice(&[0; 10]);
+        Self { buf, max_len, count: 0 }
+    }
+
+    /// Empties the packet. Its first message will be `sequence`.
+    pub fn reset(&mut self, sequence: u64) {
+        self.buf.truncate(PACKET_HEADER_LEN);
+        self.buf[SESSION_LEN..SESSION_LEN + 8].copy_from_slice(&sequence.to_be_bytes());
+        self.count = 0;
+        self.write_count();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Sequence of the message after the last one in the packet.
+    pub fn next_sequence(&self) -> u64 {
+        let sequence = u64::from_be_bytes(self.buf[SESSION_LEN..SESSION_LEN + 8].try_into().unwrap());
+        sequence + u64::from(self.count)
+    }
+
+    /// Adds the next message. Returns false, leaving the packet as it was,
+    /// if the message would not fit; an empty packet takes any message.
+    pub fn push(&mut self, message: &Message) -> bool {
+        if self.count == u16::MAX {
+            return false;
+        }
+        let start = self.buf.len();
+        self.buf.extend_from_slice(&[0, 0]);
+        message.encode(&mut self.buf);
+        if self.buf.len() > self.max_len && self.count > 0 {
+            self.buf.truncate(start);
+            return false;
+        }
+        let len = (self.buf.len() - start - 2) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_be_bytes());
+        self.count += 1;
+        self.write_count();
+        true
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn write_count(&mut self) {
+        self.buf[SESSION_LEN + 8..PACKET_HEADER_LEN].copy_from_slice(&self.count.to_be_bytes());
+    }
+}
+
+/// Decodes a packet and every message in it.
+pub fn decode_packet(bytes: &[u8]) -> Result<(PacketHeader, Vec<Message>), DecodeError> {
+    let mut r = Reader { bytes };
+    let header = PacketHeader {
+        session: r.take(SESSION_LEN)?.try_into().unwrap(),
+        sequence: r.u64()?,
+        count: r.u16()?,
+    };
+    let mut messages = Vec::with_capacity(header.count.into());
+    for _ in 0..header.count {
+        let len = r.u16()?;
+        messages.push(Message::decode(r.take(len.into())?)?);
+    }
+    Ok((header, messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_message() -> Vec<Message> {
+        vec![
+            Message::SystemEvent {
+                timestamp: 1,
+                code: SystemEventCode::StartOfMessages,
+            },
+            Message::SymbolDirectory {
+                locate: 1,
+                symbol: "BTC-USD".into(),
+                price_decimals: 2,
+                qty_decimals: 8,
+            },
+            Message::AddOrder {
+                locate: 1,
+                timestamp: 2,
+                order_ref: 7,
+                side: OrderSide::Buy,
+                amount: Qty::from_raw(300),
+                price: Price::from_raw(10_150),
+            },
+            Message::OrderExecuted {
+                locate: 1,
+                timestamp: 3,
+                order_ref: 7,
+                amount: Qty::from_raw(100),
+                match_number: 42,
+            },
+            Message::OrderCancel {
+                locate: 1,
+                timestamp: 4,
+                order_ref: 7,
+                cancelled: Qty::from_raw(50),
+            },
+            Message::OrderDelete {
+                locate: 1,
+                timestamp: 5,
+                order_ref: 7,
+            },
+            Message::Trade {
+                locate: 1,
+                timestamp: 3,
+                side: OrderSide::Sell,
+                amount: Qty::from_raw(100),
+                price: Price::from_raw(10_150),
+                match_number: 42,
+            },
+            Message::EndOfSnapshot { sequence: 9 },
+        ]
+    }
+
+    #[test]
+    fn test_messages_round_trip_at_fixed_lengths() {
+        let lengths = [10, 21, 36, 35, 27, 19, 36, 9];
+        for (message, len) in every_message().into_iter().zip(lengths) {
+            let mut bytes = Vec::new();
+            message.encode(&mut bytes);
+            assert_eq!(bytes.len(), len, "{:?}", message);
+            assert_eq!(Message::decode(&bytes), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_packet_stops_at_max_len() {
+        let messages = every_message();
+        let mut packet = PacketBuilder::new(session_id("TEST"), 100);
+        packet.reset(5);
+        let taken = messages.iter().take_while(|m| packet.push(m)).count();
+        assert_eq!(taken, 3);
+        assert!(packet.bytes().len() <= 100);
+
+        let (header, decoded) = decode_packet(packet.bytes()).unwrap();
+        assert_eq!((&header.session, header.sequence, header.count), (b"TEST      ", 5, 3));
+        assert_eq!(header.next_sequence(), 8);
+        assert_eq!(decoded, messages[..3]);
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let mut bytes = Vec::new();
+        every_message()[2].encode(&mut bytes);
+        assert_eq!(
+            Message::decode(&bytes[..20]),
+            Err(DecodeError::Truncated {
+                needed: 8,
+                available: 0
+            })
+        );
+        bytes[19] = b'Z';
+        assert_eq!(
+            Message::decode(&bytes),
+            Err(DecodeError::InvalidField {
+                field: "side",
+                value: b'Z'
+            })
+        );
+        assert_eq!(Message::decode(b"Q"), Err(DecodeError::UnknownType(b'Q')));
+    }
+}
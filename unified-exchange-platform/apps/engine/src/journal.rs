@@ -0,0 +1,71 @@
+/// Event journal: appends every engine event to a writer as one JSON line
+/// tagged with its event ring sequence, for audit and replay.
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use serde::Serialize;
+use std::io::Write;
+use tracing::error;
+
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    sequence: u64,
+    event: &'a EngineEvent,
+}
+
+pub struct Journal<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> Journal<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write + Send> EventHandler<EngineEvent> for Journal<W> {
+    fn on_event(&mut self, event: &EngineEvent, sequence: u64, end_of_batch: bool) {
+        let written = serde_json::to_writer(&mut self.writer, &JournalEntry { sequence, event })
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+
+        // Flush once per batch rather than per event.
+        let flushed = if end_of_batch { self.writer.flush() } else { Ok(()) };
+
+        if let Err(e) = written.and(flushed) {
+            error!("Failed to journal event {}: {}", sequence, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed_point::{Price, Qty};
+    use crate::types::{Order, OrderSide, OrderStatus, OrderType};
+
+    #[test]
+    fn test_writes_one_line_per_event() {
+        let order = Order {
+            id: "o1".into(),
+            user_id: "u1".into(),
+            symbol: "BTC-USD".into(),
+            side: OrderSide::Buy,
+            order_type: OrderType::Limit,
+            price: Price::from_raw(100),
+            amount: Qty::from_raw(1),
+            filled: Qty::ZERO,
+            status: OrderStatus::New,
+            timestamp: 0,
+        };
+
+        let mut journal = Journal::new(Vec::new());
+        journal.on_event(&EngineEvent::OrderRested(order.clone()), 7, false);
+        journal.on_event(&EngineEvent::OrderCancelled(order), 8, true);
+
+        let text = String::from_utf8(journal.writer).unwrap();
+        let lines: Vec<serde_json::Value> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["sequence"], 7);
+        assert_eq!(lines[1]["event"]["OrderCancelled"]["id"], "o1");
+    }
+}
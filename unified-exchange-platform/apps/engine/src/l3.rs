@@ -0,0 +1,502 @@
+/// Order-by-order (L3) market data feed.
+///
+/// `L3Publisher` runs as an `EventHandler` and turns every change to a
+/// resting order into an `L3Event`. The feed has four kinds of event:
+///
+/// - `Add`: an order joins the back of its price level.
+/// - `Modify`: an amend reduces an order in place and keeps its priority.
+/// - `Execute`: a resting order is filled.
+/// - `Delete`: an order is cancelled, or amended in a way that loses its
+///   place in the queue.
+///
+/// An amend that requeues the order becomes a `Delete` followed by an `Add`
+/// under a new id.
+///
+/// Orders appear under public ids assigned by the feed, so the feed
+/// reveals neither the client's order id nor the user. Ids are handed out
+/// in arrival order. Within a level, queue priority is therefore id order,
+/// which lets `L3Book` rebuild the exact queues from a snapshot and the
+/// updates after it. Sequencing, gap detection and slow-subscriber handling
+/// work as for the L2 feed in `depth`, and so does retransmission with
+/// `L3Feed::retransmit`.
+use crate::depth::SequenceGap;
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::feed::{evict_idle, publish};
+use crate::fixed_point::{Price, Qty};
+use crate::ids::{OrderId, Symbol, TradeId};
+use crate::retransmit::{RetransmitConfig, RetransmitRing, Retransmission, Sequenced};
+use crate::types::{DepthLevel, OrderSide};
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Public id of a resting order on the L3 feed.
+pub type PublicOrderId = u64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum L3Event {
+    Add {
+        order_id: PublicOrderId,
+        side: OrderSide,
+        price: Price,
+        amount: Qty,
+    },
+    /// `amount` is what is left of the order.
+    Modify { order_id: PublicOrderId, amount: Qty },
+    /// `amount` traded; the order is gone once nothing is left.
+    Execute {
+        order_id: PublicOrderId,
+        amount: Qty,
+        trade_id: TradeId,
+    },
+    Delete { order_id: PublicOrderId },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct L3Update {
+    /// Starts at 1 and increases by one per event on the symbol.
+    pub sequence: u64,
+    pub symbol: Symbol,
+    pub event: L3Event,
+}
+
+impl Sequenced for L3Update {
+    fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct L3Order {
+    pub order_id: PublicOrderId,
+    pub amount: Qty,
+}
+
+/// The orders resting at one price, front of the queue first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct L3Level {
+    pub price: Price,
+    pub orders: Vec<L3Order>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct L3Snapshot {
+    pub symbol: Symbol,
+    pub sequence: u64,
+    /// Best price first on each side.
+    pub bids: Vec<L3Level>,
+    pub asks: Vec<L3Level>,
+}
+
+/// One side's queues: price -> public id -> remaining amount. Ids increase
+/// in arrival order, so each inner map iterates in queue priority.
+type Queues<K> = BTreeMap<K, BTreeMap<PublicOrderId, Qty>>;
+
+/// Every resting order of one book, kept current by applying `L3Update`s
+/// in sequence.
+#[derive(Debug, Clone, Default)]
+pub struct L3Book {
+    symbol: Symbol,
+    sequence: u64,
+    bids: Queues<Reverse<Price>>,
+    asks: Queues<Price>,
+    orders: HashMap<PublicOrderId, (OrderSide, Price)>,
+}
+
+impl L3Book {
+    pub fn new(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &L3Snapshot) -> Self {
+        let mut book = Self::new(snapshot.symbol.clone());
+        book.sequence = snapshot.sequence;
+        for (side, levels) in [(OrderSide::Buy, &snapshot.bids), (OrderSide::Sell, &snapshot.asks)] {
+            for level in levels {
+                for order in &level.orders {
+                    book.insert(order.order_id, side, level.price, order.amount);
+                }
+            }
+        }
+        book
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
+    /// Sequence of the last update applied.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Remaining amount of a resting order.
+    pub fn order(&self, order_id: PublicOrderId) -> Option<Qty> {
+        let &(side, price) = self.orders.get(&order_id)?;
+        self.queue(side, price)?.get(&order_id).copied()
+    }
+
+    /// Applies the next update. An out-of-order update leaves the book
+    /// unchanged.
+    pub fn apply(&mut self, update: &L3Update) -> Result<(), SequenceGap> {
+        if update.sequence != self.sequence + 1 {
+            return Err(SequenceGap {
+                expected: self.sequence + 1,
+                received: update.sequence,
+            });
+        }
+        self.sequence = update.sequence;
+
+        match update.event {
+            L3Event::Add {
+                order_id,
+                side,
+                price,
+                amount,
+            } => self.insert(order_id, side, price, amount),
+            L3Event::Modify { order_id, amount } => {
+                if let Some(remaining) = self.remaining_mut(order_id) {
+                    *remaining = amount;
+                }
+            }
+            L3Event::Execute { order_id, amount, .. } => {
+                if let Some(remaining) = self.remaining_mut(order_id) {
+                    *remaining = remaining.saturating_sub(amount);
+                    if remaining.is_zero() {
+                        self.remove(order_id);
+                    }
+                }
+            }
+            L3Event::Delete { order_id } => self.remove(order_id),
+        }
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> L3Snapshot {
+        fn level(price: Price, queue: &BTreeMap<PublicOrderId, Qty>) -> L3Level {
+            L3Level {
+                price,
+                orders: queue
+                    .iter()
+                    .map(|(&order_id, &amount)| L3Order { order_id, amount })
+                    .collect(),
+            }
+        }
+
+        L3Snapshot {
+            symbol: self.symbol.clone(),
+            sequence: self.sequence,
+            bids: self.bids.iter().map(|(price, queue)| level(price.0, queue)).collect(),
+            asks: self.asks.iter().map(|(&price, queue)| level(price, queue)).collect(),
+        }
+    }
+
+    /// The book aggregated by price, as on the L2 feed.
+    pub fn depth(&self) -> (Vec<DepthLevel>, Vec<DepthLevel>) {
+        fn level(price: Price, queue: &BTreeMap<PublicOrderId, Qty>) -> DepthLevel {
+            DepthLevel {
+                price,
+                amount: queue.values().copied().sum(),
+                order_count: queue.len(),
+            }
+        }
+
+        (
+            self.bids.iter().map(|(price, queue)| level(price.0, queue)).collect(),
+            self.asks.iter().map(|(&price, queue)| level(price, queue)).collect(),
+        )
+    }
+
+    fn queue(&self, side: OrderSide, price: Price) -> Option<&BTreeMap<PublicOrderId, Qty>> {
+        match side {
+            OrderSide::Buy => self.bids.get(&Reverse(price)),
+            OrderSide::Sell => self.asks.get(&price),
+        }
+    }
+
+    fn remaining_mut(&mut self, order_id: PublicOrderId) -> Option<&mut Qty> {
+        let &(side, price) = self.orders.get(&order_id)?;
+        let queue = match side {
+            OrderSide::Buy => self.bids.get_mut(&Reverse(price)),
+            OrderSide::Sell => self.asks.get_mut(&price),
+        }?;
+        queue.get_mut(&order_id)
+    }
+
+    fn insert(&mut self, order_id: PublicOrderId, side: OrderSide, price: Price, amount: Qty) {
+        self.orders.insert(order_id, (side, price));
+        let queue = match side {
+            OrderSide::Buy => self.bids.entry(Reverse(price)).or_default(),
+            OrderSide::Sell => self.asks.entry(price).or_default(),
+        };
+        queue.insert(order_id, amount);
+    }
+
+    fn remove(&mut self, order_id: PublicOrderId) {
+        let Some((side, price)) = self.orders.remove(&order_id) else {
+            return;
+        };
+        match side {
+            OrderSide::Buy => remove_from(&mut self.bids, Reverse(price), order_id),
+            OrderSide::Sell => remove_from(&mut self.asks, price, order_id),
+        }
+    }
+}
+
+fn remove_from<K: Ord>(queues: &mut Queues<K>, price: K, order_id: PublicOrderId) {
+    if let Some(queue) = queues.get_mut(&price) {
+        queue.remove(&order_id);
+        if queue.is_empty() {
+            queues.remove(&price);
+        }
+    }
+}
+
+struct FeedBook {
+    book: L3Book,
+    updates: broadcast::Sender<L3Update>,
+    history: RetransmitRing<L3Update>,
+}
+
+/// L3 books and their update channels, by symbol.
+pub struct L3Feed {
+    books: DashMap<Symbol, Arc<Mutex<FeedBook>>>,
+    capacity: usize,
+    retransmit: RetransmitConfig,
+}
+
+impl L3Feed {
+    /// Each subscriber may fall `capacity` updates behind before it lags.
+    /// No updates are kept for retransmission.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_retransmit(capacity, RetransmitConfig::default())
+    }
+
+    pub fn with_retransmit(capacity: usize, retransmit: RetransmitConfig) -> Self {
+        Self {
+            books: DashMap::new(),
+            capacity,
+            retransmit,
+        }
+    }
+
+    /// Every order resting on `symbol` now and every update after it.
+    pub fn subscribe(&self, symbol: &str) -> (L3Snapshot, broadcast::Receiver<L3Update>) {
+        let book = self.book(symbol);
+        let book = book.lock();
+        (book.book.snapshot(), book.updates.subscribe())
+    }
+
+    /// Updates `from..=to` on `symbol` again.
+    pub fn retransmit(&self, symbol: &str, from: u64, to: u64) -> Retransmission<L3Update> {
+        match self.books.get(symbol).map(|book| Arc::clone(&book)) {
+            Some(book) => {
+                let lookup = book.lock().history.get(from, to);
+                lookup.finish()
+            }
+            None => Retransmission::Messages(Vec::new()),
+        }
+    }
+
+    fn book(&self, symbol: &str) -> Arc<Mutex<FeedBook>> {
+        if let Some(book) = self.books.get(symbol) {
+            return Arc::clone(&book);
+        }
+        // A book only the map holds has no publisher and no subscribers.
+        evict_idle(&self.books, |book| Arc::strong_count(book) == 1 && book.lock().updates.receiver_count() == 0);
+        let book = self.books.entry(Symbol::from(symbol)).or_insert_with(|| {
+            Arc::new(Mutex::new(FeedBook {
+                book: L3Book::new(Symbol::from(symbol)),
+                updates: broadcast::channel(self.capacity).0,
+                history: RetransmitRing::new(&format!("orders-{symbol}"), &self.retransmit),
+            }))
+        });
+        Arc::clone(&book)
+    }
+}
+
+/// Assigns public ids to resting orders and turns engine events into the
+/// `L3Event`s they cause. Shared with the binary feed in `multicast`.
+#[derive(Default)]
+pub(crate) struct PublicOrders {
+    /// Resting order id -> (public id, remaining).
+    resting: HashMap<OrderId, (PublicOrderId, Qty)>,
+    last_public_id: PublicOrderId,
+}
+
+impl PublicOrders {
+    /// Calls `emit` for each L3 event of `event`, in order.
+    pub(crate) fn map(&mut self, event: &EngineEvent, mut emit: impl FnMut(&Symbol, L3Event)) {
+        match event {
+            EngineEvent::OrderRested(order) => {
+                self.last_public_id += 1;
+                let public_id = self.last_public_id;
+                self.resting.insert(order.id.clone(), (public_id, order.remaining()));
+                emit(
+                    &order.symbol,
+                    L3Event::Add {
+                        order_id: public_id,
+                        side: order.side,
+                        price: order.price,
+                        amount: order.remaining(),
+                    },
+                );
+            }
+            EngineEvent::Trade(trade) => {
+                let Some((public_id, remaining)) = self.resting.get_mut(&trade.maker_order_id) else {
+                    return;
+                };
+                let public_id = *public_id;
+                *remaining = remaining.saturating_sub(trade.amount);
+                if remaining.is_zero() {
+                    self.resting.remove(&trade.maker_order_id);
+                }
+                emit(
+                    &trade.symbol,
+                    L3Event::Execute {
+                        order_id: public_id,
+                        amount: trade.amount,
+                        trade_id: trade.id,
+                    },
+                );
+            }
+            EngineEvent::OrderAmended { order, requeued: false } => {
+                if let Some((public_id, remaining)) = self.resting.get_mut(&order.id) {
+                    *remaining = order.remaining();
+                    emit(
+                        &order.symbol,
+                        L3Event::Modify {
+                            order_id: *public_id,
+                            amount: order.remaining(),
+                        },
+                    );
+                }
+            }
+            // A requeued order comes back with a new id when it rests again.
+            EngineEvent::OrderAmended { order, requeued: true } | EngineEvent::OrderCancelled(order) => {
+                if let Some((public_id, _)) = self.resting.remove(&order.id) {
+                    emit(&order.symbol, L3Event::Delete { order_id: public_id });
+                }
+            }
+            EngineEvent::OrderAccepted(_) | EngineEvent::OrderExpired(_) => {}
+        }
+    }
+}
+
+pub struct L3Publisher {
+    feed: Arc<L3Feed>,
+    books: HashMap<Symbol, Arc<Mutex<FeedBook>>>,
+    orders: PublicOrders,
+}
+
+impl L3Publisher {
+    pub fn new(feed: Arc<L3Feed>) -> Self {
+        Self {
+            feed,
+            books: HashMap::new(),
+            orders: PublicOrders::default(),
+        }
+    }
+}
+
+impl EventHandler<EngineEvent> for L3Publisher {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {
+        let Self { feed, books, orders } = self;
+        orders.map(event, |symbol, event| {
+            let book = books.entry(symbol.clone()).or_insert_with(|| feed.book(symbol));
+            let mut book = book.lock();
+
+            let update = L3Update {
+                sequence: book.book.sequence() + 1,
+                symbol: symbol.clone(),
+                event,
+            };
+            book.book.apply(&update).expect("publisher numbers updates in order");
+            book.history.record(&update);
+            publish(&book.updates, update);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, MatchingEngine};
+    use crate::types::test_order;
+
+    fn queue(level: &L3Level) -> Vec<(PublicOrderId, u64)> {
+        level.orders.iter().map(|o| (o.order_id, o.amount.raw())).collect()
+    }
+
+    #[test]
+    fn test_rebuilds_queue_priority() {
+        let feed = Arc::new(L3Feed::new(256));
+        let engine = MatchingEngine::start(
+            EngineConfig::default(),
+            vec![Box::new(L3Publisher::new(Arc::clone(&feed)))],
+        );
+        let (snapshot, mut rx) = feed.subscribe("BTC-USD");
+        let mut client = L3Book::from_snapshot(&snapshot);
+
+        for id in ["a", "b", "c"] {
+            engine.place_order(test_order(id, OrderSide::Sell, 100, 5)).unwrap();
+        }
+        // Shrinking keeps a at the front; growing sends b to the back.
+        engine.amend_order("a", None, Some(Qty::from_raw(4))).unwrap();
+        engine.amend_order("b", None, Some(Qty::from_raw(6))).unwrap();
+        engine.place_order(test_order("t", OrderSide::Buy, 100, 6)).unwrap();
+        engine.place_order(test_order("d", OrderSide::Buy, 90, 1)).unwrap();
+        engine.cancel_order("d");
+        let expected = engine.get_order_book("BTC-USD", 50).unwrap();
+        drop(engine);
+
+        let mut events = Vec::new();
+        while let Ok(update) = rx.try_recv() {
+            client.apply(&update).unwrap();
+            events.push(update.event);
+        }
+        assert!(events.contains(&L3Event::Modify { order_id: 1, amount: Qty::from_raw(4) }));
+        assert!(events.contains(&L3Event::Delete { order_id: 2 }));
+
+        // t takes all of a (id 1) and 2 of c (id 3); b rests behind c as id 4.
+        let book = client.snapshot();
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(queue(&book.asks[0]), vec![(3, 3), (4, 6)]);
+        let (_, asks) = client.depth();
+        assert_eq!(asks, expected.asks);
+    }
+
+    #[test]
+    fn test_public_ids_hide_client_ids() {
+        let feed = Arc::new(L3Feed::new(16));
+        let (_, mut rx) = feed.subscribe("BTC-USD");
+        let mut publisher = L3Publisher::new(Arc::clone(&feed));
+
+        publisher.on_event(&EngineEvent::OrderRested(test_order("secret", OrderSide::Buy, 100, 1)), 0, true);
+
+        let update = rx.try_recv().unwrap();
+        assert_eq!(update.sequence, 1);
+        assert!(matches!(update.event, L3Event::Add { order_id: 1, .. }));
+        assert!(!serde_json::to_string(&update).unwrap().contains("secret"));
+    }
+
+    #[test]
+    fn test_apply_detects_gap() {
+        let mut book = L3Book::new("BTC-USD".into());
+        let update = L3Update {
+            sequence: 3,
+            symbol: "BTC-USD".into(),
+            event: L3Event::Delete { order_id: 1 },
+        };
+        assert_eq!(book.apply(&update), Err(SequenceGap { expected: 1, received: 3 }));
+    }
+}
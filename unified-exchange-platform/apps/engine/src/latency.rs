@@ -0,0 +1,167 @@
+/// In-process latency recording for the order path.
+///
+/// Each matching thread owns a `LatencyRecorder` and records, per command,
+/// the time from submission by the caller to the reply being sent, so the
+/// figure includes ring queueing as well as matching. Reports are built by
+/// merging the per-shard histograms.
+use hdrhistogram::Histogram;
+use std::fmt;
+use std::time::Duration;
+
+/// Latencies above this are clamped; nothing on the order path should take
+/// a second.
+const MAX_TRACKABLE_NANOS: u64 = 1_000_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Place,
+    Cancel,
+    Amend,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 3] = [Operation::Place, Operation::Cancel, Operation::Amend];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Place => "place",
+            Operation::Cancel => "cancel",
+            Operation::Amend => "amend",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LatencyRecorder {
+    histograms: [Histogram<u64>; 3],
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        let histogram = || {
+            Histogram::new_with_bounds(1, MAX_TRACKABLE_NANOS, SIGNIFICANT_DIGITS)
+                .expect("valid histogram bounds")
+        };
+        Self {
+            histograms: [histogram(), histogram(), histogram()],
+        }
+    }
+
+    pub fn record(&mut self, operation: Operation, latency: Duration) {
+        let nanos = (latency.as_nanos() as u64).clamp(1, MAX_TRACKABLE_NANOS);
+        self.histograms[operation as usize].saturating_record(nanos);
+    }
+
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        for (mine, theirs) in self.histograms.iter_mut().zip(&other.histograms) {
+            mine.add(theirs).expect("histograms share bounds");
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            operations: Operation::ALL
+                .iter()
+                .map(|&operation| {
+                    let histogram = &self.histograms[operation as usize];
+                    OperationLatency {
+                        operation,
+                        count: histogram.len(),
+                        p50_nanos: histogram.value_at_quantile(0.50),
+                        p99_nanos: histogram.value_at_quantile(0.99),
+                        p999_nanos: histogram.value_at_quantile(0.999),
+                        max_nanos: histogram.max(),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OperationLatency {
+    pub operation: Operation,
+    pub count: u64,
+    pub p50_nanos: u64,
+    pub p99_nanos: u64,
+    pub p999_nanos: u64,
+    pub max_nanos: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencyReport {
+    pub operations: Vec<OperationLatency>,
+}
+
+impl LatencyReport {
+    pub fn get(&self, operation: Operation) -> &OperationLatency {
+        self.operations
+            .iter()
+            .find(|o| o.operation == operation)
+            .expect("report covers every operation")
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.operations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(
+                f,
+                "{} n={} p50={}ns p99={}ns p99.9={}ns max={}ns",
+                op.operation.name(),
+                op.count,
+                op.p50_nanos,
+                op.p99_nanos,
+                op.p999_nanos,
+                op.max_nanos
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_per_operation() {
+        let mut recorder = LatencyRecorder::new();
+        for micros in 1..=1000 {
+            recorder.record(Operation::Place, Duration::from_micros(micros));
+        }
+        recorder.record(Operation::Cancel, Duration::from_nanos(500));
+
+        let report = recorder.report();
+        let place = report.get(Operation::Place);
+        assert_eq!(place.count, 1000);
+        assert!((499_000..=501_000).contains(&place.p50_nanos));
+        assert!((989_000..=991_000).contains(&place.p99_nanos));
+        assert!(place.p999_nanos >= 998_000);
+        assert_eq!(report.get(Operation::Cancel).count, 1);
+        assert_eq!(report.get(Operation::Amend).count, 0);
+    }
+
+    #[test]
+    fn test_merge_combines_shards() {
+        let mut a = LatencyRecorder::new();
+        let mut b = LatencyRecorder::new();
+        a.record(Operation::Amend, Duration::from_nanos(100));
+        b.record(Operation::Amend, Duration::from_secs(5));
+
+        a.merge(&b);
+        let amend = a.report().get(Operation::Amend).clone();
+        assert_eq!(amend.count, 2);
+        assert!(amend.max_nanos >= MAX_TRACKABLE_NANOS);
+    }
+}
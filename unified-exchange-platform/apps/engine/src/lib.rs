@@ -0,0 +1,31 @@
+pub mod auth;
+pub mod candles;
+pub mod checksum;
+pub mod conflation;
+pub mod depth;
+pub mod disruptor;
+pub mod engine;
+pub mod execution;
+mod feed;
+pub mod fix;
+pub mod fix_gateway;
+mod fix_store;
+pub mod fixed_point;
+pub mod grpc;
+pub mod ids;
+pub mod itch;
+pub mod journal;
+pub mod l3;
+pub mod latency;
+pub mod multicast;
+pub mod order_book;
+pub mod ouch;
+pub mod ouch_gateway;
+pub mod pool;
+pub mod rest;
+pub mod retransmit;
+pub mod stats;
+pub mod ticker;
+pub mod trades;
+pub mod types;
+pub mod ws_gateway;
@@ -1,150 +1,40 @@
-mod types;
-mod engine;
-
-use engine::MatchingEngine;
-use tonic::{transport::Server, Request, Response, Status};
-use tracing::{info, Level};
-use tracing_subscriber;
+use kk99_matching_engine::auth::Credentials;
+use kk99_matching_engine::fix_gateway::{FixAcceptor, FixConfig};
+use kk99_matching_engine::grpc::matching::matching_engine_server::MatchingEngineServer;
+use kk99_matching_engine::grpc::{MatchingEngineService, MULTICAST_GROUP};
+use kk99_matching_engine::multicast;
+use kk99_matching_engine::ouch_gateway::OuchGateway;
+use kk99_matching_engine::rest::{self, RestApi};
+use kk99_matching_engine::ws_gateway::{MarketDataFeeds, WebSocketConfig, WebSocketGateway};
+use tonic::transport::Server;
+use tracing::{error, info, warn, Level};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-// Include generated protobuf code
-pub mod matching {
-    tonic::include_proto!("matching");
-}
+/// Where the binary feed's snapshot and retransmission channel listens.
+const RECOVERY_ADDR: &str = "[::1]:31002";
 
-use matching::{
-    matching_engine_server::{MatchingEngine as MatchingEngineTrait, MatchingEngineServer},
-    OrderRequest, OrderResponse, CancelRequest, CancelResponse,
-    OrderBookRequest, OrderBookResponse, StreamRequest, TradeEvent,
-    Fill, PriceLevel,
-};
+/// Recovery connections served at once.
+const MAX_RECOVERY_CONNECTIONS: usize = 64;
 
-pub struct MatchingEngineService {
-    engine: Arc<MatchingEngine>,
-}
+/// Where the FIX 4.4 order entry gateway listens.
+const FIX_ADDR: &str = "[::1]:9878";
 
-impl MatchingEngineService {
-    pub fn new() -> Self {
-        Self {
-            engine: Arc::new(MatchingEngine::new()),
-
-
-#[tonic::async_trait]
-impl MatchingEngineTrait for MatchingEngineService {
-    async fn place_order(
-        &self,
-        request: Request<OrderRequest>,
-    ) -> Result<Response<OrderResponse>, Status> {
-        let req = request.into_inner();
-        
-        // Convert proto to internal Order
-        let order = types::Order {
-            id: req.order_id,
-            user_id: req.user_id,
-            symbol: req.symbol,
-            side: match req.side {
-                0 => types::OrderSide::Buy,
-                1 => types::OrderSide::Sell,
-                _ => return Err(Status::invalid_argument("Invalid order side")),
-            },
-            order_type: match req.r#type {
-                0 => types::OrderType::Limit,
-                1 => types::OrderType::Market,
-                _ => return Err(Status::invalid_argument("Invalid order type")),
-            },
-            price: req.price.parse().map_err(|_| Status::invalid_argument("Invalid price"))?,
-            amount: req.amount.parse().map_err(|_| Status::invalid_argument("Invalid amount"))?,
-            filled: rust_decimal::Decimal::ZERO,
-            timestamp: req.timestamp,
-        };
-
-        let result = self.engine.place_order(order);
-
-        let fills: Vec<Fill> = result
-            .trades
-            .iter()
-            .map(|t| Fill {
-                trade_id: t.id.clone(),
-                price: t.price.to_string(),
-                amount: t.amount.to_string(),
-                timestamp: t.timestamp,
-            })
-            .collect();
-
-        Ok(Response::new(OrderResponse {
-            success: true,
-            order_id: req.order_id,
-            message: format!("Order placed with {} fills", fills.len()),
-            fills,
-        }))
-    }
+/// CompID of the FIX gateway, sent by counterparties as TargetCompID.
+const FIX_COMP_ID: &str = "KK99";
 
-    async fn cancel_order(
-        &self,
-        request: Request<CancelRequest>,
-    ) -> Result<Response<CancelResponse>, Status> {
-        let req = request.into_inner();
-
-        match self.engine.cancel_order(&req.order_id) {
-            Some(_) => Ok(Response::new(CancelResponse {
-                success: true,
-                message: "Order cancelled".to_string(),
-            })),
-            None => Ok(Response::new(CancelResponse {
-                success: false,
-                message: "Order not found".to_string(),
-            })),
-        }
-    }
+/// Where the binary order entry gateway listens.
+const OUCH_ADDR: &str = "[::1]:9879";
 
-    async fn get_order_book(
-        &self,
-        request: Request<OrderBookRequest>,
-    ) -> Result<Response<OrderBookResponse>, Status> {
-        let req = request.into_inner();
-
-        match self.engine.get_order_book(&req.symbol, req.depth as usize) {
-            Some(book) => {
-                let bids: Vec<PriceLevel> = book
-                    .bids
-                    .iter()
-                    .map(|(price, level)| PriceLevel {
-                        price: price.to_string(),
-                        amount: level.total_amount().to_string(),
-                        order_count: level.orders.len() as i32,
-                    })
-                    .collect();
-
-                let asks: Vec<PriceLevel> = book
-                    .asks
-                    .iter()
-                    .map(|(price, level)| PriceLevel {
-                        price: price.to_string(),
-                        amount: level.total_amount().to_string(),
-                        order_count: level.orders.len() as i32,
-                    })
-                    .collect();
-
-                Ok(Response::new(OrderBookResponse { bids, asks }))
-            }
-            None => Err(Status::not_found("Symbol not found")),
-        }
-    }
+/// Where the REST/JSON API and the WebSocket market data gateway listen.
+const REST_ADDR: &str = "[::1]:8080";
 
-    type StreamTradesStream = tokio_stream::wrappers::ReceiverStream<Result<TradeEvent, Status>>;
-
-    async fn stream_trades(
-        &self,
-        _request: Request<StreamRequest>,
-    ) -> Result<Response<Self::StreamTradesStream>, Status> {
-        let (_tx, rx) = tokio::sync::mpsc::channel(100);
-        
-        // TODO: Implement trade streaming
-        // This would connect to Kafka or internal event system
-        
-        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(rx)))
-    }
-}
+/// How often candles whose interval has ended are closed.
+const CANDLE_CLOSE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often order path latency percentiles are logged.
+const LATENCY_REPORT_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -156,7 +46,94 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let service = MatchingEngineService::new();
 
     info!("🦀 KK99 Rust Matching Engine starting on {}", addr);
-    info!("Sub-microsecond latency order matching ready");
+
+    let engine = Arc::clone(&service.engine);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LATENCY_REPORT_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            info!("Order latency: {}", engine.latency_report_async().await);
+        }
+    });
+
+    let candle_feed = Arc::clone(&service.candle_feed);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CANDLE_CLOSE_INTERVAL);
+        loop {
+            interval.tick().await;
+            candle_feed.close_elapsed(chrono::Utc::now().timestamp_millis());
+        }
+    });
+
+    let recovery = std::net::TcpListener::bind(RECOVERY_ADDR)?;
+    let itch_feed = Arc::clone(&service.itch_feed);
+    info!("Multicast feed on {}, recovery on {}", MULTICAST_GROUP, RECOVERY_ADDR);
+    std::thread::spawn(move || {
+        if let Err(e) = multicast::serve_recovery(recovery, itch_feed, MAX_RECOVERY_CONNECTIONS) {
+            error!("Multicast recovery channel stopped: {}", e);
+        }
+    });
+
+    // USER:PASSWORD pairs for the session gateways; without any, every logon is refused.
+    let credentials = match std::env::var("CREDENTIALS") {
+        Ok(spec) => Credentials::parse(&spec).unwrap_or_else(|e| panic!("Invalid CREDENTIALS: {}", e)),
+        Err(_) => Credentials::new(),
+    };
+    if credentials.is_empty() {
+        warn!("No CREDENTIALS set, FIX and binary order entry logins will be refused");
+    }
+
+    let fix_config = FixConfig {
+        store_dir: std::env::var_os("FIX_STORE_DIR").map(PathBuf::from),
+        credentials: credentials.clone(),
+        ..FixConfig::new(FIX_COMP_ID)
+    };
+    let fix = Arc::new(FixAcceptor::new(
+        Arc::clone(&service.engine),
+        Arc::clone(&service.execution_reports),
+        fix_config,
+    )?);
+    let fix_listener = tokio::net::TcpListener::bind(FIX_ADDR).await?;
+    info!("FIX 4.4 order entry on {}", FIX_ADDR);
+    tokio::spawn(async move {
+        if let Err(e) = fix.serve(fix_listener).await {
+            error!("FIX gateway stopped: {}", e);
+        }
+    });
+
+    let ouch = Arc::new(OuchGateway::new(
+        Arc::clone(&service.engine),
+        Arc::clone(&service.execution_reports),
+        credentials,
+    ));
+    let ouch_listener = tokio::net::TcpListener::bind(OUCH_ADDR).await?;
+    info!("Binary order entry on {}", OUCH_ADDR);
+    tokio::spawn(async move {
+        if let Err(e) = ouch.serve(ouch_listener).await {
+            error!("Binary order entry gateway stopped: {}", e);
+        }
+    });
+
+    let rest = Arc::new(RestApi::new(
+        Arc::clone(&service.engine),
+        Arc::clone(&service.execution_reports),
+        Arc::clone(&service.trade_feed),
+    ));
+    let feeds = MarketDataFeeds {
+        trades: Arc::clone(&service.trade_feed),
+        depth: Arc::clone(&service.depth_feed),
+        tickers: Arc::clone(&service.ticker_feed),
+        candles: Arc::clone(&service.candle_feed),
+    };
+    let ws = Arc::new(WebSocketGateway::new(Arc::clone(&service.engine), feeds, WebSocketConfig::default()));
+    let rest_listener = tokio::net::TcpListener::bind(REST_ADDR).await?;
+    info!("REST API on {}, WebSocket market data on {}/ws", REST_ADDR, REST_ADDR);
+    tokio::spawn(async move {
+        if let Err(e) = rest::serve(rest.router().merge(ws.router()), rest_listener).await {
+            error!("REST API stopped: {}", e);
+        }
+    });
 
     Server::builder()
         .add_service(MatchingEngineServer::new(service))
@@ -164,6 +141,4 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
 
     Ok(())
-}
-    }
 }
@@ -0,0 +1,702 @@
+/// UDP multicast publication of the binary feed in `itch`, with a TCP
+/// channel for snapshots and retransmission.
+///
+/// `ItchPublisher` runs as an `EventHandler`. It turns engine events into
+/// `itch::Message`s and numbers them with one sequence across all symbols.
+/// Messages are packed into datagrams for the group, and a packet goes out
+/// when it is full or a batch ends. Orders carry the same kind of public
+/// ids as on the L3 feed. A symbol's first message is its
+/// `SymbolDirectory` entry, which gives the locate code its messages use.
+/// The feed maps engine events to messages as follows:
+///
+/// - An amend that keeps priority is an `OrderCancel` for the amount taken
+///   off.
+/// - An amend that loses priority is an `OrderDelete`, then an `AddOrder`
+///   under a new id.
+/// - A trade is an `OrderExecuted` for the resting order, followed by a
+///   `Trade` with the price.
+///
+/// UDP drops packets. `MulticastReceiver` finds gaps from the sequence
+/// numbers, and the client fills them over TCP from `serve_recovery`. A
+/// request is one type byte and its payload:
+///
+/// - `S`: a snapshot. The reply is every symbol's directory entry and every
+///   resting order as `AddOrder`s in queue order, then `EndOfSnapshot`.
+///   Each message is prefixed by its length (u16).
+/// - `R` from (u64) count (u16): a retransmission of up to
+///   `MAX_RETRANSMIT` messages. `from` and `count` must both be at least
+///   one; otherwise the connection is closed. The reply is one packet
+///   prefixed by its length (u16). A packet with no messages means the
+///   range has aged out and the client needs a snapshot.
+///
+/// Recovery requests copy what they need out of the feed's state and
+/// encode it after releasing the lock, so they never hold up the
+/// publisher for longer than the copy.
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::ids::Symbol;
+use crate::itch::{self, Message, PacketBuilder, PacketHeader, SystemEventCode, SESSION_LEN};
+use crate::l3::{L3Book, L3Event, L3Update, PublicOrders};
+use crate::retransmit::{RetransmitConfig, RetransmitRing, Retransmission, Sequenced};
+use crate::types::{Instrument, OrderSide};
+use chrono::Utc;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+/// Most messages in one retransmission.
+pub const MAX_RETRANSMIT: u16 = 1000;
+
+#[derive(Debug, Clone)]
+pub struct MulticastConfig {
+    /// Where packets are sent, normally a multicast group.
+    pub group: SocketAddr,
+    /// Local address of the sending socket.
+    pub bind: SocketAddr,
+    /// Multicast hops; one stays on the local network.
+    pub ttl: u32,
+    /// Largest datagram, header included.
+    pub max_packet: usize,
+}
+
+impl MulticastConfig {
+    pub fn new(group: SocketAddr) -> Self {
+        Self {
+            group,
+            bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            ttl: 1,
+            max_packet: 1400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Numbered {
+    sequence: u64,
+    message: Message,
+}
+
+impl Sequenced for Numbered {
+    fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+struct FeedState {
+    /// Sequence of the last message.
+    sequence: u64,
+    /// Locate code per symbol; `None` for a symbol the feed cannot carry.
+    locates: HashMap<Symbol, Option<u16>>,
+    /// Books by locate code - 1.
+    books: Vec<L3Book>,
+    history: RetransmitRing<Numbered>,
+}
+
+impl FeedState {
+    /// Locate code of `symbol`, adding its directory entry to `out` the
+    /// first time.
+    fn locate(&mut self, symbol: &Symbol, instruments: &DashMap<Symbol, Instrument>, out: &mut Vec<Message>) -> Option<u16> {
+        if let Some(&locate) = self.locates.get(symbol) {
+            return locate;
+        }
+        let locate = u16::try_from(self.books.len() + 1)
+            .ok()
+            .filter(|_| itch::is_encodable_symbol(symbol));
+        match locate {
+            Some(locate) => {
+                self.books.push(L3Book::new(symbol.clone()));
+                out.push(directory_entry(locate, symbol, instruments));
+            }
+            None => warn!("Symbol {} cannot be carried on the multicast feed", symbol),
+        }
+        self.locates.insert(symbol.clone(), locate);
+        locate
+    }
+
+    /// Applies `event` to the symbol's book and returns its message.
+    fn apply(&mut self, locate: u16, event: L3Event, timestamp: u64) -> Message {
+        let book = &mut self.books[usize::from(locate) - 1];
+        let message = match event {
+            L3Event::Add {
+                order_id,
+                side,
+                price,
+                amount,
+            } => Message::AddOrder {
+                locate,
+                timestamp,
+                order_ref: order_id,
+                side,
+                amount,
+                price,
+            },
+            L3Event::Modify { order_id, amount } => Message::OrderCancel {
+                locate,
+                timestamp,
+                order_ref: order_id,
+                cancelled: book.order(order_id).map_or(amount, |before| before.saturating_sub(amount)),
+            },
+            L3Event::Execute {
+                order_id,
+                amount,
+                trade_id,
+            } => Message::OrderExecuted {
+                locate,
+                timestamp,
+                order_ref: order_id,
+                amount,
+                match_number: trade_id,
+            },
+            L3Event::Delete { order_id } => Message::OrderDelete {
+                locate,
+                timestamp,
+                order_ref: order_id,
+            },
+        };
+        let update = L3Update {
+            sequence: book.sequence() + 1,
+            symbol: book.symbol().clone(),
+            event,
+        };
+        book.apply(&update).expect("feed numbers updates in order");
+        message
+    }
+
+    fn record(&mut self, message: Message) -> u64 {
+        self.sequence += 1;
+        self.history.record(&Numbered {
+            sequence: self.sequence,
+            message,
+        });
+        self.sequence
+    }
+}
+
+fn directory_entry(locate: u16, symbol: &Symbol, instruments: &DashMap<Symbol, Instrument>) -> Message {
+    let instrument = instruments
+        .get(symbol)
+        .map(|i| i.clone())
+        .unwrap_or_else(|| Instrument::with_default_precision(symbol.clone()));
+    Message::SymbolDirectory {
+        locate,
+        symbol: symbol.clone(),
+        price_decimals: instrument.price_decimals as u8,
+        qty_decimals: instrument.qty_decimals as u8,
+    }
+}
+
+/// State of the binary feed shared between the publisher and the
+/// recovery channel.
+pub struct ItchFeed {
+    session: [u8; SESSION_LEN],
+    instruments: DashMap<Symbol, Instrument>,
+    state: Mutex<FeedState>,
+}
+
+impl ItchFeed {
+    /// `session` names the feed in every packet header, cut to ten bytes.
+    pub fn new(session: &str, retransmit: RetransmitConfig) -> Self {
+        Self {
+            session: itch::session_id(session),
+            instruments: DashMap::new(),
+            state: Mutex::new(FeedState {
+                sequence: 0,
+                locates: HashMap::new(),
+                books: Vec::new(),
+                history: RetransmitRing::new("itch", &retransmit),
+            }),
+        }
+    }
+
+    /// Precision announced for the symbol. Unregistered symbols use the
+    /// engine's default precision, as the engine does.
+    pub fn register_instrument(&self, instrument: Instrument) {
+        self.instruments.insert(instrument.symbol.clone(), instrument);
+    }
+
+    /// Every directory entry and resting order, ending with
+    /// `EndOfSnapshot`.
+    pub fn snapshot(&self) -> Vec<Message> {
+        let (sequence, books) = {
+            let state = self.state.lock();
+            let books: Vec<_> = state.books.iter().map(L3Book::snapshot).collect();
+            (state.sequence, books)
+        };
+        let mut messages = Vec::new();
+        for (index, snapshot) in books.iter().enumerate() {
+            let locate = index as u16 + 1;
+            messages.push(directory_entry(locate, &snapshot.symbol, &self.instruments));
+            for (side, levels) in [(OrderSide::Buy, &snapshot.bids), (OrderSide::Sell, &snapshot.asks)] {
+                for level in levels {
+                    messages.extend(level.orders.iter().map(|order| Message::AddOrder {
+                        locate,
+                        timestamp: 0,
+                        order_ref: order.order_id,
+                        side,
+                        amount: order.amount,
+                        price: level.price,
+                    }));
+                }
+            }
+        }
+        messages.push(Message::EndOfSnapshot { sequence });
+        messages
+    }
+
+    /// Up to `count` messages from `from`, empty if they are no longer
+    /// kept.
+    pub fn retransmit(&self, from: u64, count: u16) -> Vec<Message> {
+        let count = count.min(MAX_RETRANSMIT);
+        if from == 0 || count == 0 {
+            return Vec::new();
+        }
+        let lookup = self.state.lock().history.get(from, from.saturating_add(u64::from(count) - 1));
+        match lookup.finish() {
+            Retransmission::Messages(messages) => messages.into_iter().map(|n| n.message).collect(),
+            Retransmission::Expired { .. } => Vec::new(),
+        }
+    }
+}
+
+pub struct ItchPublisher {
+    feed: Arc<ItchFeed>,
+    socket: UdpSocket,
+    group: SocketAddr,
+    orders: PublicOrders,
+    packet: PacketBuilder,
+    events: Vec<(Symbol, L3Event)>,
+    messages: Vec<Message>,
+}
+
+impl ItchPublisher {
+    /// Binds the sending socket and announces the start of messages.
+    pub fn new(feed: Arc<ItchFeed>, config: &MulticastConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind(config.bind)?;
+        if config.group.ip().is_multicast() {
+            match config.group {
+                SocketAddr::V4(_) => {
+                    socket.set_multicast_ttl_v4(config.ttl)?;
+                    socket.set_multicast_loop_v4(true)?;
+                }
+                SocketAddr::V6(_) => socket.set_multicast_loop_v6(true)?,
+            }
+        }
+        let mut publisher = Self {
+            packet: PacketBuilder::new(feed.session, config.max_packet),
+            feed,
+            socket,
+            group: config.group,
+            orders: PublicOrders::default(),
+            events: Vec::new(),
+            messages: Vec::new(),
+        };
+        publisher.system_event(SystemEventCode::StartOfMessages);
+        Ok(publisher)
+    }
+
+    fn system_event(&mut self, code: SystemEventCode) {
+        let message = Message::SystemEvent { timestamp: now(), code };
+        let sequence = self.feed.state.lock().record(message.clone());
+        self.send(sequence, &message);
+        self.flush();
+    }
+
+    fn send(&mut self, sequence: u64, message: &Message) {
+        if self.packet.is_empty() {
+            self.packet.reset(sequence);
+        }
+        if !self.packet.push(message) {
+            self.flush();
+            self.packet.reset(sequence);
+            self.packet.push(message);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.packet.is_empty() {
+            return;
+        }
+        if let Err(e) = self.socket.send_to(self.packet.bytes(), self.group) {
+            error!("Failed to send multicast packet to {}: {}", self.group, e);
+        }
+        let next = self.packet.next_sequence();
+        self.packet.reset(next);
+    }
+}
+
+impl EventHandler<EngineEvent> for ItchPublisher {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, end_of_batch: bool) {
+        let events = &mut self.events;
+        self.orders.map(event, |symbol, event| events.push((symbol.clone(), event)));
+        let trade = match event {
+            EngineEvent::Trade(trade) => Some(trade),
+            _ => None,
+        };
+
+        if !self.events.is_empty() || trade.is_some() {
+            let timestamp = now();
+            let mut state = self.feed.state.lock();
+            for (symbol, event) in self.events.drain(..) {
+                if let Some(locate) = state.locate(&symbol, &self.feed.instruments, &mut self.messages) {
+                    self.messages.push(state.apply(locate, event, timestamp));
+                }
+            }
+            if let Some(trade) = trade {
+                if let Some(locate) = state.locate(&trade.symbol, &self.feed.instruments, &mut self.messages) {
+                    self.messages.push(Message::Trade {
+                        locate,
+                        timestamp,
+                        side: trade.taker_side,
+                        amount: trade.amount,
+                        price: trade.price,
+                        match_number: trade.id,
+                    });
+                }
+            }
+            let first = state.sequence + 1;
+            for message in &self.messages {
+                state.record(message.clone());
+            }
+            drop(state);
+
+            let messages = std::mem::take(&mut self.messages);
+            for (sequence, message) in (first..).zip(&messages) {
+                self.send(sequence, message);
+            }
+            self.messages = messages;
+            self.messages.clear();
+        }
+
+        if end_of_batch {
+            self.flush();
+        }
+    }
+}
+
+impl Drop for ItchPublisher {
+    fn drop(&mut self) {
+        self.system_event(SystemEventCode::EndOfMessages);
+    }
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp_nanos_opt().unwrap_or(0) as u64
+}
+
+/// Serves snapshot and retransmission requests, one thread per
+/// connection. Beyond `max_connections` at once, new connections are
+/// closed straight away. Returns only if accepting fails.
+pub fn serve_recovery(listener: TcpListener, feed: Arc<ItchFeed>, max_connections: usize) -> io::Result<()> {
+    let open = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (stream, peer) = listener.accept()?;
+        if open.fetch_add(1, Ordering::AcqRel) >= max_connections {
+            open.fetch_sub(1, Ordering::AcqRel);
+            warn!("Refusing recovery connection from {}: {} already open", peer, max_connections);
+            continue;
+        }
+        let (feed, open) = (Arc::clone(&feed), Arc::clone(&open));
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &feed) {
+                warn!("Recovery connection from {} failed: {}", peer, e);
+            }
+            open.fetch_sub(1, Ordering::AcqRel);
+        });
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, feed: &ItchFeed) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut request = [0u8; 1];
+    loop {
+        match stream.read_exact(&mut request) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        match request[0] {
+            b'S' => {
+                let mut out = Vec::new();
+                for message in feed.snapshot() {
+                    write_frame(&mut out, |buf| message.encode(buf));
+                }
+                stream.write_all(&out)?;
+            }
+            b'R' => {
+                let mut args = [0u8; 10];
+                stream.read_exact(&mut args)?;
+                let from = u64::from_be_bytes(args[..8].try_into().unwrap());
+                let count = u16::from_be_bytes(args[8..].try_into().unwrap());
+                if from == 0 || count == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("empty retransmit range {} +{}", from, count),
+                    ));
+                }
+
+                // At most `MAX_RETRANSMIT` messages, well within a frame.
+                let mut packet = PacketBuilder::new(feed.session, u16::MAX.into());
+                packet.reset(from);
+                for message in feed.retransmit(from, count) {
+                    packet.push(&message);
+                }
+                let mut out = Vec::new();
+                write_frame(&mut out, |buf| buf.extend_from_slice(packet.bytes()));
+                stream.write_all(&out)?;
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown request {:#04x}", other),
+                ))
+            }
+        }
+    }
+}
+
+fn write_frame(out: &mut Vec<u8>, body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0, 0]);
+    body(out);
+    let len = u16::try_from(out.len() - start - 2).expect("frame fits a u16 length");
+    out[start..start + 2].copy_from_slice(&len.to_be_bytes());
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut frame = vec![0; u16::from_be_bytes(len).into()];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+fn invalid_data(e: itch::DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Requests a snapshot over a recovery connection. The last message is
+/// `EndOfSnapshot`.
+pub fn request_snapshot(stream: &mut TcpStream) -> io::Result<Vec<Message>> {
+    stream.write_all(b"S")?;
+    let mut messages = Vec::new();
+    loop {
+        let message = Message::decode(&read_frame(stream)?).map_err(invalid_data)?;
+        let done = matches!(message, Message::EndOfSnapshot { .. });
+        messages.push(message);
+        if done {
+            return Ok(messages);
+        }
+    }
+}
+
+/// Requests `count` messages from `from` over a recovery connection. No
+/// messages means the range has aged out.
+pub fn request_retransmit(stream: &mut TcpStream, from: u64, count: u16) -> io::Result<(PacketHeader, Vec<Message>)> {
+    let mut request = [0u8; 11];
+    request[0] = b'R';
+    request[1..9].copy_from_slice(&from.to_be_bytes());
+    request[9..].copy_from_slice(&count.to_be_bytes());
+    stream.write_all(&request)?;
+    itch::decode_packet(&read_frame(stream)?).map_err(invalid_data)
+}
+
+/// A packet from the feed, with any messages missed before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedPacket {
+    pub header: PacketHeader,
+    pub messages: Vec<Message>,
+    /// First and last sequence missed since the previous packet.
+    pub gap: Option<(u64, u64)>,
+}
+
+/// Receives the feed and tracks its sequence.
+pub struct MulticastReceiver {
+    socket: UdpSocket,
+    next_sequence: Option<u64>,
+    buf: Vec<u8>,
+}
+
+impl MulticastReceiver {
+    /// Joins `group` on `interface` (`Ipv4Addr::UNSPECIFIED` for the
+    /// default).
+    pub fn join(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group.port()))?;
+        socket.join_multicast_v4(group.ip(), &interface)?;
+        Ok(Self::from_socket(socket))
+    }
+
+    /// Reads the feed from an already bound socket.
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            next_sequence: None,
+            buf: vec![0; 65536],
+        }
+    }
+
+    /// Waits for the next packet with new messages. Packets wholly before
+    /// the expected sequence are skipped.
+    pub fn recv(&mut self) -> io::Result<FeedPacket> {
+        loop {
+            let len = self.socket.recv(&mut self.buf)?;
+            let (header, mut messages) = itch::decode_packet(&self.buf[..len]).map_err(invalid_data)?;
+            let expected = self.next_sequence.unwrap_or(header.sequence);
+            if messages.is_empty() || header.next_sequence() <= expected {
+                continue;
+            }
+
+            let gap = (header.sequence > expected).then(|| (expected, header.sequence - 1));
+            // Drop the part of an overlapping packet already seen.
+            let seen = expected.saturating_sub(header.sequence) as usize;
+            messages.drain(..seen);
+            self.next_sequence = Some(header.next_sequence());
+            return Ok(FeedPacket { header, messages, gap });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed_point::{Price, Qty};
+    use crate::types::{test_order, Trade};
+    use std::time::Duration;
+
+    fn feed_on_loopback() -> (Arc<ItchFeed>, ItchPublisher, MulticastReceiver) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut config = MulticastConfig::new(socket.local_addr().unwrap());
+        config.bind = "127.0.0.1:0".parse().unwrap();
+
+        let feed = Arc::new(ItchFeed::new("TEST", RetransmitConfig { capacity: 64, disk: None }));
+        feed.register_instrument(Instrument::new("BTC-USD", 2, 4));
+        let publisher = ItchPublisher::new(Arc::clone(&feed), &config).unwrap();
+        (feed, publisher, MulticastReceiver::from_socket(socket))
+    }
+
+    fn events() -> Vec<EngineEvent> {
+        let trade = Trade {
+            id: 9,
+            symbol: "BTC-USD".into(),
+            maker_order_id: "a".into(),
+            taker_order_id: "c".into(),
+            price: Price::from_raw(100),
+            amount: Qty::from_raw(2),
+            taker_side: OrderSide::Sell,
+            timestamp: 0,
+        };
+        let mut amended = test_order("b", OrderSide::Sell, 105, 1);
+        amended.amount = Qty::from_raw(1);
+        vec![
+            EngineEvent::OrderRested(test_order("a", OrderSide::Buy, 100, 5)),
+            EngineEvent::OrderRested(test_order("b", OrderSide::Sell, 105, 4)),
+            EngineEvent::Trade(trade),
+            EngineEvent::OrderAmended {
+                order: amended,
+                requeued: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_publishes_sequenced_packets_on_loopback() {
+        let (_feed, mut publisher, mut receiver) = feed_on_loopback();
+        let start = receiver.recv().unwrap();
+        assert_eq!(start.header.sequence, 1);
+        assert!(matches!(
+            start.messages[..],
+            [Message::SystemEvent {
+                code: SystemEventCode::StartOfMessages,
+                ..
+            }]
+        ));
+
+        let events = events();
+        for (i, event) in events.iter().enumerate() {
+            publisher.on_event(event, i as u64, i == events.len() - 1);
+        }
+        let packet = receiver.recv().unwrap();
+        assert_eq!((packet.header.sequence, packet.gap), (2, None));
+        let kinds: Vec<_> = packet
+            .messages
+            .iter()
+            .map(|m| match m {
+                Message::SymbolDirectory {
+                    price_decimals,
+                    qty_decimals,
+                    ..
+                } => format!("R{price_decimals}{qty_decimals}"),
+                Message::AddOrder { order_ref, .. } => format!("A{order_ref}"),
+                Message::OrderExecuted { order_ref, amount, .. } => format!("E{order_ref}:{}", amount.raw()),
+                Message::Trade { price, .. } => format!("P{}", price.raw()),
+                Message::OrderCancel { order_ref, cancelled, .. } => format!("X{order_ref}:{}", cancelled.raw()),
+                other => format!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(kinds, ["R24", "A1", "A2", "E1:2", "P100", "X2:3"]);
+
+        drop(publisher);
+        let end = receiver.recv().unwrap();
+        assert_eq!(end.header.sequence, 8);
+    }
+
+    #[test]
+    fn test_receiver_reports_gaps() {
+        let (_feed, mut publisher, mut receiver) = feed_on_loopback();
+        receiver.recv().unwrap();
+
+        // A packet lost on the way.
+        let lost = std::mem::replace(&mut publisher.group, "127.0.0.1:9".parse().unwrap());
+        publisher.on_event(&events()[0], 0, true);
+        publisher.group = lost;
+        publisher.on_event(&events()[1], 1, true);
+
+        let packet = receiver.recv().unwrap();
+        assert_eq!(packet.gap, Some((2, 3)));
+        assert_eq!(packet.header.sequence, 4);
+    }
+
+    #[test]
+    fn test_recovery_snapshot_and_retransmit() {
+        let (feed, mut publisher, _receiver) = feed_on_loopback();
+        let events = events();
+        for (i, event) in events.iter().enumerate() {
+            publisher.on_event(event, i as u64, true);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_feed = Arc::clone(&feed);
+        std::thread::spawn(move || serve_recovery(listener, server_feed, 1));
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let snapshot = request_snapshot(&mut stream).unwrap();
+        assert_eq!(snapshot.len(), 4);
+        assert!(matches!(snapshot[1], Message::AddOrder { order_ref: 1, amount, .. } if amount.raw() == 3));
+        assert!(matches!(snapshot[2], Message::AddOrder { order_ref: 2, amount, .. } if amount.raw() == 1));
+        assert_eq!(snapshot[3], Message::EndOfSnapshot { sequence: 7 });
+
+        let (header, messages) = request_retransmit(&mut stream, 4, 2).unwrap();
+        assert_eq!((header.sequence, header.count), (4, 2));
+        assert!(matches!(messages[0], Message::AddOrder { order_ref: 2, .. }));
+
+        // Beyond the retention window nothing comes back.
+        let (header, messages) = request_retransmit(&mut stream, 1_000, 5).unwrap();
+        assert_eq!((header.count, messages.len()), (0, 0));
+        let (header, _) = request_retransmit(&mut stream, u64::MAX, MAX_RETRANSMIT).unwrap();
+        assert_eq!(header.count, 0);
+
+        // Only one connection is served at a time.
+        let mut second = TcpStream::connect(addr).unwrap();
+        assert!(request_snapshot(&mut second).is_err());
+
+        // An empty range closes the connection.
+        assert!(request_retransmit(&mut stream, 4, 0).is_err());
+    }
+}
@@ -1,126 +1,469 @@
-use crate::{Order, Side, Trade};
-use parking_lot::Mutex;
-use std::collections::BTreeMap;
+/// The per-symbol limit order book: price levels in time priority, keyed by
+/// fixed-point price. Matching itself lives in `engine`.
+use crate::fixed_point::{Price, Qty};
+use crate::ids::OrderId;
+use crate::types::{DepthLevel, DepthSnapshot, Instrument, Order, OrderSide};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
 
-pub struct OrderBook {
-    pub symbol: String,
-    buy_orders: BTreeMap<u64, Vec<Order>>, // price (inverted for sorting) -> orders
-    sell_orders: BTreeMap<u64, Vec<Order>>, // price -> orders
+/// Emptied price levels kept per book for reuse, with their capacity.
+const SPARE_LEVELS: usize = 64;
+
+/// Sentinel slot index marking the end of a level's order list.
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+struct OrderNode {
+    order: Order,
+    prev: usize,
+    next: usize,
 }
 
-impl OrderBook {
-    pub fn new(symbol: String) -> Self {
+/// Resting orders at a single price, in time priority.
+///
+/// Orders are stored in a slab of slots threaded into a doubly linked list,
+/// and `index` maps order ids to their slot, so a cancel anywhere in the
+/// queue is a hash lookup plus an O(1) unlink. Freed slots are reused, but
+/// queue position only ever comes from the list links.
+///
+/// The aggregate remaining quantity is cached and adjusted on every add,
+/// fill and removal, and the order count is the size of `index`, so depth
+/// queries never walk the queue. The total saturates at `u64::MAX` lots
+/// rather than wrapping.
+#[derive(Debug, Clone)]
+pub struct PriceLevel {
+    pub price: Price,
+    total: Qty,
+    slots: Vec<Option<OrderNode>>,
+    free: Vec<usize>,
+    index: HashMap<OrderId, usize>,
+    head: usize,
+    tail: usize,
+}
+
+impl PriceLevel {
+    pub fn new(price: Price) -> Self {
         Self {
-            symbol,
-            buy_orders: BTreeMap::new(),
-            sell_orders: BTreeMap::new(),
+            price,
+            total: Qty::ZERO,
+            slots: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            head: NIL,
+            tail: NIL,
         }
     }
 
-    pub fn process_order(&mut self, order: Order) -> Result<Vec<Trade>, String> {
-        let mut trades = Vec::new();
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn total_amount(&self) -> Qty {
+        self.total
+    }
 
-        match order.side {
-            Side::Buy => {
-                // Try to match against sell orders
-                trades.extend(self.match_buy_order(&order)?);
+    /// Appends an order to the back of the queue. An order whose id already
+    /// rests at this level is handed back and the level is left unchanged.
+    pub fn add_order(&mut self, order: Order) -> Result<(), Order> {
+        if self.index.contains_key(&order.id) {
+            return Err(order);
+        }
+        let id = order.id.clone();
+        self.total = self.total.saturating_add(order.remaining());
+        let node = OrderNode {
+            order,
+            prev: self.tail,
+            next: NIL,
+        };
+
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(node);
+                slot
             }
-            Side::Sell => {
-                // Try to match against buy orders
-                trades.extend(self.match_sell_order(&order)?);
+            None => {
+                self.slots.push(Some(node));
+                self.slots.len() - 1
             }
+        };
+
+        match self.tail {
+            NIL => self.head = slot,
+            tail => self.node_mut(tail).next = slot,
         }
+        self.tail = slot;
+        self.index.insert(id, slot);
+        self.debug_check_aggregates();
+        Ok(())
+    }
 
-        Ok(trades)
+    pub fn remove_order(&mut self, order_id: &str) -> Option<Order> {
+        let slot = self.index.remove(order_id)?;
+        let order = self.unlink(slot);
+        self.debug_check_aggregates();
+        Some(order)
     }
 
-    fn match_buy_order(&mut self, order: &Order) -> Result<Vec<Trade>, String> {
-        let mut trades = Vec::new();
-        let mut remaining_qty = order.quantity;
+    /// Lowers an order's total amount in place, keeping its queue position.
+    pub fn reduce_order(&mut self, order_id: &str, amount: Qty) -> Option<&Order> {
+        let slot = *self.index.get(order_id)?;
+        let order = &mut self.node_mut(slot).order;
+        debug_assert!(amount <= order.amount && amount > order.filled);
 
-        // Match against sell orders (lowest prices first)
-        for (price, sell_orders) in self.sell_orders.iter_mut() {
-            if remaining_qty <= 0.0 {
-                break;
-            }
+        let released = order.amount.saturating_sub(amount);
+        order.amount = amount;
+        self.total = self.total.saturating_sub(released);
+        self.debug_check_aggregates();
+        Some(&self.node(slot).order)
+    }
 
-            let fill_price = (*price as f64) / 1_000_000.0; // Convert from micros
-
-            if let Some(sell_order) = sell_orders.first() {
-                if order.price.is_none() || order.price.unwrap() >= fill_price {
-                    let match_qty = remaining_qty.min(sell_order.quantity);
-                    
-                    trades.push(Trade {
-                        id: format!("{}-{}", order.id, sell_order.id),
-                        buy_order_id: order.id.clone(),
-                        sell_order_id: sell_order.id.clone(),
-                        symbol: order.symbol.clone(),
-                        quantity: match_qty,
-                        price: fill_price,
-                        timestamp: order.timestamp,
-                    });
-
-                    remaining_qty -= match_qty;
-                }
-            }
+    pub fn get(&self, order_id: &str) -> Option<&Order> {
+        self.index.get(order_id).map(|&slot| &self.node(slot).order)
+    }
+
+    /// The order with time priority at this level.
+    pub fn front(&self) -> Option<&Order> {
+        match self.head {
+            NIL => None,
+            head => Some(&self.node(head).order),
         }
+    }
 
-        // Add remaining to buy orders if not fully filled
-        if remaining_qty > 0.0 {
-            let price_micros = order.price.map(|p| (p * 1_000_000.0) as u64).unwrap_or(u64::MAX);
-            self.buy_orders.entry(price_micros)
-                .or_insert_with(Vec::new)
-                .push(Order {
-                    quantity: remaining_qty,
-                    ..order.clone()
-                });
+    /// Fills `amount` of the order at the front of the queue and returns it.
+    /// The order stays queued even when fully filled; callers pop it.
+    pub fn fill_front(&mut self, amount: Qty) -> Option<&Order> {
+        let head = self.head;
+        if head == NIL {
+            return None;
         }
+        self.node_mut(head).order.fill(amount);
+        self.total = self.total.saturating_sub(amount);
+        self.debug_check_aggregates();
+        Some(&self.node(head).order)
+    }
 
-        Ok(trades)
+    pub fn pop_front(&mut self) -> Option<Order> {
+        let head = self.head;
+        if head == NIL {
+            return None;
+        }
+        let order = self.unlink(head);
+        self.index.remove(&order.id);
+        self.debug_check_aggregates();
+        Some(order)
     }
 
-    fn match_sell_order(&mut self, order: &Order) -> Result<Vec<Trade>, String> {
-        let mut trades = Vec::new();
-        let mut remaining_qty = order.quantity;
+    /// Iterates orders from oldest to newest.
+    pub fn iter(&self) -> PriceLevelIter<'_> {
+        PriceLevelIter {
+            level: self,
+            cursor: self.head,
+        }
+    }
 
-        // Match against buy orders (highest prices first)
-        for (price, buy_orders) in self.buy_orders.iter_mut().rev() {
-            if remaining_qty <= 0.0 {
-                break;
-            }
+    fn node(&self, slot: usize) -> &OrderNode {
+        self.slots[slot].as_ref().expect("linked slot is occupied")
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut OrderNode {
+        self.slots[slot].as_mut().expect("linked slot is occupied")
+    }
+
+    fn unlink(&mut self, slot: usize) -> Order {
+        let node = self.slots[slot].take().expect("linked slot is occupied");
+
+        match node.prev {
+            NIL => self.head = node.next,
+            prev => self.node_mut(prev).next = node.next,
+        }
+        match node.next {
+            NIL => self.tail = node.prev,
+            next => self.node_mut(next).prev = node.prev,
+        }
 
-            let fill_price = (*price as f64) / 1_000_000.0;
-
-            if let Some(buy_order) = buy_orders.first() {
-                if order.price.is_none() || order.price.unwrap() <= fill_price {
-                    let match_qty = remaining_qty.min(buy_order.quantity);
-                    
-                    trades.push(Trade {
-                        id: format!("{}-{}", buy_order.id, order.id),
-                        buy_order_id: buy_order.id.clone(),
-                        sell_order_id: order.id.clone(),
-                        symbol: order.symbol.clone(),
-                        quantity: match_qty,
-                        price: fill_price,
-                        timestamp: order.timestamp,
-                    });
-
-                    remaining_qty -= match_qty;
+        self.free.push(slot);
+        self.total = self.total.saturating_sub(node.order.remaining());
+        node.order
+    }
+
+    /// Recomputes the aggregates from the queue and compares them with the
+    /// cached values. Compiled out of release builds.
+    fn debug_check_aggregates(&self) {
+        if cfg!(debug_assertions) {
+            let total: Qty = self.iter().map(|o| o.remaining()).sum();
+            let count = self.iter().count();
+            assert_eq!(self.total, total, "cached total drifted at {}", self.price);
+            assert_eq!(self.index.len(), count, "cached count drifted at {}", self.price);
+        }
+    }
+}
+
+pub struct PriceLevelIter<'a> {
+    level: &'a PriceLevel,
+    cursor: usize,
+}
+
+impl<'a> Iterator for PriceLevelIter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cursor == NIL {
+            return None;
+        }
+        let node = self.level.node(self.cursor);
+        self.cursor = node.next;
+        Some(&node.order)
+    }
+}
+
+pub struct OrderBook {
+    pub instrument: Instrument,
+    pub bids: BTreeMap<Price, PriceLevel>, // Buy orders (highest first)
+    pub asks: BTreeMap<Price, PriceLevel>, // Sell orders (lowest first)
+    spare_levels: Vec<PriceLevel>,
+}
+
+impl OrderBook {
+    pub fn new(instrument: Instrument) -> Self {
+        Self {
+            instrument,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            spare_levels: Vec::with_capacity(SPARE_LEVELS),
+        }
+    }
+
+    /// See `PriceLevel::add_order`. Ids are only checked against the level
+    /// at the order's price; the engine keeps them unique across the book.
+    pub fn add_order(&mut self, order: Order) -> Result<(), Order> {
+        let levels = match order.side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        let spare_levels = &mut self.spare_levels;
+        levels
+            .entry(order.price)
+            .or_insert_with(|| match spare_levels.pop() {
+                Some(mut level) => {
+                    level.price = order.price;
+                    level
                 }
+                None => PriceLevel::new(order.price),
+            })
+            .add_order(order)
+    }
+
+    pub fn remove_order(&mut self, order_id: &str, side: OrderSide, price: Price) -> Option<Order> {
+        let levels = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        let order = levels.get_mut(&price)?.remove_order(order_id);
+        self.remove_level_if_empty(side, price);
+        order
+    }
+
+    /// Takes the level at `price` out of the book once its last order has
+    /// gone, keeping it (and its allocated capacity) for the next new level.
+    pub fn remove_level_if_empty(&mut self, side: OrderSide, price: Price) {
+        let levels = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        if levels.get(&price).is_some_and(PriceLevel::is_empty) {
+            let level = levels.remove(&price).expect("level exists");
+            if self.spare_levels.len() < SPARE_LEVELS {
+                self.spare_levels.push(level);
             }
         }
+    }
+
+    /// Aggregated depth for the best `depth` levels per side. Costs
+    /// O(depth) since each level's totals are cached.
+    pub fn depth(&self, depth: usize) -> DepthSnapshot {
+        let mut snapshot = DepthSnapshot::default();
+        self.depth_into(depth, &mut snapshot);
+        snapshot
+    }
+
+    /// Like `depth`, but overwrites `snapshot` in place so its buffers can
+    /// be reused. Leaves `sequence` to the caller.
+    pub fn depth_into(&self, depth: usize, snapshot: &mut DepthSnapshot) {
+        let aggregate = |level: &PriceLevel| DepthLevel {
+            price: level.price,
+            amount: level.total_amount(),
+            order_count: level.len(),
+        };
+
+        snapshot.symbol = self.instrument.symbol.clone();
+        snapshot.bids.clear();
+        snapshot.bids.extend(self.bids.values().rev().take(depth).map(aggregate));
+        snapshot.asks.clear();
+        snapshot.asks.extend(self.asks.values().take(depth).map(aggregate));
+    }
+
+    pub fn best_bid(&self) -> Option<Price> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<Price> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn spread(&self) -> Option<Price> {
+        match (self.best_ask(), self.best_bid()) {
+            (Some(ask), Some(bid)) => ask.checked_sub(bid),
+            _ => None,
+        }
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        match (self.best_ask(), self.best_bid()) {
+            (Some(ask), Some(bid)) => {
+                let decimals = self.instrument.price_decimals;
+                Some((ask.to_decimal(decimals) + bid.to_decimal(decimals)) / Decimal::from(2))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{test_order, OrderStatus};
+
+    fn ids(level: &PriceLevel) -> Vec<&str> {
+        level.iter().map(|o| o.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_cancel_keeps_time_priority() {
+        let mut level = PriceLevel::new(Price::from_raw(100));
+        for id in ["a", "b", "c", "d"] {
+            level.add_order(test_order(id, OrderSide::Buy, 100, 1)).unwrap();
+        }
+
+        assert_eq!(level.remove_order("b").unwrap().id, "b");
+        assert_eq!(level.remove_order("d").unwrap().id, "d");
+        assert!(level.remove_order("b").is_none());
+        assert_eq!(ids(&level), vec!["a", "c"]);
+        assert_eq!(level.len(), 2);
+    }
+
+    #[test]
+    fn test_reused_slot_goes_to_back_of_queue() {
+        let mut level = PriceLevel::new(Price::from_raw(100));
+        for id in ["a", "b", "c"] {
+            level.add_order(test_order(id, OrderSide::Buy, 100, 1)).unwrap();
+        }
+
+        level.remove_order("a");
+        level.add_order(test_order("d", OrderSide::Buy, 100, 1)).unwrap();
+        assert_eq!(ids(&level), vec!["b", "c", "d"]);
+
+        assert_eq!(level.pop_front().unwrap().id, "b");
+        assert_eq!(level.front().unwrap().id, "c");
+        assert!(level.get("b").is_none());
+        assert_eq!(level.total_amount(), Qty::from_raw(2));
+    }
+
+    #[test]
+    fn test_duplicate_id_is_refused() {
+        let mut level = PriceLevel::new(Price::from_raw(100));
+        level.add_order(test_order("a", OrderSide::Buy, 100, 1)).unwrap();
+        level.add_order(test_order("b", OrderSide::Buy, 100, 1)).unwrap();
+
+        let duplicate = level.add_order(test_order("a", OrderSide::Buy, 100, 5)).unwrap_err();
+        assert_eq!(duplicate.amount, Qty::from_raw(5));
+        assert_eq!(ids(&level), vec!["a", "b"]);
+        assert_eq!(level.total_amount(), Qty::from_raw(2));
+
+        level.remove_order("a");
+        assert_eq!(ids(&level), vec!["b"]);
+    }
+
+    #[test]
+    fn test_remove_last_order_empties_level() {
+        let mut level = PriceLevel::new(Price::from_raw(100));
+        level.add_order(test_order("a", OrderSide::Buy, 100, 1)).unwrap();
+        level.remove_order("a");
+
+        assert!(level.is_empty());
+        assert!(level.front().is_none());
+        assert!(level.pop_front().is_none());
+    }
+
+    #[test]
+    fn test_aggregates_follow_fills_and_cancels() {
+        let mut level = PriceLevel::new(Price::from_raw(100));
+        level.add_order(test_order("a", OrderSide::Buy, 100, 5)).unwrap();
+        level.add_order(test_order("b", OrderSide::Buy, 100, 3)).unwrap();
+        assert_eq!(level.total_amount(), Qty::from_raw(8));
+
+        let front = level.fill_front(Qty::from_raw(2)).unwrap();
+        assert_eq!(front.remaining(), Qty::from_raw(3));
+        assert_eq!(front.status, OrderStatus::PartiallyFilled);
+        assert_eq!(level.total_amount(), Qty::from_raw(6));
+
+        level.remove_order("b");
+        assert_eq!(level.total_amount(), Qty::from_raw(3));
+        assert_eq!(level.len(), 1);
+
+        assert_eq!(level.fill_front(Qty::from_raw(3)).unwrap().status, OrderStatus::Filled);
+        level.pop_front();
+        assert_eq!(level.total_amount(), Qty::ZERO);
+    }
+
+    #[test]
+    fn test_emptied_level_is_reused() {
+        let mut book = OrderBook::new(Instrument::with_default_precision("BTC-USD"));
+        book.add_order(test_order("a", OrderSide::Buy, 100, 1)).unwrap();
+        book.remove_order("a", OrderSide::Buy, Price::from_raw(100));
+        assert!(book.bids.is_empty());
+        assert_eq!(book.spare_levels.len(), 1);
+
+        book.add_order(Order {
+            price: Price::from_raw(105),
+            ..test_order("b", OrderSide::Buy, 100, 1)
+        })
+        .unwrap();
+        assert!(book.spare_levels.is_empty());
+        assert_eq!(book.bids[&Price::from_raw(105)].price, Price::from_raw(105));
+        assert_eq!(book.best_bid(), Some(Price::from_raw(105)));
+    }
 
-        // Add remaining to sell orders if not fully filled
-        if remaining_qty > 0.0 {
-            let price_micros = order.price.map(|p| (p * 1_000_000.0) as u64).unwrap_or(0);
-            self.sell_orders.entry(price_micros)
-                .or_insert_with(Vec::new)
-                .push(Order {
-                    quantity: remaining_qty,
-                    ..order.clone()
-                });
+    #[test]
+    fn test_depth_is_best_price_first() {
+        let mut book = OrderBook::new(Instrument::with_default_precision("BTC-USD"));
+        for (id, side, price) in [
+            ("b1", OrderSide::Buy, 98),
+            ("b2", OrderSide::Buy, 99),
+            ("b3", OrderSide::Buy, 99),
+            ("a1", OrderSide::Sell, 102),
+            ("a2", OrderSide::Sell, 101),
+        ] {
+            book.add_order(Order {
+                side,
+                price: Price::from_raw(price),
+                ..test_order(id, OrderSide::Buy, 100, 1)
+            })
+            .unwrap();
         }
 
-        Ok(trades)
+        let depth = book.depth(1);
+        assert_eq!(
+            depth.bids,
+            vec![DepthLevel { price: Price::from_raw(99), amount: Qty::from_raw(2), order_count: 2 }]
+        );
+        assert_eq!(depth.asks[0].price, Price::from_raw(101));
+        assert_eq!(depth.asks.len(), 1);
     }
 }
@@ -0,0 +1,501 @@
+/// Binary order entry protocol, in the style of OUCH.
+///
+/// Messages have a fixed layout per type: a type byte, then big-endian
+/// integers with no padding, as in `itch`. Each message is framed on the
+/// TCP stream by a u16 length. Prices and quantities are the raw fixed-point
+/// integers at the instrument's precision, so nothing is parsed on the way
+/// in. Orders are named by a client-chosen token, unique among the client's
+/// open orders. Timestamps are nanoseconds since the Unix epoch.
+///
+/// Requests, client to engine:
+///
+/// | Type | Message        | Fields after the type byte                          |
+/// |------|----------------|-----------------------------------------------------|
+/// | `L`  | `Login`        | user [16], password [16]                            |
+/// | `O`  | `EnterOrder`   | token u64, symbol [16], side u8 (`B`/`S`), order type u8 (`L`/`M`), price u64, quantity u64 |
+/// | `U`  | `ReplaceOrder` | token u64, new_token u64, price u64, quantity u64   |
+/// | `X`  | `CancelOrder`  | token u64                                           |
+///
+/// Responses, engine to client:
+///
+/// | Type | Message         | Fields after the type byte                         |
+/// |------|-----------------|----------------------------------------------------|
+/// | `L`  | `LoginAccepted` | timestamp u64                                      |
+/// | `A`  | `Accepted`      | timestamp u64, token u64, symbol [16], side u8, price u64, quantity u64 |
+/// | `U`  | `Replaced`      | timestamp u64, token u64, previous_token u64, price u64, quantity u64 |
+/// | `E`  | `Executed`      | timestamp u64, token u64, quantity u64, price u64, match_number u64 |
+/// | `C`  | `Cancelled`     | timestamp u64, token u64, quantity u64, reason u8 (`U` user, `I` no liquidity) |
+/// | `J`  | `Rejected`      | timestamp u64, token u64, reason u8                |
+///
+/// Symbols, users and passwords are ASCII, padded with spaces to 16 bytes. A market
+/// order's price is zero. In `ReplaceOrder`, a zero price or quantity keeps
+/// the current one.
+use crate::fixed_point::{Price, Qty};
+use crate::ids::{Symbol, TradeId, UserId};
+use crate::itch::{side_code, write_symbol, DecodeError, Reader, SYMBOL_LEN};
+use crate::types::{OrderSide, OrderType, RejectReason};
+use serde::{Deserialize, Serialize};
+
+/// Longest message in either direction.
+pub const MAX_MESSAGE_LEN: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// First message on a connection; names the user orders belong to.
+    Login { user: UserId, password: String },
+    EnterOrder {
+        token: u64,
+        symbol: Symbol,
+        side: OrderSide,
+        order_type: OrderType,
+        price: Price,
+        quantity: Qty,
+    },
+    ReplaceOrder {
+        token: u64,
+        new_token: u64,
+        price: Price,
+        quantity: Qty,
+    },
+    CancelOrder { token: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelReason {
+    UserRequested,
+    /// A market order's remainder, after it ran out of liquidity.
+    NoLiquidity,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    LoginAccepted {
+        timestamp: u64,
+    },
+    Accepted {
+        timestamp: u64,
+        token: u64,
+        symbol: Symbol,
+        side: OrderSide,
+        price: Price,
+        quantity: Qty,
+    },
+    /// `quantity` is the new order quantity, including what has filled.
+    Replaced {
+        timestamp: u64,
+        token: u64,
+        previous_token: u64,
+        price: Price,
+        quantity: Qty,
+    },
+    Executed {
+        timestamp: u64,
+        token: u64,
+        quantity: Qty,
+        price: Price,
+        match_number: TradeId,
+    },
+    /// `quantity` is what was taken off the book.
+    Cancelled {
+        timestamp: u64,
+        token: u64,
+        quantity: Qty,
+        reason: CancelReason,
+    },
+    Rejected {
+        timestamp: u64,
+        token: u64,
+        reason: RejectReason,
+    },
+}
+
+/// Wire codes for `RejectReason`.
+const REJECT_CODES: [(RejectReason, u8); 15] = [
+    (RejectReason::UnknownSymbol, b'S'),
+    (RejectReason::InvalidSide, b'D'),
+    (RejectReason::InvalidOrderType, b'T'),
+    (RejectReason::InvalidPrice, b'X'),
+    (RejectReason::InvalidAmount, b'Q'),
+    (RejectReason::BadTick, b'K'),
+    (RejectReason::BadLot, b'L'),
+    (RejectReason::ZeroAmount, b'Z'),
+    (RejectReason::ZeroPrice, b'P'),
+    (RejectReason::RiskLimit, b'R'),
+    (RejectReason::MarketClosed, b'C'),
+    (RejectReason::DuplicateOrderId, b'd'),
+    (RejectReason::BatchRejected, b'B'),
+    (RejectReason::UnknownOrder, b'U'),
+    (RejectReason::AmountBelowFilled, b'F'),
+];
+
+fn reject_code(reason: RejectReason) -> u8 {
+    REJECT_CODES.iter().find(|(r, _)| *r == reason).map(|(_, code)| *code).unwrap()
+}
+
+fn reject_reason(code: u8) -> Result<RejectReason, DecodeError> {
+    REJECT_CODES
+        .iter()
+        .find(|(_, c)| *c == code)
+        .map(|(reason, _)| *reason)
+        .ok_or(DecodeError::InvalidField { field: "reject reason", value: code })
+}
+
+/// Whether `user` fits a `Login`.
+pub fn is_encodable_user(user: &str) -> bool {
+    crate::itch::is_encodable_symbol(user)
+}
+
+/// Whether `password` fits a `Login`.
+pub fn is_encodable_password(password: &str) -> bool {
+    crate::itch::is_encodable_symbol(password)
+}
+
+impl Request {
+    /// Appends the message, without its length prefix, to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Request::Login { user, password } => {
+                debug_assert!(is_encodable_user(user) && is_encodable_password(password));
+                out.push(b'L');
+                write_symbol(user, out);
+                write_symbol(password, out);
+            }
+            Request::EnterOrder {
+                token,
+                symbol,
+                side,
+                order_type,
+                price,
+                quantity,
+            } => {
+                debug_assert!(symbol.len() <= SYMBOL_LEN);
+                out.push(b'O');
+                out.extend_from_slice(&token.to_be_bytes());
+                write_symbol(symbol, out);
+                out.push(side_code(*side));
+                out.push(match order_type {
+                    OrderType::Limit => b'L',
+                    OrderType::Market => b'M',
+                });
+                out.extend_from_slice(&price.raw().to_be_bytes());
+                out.extend_from_slice(&quantity.raw().to_be_bytes());
+            }
+            Request::ReplaceOrder {
+                token,
+                new_token,
+                price,
+                quantity,
+            } => {
+                out.push(b'U');
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&new_token.to_be_bytes());
+                out.extend_from_slice(&price.raw().to_be_bytes());
+                out.extend_from_slice(&quantity.raw().to_be_bytes());
+            }
+            Request::CancelOrder { token } => {
+                out.push(b'X');
+                out.extend_from_slice(&token.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader { bytes };
+        let request = match r.u8()? {
+            b'L' => Request::Login {
+                user: r.symbol()?.as_str().into(),
+                password: r.symbol()?.to_string(),
+            },
+            b'O' => Request::EnterOrder {
+                token: r.u64()?,
+                symbol: r.symbol()?,
+                side: r.side()?,
+                order_type: match r.u8()? {
+                    b'L' => OrderType::Limit,
+                    b'M' => OrderType::Market,
+                    value => return Err(DecodeError::InvalidField { field: "order type", value }),
+                },
+                price: Price::from_raw(r.u64()?),
+                quantity: Qty::from_raw(r.u64()?),
+            },
+            b'U' => Request::ReplaceOrder {
+                token: r.u64()?,
+                new_token: r.u64()?,
+                price: Price::from_raw(r.u64()?),
+                quantity: Qty::from_raw(r.u64()?),
+            },
+            b'X' => Request::CancelOrder { token: r.u64()? },
+            other => return Err(DecodeError::UnknownType(other)),
+        };
+        Ok(request)
+    }
+}
+
+impl Response {
+    /// Appends the message, without its length prefix, to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Response::LoginAccepted { timestamp } => {
+                out.push(b'L');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+            }
+            Response::Accepted {
+                timestamp,
+                token,
+                symbol,
+                side,
+                price,
+                quantity,
+            } => {
+                out.push(b'A');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&token.to_be_bytes());
+                write_symbol(symbol, out);
+                out.push(side_code(*side));
+                out.extend_from_slice(&price.raw().to_be_bytes());
+                out.extend_from_slice(&quantity.raw().to_be_bytes());
+            }
+            Response::Replaced {
+                timestamp,
+                token,
+                previous_token,
+                price,
+                quantity,
+            } => {
+                out.push(b'U');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&previous_token.to_be_bytes());
+                out.extend_from_slice(&price.raw().to_be_bytes());
+                out.extend_from_slice(&quantity.raw().to_be_bytes());
+            }
+            Response::Executed {
+                timestamp,
+                token,
+                quantity,
+                price,
+                match_number,
+            } => {
+                out.push(b'E');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&quantity.raw().to_be_bytes());
+                out.extend_from_slice(&price.raw().to_be_bytes());
+                out.extend_from_slice(&match_number.to_be_bytes());
+            }
+            Response::Cancelled {
+                timestamp,
+                token,
+                quantity,
+                reason,
+            } => {
+                out.push(b'C');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&token.to_be_bytes());
+                out.extend_from_slice(&quantity.raw().to_be_bytes());
+                out.push(match reason {
+                    CancelReason::UserRequested => b'U',
+                    CancelReason::NoLiquidity => b'I',
+                });
+            }
+            Response::Rejected { timestamp, token, reason } => {
+                out.push(b'J');
+                out.extend_from_slice(&timestamp.to_be_bytes());
+                out.extend_from_slice(&token.to_be_bytes());
+                out.push(reject_code(*reason));
+            }
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader { bytes };
+        let response = match r.u8()? {
+            b'L' => Response::LoginAccepted { timestamp: r.u64()? },
+            b'A' => Response::Accepted {
+                timestamp: r.u64()?,
+                token: r.u64()?,
+                symbol: r.symbol()?,
+                side: r.side()?,
+                price: Price::from_raw(r.u64()?),
+                quantity: Qty::from_raw(r.u64()?),
+            },
+            b'U' => Response::Replaced {
+                timestamp: r.u64()?,
+                token: r.u64()?,
+                previous_token: r.u64()?,
+                price: Price::from_raw(r.u64()?),
+                quantity: Qty::from_raw(r.u64()?),
+            },
+            b'E' => Response::Executed {
+                timestamp: r.u64()?,
+                token: r.u64()?,
+                quantity: Qty::from_raw(r.u64()?),
+                price: Price::from_raw(r.u64()?),
+                match_number: r.u64()?,
+            },
+            b'C' => Response::Cancelled {
+                timestamp: r.u64()?,
+                token: r.u64()?,
+                quantity: Qty::from_raw(r.u64()?),
+                reason: match r.u8()? {
+                    b'U' => CancelReason::UserRequested,
+                    b'I' => CancelReason::NoLiquidity,
+                    value => return Err(DecodeError::InvalidField { field: "cancel reason", value }),
+                },
+            },
+            b'J' => Response::Rejected {
+                timestamp: r.u64()?,
+                token: r.u64()?,
+                reason: reject_reason(r.u8()?)?,
+            },
+            other => return Err(DecodeError::UnknownType(other)),
+        };
+        Ok(response)
+    }
+}
+
+/// Reserves the u16 length prefix of the message about to be appended, and
+/// returns where the message starts.
+fn begin_frame(out: &mut Vec<u8>) -> usize {
+    out.extend_from_slice(&[0, 0]);
+    out.len()
+}
+
+fn end_frame(out: &mut [u8], start: usize) {
+    let len = (out.len() - start) as u16;
+    out[start - 2..start].copy_from_slice(&len.to_be_bytes());
+}
+
+/// Appends `request` to `out` with its length prefix.
+pub fn write_request(request: &Request, out: &mut Vec<u8>) {
+    let start = begin_frame(out);
+    request.encode(out);
+    end_frame(out, start);
+}
+
+/// Appends `response` to `out` with its length prefix.
+pub fn write_response(response: &Response, out: &mut Vec<u8>) {
+    let start = begin_frame(out);
+    response.encode(out);
+    end_frame(out, start);
+}
+
+/// The first message in `buf` and the bytes it took with its length prefix,
+/// or `None` until all of it has arrived.
+pub fn next_frame(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let len = usize::from(u16::from_be_bytes(buf.get(..2)?.try_into().unwrap()));
+    buf.get(2..2 + len).map(|message| (message, 2 + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_request(request: Request) {
+        let mut out = Vec::new();
+        write_request(&request, &mut out);
+        let (message, used) = next_frame(&out).unwrap();
+        assert_eq!(used, out.len());
+        assert_eq!(Request::decode(message), Ok(request));
+    }
+
+    fn round_trip_response(response: Response) {
+        let mut out = Vec::new();
+        write_response(&response, &mut out);
+        let (message, _) = next_frame(&out).unwrap();
+        assert!(message.len() <= MAX_MESSAGE_LEN);
+        assert_eq!(Response::decode(message), Ok(response));
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        round_trip_request(Request::Login {
+            user: "alice".into(),
+            password: "s3cret".to_string(),
+        });
+        round_trip_request(Request::EnterOrder {
+            token: 7,
+            symbol: "BTC-USD".into(),
+            side: OrderSide::Sell,
+            order_type: OrderType::Market,
+            price: Price::ZERO,
+            quantity: Qty::from_raw(150),
+        });
+        round_trip_request(Request::ReplaceOrder {
+            token: 7,
+            new_token: 8,
+            price: Price::from_raw(1_000_500),
+            quantity: Qty::ZERO,
+        });
+        round_trip_request(Request::CancelOrder { token: 8 });
+
+        round_trip_response(Response::Accepted {
+            timestamp: 1,
+            token: 7,
+            symbol: "BTC-USD".into(),
+            side: OrderSide::Buy,
+            price: Price::from_raw(1_000_000),
+            quantity: Qty::from_raw(150),
+        });
+        round_trip_response(Response::Executed {
+            timestamp: 2,
+            token: 7,
+            quantity: Qty::from_raw(50),
+            price: Price::from_raw(1_000_000),
+            match_number: 99,
+        });
+        round_trip_response(Response::Cancelled {
+            timestamp: 3,
+            token: 7,
+            quantity: Qty::from_raw(100),
+            reason: CancelReason::NoLiquidity,
+        });
+        for (reason, _) in REJECT_CODES {
+            round_trip_response(Response::Rejected {
+                timestamp: 4,
+                token: 9,
+                reason,
+            });
+        }
+    }
+
+    #[test]
+    fn test_fixed_lengths() {
+        let lengths = [
+            (
+                Request::Login {
+                    user: "alice".into(),
+                    password: "s3cret".to_string(),
+                },
+                33,
+            ),
+            (Request::CancelOrder { token: 1 }, 9),
+            (
+                Request::ReplaceOrder {
+                    token: 1,
+                    new_token: 2,
+                    price: Price::ZERO,
+                    quantity: Qty::ZERO,
+                },
+                33,
+            ),
+        ];
+        for (request, len) in lengths {
+            let mut out = Vec::new();
+            request.encode(&mut out);
+            assert_eq!(out.len(), len, "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn test_partial_frames_wait() {
+        let mut out = Vec::new();
+        write_request(&Request::CancelOrder { token: 1 }, &mut out);
+        assert_eq!(next_frame(&out[..1]), None);
+        assert_eq!(next_frame(&out[..out.len() - 1]), None);
+        assert_eq!(
+            Request::decode(&[b'O', 0, 0]),
+            Err(DecodeError::Truncated { needed: 8, available: 2 })
+        );
+    }
+}
@@ -0,0 +1,585 @@
+/// Binary order entry over TCP, for clients that cannot afford gRPC.
+///
+/// `OuchGateway` serves the `ouch` protocol. A connection starts with a
+/// `Login` naming its user, whose password is checked against the
+/// gateway's `Credentials`, and only one connection per user is allowed at
+/// a time. `EnterOrder` becomes an `Order` with id `{user}-{token}` and goes
+/// straight to `place_order`. `ReplaceOrder` and `CancelOrder` become
+/// `amend_order` and `cancel_order` calls. Responses come from the user's
+/// `ExecutionReports` stream, the same as for FIX and gRPC clients. Requests
+/// the engine refuses are answered with `Rejected` at once.
+///
+/// Tokens are tracked per user and outlive the connection, so an order
+/// entered before a reconnect can still be replaced or cancelled by its
+/// token. Reports produced while the user is logged out are not sent; at
+/// the next login, orders that closed in the meantime are dropped and their
+/// tokens freed. Reports for orders entered through other gateways are not
+/// sent.
+use crate::auth::Credentials;
+use crate::engine::MatchingEngine;
+use crate::execution::{ExecType, ExecutionReport, ExecutionReports};
+use crate::fixed_point::Qty;
+use crate::ids::{OrderId, UserId};
+use crate::itch::is_encodable_symbol;
+use crate::ouch::{next_frame, write_response, CancelReason, Request, Response};
+use crate::types::{Order, OrderStatus, RejectReason};
+use chrono::Utc;
+use parking_lot::Mutex;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// How long a new connection has to send its `Login`.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct OuchGateway {
+    engine: Arc<MatchingEngine>,
+    reports: Arc<ExecutionReports>,
+    credentials: Credentials,
+    /// Users' orders, from their first login on. `None` while one is
+    /// logged in.
+    users: Mutex<HashMap<UserId, Option<UserOrders>>>,
+}
+
+impl OuchGateway {
+    pub fn new(engine: Arc<MatchingEngine>, reports: Arc<ExecutionReports>, credentials: Credentials) -> Self {
+        Self {
+            engine,
+            reports,
+            credentials,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accepts connections until the listener fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let gateway = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = gateway.connection(stream).await {
+                    warn!("Order entry connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn connection(self: Arc<Self>, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let (mut reader, writer) = stream.into_split();
+        let mut buf = Vec::with_capacity(4096);
+
+        let login = tokio::time::timeout(LOGIN_TIMEOUT, async {
+            loop {
+                if let Some((message, used)) = next_frame(&buf) {
+                    let request = Request::decode(message);
+                    buf.drain(..used);
+                    return request.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                if reader.read_buf(&mut buf).await? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no Login"))??;
+        let Request::Login { user, password } = login else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "first message is not a Login"));
+        };
+        if !self.credentials.verify(&user, &password) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("invalid credentials for {:?}", user)));
+        }
+        let Some(user_orders) = self.checkout(&user) else {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{:?} is already logged in", user)));
+        };
+
+        info!("Order entry session for {} logged in", user);
+        let mut connection = Connection {
+            reports: self.reports.subscribe(&user),
+            gateway: Arc::clone(&self),
+            user: user.clone(),
+            writer,
+            out: Vec::with_capacity(4096),
+            user_orders,
+        };
+        let result = connection.run(reader, buf).await;
+        info!("Order entry session for {} closed", user);
+        result
+    }
+
+    /// Takes a user's orders for a new connection, or `None` if the user
+    /// is already logged in. Orders that closed while the user was away
+    /// are dropped.
+    fn checkout(&self, user: &UserId) -> Option<UserOrders> {
+        let mut orders = match self.users.lock().entry(user.clone()) {
+            Entry::Occupied(mut entry) => entry.get_mut().take()?,
+            Entry::Vacant(entry) => {
+                entry.insert(None);
+                return Some(UserOrders::default());
+            }
+        };
+        let UserOrders { tokens, orders: by_id } = &mut orders;
+        by_id.retain(|order_id, order_tokens| {
+            // The report for a replace made just before the disconnect is lost.
+            if let Some(previous) = order_tokens.previous.take() {
+                tokens.remove(&previous);
+            }
+            let open = self.reports.order_status(order_id).is_some_and(|report| report.status.is_open());
+            if !open {
+                tokens.remove(&order_tokens.token);
+            }
+            open
+        });
+        Some(orders)
+    }
+}
+
+fn now_nanos() -> u64 {
+    Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64
+}
+
+/// An order's tokens.
+struct Tokens {
+    token: u64,
+    /// The token before a replace that has not been reported yet.
+    previous: Option<u64>,
+}
+
+/// A user's open orders by token and by id.
+#[derive(Default)]
+struct UserOrders {
+    tokens: HashMap<u64, OrderId>,
+    orders: HashMap<OrderId, Tokens>,
+}
+
+struct Connection {
+    gateway: Arc<OuchGateway>,
+    user: UserId,
+    writer: OwnedWriteHalf,
+    /// Responses waiting to be written.
+    out: Vec<u8>,
+    user_orders: UserOrders,
+    reports: mpsc::Receiver<ExecutionReport>,
+}
+
+/// Hands the user's orders back to the gateway however the connection
+/// ends, panics included, so the user can log in again.
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let orders = std::mem::take(&mut self.user_orders);
+        self.gateway.users.lock().insert(self.user.clone(), Some(orders));
+    }
+}
+
+impl Connection {
+    async fn run(&mut self, mut reader: OwnedReadHalf, mut buf: Vec<u8>) -> io::Result<()> {
+        self.respond(Response::LoginAccepted { timestamp: now_nanos() });
+        self.flush().await?;
+
+        loop {
+            tokio::select! {
+                read = reader.read_buf(&mut buf) => {
+                    if read? == 0 {
+                        return Ok(());
+                    }
+                    let mut used = 0;
+                    while let Some((message, len)) = next_frame(&buf[used..]) {
+                        let request = Request::decode(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                        used += len;
+                        self.on_request(request).await;
+                    }
+                    buf.drain(..used);
+                }
+                report = self.reports.recv() => {
+                    let Some(report) = report else {
+                        return Err(io::Error::other("execution reports fell behind"));
+                    };
+                    self.on_report(report);
+                    while let Ok(report) = self.reports.try_recv() {
+                        self.on_report(report);
+                    }
+                }
+            }
+            self.flush().await?;
+        }
+    }
+
+    fn respond(&mut self, response: Response) {
+        write_response(&response, &mut self.out);
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if !self.out.is_empty() {
+            self.writer.write_all(&self.out).await?;
+            self.out.clear();
+        }
+        Ok(())
+    }
+
+    fn reject(&mut self, token: u64, reason: RejectReason) {
+        self.respond(Response::Rejected {
+            timestamp: now_nanos(),
+            token,
+            reason,
+        });
+    }
+
+    async fn on_request(&mut self, request: Request) {
+        match request {
+            Request::Login { .. } => warn!("Ignoring repeated Login from {}", self.user),
+            Request::EnterOrder {
+                token,
+                symbol,
+                side,
+                order_type,
+                price,
+                quantity,
+            } => {
+                if self.user_orders.tokens.contains_key(&token) {
+                    return self.reject(token, RejectReason::DuplicateOrderId);
+                }
+                let order_id = OrderId::from(format!("{}-{}", self.user, token));
+                self.user_orders.tokens.insert(token, order_id.clone());
+                self.user_orders.orders.insert(order_id.clone(), Tokens { token, previous: None });
+
+                let order = Order {
+                    id: order_id.clone(),
+                    user_id: self.user.clone(),
+                    symbol,
+                    side,
+                    order_type,
+                    price,
+                    amount: quantity,
+                    filled: Qty::ZERO,
+                    status: OrderStatus::New,
+                    timestamp: Utc::now().timestamp_millis(),
+                };
+                if let Err(reason) = self.gateway.engine.place_order_async(order).await {
+                    self.user_orders.tokens.remove(&token);
+                    self.user_orders.orders.remove(&order_id);
+                    self.reject(token, reason);
+                }
+            }
+            Request::ReplaceOrder {
+                token,
+                new_token,
+                price,
+                quantity,
+            } => {
+                let Some(order_id) = self.user_orders.tokens.get(&token).cloned() else {
+                    return self.reject(new_token, RejectReason::UnknownOrder);
+                };
+                if self.user_orders.tokens.contains_key(&new_token) {
+                    return self.reject(new_token, RejectReason::DuplicateOrderId);
+                }
+                let price = (!price.is_zero()).then_some(price);
+                let quantity = (!quantity.is_zero()).then_some(quantity);
+                match self.gateway.engine.amend_order_async(&order_id, price, quantity).await {
+                    Ok(_) => {
+                        self.user_orders.tokens.insert(new_token, order_id.clone());
+                        if let Some(tokens) = self.user_orders.orders.get_mut(&order_id) {
+                            tokens.previous = Some(tokens.token);
+                            tokens.token = new_token;
+                        }
+                    }
+                    Err(reason) => self.reject(new_token, reason),
+                }
+            }
+            Request::CancelOrder { token } => {
+                let cancelled = match self.user_orders.tokens.get(&token) {
+                    Some(order_id) => self.gateway.engine.cancel_order_async(order_id).await,
+                    None => None,
+                };
+                if cancelled.is_none() {
+                    self.reject(token, RejectReason::UnknownOrder);
+                }
+            }
+        }
+    }
+
+    /// Token to report `order_id` under, if it is one of this user's OUCH
+    /// orders.
+    fn token(&mut self, order_id: &OrderId) -> Option<(u64, Option<u64>)> {
+        if let Some(tokens) = self.user_orders.orders.get_mut(order_id) {
+            return Some((tokens.token, tokens.previous.take()));
+        }
+        let token = order_id.strip_prefix(self.user.as_str())?.strip_prefix('-')?.parse().ok()?;
+        Some((token, None))
+    }
+
+    fn on_report(&mut self, report: ExecutionReport) {
+        let Some((token, previous)) = self.token(&report.order_id) else {
+            return;
+        };
+        let timestamp = report.timestamp as u64 * 1_000_000;
+        let response = match report.exec_type {
+            ExecType::New if is_encodable_symbol(&report.symbol) => Some(Response::Accepted {
+                timestamp,
+                token,
+                symbol: report.symbol.clone(),
+                side: report.side,
+                price: report.price,
+                quantity: report.amount,
+            }),
+            ExecType::New => None,
+            ExecType::Trade => report.last_fill.as_ref().map(|fill| Response::Executed {
+                timestamp,
+                token,
+                quantity: fill.qty,
+                price: fill.price,
+                match_number: fill.trade_id,
+            }),
+            ExecType::Amended => {
+                if let Some(previous) = previous {
+                    self.user_orders.tokens.remove(&previous);
+                }
+                Some(Response::Replaced {
+                    timestamp,
+                    token,
+                    previous_token: previous.unwrap_or(token),
+                    price: report.price,
+                    quantity: report.amount,
+                })
+            }
+            ExecType::Cancelled | ExecType::Expired => Some(Response::Cancelled {
+                timestamp,
+                token,
+                quantity: report.amount.saturating_sub(report.cum_qty),
+                reason: if report.exec_type == ExecType::Cancelled {
+                    CancelReason::UserRequested
+                } else {
+                    CancelReason::NoLiquidity
+                },
+            }),
+        };
+
+        if !report.status.is_open() {
+            if let Some(tokens) = self.user_orders.orders.remove(&report.order_id) {
+                self.user_orders.tokens.remove(&tokens.token);
+                if let Some(previous) = tokens.previous {
+                    self.user_orders.tokens.remove(&previous);
+                }
+            }
+        }
+        if let Some(response) = response {
+            self.respond(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+    use crate::execution::ExecutionReporter;
+    use crate::fixed_point::Price;
+    use crate::ouch::write_request;
+    use crate::types::{OrderSide, OrderType};
+    use std::net::SocketAddr;
+
+    const PASSWORD: &str = "pw";
+
+    async fn start() -> SocketAddr {
+        let reports = Arc::new(ExecutionReports::new(64));
+        let engine = MatchingEngine::start(EngineConfig::default(), vec![Box::new(ExecutionReporter::new(Arc::clone(&reports)))]);
+        let credentials = ["maker", "taker", "alice"]
+            .into_iter()
+            .fold(Credentials::new(), |credentials, user| credentials.with_user(user, PASSWORD));
+        let gateway = Arc::new(OuchGateway::new(Arc::new(engine), reports, credentials));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(gateway.serve(listener));
+        addr
+    }
+
+    struct Client {
+        stream: TcpStream,
+        buf: Vec<u8>,
+    }
+
+    impl Client {
+        async fn login(addr: SocketAddr, user: &str) -> Self {
+            let mut client = Self {
+                stream: TcpStream::connect(addr).await.unwrap(),
+                buf: Vec::new(),
+            };
+            client.send(login(user, PASSWORD)).await;
+            assert!(matches!(client.recv().await, Response::LoginAccepted { .. }));
+            client
+        }
+
+        async fn send(&mut self, request: Request) {
+            let mut out = Vec::new();
+            write_request(&request, &mut out);
+            self.stream.write_all(&out).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> Response {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Some((message, used)) = next_frame(&self.buf) {
+                        let response = Response::decode(message).unwrap();
+                        self.buf.drain(..used);
+                        return response;
+                    }
+                    assert!(self.stream.read_buf(&mut self.buf).await.unwrap() > 0, "gateway closed the connection");
+                }
+            })
+            .await
+            .expect("no response from the gateway")
+        }
+    }
+
+    fn login(user: &str, password: &str) -> Request {
+        Request::Login {
+            user: user.into(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Whether the gateway closes a connection that logs in with `request`.
+    async fn is_refused(addr: SocketAddr, request: Request) -> bool {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut out = Vec::new();
+        write_request(&request, &mut out);
+        stream.write_all(&out).await.unwrap();
+        let mut buf = [0u8; 64];
+        stream.read(&mut buf).await.unwrap() == 0
+    }
+
+    fn enter(token: u64, side: OrderSide, price: u64, quantity: u64) -> Request {
+        Request::EnterOrder {
+            token,
+            symbol: "BTC-USD".into(),
+            side,
+            order_type: OrderType::Limit,
+            price: Price::from_raw(price),
+            quantity: Qty::from_raw(quantity),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enter_execute_replace_cancel() {
+        let addr = start().await;
+        let mut maker = Client::login(addr, "maker").await;
+        maker.send(enter(1, OrderSide::Sell, 1_000, 10)).await;
+        assert!(matches!(maker.recv().await, Response::Accepted { token: 1, .. }));
+
+        let mut taker = Client::login(addr, "taker").await;
+        taker.send(enter(1, OrderSide::Buy, 1_000, 4)).await;
+        assert!(matches!(taker.recv().await, Response::Accepted { token: 1, .. }));
+        let executed = |response: Response| matches!(response, Response::Executed { token: 1, quantity, .. } if quantity == Qty::from_raw(4));
+        assert!(executed(taker.recv().await));
+        assert!(executed(maker.recv().await));
+
+        maker
+            .send(Request::ReplaceOrder {
+                token: 1,
+                new_token: 2,
+                price: Price::ZERO,
+                quantity: Qty::from_raw(8),
+            })
+            .await;
+        match maker.recv().await {
+            Response::Replaced {
+                token,
+                previous_token,
+                price,
+                quantity,
+                ..
+            } => {
+                assert_eq!((token, previous_token), (2, 1));
+                assert_eq!((price.raw(), quantity.raw()), (1_000, 8));
+            }
+            other => panic!("expected Replaced, got {:?}", other),
+        }
+
+        maker.send(Request::CancelOrder { token: 1 }).await;
+        assert!(matches!(
+            maker.recv().await,
+            Response::Rejected { token: 1, reason: RejectReason::UnknownOrder, .. }
+        ));
+        maker.send(Request::CancelOrder { token: 2 }).await;
+        assert!(matches!(
+            maker.recv().await,
+            Response::Cancelled { token: 2, quantity, reason: CancelReason::UserRequested, .. } if quantity == Qty::from_raw(4)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_and_duplicate_tokens() {
+        let addr = start().await;
+        let mut client = Client::login(addr, "alice").await;
+        client.send(enter(1, OrderSide::Buy, 0, 10)).await;
+        assert!(matches!(
+            client.recv().await,
+            Response::Rejected { token: 1, reason: RejectReason::ZeroPrice, .. }
+        ));
+
+        client.send(enter(2, OrderSide::Buy, 500, 10)).await;
+        client.send(enter(2, OrderSide::Buy, 500, 10)).await;
+        let responses = [client.recv().await, client.recv().await];
+        assert!(responses.iter().any(|r| matches!(r, Response::Accepted { token: 2, .. })));
+        assert!(responses
+            .iter()
+            .any(|r| matches!(r, Response::Rejected { token: 2, reason: RejectReason::DuplicateOrderId, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_one_connection_per_user() {
+        let addr = start().await;
+        let first = Client::login(addr, "alice").await;
+        assert!(is_refused(addr, login("alice", PASSWORD)).await);
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let _second = Client::login(addr, "alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_login_requires_password() {
+        let addr = start().await;
+        assert!(is_refused(addr, login("alice", "wrong")).await);
+        assert!(is_refused(addr, login("mallory", PASSWORD)).await);
+        let _alice = Client::login(addr, "alice").await;
+    }
+
+    #[tokio::test]
+    async fn test_tokens_survive_reconnect() {
+        let addr = start().await;
+        let mut client = Client::login(addr, "alice").await;
+        client.send(enter(1, OrderSide::Buy, 500, 10)).await;
+        client.send(enter(2, OrderSide::Buy, 500, 10)).await;
+        assert!(matches!(client.recv().await, Response::Accepted { token: 1, .. }));
+        assert!(matches!(client.recv().await, Response::Accepted { token: 2, .. }));
+        client.send(Request::CancelOrder { token: 2 }).await;
+        assert!(matches!(client.recv().await, Response::Cancelled { token: 2, .. }));
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut client = Client::login(addr, "alice").await;
+        client.send(enter(1, OrderSide::Buy, 500, 10)).await;
+        assert!(matches!(
+            client.recv().await,
+            Response::Rejected { token: 1, reason: RejectReason::DuplicateOrderId, .. }
+        ));
+        client
+            .send(Request::ReplaceOrder {
+                token: 1,
+                new_token: 3,
+                price: Price::from_raw(600),
+                quantity: Qty::ZERO,
+            })
+            .await;
+        assert!(matches!(client.recv().await, Response::Replaced { token: 3, previous_token: 1, .. }));
+        client.send(Request::CancelOrder { token: 3 }).await;
+        assert!(matches!(client.recv().await, Response::Cancelled { token: 3, .. }));
+    }
+}
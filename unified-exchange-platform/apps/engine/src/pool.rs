@@ -0,0 +1,89 @@
+/// Buffer pool for `MatchingResult`s.
+///
+/// A matching thread hands each result to another thread, so it cannot
+/// reuse the buffers itself. Instead every pooled result returns its trade
+/// and order vectors here when dropped, wherever that happens, and the
+/// matching thread takes them back for the next order. Once enough results
+/// are in flight to fill the pool, producing a result allocates nothing.
+use crate::types::{MatchingResult, Order, OrderStatus, Trade};
+use crossbeam::queue::ArrayQueue;
+use std::fmt;
+use std::sync::Arc;
+
+/// Trades and updated makers a fresh buffer has room for.
+const INITIAL_FILLS: usize = 16;
+
+#[derive(Clone)]
+pub struct ResultPool {
+    free: Arc<ArrayQueue<(Vec<Trade>, Vec<Order>)>>,
+}
+
+impl ResultPool {
+    /// A pool keeping at most `capacity` sets of buffers.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            free: Arc::new(ArrayQueue::new(capacity)),
+        }
+    }
+
+    /// An empty result whose buffers come back to this pool.
+    pub fn take(&self) -> MatchingResult {
+        let (trades, updated_orders) = self
+            .free
+            .pop()
+            .unwrap_or_else(|| (Vec::with_capacity(INITIAL_FILLS), Vec::with_capacity(INITIAL_FILLS)));
+
+        MatchingResult {
+            status: OrderStatus::New,
+            trades,
+            updated_orders,
+            pool: Some(self.clone()),
+        }
+    }
+
+    /// Buffers that no longer fit are freed.
+    pub(crate) fn recycle(&self, mut trades: Vec<Trade>, mut updated_orders: Vec<Order>) {
+        trades.clear();
+        updated_orders.clear();
+        let _ = self.free.push((trades, updated_orders));
+    }
+
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+}
+
+impl fmt::Debug for ResultPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResultPool")
+            .field("available", &self.free.len())
+            .field("capacity", &self.free.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropped_result_returns_buffers() {
+        let pool = ResultPool::new(2);
+        let result = pool.take();
+        let capacity = result.trades.capacity();
+        assert_eq!(pool.available(), 0);
+
+        drop(result);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.take().trades.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_full_pool_frees_extra_buffers() {
+        let pool = ResultPool::new(1);
+        let (a, b) = (pool.take(), pool.take());
+        drop(a);
+        drop(b);
+        assert_eq!(pool.available(), 1);
+    }
+}
@@ -0,0 +1,617 @@
+/// REST/JSON API next to gRPC, for web backends and scripts.
+///
+/// `RestApi` serves:
+///
+/// - `POST /orders` places an order
+/// - `GET /orders/{order_id}` is the order's status
+/// - `DELETE /orders/{order_id}?user_id=U` cancels it, if it is `U`'s
+/// - `GET /orderbook/{symbol}?depth=N` is aggregated depth, at most
+///   `EngineConfig::snapshot_depth` levels per side
+/// - `GET /trades/{symbol}?limit=N` is the latest public trades
+/// - `GET /openapi.json` describes all of the above
+///
+/// Prices and amounts are decimal strings at the instrument's precision,
+/// as over gRPC. Order status comes from `ExecutionReports`, so it follows
+/// the event stream and may trail a placement's reply by a moment.
+///
+/// Refused requests are answered with an `ApiError` body and a status
+/// code: 400 for a body or query that does not parse or is out of range,
+/// 403 for another user's order, 404 for an unknown order or book, 409
+/// when the request conflicts with an existing order, 422 for an order the
+/// engine rejects and 503 while the market is closed.
+use crate::engine::MatchingEngine;
+use crate::execution::{ExecutionReport, ExecutionReports};
+use crate::fixed_point::{Price, Qty};
+use crate::trades::{PublicTrade, TradeFeed};
+use crate::types::{DepthLevel, Instrument, Order, OrderSide, OrderStatus, OrderType, RejectReason};
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// The OpenAPI 3 description served at `/openapi.json`.
+pub const OPENAPI: &str = include_str!("openapi.json");
+
+/// Levels per side when `depth` is not given.
+const DEFAULT_DEPTH: usize = 20;
+
+/// Trades returned when `limit` is not given.
+const DEFAULT_TRADES: usize = 100;
+
+/// Most trades one request may ask for.
+const MAX_TRADES: usize = 1000;
+
+pub struct RestApi {
+    engine: Arc<MatchingEngine>,
+    reports: Arc<ExecutionReports>,
+    trades: Arc<TradeFeed>,
+}
+
+impl RestApi {
+    pub fn new(engine: Arc<MatchingEngine>, reports: Arc<ExecutionReports>, trades: Arc<TradeFeed>) -> Self {
+        Self {
+            engine,
+            reports,
+            trades,
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/orders", post(place_order))
+            .route("/orders/:order_id", get(order_status).delete(cancel_order))
+            .route("/orderbook/:symbol", get(order_book))
+            .route("/trades/:symbol", get(recent_trades))
+            .route("/openapi.json", get(openapi))
+            .with_state(self)
+    }
+
+    /// Serves requests until the listener fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        serve(self.router(), listener).await
+    }
+}
+
+/// Serves `router` until the listener fails, e.g. the API merged with the
+/// WebSocket gateway's routes.
+pub async fn serve(router: Router, listener: TcpListener) -> io::Result<()> {
+    axum::Server::from_tcp(listener.into_std()?)
+        .map_err(io::Error::other)?
+        .serve(router.into_make_service())
+        .await
+        .map_err(io::Error::other)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaceOrderRequest {
+    pub order_id: String,
+    pub user_id: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    #[serde(rename = "type", default = "limit")]
+    pub order_type: OrderType,
+    /// Required for limit orders, ignored for market orders.
+    #[serde(default)]
+    pub price: Option<String>,
+    pub amount: String,
+}
+
+fn limit() -> OrderType {
+    OrderType::Limit
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelQuery {
+    /// Must be the user who placed the order.
+    pub user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepthQuery {
+    pub depth: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TradesQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaceOrderResponse {
+    pub order_id: String,
+    pub status: OrderStatus,
+    pub fills: Vec<FillView>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FillView {
+    pub trade_id: u64,
+    pub price: String,
+    pub amount: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderView {
+    pub order_id: String,
+    pub user_id: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub status: OrderStatus,
+    pub price: String,
+    pub amount: String,
+    pub filled: String,
+    /// Zero once the order is final.
+    pub remaining: String,
+    /// Absent until the first fill.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_price: Option<String>,
+    pub timestamp: i64,
+}
+
+impl OrderView {
+    fn from_report(report: &ExecutionReport, instrument: &Instrument) -> Self {
+        Self {
+            order_id: report.order_id.to_string(),
+            user_id: report.user_id.to_string(),
+            symbol: report.symbol.to_string(),
+            side: report.side,
+            status: report.status,
+            price: instrument.format_price(report.price),
+            amount: instrument.format_qty(report.amount),
+            filled: instrument.format_qty(report.cum_qty),
+            remaining: instrument.format_qty(report.leaves_qty),
+            avg_price: report.avg_price.map(|price| instrument.format_price(price)),
+            timestamp: report.timestamp,
+        }
+    }
+
+    fn from_order(order: &Order, avg_price: Option<Price>, instrument: &Instrument) -> Self {
+        Self {
+            order_id: order.id.to_string(),
+            user_id: order.user_id.to_string(),
+            symbol: order.symbol.to_string(),
+            side: order.side,
+            status: order.status,
+            price: instrument.format_price(order.price),
+            amount: instrument.format_qty(order.amount),
+            filled: instrument.format_qty(order.filled),
+            remaining: instrument.format_qty(if order.status.is_open() { order.remaining() } else { Qty::ZERO }),
+            avg_price: avg_price.map(|price| instrument.format_price(price)),
+            timestamp: order.timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderBookView {
+    pub symbol: String,
+    pub sequence: u64,
+    pub bids: Vec<LevelView>,
+    pub asks: Vec<LevelView>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LevelView {
+    pub price: String,
+    pub amount: String,
+    pub order_count: usize,
+}
+
+impl LevelView {
+    pub fn new(level: &DepthLevel, instrument: &Instrument) -> Self {
+        Self {
+            price: instrument.format_price(level.price),
+            amount: instrument.format_qty(level.amount),
+            order_count: level.order_count,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TradeView {
+    pub sequence: u64,
+    pub trade_id: u64,
+    pub symbol: String,
+    pub price: String,
+    pub amount: String,
+    pub taker_side: OrderSide,
+    pub timestamp: i64,
+}
+
+impl TradeView {
+    pub fn new(public: &PublicTrade, instrument: &Instrument) -> Self {
+        let trade = &public.trade;
+        Self {
+            sequence: public.sequence,
+            trade_id: trade.id,
+            symbol: trade.symbol.to_string(),
+            price: instrument.format_price(trade.price),
+            amount: instrument.format_qty(trade.amount),
+            taker_side: trade.taker_side,
+            timestamp: trade.timestamp,
+        }
+    }
+}
+
+/// Why a request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    Rejected(RejectReason),
+    BadRequest(String),
+    Forbidden(&'static str),
+    NotFound(&'static str),
+}
+
+/// The JSON body of an error response. `error` is a `RejectReason` name,
+/// `BadRequest`, `Forbidden` or `NotFound`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Rejected(reason) => match reason {
+                RejectReason::UnknownOrder => StatusCode::NOT_FOUND,
+                RejectReason::DuplicateOrderId | RejectReason::AmountBelowFilled => StatusCode::CONFLICT,
+                RejectReason::MarketClosed => StatusCode::SERVICE_UNAVAILABLE,
+                RejectReason::UnknownSymbol
+                | RejectReason::InvalidSide
+                | RejectReason::InvalidOrderType
+                | RejectReason::InvalidPrice
+                | RejectReason::InvalidAmount
+                | RejectReason::BadTick
+                | RejectReason::BadLot
+                | RejectReason::ZeroAmount
+                | RejectReason::ZeroPrice
+                | RejectReason::RiskLimit
+                | RejectReason::BatchRejected => StatusCode::UNPROCESSABLE_ENTITY,
+            },
+        }
+    }
+
+    fn body(&self) -> ErrorBody {
+        let (error, message) = match self {
+            ApiError::Rejected(reason) => (format!("{:?}", reason), reason.to_string()),
+            ApiError::BadRequest(message) => ("BadRequest".to_string(), message.clone()),
+            ApiError::Forbidden(message) => ("Forbidden".to_string(), message.to_string()),
+            ApiError::NotFound(message) => ("NotFound".to_string(), message.to_string()),
+        };
+        ErrorBody { error, message }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+impl From<RejectReason> for ApiError {
+    fn from(reason: RejectReason) -> Self {
+        ApiError::Rejected(reason)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(format!("invalid order: {}", rejection.body_text()))
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+async fn place_order(
+    State(api): State<Arc<RestApi>>,
+    req: Result<Json<PlaceOrderRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<PlaceOrderResponse>), ApiError> {
+    let Json(req) = req?;
+    let instrument = api.engine.instrument(&req.symbol);
+    let order = order_from_request(req, &instrument)?;
+    let order_id = order.id.to_string();
+    let result = api.engine.place_order_async(order).await?;
+
+    let response = PlaceOrderResponse {
+        order_id,
+        status: result.status,
+        fills: result
+            .trades
+            .iter()
+            .map(|t| FillView {
+                trade_id: t.id,
+                price: instrument.format_price(t.price),
+                amount: instrument.format_qty(t.amount),
+                timestamp: t.timestamp,
+            })
+            .collect(),
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+async fn order_status(
+    State(api): State<Arc<RestApi>>,
+    Path(order_id): Path<String>,
+) -> Result<Json<OrderView>, ApiError> {
+    let report = api
+        .reports
+        .order_status(&order_id)
+        .ok_or(ApiError::NotFound("no open or recently closed order with this id"))?;
+    let instrument = api.engine.instrument(&report.symbol);
+    Ok(Json(OrderView::from_report(&report, &instrument)))
+}
+
+/// Checks the owner against the engine's own record, so an order can be
+/// cancelled as soon as its placement has returned.
+async fn cancel_order(
+    State(api): State<Arc<RestApi>>,
+    Path(order_id): Path<String>,
+    query: Result<Query<CancelQuery>, QueryRejection>,
+) -> Result<Json<OrderView>, ApiError> {
+    let Query(CancelQuery { user_id }) = query?;
+    let owner = api
+        .engine
+        .order_owner(&order_id)
+        .ok_or(ApiError::NotFound("no open order with this id"))?;
+    if owner.as_str() != user_id {
+        return Err(ApiError::Forbidden("the order belongs to another user"));
+    }
+    let order = api
+        .engine
+        .cancel_order_async(&order_id)
+        .await
+        .ok_or(ApiError::NotFound("no open order with this id"))?;
+    let avg_price = api.reports.order_status(&order_id).and_then(|report| report.avg_price);
+    let instrument = api.engine.instrument(&order.symbol);
+    Ok(Json(OrderView::from_order(&order, avg_price, &instrument)))
+}
+
+async fn order_book(
+    State(api): State<Arc<RestApi>>,
+    Path(symbol): Path<String>,
+    query: Result<Query<DepthQuery>, QueryRejection>,
+) -> Result<Json<OrderBookView>, ApiError> {
+    let depth = query?.depth.unwrap_or(DEFAULT_DEPTH);
+    let max_depth = api.engine.snapshot_depth();
+    if depth > max_depth {
+        return Err(ApiError::BadRequest(format!("depth may be at most {}", max_depth)));
+    }
+    let book = api
+        .engine
+        .get_order_book(&symbol, depth)
+        .ok_or(ApiError::NotFound("no order book for this symbol"))?;
+    let instrument = api.engine.instrument(&symbol);
+
+    let view = OrderBookView {
+        symbol: book.symbol.to_string(),
+        sequence: book.sequence,
+        bids: book.bids.iter().map(|level| LevelView::new(level, &instrument)).collect(),
+        asks: book.asks.iter().map(|level| LevelView::new(level, &instrument)).collect(),
+    };
+    Ok(Json(view))
+}
+
+async fn recent_trades(
+    State(api): State<Arc<RestApi>>,
+    Path(symbol): Path<String>,
+    query: Result<Query<TradesQuery>, QueryRejection>,
+) -> Result<Json<Vec<TradeView>>, ApiError> {
+    let limit = query?.limit.unwrap_or(DEFAULT_TRADES);
+    if limit > MAX_TRADES {
+        return Err(ApiError::BadRequest(format!("limit may be at most {}", MAX_TRADES)));
+    }
+    let instrument = api.engine.instrument(&symbol);
+    let trades: Vec<_> = api
+        .trades
+        .recent(&symbol, limit)
+        .iter()
+        .map(|public| TradeView::new(public, &instrument))
+        .collect();
+    Ok(Json(trades))
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+fn order_from_request(req: PlaceOrderRequest, instrument: &Instrument) -> Result<Order, RejectReason> {
+    let price = match req.price.as_deref() {
+        Some(price) if req.order_type == OrderType::Limit => instrument
+            .parse_price(price)
+            .map_err(|e| e.price_reject())?,
+        _ => Price::ZERO,
+    };
+    Ok(Order {
+        id: req.order_id.into(),
+        user_id: req.user_id.into(),
+        symbol: instrument.symbol.clone(),
+        side: req.side,
+        order_type: req.order_type,
+        price,
+        amount: instrument
+            .parse_qty(&req.amount)
+            .map_err(|e| e.qty_reject())?,
+        filled: Qty::ZERO,
+        status: OrderStatus::New,
+        timestamp: Utc::now().timestamp_millis(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+    use crate::execution::ExecutionReporter;
+    use crate::retransmit::RetransmitConfig;
+    use crate::trades::TradePublisher;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use serde::de::DeserializeOwned;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn api() -> Arc<RestApi> {
+        let reports = Arc::new(ExecutionReports::with_order_history(64, 16));
+        let config = RetransmitConfig {
+            capacity: 16,
+            disk: None,
+        };
+        let trades = Arc::new(TradeFeed::with_retransmit(16, config));
+        let engine = MatchingEngine::start(
+            EngineConfig::default(),
+            vec![
+                Box::new(ExecutionReporter::new(Arc::clone(&reports))),
+                Box::new(TradePublisher::new(Arc::clone(&trades))),
+            ],
+        );
+        engine.register_instrument(Instrument::new("BTC-USD", 2, 4));
+        Arc::new(RestApi::new(Arc::new(engine), reports, trades))
+    }
+
+    async fn call<T: DeserializeOwned>(api: &Arc<RestApi>, method: Method, uri: &str, body: &str) -> (StatusCode, T) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = Arc::clone(api).router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    fn order(id: &str, user: &str, side: &str, price: &str, amount: &str) -> String {
+        format!(
+            r#"{{"order_id":"{id}","user_id":"{user}","symbol":"BTC-USD","side":"{side}","price":"{price}","amount":"{amount}"}}"#
+        )
+    }
+
+    /// Order status follows the event stream, so wait for it to settle.
+    async fn status_of(api: &Arc<RestApi>, order_id: &str, status: OrderStatus) -> OrderView {
+        for _ in 0..100 {
+            let (code, view): (_, serde_json::Value) = call(api, Method::GET, &format!("/orders/{order_id}"), "").await;
+            if code == StatusCode::OK && view["status"] == serde_json::to_value(status).unwrap() {
+                return serde_json::from_value(view).unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("{order_id} never reached {status:?}");
+    }
+
+    #[tokio::test]
+    async fn test_place_status_cancel() {
+        let api = api();
+        let (code, placed): (_, PlaceOrderResponse) =
+            call(&api, Method::POST, "/orders", &order("a", "maker", "Sell", "100.50", "2")).await;
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(placed.status, OrderStatus::New);
+
+        let (code, taken): (_, PlaceOrderResponse) =
+            call(&api, Method::POST, "/orders", &order("b", "taker", "Buy", "101", "0.5")).await;
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(taken.status, OrderStatus::Filled);
+        assert_eq!((taken.fills[0].price.as_str(), taken.fills[0].amount.as_str()), ("100.5", "0.5"));
+
+        let view = status_of(&api, "a", OrderStatus::PartiallyFilled).await;
+        assert_eq!((view.filled.as_str(), view.remaining.as_str()), ("0.5", "1.5"));
+        assert_eq!(view.avg_price.as_deref(), Some("100.5"));
+
+        let (code, book): (_, OrderBookView) = call(&api, Method::GET, "/orderbook/BTC-USD?depth=5", "").await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!((book.asks[0].price.as_str(), book.asks[0].amount.as_str()), ("100.5", "1.5"));
+        assert!(book.bids.is_empty());
+
+        // Trades also follow the event stream.
+        let mut trades: Vec<TradeView> = Vec::new();
+        for _ in 0..100 {
+            let (code, latest) = call(&api, Method::GET, "/trades/BTC-USD?limit=10", "").await;
+            assert_eq!(code, StatusCode::OK);
+            trades = latest;
+            if !trades.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(trades.iter().map(|t| (t.sequence, t.taker_side)).collect::<Vec<_>>(), vec![(1, OrderSide::Buy)]);
+
+        let (code, refused): (_, ErrorBody) = call(&api, Method::DELETE, "/orders/a?user_id=taker", "").await;
+        assert_eq!((code, refused.error.as_str()), (StatusCode::FORBIDDEN, "Forbidden"));
+        let (code, cancelled): (_, OrderView) = call(&api, Method::DELETE, "/orders/a?user_id=maker", "").await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!((cancelled.status, cancelled.remaining.as_str()), (OrderStatus::Cancelled, "0"));
+        status_of(&api, "a", OrderStatus::Cancelled).await;
+
+        // Cancels need not wait for the order's execution report.
+        let (code, _): (_, PlaceOrderResponse) =
+            call(&api, Method::POST, "/orders", &order("c", "maker", "Sell", "102", "1")).await;
+        assert_eq!(code, StatusCode::CREATED);
+        let (code, cancelled): (_, OrderView) = call(&api, Method::DELETE, "/orders/c?user_id=maker", "").await;
+        assert_eq!((code, cancelled.status), (StatusCode::OK, OrderStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_status_codes() {
+        let api = api();
+        let cases = [
+            (Method::POST, "/orders", "{".to_string(), StatusCode::BAD_REQUEST, "BadRequest"),
+            (Method::POST, "/orders", order("a", "u1", "Buy", "1.001", "1"), StatusCode::UNPROCESSABLE_ENTITY, "BadTick"),
+            (Method::POST, "/orders", order("a", "u1", "Buy", "1", "0"), StatusCode::UNPROCESSABLE_ENTITY, "ZeroAmount"),
+            (Method::POST, "/orders", order("a", "u1", "Buy", "1", "1"), StatusCode::CREATED, ""),
+            (Method::POST, "/orders", order("a", "u1", "Buy", "1", "1"), StatusCode::CONFLICT, "DuplicateOrderId"),
+            (Method::DELETE, "/orders/a", String::new(), StatusCode::BAD_REQUEST, "BadRequest"),
+            (Method::DELETE, "/orders/missing?user_id=u1", String::new(), StatusCode::NOT_FOUND, "NotFound"),
+            (Method::GET, "/orders/missing", String::new(), StatusCode::NOT_FOUND, "NotFound"),
+            (Method::GET, "/orderbook/ETH-USD", String::new(), StatusCode::NOT_FOUND, "NotFound"),
+            (Method::GET, "/orderbook/BTC-USD?depth=51", String::new(), StatusCode::BAD_REQUEST, "BadRequest"),
+            (Method::GET, "/trades/BTC-USD?limit=x", String::new(), StatusCode::BAD_REQUEST, "BadRequest"),
+        ];
+        for (method, uri, body, status, error) in cases {
+            let (code, body): (_, serde_json::Value) = call(&api, method, uri, &body).await;
+            assert_eq!(code, status, "{uri}: {body}");
+            if !error.is_empty() {
+                assert_eq!(body["error"], error, "{uri}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openapi_describes_every_route() {
+        let api = api();
+        let (code, spec): (_, serde_json::Value) = call(&api, Method::GET, "/openapi.json", "").await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(spec["openapi"], "3.0.3");
+
+        let paths = spec["paths"].as_object().unwrap();
+        let documented: Vec<_> = paths
+            .iter()
+            .flat_map(|(path, item)| item.as_object().unwrap().keys().map(move |method| format!("{method} {path}")))
+            .collect();
+        for route in [
+            "post /orders",
+            "get /orders/{order_id}",
+            "delete /orders/{order_id}",
+            "get /orderbook/{symbol}",
+            "get /trades/{symbol}",
+        ] {
+            assert!(documented.iter().any(|d| d == route), "{route} is not documented");
+        }
+    }
+}
@@ -0,0 +1,505 @@
+/// Gap fill for the sequenced market data feeds.
+///
+/// The trade, depth and L3 feeds each keep a `RetransmitRing` per symbol.
+/// A message is recorded before it is broadcast, so a client that sees a
+/// gap can ask for the missing range instead of resubscribing. Once part of
+/// the range has aged out the answer is `Retransmission::Expired`, and the
+/// client resyncs from a snapshot.
+///
+/// Memory holds the latest `capacity` messages. With `disk` set, every
+/// message is also appended as a JSON line to segment files of
+/// `segment_len` messages. Only the newest two segments are kept, so disk
+/// reaches at least `segment_len` messages back. A ring creates its first
+/// segment when it records its first message and removes its segments when
+/// it is dropped. Sequences restart with the engine, so the segments an
+/// earlier run left behind are removed once at startup with
+/// `DiskRetention::remove_stale`. If a write fails, the ring logs it and
+/// carries on from memory alone.
+///
+/// Feeds keep a ring behind the same lock as the book it belongs to. So
+/// `RetransmitRing::get` only opens the segment files it needs, and the
+/// caller reads them with `Lookup::finish` once that lock is released.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+/// A feed message numbered per symbol from 1.
+pub trait Sequenced {
+    fn sequence(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RetransmitConfig {
+    /// Latest messages kept in memory per symbol. Zero keeps none.
+    pub capacity: usize,
+    pub disk: Option<DiskRetention>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskRetention {
+    pub dir: PathBuf,
+    /// Messages per segment file.
+    pub segment_len: usize,
+}
+
+impl DiskRetention {
+    /// Removes the segment files an earlier run left in `dir`, which no
+    /// longer match any sequence. Call it once, before any ring records.
+    pub fn remove_stale(&self) -> io::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.file_name().and_then(|f| f.to_str()).is_some_and(is_segment_file) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `name` as a file name stem: ASCII letters, digits and `-` stay as they
+/// are, and every other byte becomes `%XX`. Distinct names never share a
+/// stem, and a stem has no `.`.
+pub(crate) fn file_stem(name: &str) -> String {
+    let mut stem = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            stem.push(byte as char);
+        } else {
+            stem.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    stem
+}
+
+/// Whether `file_name` is `{stem}.{index}.jsonl`, as segments are named.
+fn is_segment_file(file_name: &str) -> bool {
+    let Some((stem, index)) = file_name.strip_suffix(".jsonl").and_then(|rest| rest.rsplit_once('.')) else {
+        return false;
+    };
+    !stem.is_empty()
+        && stem.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'%')
+        && index.parse::<u64>().is_ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Retransmission<T> {
+    /// The published messages of the range, in order. Empty if none of it
+    /// has been published yet.
+    Messages(Vec<T>),
+    /// Part of the range is no longer kept. `first_available` is the oldest
+    /// message that still is.
+    Expired { first_available: Option<u64> },
+}
+
+struct Segment {
+    path: PathBuf,
+    first: u64,
+    len: usize,
+}
+
+/// A segment file opened for a read, with the sequence of its first line.
+struct OpenSegment {
+    file: File,
+    first: u64,
+}
+
+impl OpenSegment {
+    fn read<T: DeserializeOwned>(self, from: u64, to: u64, out: &mut Vec<T>) -> io::Result<()> {
+        let skip = from.saturating_sub(self.first) as usize;
+        let take = (to + 1 - self.first.max(from)) as usize;
+        for line in BufReader::new(self.file).lines().skip(skip).take(take) {
+            out.push(serde_json::from_str(&line?)?);
+        }
+        Ok(())
+    }
+}
+
+struct Segments {
+    dir: PathBuf,
+    stem: String,
+    segment_len: usize,
+    previous: Option<Segment>,
+    current: Segment,
+    writer: BufWriter<File>,
+    next_index: u64,
+}
+
+impl Segments {
+    fn open(disk: &DiskRetention, name: &str) -> io::Result<Self> {
+        fs::create_dir_all(&disk.dir)?;
+        let stem = file_stem(name);
+        let (current, writer) = Self::create(&disk.dir, &stem, 0)?;
+        Ok(Self {
+            dir: disk.dir.clone(),
+            stem,
+            segment_len: disk.segment_len.max(1),
+            previous: None,
+            current,
+            writer,
+            next_index: 1,
+        })
+    }
+
+    fn create(dir: &Path, stem: &str, index: u64) -> io::Result<(Segment, BufWriter<File>)> {
+        let path = dir.join(format!("{stem}.{index}.jsonl"));
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok((Segment { path, first: 0, len: 0 }, writer))
+    }
+
+    fn first(&self) -> Option<u64> {
+        match &self.previous {
+            Some(previous) => Some(previous.first),
+            None => (self.current.len > 0).then_some(self.current.first),
+        }
+    }
+
+    fn append<T: Sequenced + Serialize>(&mut self, message: &T) -> io::Result<()> {
+        if self.current.len == self.segment_len {
+            self.writer.flush()?;
+            let (segment, writer) = Self::create(&self.dir, &self.stem, self.next_index)?;
+            self.next_index += 1;
+            self.writer = writer;
+            if let Some(stale) = self.previous.replace(std::mem::replace(&mut self.current, segment)) {
+                fs::remove_file(stale.path)?;
+            }
+        }
+        if self.current.len == 0 {
+            self.current.first = message.sequence();
+        }
+        serde_json::to_writer(&mut self.writer, message)?;
+        self.writer.write_all(b"\n")?;
+        self.current.len += 1;
+        Ok(())
+    }
+
+    /// Opens the segments holding `from..=to`, oldest first. Opening them
+    /// now keeps them readable even if a rotation removes one meanwhile.
+    fn open_range(&mut self, to: u64) -> io::Result<Vec<OpenSegment>> {
+        self.writer.flush()?;
+        self.previous
+            .iter()
+            .chain([&self.current])
+            .filter(|segment| segment.len > 0 && segment.first <= to)
+            .map(|segment| {
+                Ok(OpenSegment {
+                    file: File::open(&segment.path)?,
+                    first: segment.first,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Segments already opened for a read stay readable.
+impl Drop for Segments {
+    fn drop(&mut self) {
+        for segment in self.previous.iter().chain([&self.current]) {
+            if let Err(e) = fs::remove_file(&segment.path) {
+                error!("Failed to remove retransmit segment {}: {}", segment.path.display(), e);
+            }
+        }
+    }
+}
+
+/// The answer to `RetransmitRing::get`, which may still have to be read
+/// from disk.
+pub struct Lookup<T> {
+    state: LookupState<T>,
+}
+
+enum LookupState<T> {
+    Ready(Retransmission<T>),
+    Disk {
+        name: String,
+        segments: Vec<OpenSegment>,
+        from: u64,
+        to: u64,
+        first_available: Option<u64>,
+    },
+}
+
+impl<T: DeserializeOwned> Lookup<T> {
+    fn ready(retransmission: Retransmission<T>) -> Self {
+        Self {
+            state: LookupState::Ready(retransmission),
+        }
+    }
+
+    /// The retransmission, reading whatever is on disk. Call it without
+    /// holding the lock the ring is kept behind.
+    pub fn finish(self) -> Retransmission<T> {
+        match self.state {
+            LookupState::Ready(retransmission) => retransmission,
+            LookupState::Disk {
+                name,
+                segments,
+                from,
+                to,
+                first_available,
+            } => {
+                let mut messages = Vec::with_capacity((to + 1 - from) as usize);
+                match segments.into_iter().try_for_each(|segment| segment.read(from, to, &mut messages)) {
+                    Ok(()) => Retransmission::Messages(messages),
+                    Err(e) => {
+                        error!("Failed to read retransmit segments for {}: {}", name, e);
+                        Retransmission::Expired { first_available }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The latest messages of one feed on one symbol.
+pub struct RetransmitRing<T> {
+    name: String,
+    recent: VecDeque<T>,
+    capacity: usize,
+    /// Where segments go, until the first message creates them.
+    unopened: Option<DiskRetention>,
+    disk: Option<Segments>,
+    last: u64,
+}
+
+impl<T: Sequenced + Serialize + DeserializeOwned + Clone> RetransmitRing<T> {
+    /// `name` identifies the ring in logs and segment file names.
+    pub fn new(name: &str, config: &RetransmitConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            recent: VecDeque::with_capacity(config.capacity),
+            capacity: config.capacity,
+            unopened: config.disk.clone(),
+            disk: None,
+            last: 0,
+        }
+    }
+
+    /// Records the next message. Sequences must follow on from the last.
+    pub fn record(&mut self, message: &T) {
+        self.last = message.sequence();
+        if self.capacity > 0 {
+            if self.recent.len() == self.capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(message.clone());
+        }
+        if let Some(disk) = self.unopened.take() {
+            match Segments::open(&disk, &self.name) {
+                Ok(segments) => self.disk = Some(segments),
+                Err(e) => error!("Failed to open retransmit segments for {}: {}", self.name, e),
+            }
+        }
+        if let Some(Err(e)) = self.disk.as_mut().map(|disk| disk.append(message)) {
+            error!("Failed to write retransmit segment for {}, keeping memory only: {}", self.name, e);
+            self.disk = None;
+        }
+    }
+
+    /// Sequence of the last message recorded, zero before the first.
+    pub fn last_sequence(&self) -> u64 {
+        self.last
+    }
+
+    /// Oldest message still kept.
+    pub fn first_available(&self) -> Option<u64> {
+        let in_memory = self.recent.front().map(Sequenced::sequence);
+        match self.disk.as_ref().and_then(Segments::first) {
+            Some(on_disk) => Some(in_memory.map_or(on_disk, |m| m.min(on_disk))),
+            None => in_memory,
+        }
+    }
+
+    /// Messages `from..=to`, cut off at the last one recorded.
+    pub fn get(&mut self, from: u64, to: u64) -> Lookup<T> {
+        let to = to.min(self.last);
+        if from > to {
+            return Lookup::ready(Retransmission::Messages(Vec::new()));
+        }
+
+        if let Some(first) = self.recent.front().map(Sequenced::sequence) {
+            if from >= first {
+                let messages = self
+                    .recent
+                    .iter()
+                    .skip((from - first) as usize)
+                    .take((to + 1 - from) as usize)
+                    .cloned()
+                    .collect();
+                return Lookup::ready(Retransmission::Messages(messages));
+            }
+        }
+
+        let first_available = self.first_available();
+        if let Some(disk) = &mut self.disk {
+            if disk.first().is_some_and(|first| from >= first) {
+                match disk.open_range(to) {
+                    Ok(segments) => {
+                        return Lookup {
+                            state: LookupState::Disk {
+                                name: self.name.clone(),
+                                segments,
+                                from,
+                                to,
+                                first_available,
+                            },
+                        }
+                    }
+                    Err(e) => error!("Failed to open retransmit segments for {}: {}", self.name, e),
+                }
+            }
+        }
+
+        Lookup::ready(Retransmission::Expired { first_available })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Message(u64);
+
+    impl Sequenced for Message {
+        fn sequence(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn sequences(retransmission: Retransmission<Message>) -> Vec<u64> {
+        match retransmission {
+            Retransmission::Messages(messages) => messages.into_iter().map(|m| m.0).collect(),
+            Retransmission::Expired { .. } => panic!("expected messages"),
+        }
+    }
+
+    #[test]
+    fn test_memory_ring_keeps_latest() {
+        let config = RetransmitConfig {
+            capacity: 4,
+            disk: None,
+        };
+        let mut ring = RetransmitRing::new("trades-BTC-USD", &config);
+        (1..=10).for_each(|seq| ring.record(&Message(seq)));
+
+        assert_eq!(sequences(ring.get(8, 9).finish()), vec![8, 9]);
+        assert_eq!(sequences(ring.get(9, 50).finish()), vec![9, 10]);
+        assert_eq!(sequences(ring.get(11, 12).finish()), Vec::<u64>::new());
+        assert_eq!(ring.get(5, 8).finish(), Retransmission::Expired { first_available: Some(7) });
+    }
+
+    #[test]
+    fn test_disk_reaches_past_memory() {
+        let dir = std::env::temp_dir().join(format!("retransmit-test-{}", std::process::id()));
+        let config = RetransmitConfig {
+            capacity: 2,
+            disk: Some(DiskRetention {
+                dir: dir.clone(),
+                segment_len: 3,
+            }),
+        };
+        let mut ring = RetransmitRing::new("depth-BTC/USD", &config);
+        (1..=10).for_each(|seq| ring.record(&Message(seq)));
+
+        // Segments [7, 8, 9] and [10] remain; memory holds 9 and 10.
+        assert_eq!(ring.first_available(), Some(7));
+        assert_eq!(sequences(ring.get(7, 10).finish()), vec![7, 8, 9, 10]);
+        assert_eq!(sequences(ring.get(8, 8).finish()), vec![8]);
+        assert_eq!(ring.get(4, 8).finish(), Retransmission::Expired { first_available: Some(7) });
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        drop(ring);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn disk_config(dir: &Path, segment_len: usize) -> RetransmitConfig {
+        RetransmitConfig {
+            capacity: 0,
+            disk: Some(DiskRetention {
+                dir: dir.to_path_buf(),
+                segment_len,
+            }),
+        }
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_segments_follow_the_ring() {
+        let dir = std::env::temp_dir().join(format!("retransmit-rings-{}", std::process::id()));
+        let config = disk_config(&dir, 2);
+
+        // Nothing is written for a ring that records nothing.
+        let idle = RetransmitRing::<Message>::new("depth-ETH", &config);
+        assert!(!dir.exists());
+        drop(idle);
+
+        // Names that differ only in punctuation get their own files.
+        let mut slash = RetransmitRing::new("depth-BTC/USD", &config);
+        let mut underscore = RetransmitRing::new("depth-BTC_USD", &config);
+        (1..=3).for_each(|seq| slash.record(&Message(seq)));
+        underscore.record(&Message(1));
+        assert_eq!(
+            file_names(&dir),
+            vec!["depth-BTC%2FUSD.0.jsonl", "depth-BTC%2FUSD.1.jsonl", "depth-BTC%5FUSD.0.jsonl"]
+        );
+        assert_eq!(sequences(slash.get(1, 3).finish()), vec![1, 2, 3]);
+        assert_eq!(sequences(underscore.get(1, 3).finish()), vec![1]);
+
+        // Segments opened for a read stay readable across a rotation and
+        // past the ring.
+        let lookup = slash.get(2, 3);
+        (4..=7).for_each(|seq| slash.record(&Message(seq)));
+        drop(slash);
+        assert_eq!(sequences(lookup.finish()), vec![2, 3]);
+        assert_eq!(file_names(&dir), vec!["depth-BTC%5FUSD.0.jsonl"]);
+
+        drop(underscore);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_stale_segments() {
+        let retention = DiskRetention {
+            dir: std::env::temp_dir().join(format!("retransmit-stale-{}", std::process::id())),
+            segment_len: 2,
+        };
+        // A directory that does not exist yet holds nothing stale.
+        retention.remove_stale().unwrap();
+
+        fs::create_dir_all(&retention.dir).unwrap();
+        for name in ["trades-BTC.0.jsonl", "depth-BTC%2FUSD.12.jsonl", "CLIENT.fix.jsonl", "notes.txt"] {
+            fs::write(retention.dir.join(name), "").unwrap();
+        }
+        retention.remove_stale().unwrap();
+        assert_eq!(file_names(&retention.dir), vec!["CLIENT.fix.jsonl", "notes.txt"]);
+
+        fs::remove_dir_all(&retention.dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_stems_are_distinct() {
+        assert_eq!(file_stem("trades-BTC-USD"), "trades-BTC-USD");
+        assert_eq!(file_stem("A.B"), "A%2EB");
+        assert_eq!(file_stem("A_B"), "A%5FB");
+        assert_eq!(file_stem("A%2EB"), "A%252EB");
+        assert_eq!(file_stem("é"), "%C3%A9");
+    }
+}
@@ -0,0 +1,281 @@
+/// Rolling 24-hour statistics per symbol.
+///
+/// `StatsBuilder` runs as an `EventHandler` and adds every `Trade` to its
+/// symbol's window in `RollingStats`. A window is a run of one-minute
+/// buckets with running totals of volume, notional and trade count. A trade
+/// updates one bucket and the totals. A bucket that ages out is subtracted
+/// again. The high and low are rescanned from the buckets only when the
+/// bucket that ages out held one of them, and trades are never revisited.
+///
+/// The window covers the last 24 hours to the minute. Buckets age out on
+/// trades and on reads, so the statistics of a quiet symbol still roll.
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::fixed_point::{Price, Qty};
+use crate::ids::Symbol;
+use crate::types::Trade;
+use chrono::Utc;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+const BUCKET_MILLIS: i64 = 60_000;
+pub const WINDOW_MILLIS: i64 = 86_400_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats24h {
+    pub symbol: Symbol,
+    /// First trade price in the window, or `last` if the window is empty.
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    /// Latest trade price, even if older than the window.
+    pub last: Price,
+    pub volume: Qty,
+    /// Sum of price * qty over the window, in raw fixed-point units; see
+    /// `Instrument::format_notional`.
+    pub quote_volume: u128,
+    /// `last - open`, in raw price units.
+    pub price_change: i128,
+    /// `price_change / open`, as a percentage rounded to two places.
+    pub price_change_percent: Decimal,
+    pub trade_count: u64,
+    /// Start of the oldest bucket in the window, in milliseconds.
+    pub open_time: i64,
+    pub close_time: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    start: i64,
+    open: Price,
+    high: Price,
+    low: Price,
+    volume: Qty,
+    notional: u128,
+    trade_count: u64,
+}
+
+struct Window {
+    buckets: VecDeque<Bucket>,
+    volume: Qty,
+    notional: u128,
+    trade_count: u64,
+    high: Price,
+    low: Price,
+    last: Price,
+}
+
+impl Window {
+    fn new(price: Price) -> Self {
+        Self {
+            buckets: VecDeque::new(),
+            volume: Qty::ZERO,
+            notional: 0,
+            trade_count: 0,
+            high: price,
+            low: price,
+            last: price,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.expire(trade.timestamp);
+        let start = trade.timestamp - trade.timestamp.rem_euclid(BUCKET_MILLIS);
+        let notional = trade.price.raw() as u128 * trade.amount.raw() as u128;
+
+        match self.buckets.back_mut() {
+            // Trades timed before the newest bucket still count toward it.
+            Some(bucket) if start <= bucket.start => {
+                bucket.high = bucket.high.max(trade.price);
+                bucket.low = bucket.low.min(trade.price);
+                bucket.volume = bucket.volume.saturating_add(trade.amount);
+                bucket.notional += notional;
+                bucket.trade_count += 1;
+            }
+            _ => self.buckets.push_back(Bucket {
+                start,
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                volume: trade.amount,
+                notional,
+                trade_count: 1,
+            }),
+        }
+
+        // An empty window's high and low are the stale last price.
+        if self.trade_count == 0 {
+            self.high = trade.price;
+            self.low = trade.price;
+        } else {
+            self.high = self.high.max(trade.price);
+            self.low = self.low.min(trade.price);
+        }
+        self.volume = self.volume.saturating_add(trade.amount);
+        self.notional += notional;
+        self.trade_count += 1;
+        self.last = trade.price;
+    }
+
+    /// Drops the buckets that started a full window or more before `now`.
+    fn expire(&mut self, now: i64) {
+        let mut rescan = false;
+        while let Some(bucket) = self.buckets.front() {
+            if bucket.start > now - WINDOW_MILLIS {
+                break;
+            }
+            self.volume = self.volume.saturating_sub(bucket.volume);
+            self.notional -= bucket.notional;
+            self.trade_count -= bucket.trade_count;
+            rescan |= bucket.high == self.high || bucket.low == self.low;
+            self.buckets.pop_front();
+        }
+
+        if rescan {
+            self.high = self.buckets.iter().map(|b| b.high).max().unwrap_or(self.last);
+            self.low = self.buckets.iter().map(|b| b.low).min().unwrap_or(self.last);
+        }
+    }
+
+    fn stats(&self, symbol: &Symbol, now: i64) -> Stats24h {
+        let (open, open_time) = match self.buckets.front() {
+            Some(bucket) => (bucket.open, bucket.start),
+            None => (self.last, now),
+        };
+        let price_change = self.last.raw() as i128 - open.raw() as i128;
+        let price_change_percent = if open.is_zero() {
+            Decimal::ZERO
+        } else {
+            (Decimal::from_i128_with_scale(price_change * 100, 0) / Decimal::from(open.raw())).round_dp(2)
+        };
+
+        Stats24h {
+            symbol: symbol.clone(),
+            open,
+            high: self.high,
+            low: self.low,
+            last: self.last,
+            volume: self.volume,
+            quote_volume: self.notional,
+            price_change,
+            price_change_percent,
+            trade_count: self.trade_count,
+            open_time,
+            close_time: now,
+        }
+    }
+}
+
+/// 24-hour windows, by symbol.
+#[derive(Default)]
+pub struct RollingStats {
+    windows: DashMap<Symbol, Mutex<Window>>,
+}
+
+impl RollingStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Statistics for the 24 hours up to now, `None` if the symbol never
+    /// traded.
+    pub fn get(&self, symbol: &str) -> Option<Stats24h> {
+        self.get_at(symbol, Utc::now().timestamp_millis())
+    }
+
+    /// Statistics for the 24 hours up to `now`, in milliseconds.
+    pub fn get_at(&self, symbol: &str, now: i64) -> Option<Stats24h> {
+        let entry = self.windows.get(symbol)?;
+        let mut window = entry.lock();
+        window.expire(now);
+        Some(window.stats(entry.key(), now))
+    }
+
+    fn add_trade(&self, trade: &Trade) {
+        self.windows
+            .entry(trade.symbol.clone())
+            .or_insert_with(|| Mutex::new(Window::new(trade.price)))
+            .lock()
+            .add(trade);
+    }
+}
+
+pub struct StatsBuilder {
+    stats: Arc<RollingStats>,
+}
+
+impl StatsBuilder {
+    pub fn new(stats: Arc<RollingStats>) -> Self {
+        Self { stats }
+    }
+}
+
+impl EventHandler<EngineEvent> for StatsBuilder {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {
+        if let EngineEvent::Trade(trade) = event {
+            self.stats.add_trade(trade);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OrderSide;
+
+    const HOUR: i64 = 3_600_000;
+
+    fn trade(timestamp: i64, price: u64, amount: u64) -> EngineEvent {
+        EngineEvent::Trade(Trade {
+            id: 1,
+            symbol: "BTC-USD".into(),
+            maker_order_id: "m".into(),
+            taker_order_id: "t".into(),
+            price: Price::from_raw(price),
+            amount: Qty::from_raw(amount),
+            taker_side: OrderSide::Buy,
+            timestamp,
+        })
+    }
+
+    #[test]
+    fn test_stats_over_window() {
+        let stats = Arc::new(RollingStats::new());
+        let mut builder = StatsBuilder::new(Arc::clone(&stats));
+
+        for (i, (at, price, amount)) in [(0, 200, 1), (HOUR, 250, 2), (2 * HOUR, 150, 1)].into_iter().enumerate() {
+            builder.on_event(&trade(at, price, amount), i as u64, true);
+        }
+
+        let day = stats.get_at("BTC-USD", 3 * HOUR).unwrap();
+        assert_eq!((day.open.raw(), day.high.raw(), day.low.raw(), day.last.raw()), (200, 250, 150, 150));
+        assert_eq!((day.volume.raw(), day.quote_volume, day.trade_count), (4, 850, 3));
+        assert_eq!(day.price_change, -50);
+        assert_eq!(day.price_change_percent, Decimal::new(-2500, 2));
+        assert!(stats.get("ETH-USD").is_none());
+    }
+
+    #[test]
+    fn test_old_trades_roll_out() {
+        let stats = Arc::new(RollingStats::new());
+        let mut builder = StatsBuilder::new(Arc::clone(&stats));
+        builder.on_event(&trade(0, 300, 1), 0, true);
+        builder.on_event(&trade(HOUR, 100, 2), 1, true);
+        builder.on_event(&trade(2 * HOUR, 120, 1), 2, true);
+
+        // The high at 300 leaves the window with its bucket.
+        let day = stats.get_at("BTC-USD", WINDOW_MILLIS + 30 * 60_000).unwrap();
+        assert_eq!((day.open.raw(), day.high.raw(), day.low.raw()), (100, 120, 100));
+        assert_eq!((day.volume.raw(), day.trade_count), (3, 2));
+        assert_eq!(day.open_time, HOUR);
+
+        // Nothing left: the last price stands, with no volume.
+        let later = stats.get_at("BTC-USD", 3 * WINDOW_MILLIS).unwrap();
+        assert_eq!((later.open.raw(), later.high.raw(), later.low.raw(), later.last.raw()), (120, 120, 120, 120));
+        assert_eq!((later.volume, later.quote_volume, later.trade_count, later.price_change), (Qty::ZERO, 0, 0, 0));
+    }
+}
@@ -0,0 +1,377 @@
+/// Top-of-book ticker feed.
+///
+/// `TickerPublisher` runs as an `EventHandler`. It keeps the resting size
+/// and order count of each book's price levels, straight from the engine
+/// events, and remembers the last trade price. At the end of each batch of
+/// events, it checks every symbol the batch touched and publishes a
+/// `Ticker` only if the best bid, best ask, their sizes or the last trade
+/// price changed. Tickers for all symbols
+/// share one broadcast channel; subscribers pick out their symbols.
+///
+/// A ticker is a symbol's whole state, so `ConflatedTickers` keeps just the
+/// latest one per symbol between flushes. On lag it reloads them from the
+/// feed.
+use crate::conflation::{Conflated, Conflator};
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::feed::{publish, LevelChange, RestingOrders};
+use crate::fixed_point::{Price, Qty};
+use crate::ids::Symbol;
+use crate::types::{DepthLevel, OrderSide};
+use chrono::Utc;
+use dashmap::DashMap;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Ticker {
+    /// Starts at 1 and increases by one per ticker on the symbol.
+    pub sequence: u64,
+    pub symbol: Symbol,
+    pub best_bid: Option<DepthLevel>,
+    pub best_ask: Option<DepthLevel>,
+    pub last_price: Option<Price>,
+    pub timestamp: i64,
+}
+
+impl Ticker {
+    pub fn spread(&self) -> Option<Price> {
+        self.best_ask.as_ref()?.price.checked_sub(self.best_bid.as_ref()?.price)
+    }
+
+    /// Halfway between best bid and ask, rounded down to a raw unit.
+    pub fn mid_price(&self) -> Option<Price> {
+        let (bid, ask) = (self.best_bid.as_ref()?.price, self.best_ask.as_ref()?.price);
+        Some(Price::from_raw(bid.raw() / 2 + ask.raw() / 2 + (bid.raw() % 2 + ask.raw() % 2) / 2))
+    }
+
+    fn same_state(&self, other: &Ticker) -> bool {
+        self.best_bid == other.best_bid && self.best_ask == other.best_ask && self.last_price == other.last_price
+    }
+}
+
+/// The latest ticker per symbol and the channel carrying every new one.
+pub struct TickerFeed {
+    latest: DashMap<Symbol, Ticker>,
+    updates: broadcast::Sender<Ticker>,
+}
+
+impl TickerFeed {
+    /// Each subscriber may fall `capacity` tickers behind before it lags.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            latest: DashMap::new(),
+            updates: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Tickers for every symbol from now on. Subscribe before reading
+    /// `latest`, then skip tickers whose sequence is not newer.
+    pub fn subscribe(&self) -> broadcast::Receiver<Ticker> {
+        self.updates.subscribe()
+    }
+
+    pub fn latest(&self, symbol: &str) -> Option<Ticker> {
+        self.latest.get(symbol).map(|ticker| ticker.clone())
+    }
+
+    /// A subscription to `symbols` that keeps the latest ticker per symbol
+    /// between flushes. The first flush carries the current ones.
+    pub fn subscribe_conflated(self: &Arc<Self>, symbols: &[&str]) -> ConflatedTickers {
+        let mut tickers = ConflatedTickers {
+            feed: Arc::clone(self),
+            symbols: symbols.iter().map(|&symbol| Symbol::from(symbol)).collect(),
+            updates: self.subscribe(),
+            sequences: HashMap::new(),
+            pending: Conflator::new(),
+        };
+        tickers.reload();
+        tickers
+    }
+
+    fn publish(&self, ticker: Ticker) {
+        self.latest.insert(ticker.symbol.clone(), ticker.clone());
+        publish(&self.updates, ticker);
+    }
+}
+
+pub struct ConflatedTickers {
+    feed: Arc<TickerFeed>,
+    symbols: HashSet<Symbol>,
+    updates: broadcast::Receiver<Ticker>,
+    /// Sequence of the newest ticker taken in per symbol.
+    sequences: HashMap<Symbol, u64>,
+    pending: Conflator<Symbol, Ticker>,
+}
+
+impl ConflatedTickers {
+    fn absorb(&mut self, ticker: Ticker) {
+        if !self.symbols.contains(&ticker.symbol) {
+            return;
+        }
+        let newest = self.sequences.entry(ticker.symbol.clone()).or_insert(0);
+        if ticker.sequence > *newest {
+            *newest = ticker.sequence;
+            self.pending.push(ticker.symbol.clone(), ticker);
+        }
+    }
+
+    /// Resubscribes and takes the current ticker of every symbol.
+    fn reload(&mut self) {
+        self.updates = self.feed.subscribe();
+        let latest: Vec<_> = self.symbols.iter().filter_map(|symbol| self.feed.latest(symbol)).collect();
+        for ticker in latest {
+            self.absorb(ticker);
+        }
+    }
+}
+
+impl Conflated for ConflatedTickers {
+    type Batch = Vec<Ticker>;
+
+    fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn pump(&mut self) -> bool {
+        loop {
+            match self.updates.try_recv() {
+                Ok(ticker) => self.absorb(ticker),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Lagged(_)) => self.reload(),
+                Err(TryRecvError::Closed) => return false,
+            }
+        }
+    }
+
+    async fn changed(&mut self) -> bool {
+        match self.updates.recv().await {
+            Ok(ticker) => self.absorb(ticker),
+            Err(broadcast::error::RecvError::Lagged(_)) => self.reload(),
+            Err(broadcast::error::RecvError::Closed) => return false,
+        }
+        self.pump()
+    }
+
+    fn flush(&mut self) -> Option<Vec<Ticker>> {
+        (!self.pending.is_empty()).then(|| self.pending.drain())
+    }
+}
+
+/// The price levels of one book, best first on each side.
+#[derive(Default)]
+struct Levels {
+    bids: BTreeMap<Reverse<Price>, DepthLevel>,
+    asks: BTreeMap<Price, DepthLevel>,
+}
+
+impl Levels {
+    /// Applies `change` to its level, dropping the level once no order is
+    /// left.
+    fn change(&mut self, change: LevelChange) {
+        let LevelChange { side, price, .. } = change;
+        let empty = DepthLevel {
+            price,
+            amount: Qty::ZERO,
+            order_count: 0,
+        };
+        match side {
+            OrderSide::Buy => {
+                let level = self.bids.entry(Reverse(price)).or_insert(empty);
+                change.apply(level);
+                if level.order_count == 0 {
+                    self.bids.remove(&Reverse(price));
+                }
+            }
+            OrderSide::Sell => {
+                let level = self.asks.entry(price).or_insert(empty);
+                change.apply(level);
+                if level.order_count == 0 {
+                    self.asks.remove(&price);
+                }
+            }
+        }
+    }
+
+    fn best_bid(&self) -> Option<DepthLevel> {
+        self.bids.values().next().cloned()
+    }
+
+    fn best_ask(&self) -> Option<DepthLevel> {
+        self.asks.values().next().cloned()
+    }
+}
+
+pub struct TickerPublisher {
+    feed: Arc<TickerFeed>,
+    books: HashMap<Symbol, Levels>,
+    resting: RestingOrders,
+    last_prices: HashMap<Symbol, Price>,
+    tickers: HashMap<Symbol, Ticker>,
+    /// Symbols touched since the end of the last batch.
+    touched: HashSet<Symbol>,
+}
+
+impl TickerPublisher {
+    pub fn new(feed: Arc<TickerFeed>) -> Self {
+        Self {
+            feed,
+            books: HashMap::new(),
+            resting: RestingOrders::default(),
+            last_prices: HashMap::new(),
+            tickers: HashMap::new(),
+            touched: HashSet::new(),
+        }
+    }
+
+    fn publish_changes(&mut self) {
+        for symbol in self.touched.drain() {
+            let book = self.books.get(&symbol);
+            let mut ticker = Ticker {
+                sequence: 1,
+                symbol: symbol.clone(),
+                best_bid: book.and_then(Levels::best_bid),
+                best_ask: book.and_then(Levels::best_ask),
+                last_price: self.last_prices.get(&symbol).copied(),
+                timestamp: Utc::now().timestamp_millis(),
+            };
+            if let Some(previous) = self.tickers.get(&symbol) {
+                if previous.same_state(&ticker) {
+                    continue;
+                }
+                ticker.sequence = previous.sequence + 1;
+            }
+            self.tickers.insert(symbol, ticker.clone());
+            self.feed.publish(ticker);
+        }
+    }
+}
+
+impl EventHandler<EngineEvent> for TickerPublisher {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, end_of_batch: bool) {
+        if let Some(change) = self.resting.on_event(event) {
+            self.books.entry(event.symbol().clone()).or_default().change(change);
+        }
+        if let EngineEvent::Trade(trade) = event {
+            self.last_prices.insert(trade.symbol.clone(), trade.price);
+        }
+        if !self.touched.contains(event.symbol()) {
+            self.touched.insert(event.symbol().clone());
+        }
+        if end_of_batch {
+            self.publish_changes();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Order, OrderStatus, Trade};
+
+    fn rested(id: &str, symbol: &str, side: OrderSide, price: u64, amount: u64) -> EngineEvent {
+        EngineEvent::OrderRested(Order::limit(id, "u1", symbol, side, Price::from_raw(price), Qty::from_raw(amount)))
+    }
+
+    fn trade(maker: &str, price: u64, amount: u64) -> EngineEvent {
+        EngineEvent::Trade(Trade {
+            id: 1,
+            symbol: "BTC-USD".into(),
+            maker_order_id: maker.into(),
+            taker_order_id: "t".into(),
+            price: Price::from_raw(price),
+            amount: Qty::from_raw(amount),
+            taker_side: OrderSide::Buy,
+            timestamp: 0,
+        })
+    }
+
+    fn drain(rx: &mut broadcast::Receiver<Ticker>) -> Vec<Ticker> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn test_emits_only_on_top_of_book_change() {
+        let feed = Arc::new(TickerFeed::new(16));
+        let mut rx = feed.subscribe();
+        let mut publisher = TickerPublisher::new(Arc::clone(&feed));
+
+        publisher.on_event(&rested("a", "BTC-USD", OrderSide::Buy, 100, 1), 0, true);
+        publisher.on_event(&rested("b", "BTC-USD", OrderSide::Sell, 104, 2), 1, true);
+        // Behind the best bid: no change at the top.
+        publisher.on_event(&rested("c", "BTC-USD", OrderSide::Buy, 90, 1), 2, true);
+        publisher.on_event(&trade("b", 104, 1), 3, true);
+
+        let seen = drain(&mut rx);
+        assert_eq!(seen.iter().map(|t| t.sequence).collect::<Vec<_>>(), vec![1, 2, 3]);
+        let last = &seen[2];
+        assert_eq!(last.best_ask.as_ref().unwrap().amount, Qty::from_raw(1));
+        assert_eq!(last.last_price, Some(Price::from_raw(104)));
+        assert_eq!(last.spread(), Some(Price::from_raw(4)));
+        assert_eq!(last.mid_price(), Some(Price::from_raw(102)));
+        assert_eq!(feed.latest("BTC-USD").as_ref(), Some(last));
+    }
+
+    #[test]
+    fn test_next_level_takes_over_when_best_empties() {
+        let feed = Arc::new(TickerFeed::new(16));
+        let mut publisher = TickerPublisher::new(Arc::clone(&feed));
+        publisher.on_event(&rested("a", "BTC-USD", OrderSide::Sell, 104, 2), 0, false);
+        publisher.on_event(&rested("b", "BTC-USD", OrderSide::Sell, 105, 3), 1, true);
+        publisher.on_event(&trade("a", 104, 2), 2, true);
+
+        let ask = feed.latest("BTC-USD").unwrap().best_ask.unwrap();
+        assert_eq!((ask.price.raw(), ask.amount.raw(), ask.order_count), (105, 3, 1));
+
+        let cancelled = Order {
+            status: OrderStatus::Cancelled,
+            ..Order::limit("b", "u1", "BTC-USD", OrderSide::Sell, Price::from_raw(105), Qty::from_raw(3))
+        };
+        publisher.on_event(&EngineEvent::OrderCancelled(cancelled), 3, true);
+        assert_eq!(feed.latest("BTC-USD").unwrap().best_ask, None);
+    }
+
+    #[test]
+    fn test_one_ticker_per_batch() {
+        let feed = Arc::new(TickerFeed::new(16));
+        let mut rx = feed.subscribe();
+        let mut publisher = TickerPublisher::new(Arc::clone(&feed));
+
+        publisher.on_event(&rested("a", "BTC-USD", OrderSide::Buy, 100, 1), 0, false);
+        publisher.on_event(&rested("b", "BTC-USD", OrderSide::Buy, 101, 1), 1, false);
+        publisher.on_event(&rested("c", "ETH-USD", OrderSide::Sell, 50, 1), 2, true);
+
+        let seen = drain(&mut rx);
+        assert_eq!(seen.len(), 2);
+        let btc = seen.iter().find(|t| t.symbol == "BTC-USD").unwrap();
+        assert_eq!(btc.best_bid.as_ref().unwrap().price, Price::from_raw(101));
+    }
+
+    #[test]
+    fn test_conflated_keeps_latest_per_subscribed_symbol() {
+        let feed = Arc::new(TickerFeed::new(2));
+        let mut publisher = TickerPublisher::new(Arc::clone(&feed));
+        publisher.on_event(&rested("a", "BTC-USD", OrderSide::Buy, 100, 1), 0, true);
+
+        let mut tickers = feed.subscribe_conflated(&["BTC-USD"]);
+        let first = tickers.flush().unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].sequence, 1);
+
+        // Enough to lag the subscription, plus a symbol it did not ask for.
+        for (i, price) in [101, 102, 103, 104].into_iter().enumerate() {
+            publisher.on_event(&rested(&format!("b{i}"), "BTC-USD", OrderSide::Buy, price, 1), 1, true);
+        }
+        publisher.on_event(&rested("e", "ETH-USD", OrderSide::Buy, 10, 1), 6, true);
+        assert!(tickers.pump());
+
+        let batch = tickers.flush().unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].sequence, 5);
+        assert_eq!(batch[0].best_bid.as_ref().unwrap().price, Price::from_raw(104));
+        assert!(!tickers.has_pending());
+    }
+}
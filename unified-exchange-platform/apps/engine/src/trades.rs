@@ -0,0 +1,272 @@
+/// Public trade feed, fanned out per symbol.
+///
+/// `TradePublisher` runs as an `EventHandler` and numbers the trades of
+/// each symbol from 1 in execution order. Each symbol has its own broadcast
+/// channel in `TradeFeed`. Publishing never waits: a subscriber that falls
+/// more than the channel capacity behind loses the oldest trades and sees
+/// `RecvError::Lagged` on its next receive, at which point the gateway
+/// disconnects it. The matching path is never held up by a slow reader.
+/// The latest trades stay in each symbol's `RetransmitRing`, so a client
+/// can fill a gap with `TradeFeed::retransmit`.
+use crate::disruptor::EventHandler;
+use crate::engine::EngineEvent;
+use crate::feed::{evict_idle, publish};
+use crate::ids::Symbol;
+use crate::retransmit::{RetransmitConfig, RetransmitRing, Retransmission, Sequenced};
+use crate::types::Trade;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicTrade {
+    /// Starts at 1 and increases by one per trade on the symbol, so a gap
+    /// means trades were missed.
+    pub sequence: u64,
+    pub trade: Trade,
+}
+
+impl Sequenced for PublicTrade {
+    fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/// Broadcast channels of public trades, by symbol.
+pub struct TradeFeed {
+    channels: DashMap<Symbol, broadcast::Sender<PublicTrade>>,
+    history: DashMap<Symbol, Mutex<RetransmitRing<PublicTrade>>>,
+    capacity: usize,
+    retransmit: RetransmitConfig,
+}
+
+impl TradeFeed {
+    /// Each subscriber may fall `capacity` trades behind before it lags.
+    /// No trades are kept for retransmission.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_retransmit(capacity, RetransmitConfig::default())
+    }
+
+    pub fn with_retransmit(capacity: usize, retransmit: RetransmitConfig) -> Self {
+        Self {
+            channels: DashMap::new(),
+            history: DashMap::new(),
+            capacity,
+            retransmit,
+        }
+    }
+
+    /// Trades on `symbol` from now on.
+    pub fn subscribe(&self, symbol: &str) -> broadcast::Receiver<PublicTrade> {
+        if let Some(tx) = self.channels.get(symbol) {
+            return tx.subscribe();
+        }
+        evict_idle(&self.channels, |tx| tx.receiver_count() == 0);
+        self.channels
+            .entry(Symbol::from(symbol))
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+
+    pub fn subscriber_count(&self, symbol: &str) -> usize {
+        self.channels.get(symbol).map_or(0, |tx| tx.receiver_count())
+    }
+
+    /// Trades `from..=to` on `symbol` again.
+    pub fn retransmit(&self, symbol: &str, from: u64, to: u64) -> Retransmission<PublicTrade> {
+        let lookup = match self.history.get(symbol) {
+            Some(ring) => ring.lock().get(from, to),
+            None => return Retransmission::Messages(Vec::new()),
+        };
+        lookup.finish()
+    }
+
+    /// Up to `limit` of the latest trades on `symbol` still kept, oldest
+    /// first.
+    pub fn recent(&self, symbol: &str, limit: usize) -> Vec<PublicTrade> {
+        let lookup = {
+            let Some(ring) = self.history.get(symbol) else {
+                return Vec::new();
+            };
+            let mut ring = ring.lock();
+            let last = ring.last_sequence();
+            let Some(first) = ring.first_available() else {
+                return Vec::new();
+            };
+            ring.get(first.max((last + 1).saturating_sub(limit as u64)), last)
+        };
+        match lookup.finish() {
+            Retransmission::Messages(trades) => trades,
+            Retransmission::Expired { .. } => Vec::new(),
+        }
+    }
+
+    fn publish(&self, trade: PublicTrade) {
+        self.history
+            .entry(trade.trade.symbol.clone())
+            .or_insert_with(|| Mutex::new(RetransmitRing::new(&format!("trades-{}", trade.trade.symbol), &self.retransmit)))
+            .lock()
+            .record(&trade);
+        if let Some(tx) = self.channels.get(&trade.trade.symbol) {
+            publish(&tx, trade);
+        }
+    }
+}
+
+pub struct TradePublisher {
+    feed: Arc<TradeFeed>,
+    sequences: HashMap<Symbol, u64>,
+}
+
+impl TradePublisher {
+    pub fn new(feed: Arc<TradeFeed>) -> Self {
+        Self {
+            feed,
+            sequences: HashMap::new(),
+        }
+    }
+}
+
+impl EventHandler<EngineEvent> for TradePublisher {
+    fn on_event(&mut self, event: &EngineEvent, _sequence: u64, _end_of_batch: bool) {
+        let EngineEvent::Trade(trade) = event else {
+            return;
+        };
+        let sequence = self.sequences.entry(trade.symbol.clone()).or_insert(0);
+        *sequence += 1;
+        self.feed.publish(PublicTrade {
+            sequence: *sequence,
+            trade: trade.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed_point::{Price, Qty};
+    use crate::types::OrderSide;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    fn trade(id: u64, symbol: &str) -> EngineEvent {
+        EngineEvent::Trade(Trade {
+            id,
+            symbol: symbol.into(),
+            maker_order_id: "m".into(),
+            taker_order_id: "t".into(),
+            price: Price::from_raw(100),
+            amount: Qty::from_raw(1),
+            taker_side: OrderSide::Buy,
+            timestamp: 0,
+        })
+    }
+
+    #[test]
+    fn test_sequences_are_per_symbol() {
+        let feed = Arc::new(TradeFeed::new(16));
+        let mut btc = feed.subscribe("BTC-USD");
+        let mut eth = feed.subscribe("ETH-USD");
+        let mut publisher = TradePublisher::new(Arc::clone(&feed));
+
+        for (i, symbol) in ["BTC-USD", "ETH-USD", "BTC-USD"].into_iter().enumerate() {
+            publisher.on_event(&trade(i as u64, symbol), i as u64, true);
+        }
+
+        let seen: Vec<_> = std::iter::from_fn(|| btc.try_recv().ok()).map(|t| (t.sequence, t.trade.id)).collect();
+        assert_eq!(seen, vec![(1, 0), (2, 2)]);
+        assert_eq!(eth.try_recv().unwrap().sequence, 1);
+        assert!(matches!(eth.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn test_idle_channels_are_evicted() {
+        let feed = TradeFeed::new(16);
+        let kept = feed.subscribe("BTC-USD");
+        drop(feed.subscribe("NOPE-1"));
+        let _other = feed.subscribe("NOPE-2");
+
+        assert!(!feed.channels.contains_key("NOPE-1"));
+        assert_eq!(feed.channels.len(), 2);
+        drop(kept);
+    }
+
+    #[test]
+    fn test_retransmits_recent_trades() {
+        let config = RetransmitConfig {
+            capacity: 2,
+            disk: None,
+        };
+        let feed = Arc::new(TradeFeed::with_retransmit(16, config));
+        let mut publisher = TradePublisher::new(Arc::clone(&feed));
+        for i in 0..3 {
+            publisher.on_event(&trade(i, "BTC-USD"), i, true);
+        }
+
+        let Retransmission::Messages(trades) = feed.retransmit("BTC-USD", 2, 3) else {
+            panic!("expected trades");
+        };
+        assert_eq!(trades.iter().map(|t| (t.sequence, t.trade.id)).collect::<Vec<_>>(), vec![(2, 1), (3, 2)]);
+        assert!(matches!(
+            feed.retransmit("BTC-USD", 1, 3),
+            Retransmission::Expired { first_available: Some(2) }
+        ));
+        assert!(matches!(feed.retransmit("ETH-USD", 1, 1), Retransmission::Messages(t) if t.is_empty()));
+
+        let recent = |limit| feed.recent("BTC-USD", limit).iter().map(|t| t.sequence).collect::<Vec<_>>();
+        assert_eq!(recent(1), vec![3]);
+        assert_eq!(recent(10), vec![2, 3]);
+        assert!(feed.recent("ETH-USD", 10).is_empty());
+    }
+
+    #[test]
+    fn test_slow_subscriber_lags() {
+        let feed = Arc::new(TradeFeed::new(2));
+        let mut slow = feed.subscribe("BTC-USD");
+        let mut publisher = TradePublisher::new(Arc::clone(&feed));
+
+        for i in 0..3 {
+            publisher.on_event(&trade(i, "BTC-USD"), i, true);
+        }
+
+        assert!(matches!(slow.try_recv(), Err(TryRecvError::Lagged(1))));
+    }
+
+    #[test]
+    fn test_trades_from_running_engine() {
+        use crate::engine::{EngineConfig, MatchingEngine};
+        use crate::types::{Order, OrderStatus, OrderType};
+
+        let feed = Arc::new(TradeFeed::new(16));
+        let mut rx = feed.subscribe("BTC-USD");
+        let engine = MatchingEngine::start(
+            EngineConfig::default(),
+            vec![Box::new(TradePublisher::new(Arc::clone(&feed)))],
+        );
+
+        for (id, side) in [("a", OrderSide::Sell), ("b", OrderSide::Buy)] {
+            engine
+                .place_order(Order {
+                    id: id.into(),
+                    user_id: "u1".into(),
+                    symbol: "BTC-USD".into(),
+                    side,
+                    order_type: OrderType::Limit,
+                    price: Price::from_raw(100),
+                    amount: Qty::from_raw(2),
+                    filled: Qty::ZERO,
+                    status: OrderStatus::New,
+                    timestamp: 0,
+                })
+                .unwrap();
+        }
+        drop(engine);
+
+        let trade = rx.try_recv().unwrap();
+        assert_eq!(trade.sequence, 1);
+        assert_eq!(trade.trade.taker_order_id, "b");
+        assert_eq!(trade.trade.amount, Qty::from_raw(2));
+    }
+}
@@ -1,8 +1,79 @@
-use rust_decimal::Decimal;
+use crate::fixed_point::{format_scaled, FixedPointError, Price, Qty, MAX_DECIMALS};
+use crate::ids::{OrderId, Symbol, TradeId, UserId};
+use crate::pool::ResultPool;
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
-use chrono::Utc;
+
+/// Decimal places used for symbols that were never registered.
+pub const DEFAULT_PRICE_DECIMALS: u32 = 6;
+pub const DEFAULT_QTY_DECIMALS: u32 = 8;
+
+/// Per-symbol precision for the fixed-point `Price` and `Qty` values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: Symbol,
+    pub price_decimals: u32,
+    pub qty_decimals: u32,
+    /// Largest amount a single order may have; larger orders are rejected
+    /// with `RiskLimit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_order_qty: Option<Qty>,
+}
+
+impl Instrument {
+    pub fn new(symbol: impl Into<Symbol>, price_decimals: u32, qty_decimals: u32) -> Self {
+        assert!(
+            price_decimals <= MAX_DECIMALS && qty_decimals <= MAX_DECIMALS,
+            "instrument precision above {} decimals",
+            MAX_DECIMALS
+        );
+
+        Self {
+            symbol: symbol.into(),
+            price_decimals,
+            qty_decimals,
+            max_order_qty: None,
+        }
+    }
+
+    pub fn with_max_order_qty(mut self, max_order_qty: Qty) -> Self {
+        self.max_order_qty = Some(max_order_qty);
+        self
+    }
+
+    /// `RiskLimit` if `amount` is above the instrument's order size limit.
+    pub fn check_order_qty(&self, amount: Qty) -> Result<(), RejectReason> {
+        match self.max_order_qty {
+            Some(max) if amount > max => Err(RejectReason::RiskLimit),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn with_default_precision(symbol: impl Into<Symbol>) -> Self {
+        Self::new(symbol, DEFAULT_PRICE_DECIMALS, DEFAULT_QTY_DECIMALS)
+    }
+
+    pub fn parse_price(&self, value: &str) -> Result<Price, FixedPointError> {
+        Price::parse(value, self.price_decimals)
+    }
+
+    pub fn parse_qty(&self, value: &str) -> Result<Qty, FixedPointError> {
+        Qty::parse(value, self.qty_decimals)
+    }
+
+    pub fn format_price(&self, price: Price) -> String {
+        price.format(self.price_decimals)
+    }
+
+    pub fn format_qty(&self, qty: Qty) -> String {
+        qty.format(self.qty_decimals)
+    }
+
+    /// Formats a sum of raw price * qty products in price units, truncated
+    /// to the price precision.
+    pub fn format_notional(&self, notional: u128) -> String {
+        format_scaled(notional / 10u128.pow(self.qty_decimals), self.price_decimals)
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderSide {
@@ -16,152 +87,187 @@ pub enum OrderType {
     Market,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Order {
-    pub id: String,
-    pub user_id: String,
-    pub symbol: String,
-    pub side: OrderSide,
-    pub order_type: OrderType,
-    pub price: Decimal,
-    pub amount: Decimal,
-    pub filled: Decimal,
-    pub timestamp: i64,
+/// Where an order is in its lifecycle. `New` and `PartiallyFilled` orders
+/// are open, i.e. resting on the book; the rest are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    /// Never accepted; see the accompanying `RejectReason`.
+    Rejected,
+    /// Ran out of liquidity without resting, e.g. a market order's remainder.
+    Expired,
 }
 
-impl Order {
-    pub fn remaining(&self) -> Decimal {
-        self.amount - self.filled
-    }
-
-    pub fn is_filled(&self) -> bool {
-        self.filled >= self.amount
+impl OrderStatus {
+    pub fn is_open(self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Trade {
-    pub id: String,
-    pub symbol: String,
-    pub maker_order_id: String,
-    pub taker_order_id: String,
-    pub price: Decimal,
-    pub amount: Decimal,
-    pub taker_side: OrderSide,
+pub struct Order {
+    pub id: OrderId,
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub price: Price,
+    pub amount: Qty,
+    pub filled: Qty,
+    pub status: OrderStatus,
     pub timestamp: i64,
 }
 
-#[derive(Debug, Clone)]
-pub struct PriceLevel {
-    pub price: Decimal,
-    pub orders: Vec<Order>,
-}
-
-impl PriceLevel {
-    pub fn new(price: Decimal) -> Self {
+impl Order {
+    /// A new limit order with nothing filled yet.
+    pub fn limit(id: &str, user_id: &str, symbol: &str, side: OrderSide, price: Price, amount: Qty) -> Self {
         Self {
+            id: id.into(),
+            user_id: user_id.into(),
+            symbol: symbol.into(),
+            side,
+            order_type: OrderType::Limit,
             price,
-            orders: Vec::new(),
+            amount,
+            filled: Qty::ZERO,
+            status: OrderStatus::New,
+            timestamp: 0,
         }
     }
 
-    pub fn total_amount(&self) -> Decimal {
-        self.orders.iter().map(|o| o.remaining()).sum()
+    pub fn remaining(&self) -> Qty {
+        self.amount.saturating_sub(self.filled)
     }
 
-    pub fn add_order(&mut self, order: Order) {
-        self.orders.push(order);
+    /// Records a fill and moves the order to `PartiallyFilled` or `Filled`.
+    pub fn fill(&mut self, amount: Qty) {
+        self.filled = self.filled.saturating_add(amount);
+        self.status = if self.is_filled() {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
     }
 
-    pub fn remove_order(&mut self, order_id: &str) -> Option<Order> {
-        if let Some(pos) = self.orders.iter().position(|o| o.id == order_id) {
-            Some(self.orders.remove(pos))
-        } else {
-            None
-        }
+    pub fn is_filled(&self) -> bool {
+        self.filled >= self.amount
     }
 }
 
-pub struct OrderBook {
-    pub symbol: String,
-    pub bids: BTreeMap<Decimal, PriceLevel>, // Buy orders (highest first)
-    pub asks: BTreeMap<Decimal, PriceLevel>, // Sell orders (lowest first)
+/// A limit order from `u1` on `BTC-USD`, at raw price and amount, for
+/// the unit tests.
+#[cfg(test)]
+pub(crate) fn test_order(id: &str, side: OrderSide, price: u64, amount: u64) -> Order {
+    Order::limit(id, "u1", "BTC-USD", side, Price::from_raw(price), Qty::from_raw(amount))
 }
 
-impl OrderBook {
-    pub fn new(symbol: String) -> Self {
-        Self {
-            symbol,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
-        }
-    }
-
-    pub fn add_order(&mut self, order: Order) {
-        let levels = match order.side {
-            OrderSide::Buy => &mut self.bids,
-            OrderSide::Sell => &mut self.asks,
-        };
-
-        levels
-            .entry(order.price)
-            .or_insert_with(|| PriceLevel::new(order.price))
-            .add_order(order);
-    }
-
-    pub fn remove_order(&mut self, order_id: &str, side: OrderSide, price: Decimal) -> Option<Order> {
-        let levels = match side {
-            OrderSide::Buy => &mut self.bids,
-            OrderSide::Sell => &mut self.asks,
-        };
-
-        if let Some(level) = levels.get_mut(&price) {
-            let order = level.remove_order(order_id);
-            
-            // Remove empty price level
-            if level.orders.is_empty() {
-                levels.remove(&price);
-            }
-            
-            order
-        } else {
-            None
-        }
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub id: TradeId,
+    pub symbol: Symbol,
+    pub maker_order_id: OrderId,
+    pub taker_order_id: OrderId,
+    pub price: Price,
+    pub amount: Qty,
+    pub taker_side: OrderSide,
+    pub timestamp: i64,
+}
 
-    pub fn best_bid(&self) -> Option<Decimal> {
-        self.bids.keys().next_back().copied()
-    }
+/// Aggregated view of one price level.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub amount: Qty,
+    pub order_count: usize,
+}
 
-    pub fn best_ask(&self) -> Option<Decimal> {
-        self.asks.keys().next().copied()
-    }
+/// Top-of-book depth, best price first on each side. `sequence` counts
+/// the changes applied to the book, so newer snapshots compare greater.
+#[derive(Debug, Clone, Default)]
+pub struct DepthSnapshot {
+    pub symbol: Symbol,
+    pub sequence: u64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
 
-    pub fn spread(&self) -> Option<Decimal> {
-        match (self.best_ask(), self.best_bid()) {
-            (Some(ask), Some(bid)) => Some(ask - bid),
-            _ => None,
-        }
+impl DepthSnapshot {
+    /// See `checksum::book_checksum`. Only meaningful on a snapshot holding
+    /// at least `CHECKSUM_LEVELS` levels per side, or the whole book.
+    pub fn checksum(&self) -> u32 {
+        crate::checksum::book_checksum(&self.bids, &self.asks)
     }
+}
 
-    pub fn mid_price(&self) -> Option<Decimal> {
-        match (self.best_ask(), self.best_bid()) {
-            (Some(ask), Some(bid)) => Some((ask + bid) / Decimal::from(2)),
-            _ => None,
-        }
-    }
+/// Why an order, amend or cancel was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+pub enum RejectReason {
+    #[error("unknown symbol")]
+    UnknownSymbol,
+    #[error("invalid order side")]
+    InvalidSide,
+    #[error("invalid order type")]
+    InvalidOrderType,
+    #[error("price is not a valid decimal")]
+    InvalidPrice,
+    #[error("amount is not a valid decimal")]
+    InvalidAmount,
+    #[error("price has more decimals than the instrument's tick size")]
+    BadTick,
+    #[error("amount has more decimals than the instrument's lot size")]
+    BadLot,
+    #[error("order amount must be positive")]
+    ZeroAmount,
+    #[error("limit order price must be positive")]
+    ZeroPrice,
+    #[error("order would breach a risk limit")]
+    RiskLimit,
+    #[error("market is closed")]
+    MarketClosed,
+    #[error("order id is already in use by an open order")]
+    DuplicateOrderId,
+    #[error("another order in the all-or-none batch was rejected")]
+    BatchRejected,
+    #[error("order is not resting on the book")]
+    UnknownOrder,
+    #[error("amended amount must exceed the filled amount")]
+    AmountBelowFilled,
 }
 
+#[derive(Debug)]
 pub struct MatchingResult {
+    /// Status of the placed or amended order once matching finished.
+    pub status: OrderStatus,
     pub trades: Vec<Trade>,
     pub updated_orders: Vec<Order>,
+    /// Where the buffers go back to once the result is dropped.
+    pub(crate) pool: Option<ResultPool>,
 }
 
 impl MatchingResult {
     pub fn new() -> Self {
         Self {
+            status: OrderStatus::New,
             trades: Vec::new(),
             updated_orders: Vec::new(),
+            pool: None,
+        }
+    }
+}
+
+impl Drop for MatchingResult {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.recycle(std::mem::take(&mut self.trades), std::mem::take(&mut self.updated_orders));
         }
     }
 }
+
+impl Default for MatchingResult {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,835 @@
+/// WebSocket market data for browsers, which cannot use gRPC streams.
+///
+/// `WebSocketGateway::router` upgrades `GET /ws` to a WebSocket, and is
+/// merged into the REST router so both share one listener. Connections
+/// speak JSON text messages. A client sends
+///
+/// ```json
+/// {"op": "subscribe", "channel": "depth", "symbol": "BTC-USD"}
+/// {"op": "subscribe", "channel": "candles", "symbol": "BTC-USD", "interval": "OneMinute"}
+/// {"op": "unsubscribe", "channel": "depth", "symbol": "BTC-USD"}
+/// {"op": "ping"}
+/// ```
+///
+/// The channels are `trades`, `depth`, `ticker` and `candles`. Each request is
+/// answered with `subscribed`, `unsubscribed`, `pong` or `error`. After that,
+/// messages are tagged by `type`:
+///
+/// - `trade` is every public trade
+/// - `depth_snapshot` and `depth_update` are the depth feed, conflated per
+///   level while the client is behind
+/// - `ticker` is the latest top of book
+/// - `candle` is every change to the candles of the chosen interval
+///
+/// All of them come straight from the feeds the engine's event handlers
+/// publish. A depth, ticker or candle subscription never falls behind: it
+/// resyncs from current state on its own. A trade subscription that falls
+/// behind gets an `error` and ends, and the client resubscribes.
+///
+/// The server pings every `ping_interval` and closes a connection that has
+/// sent nothing for `idle_timeout`. Pongs count, as does the JSON `ping` that
+/// browsers send instead. Each connection may send `requests_per_second`
+/// messages of any kind, pings and pongs included, with bursts up to
+/// `request_burst`, and hold at most `max_subscriptions`. A text message
+/// over the rate gets an `error` and is otherwise ignored; anything else
+/// over it closes the connection with 1008. Fragmented messages are
+/// reassembled, up to `max_message_len` in all.
+use crate::candles::{Candle, CandleFeed, CandleInterval};
+use crate::conflation::{Conflated, ConflationMode};
+use crate::depth::{DepthBatch, DepthFeed, LevelUpdate};
+use crate::engine::MatchingEngine;
+use crate::rest::{LevelView, TradeView};
+use crate::ticker::{Ticker, TickerFeed};
+use crate::trades::TradeFeed;
+use crate::types::{DepthSnapshot, Instrument, OrderSide};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// Longest symbol a client may subscribe to.
+const MAX_SYMBOL_LEN: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// How often the server pings.
+    pub ping_interval: Duration,
+    /// How long a client may send nothing before it is disconnected. Also
+    /// how long one write to the client may take.
+    pub idle_timeout: Duration,
+    pub max_subscriptions: usize,
+    /// Sustained rate of client messages per connection.
+    pub requests_per_second: u32,
+    /// Client messages allowed at once after a quiet spell.
+    pub request_burst: u32,
+    /// Largest client message accepted, in bytes.
+    pub max_message_len: usize,
+    /// Messages queued for a client before its subscriptions start to
+    /// conflate or fall behind.
+    pub outbound_buffer: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(15),
+            idle_timeout: Duration::from_secs(45),
+            max_subscriptions: 64,
+            requests_per_second: 10,
+            request_burst: 20,
+            max_message_len: 4096,
+            outbound_buffer: 1024,
+        }
+    }
+}
+
+/// The feeds subscriptions are served from.
+#[derive(Clone)]
+pub struct MarketDataFeeds {
+    pub trades: Arc<TradeFeed>,
+    pub depth: Arc<DepthFeed>,
+    pub tickers: Arc<TickerFeed>,
+    pub candles: Arc<CandleFeed>,
+}
+
+pub struct WebSocketGateway {
+    engine: Arc<MatchingEngine>,
+    feeds: MarketDataFeeds,
+    config: WebSocketConfig,
+}
+
+impl WebSocketGateway {
+    pub fn new(engine: Arc<MatchingEngine>, feeds: MarketDataFeeds, config: WebSocketConfig) -> Self {
+        Self { engine, feeds, config }
+    }
+
+    /// Serves `GET /ws`.
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new().route("/ws", get(upgrade)).with_state(self)
+    }
+}
+
+async fn upgrade(State(gateway): State<Arc<WebSocketGateway>>, upgrade: WebSocketUpgrade) -> Response {
+    let max_len = gateway.config.max_message_len;
+    upgrade
+        .max_message_size(max_len)
+        .max_frame_size(max_len)
+        .on_upgrade(move |socket| async move {
+            let (outbound, rx) = mpsc::channel(gateway.config.outbound_buffer);
+            let mut connection = Connection {
+                limit: RateLimit::new(gateway.config.requests_per_second, gateway.config.request_burst, Instant::now()),
+                gateway,
+                socket,
+                outbound,
+                subscriptions: HashMap::new(),
+            };
+            if let Err(e) = connection.run(rx).await {
+                warn!("WebSocket connection closed: {}", e);
+            }
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Channel {
+    Trades,
+    Depth,
+    Ticker,
+    Candles,
+}
+
+/// What a subscription is to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Topic {
+    channel: Channel,
+    symbol: String,
+    /// Required for candles, and only for them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval: Option<CandleInterval>,
+}
+
+impl Topic {
+    fn check(&self) -> Result<(), &'static str> {
+        if self.symbol.is_empty() || self.symbol.len() > MAX_SYMBOL_LEN {
+            return Err("symbol must be 1 to 32 bytes");
+        }
+        match (self.channel, self.interval) {
+            (Channel::Candles, None) => Err("candles need an interval"),
+            (Channel::Candles, Some(_)) | (_, None) => Ok(()),
+            (_, Some(_)) => Err("only candles take an interval"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe(Topic),
+    Unsubscribe(Topic),
+    Ping,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Subscribed(Topic),
+    Unsubscribed(Topic),
+    Error {
+        message: String,
+        #[serde(flatten)]
+        topic: Option<Topic>,
+    },
+    Pong,
+    Trade(TradeView),
+    DepthSnapshot(DepthView),
+    DepthUpdate(DepthUpdateView),
+    Ticker(TickerView),
+    Candle(CandleView),
+}
+
+impl ServerMessage {
+    fn error(message: impl Into<String>, topic: Option<Topic>) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            topic,
+        }
+    }
+
+    fn to_text(&self) -> String {
+        serde_json::to_string(self).expect("server messages serialize")
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DepthView {
+    symbol: String,
+    sequence: u64,
+    checksum: u32,
+    bids: Vec<LevelView>,
+    asks: Vec<LevelView>,
+}
+
+impl DepthView {
+    fn new(snapshot: &DepthSnapshot, instrument: &Instrument) -> Self {
+        Self {
+            symbol: snapshot.symbol.to_string(),
+            sequence: snapshot.sequence,
+            checksum: snapshot.checksum(),
+            bids: snapshot.bids.iter().map(|level| LevelView::new(level, instrument)).collect(),
+            asks: snapshot.asks.iter().map(|level| LevelView::new(level, instrument)).collect(),
+        }
+    }
+}
+
+/// Levels changed since the last depth message; an amount of zero removes
+/// the level. The book is then as of `sequence`, with `checksum`.
+#[derive(Debug, Serialize)]
+struct DepthUpdateView {
+    symbol: String,
+    sequence: u64,
+    checksum: u32,
+    levels: Vec<LevelChange>,
+}
+
+#[derive(Debug, Serialize)]
+struct LevelChange {
+    side: OrderSide,
+    price: String,
+    amount: String,
+    order_count: usize,
+}
+
+impl LevelChange {
+    fn new(update: &LevelUpdate, instrument: &Instrument) -> Self {
+        Self {
+            side: update.side,
+            price: instrument.format_price(update.price),
+            amount: instrument.format_qty(update.amount),
+            order_count: update.order_count,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TickerView {
+    sequence: u64,
+    symbol: String,
+    best_bid: Option<LevelView>,
+    best_ask: Option<LevelView>,
+    last_price: Option<String>,
+    timestamp: i64,
+}
+
+impl TickerView {
+    fn new(ticker: &Ticker, instrument: &Instrument) -> Self {
+        Self {
+            sequence: ticker.sequence,
+            symbol: ticker.symbol.to_string(),
+            best_bid: ticker.best_bid.as_ref().map(|level| LevelView::new(level, instrument)),
+            best_ask: ticker.best_ask.as_ref().map(|level| LevelView::new(level, instrument)),
+            last_price: ticker.last_price.map(|price| instrument.format_price(price)),
+            timestamp: ticker.timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CandleView {
+    symbol: String,
+    interval: CandleInterval,
+    open_time: i64,
+    open: String,
+    high: String,
+    low: String,
+    close: String,
+    volume: String,
+    trade_count: u64,
+    vwap: Option<String>,
+    closed: bool,
+}
+
+impl CandleView {
+    fn new(candle: &Candle, instrument: &Instrument) -> Self {
+        Self {
+            symbol: candle.symbol.to_string(),
+            interval: candle.interval,
+            open_time: candle.open_time,
+            open: instrument.format_price(candle.open),
+            high: instrument.format_price(candle.high),
+            low: instrument.format_price(candle.low),
+            close: instrument.format_price(candle.close),
+            volume: instrument.format_qty(candle.volume),
+            trade_count: candle.trade_count,
+            vwap: candle.vwap.map(|price| instrument.format_price(price)),
+            closed: candle.closed,
+        }
+    }
+}
+
+/// Token bucket over a connection's messages.
+struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    fn new(per_second: u32, burst: u32, now: Instant) -> Self {
+        Self {
+            per_second: per_second.into(),
+            burst: burst.into(),
+            tokens: burst.into(),
+            last: now,
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+struct Connection {
+    gateway: Arc<WebSocketGateway>,
+    socket: WebSocket,
+    /// Where subscription tasks queue their messages.
+    outbound: mpsc::Sender<String>,
+    subscriptions: HashMap<Topic, JoinHandle<()>>,
+    limit: RateLimit,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in self.subscriptions.values() {
+            task.abort();
+        }
+    }
+}
+
+impl Connection {
+    async fn run(&mut self, mut rx: mpsc::Receiver<String>) -> io::Result<()> {
+        let config = self.gateway.config.clone();
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + config.ping_interval, config.ping_interval);
+        ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_seen = Instant::now();
+
+        loop {
+            tokio::select! {
+                message = self.socket.recv() => {
+                    let Some(message) = message else {
+                        return Ok(()); // Client went away
+                    };
+                    last_seen = Instant::now();
+                    if !self.on_message(message.map_err(io::Error::other)?).await? {
+                        return Ok(());
+                    }
+                }
+                Some(text) = rx.recv() => self.send(Message::Text(text)).await?,
+                _ = ping.tick() => {
+                    if last_seen.elapsed() >= config.idle_timeout {
+                        self.close(close_code::AWAY, "idle timeout").await?;
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "client went quiet"));
+                    }
+                    self.send(Message::Ping(Vec::new())).await?;
+                }
+            }
+        }
+    }
+
+    /// Handles one message. Returns false once the connection is closed.
+    async fn on_message(&mut self, message: Message) -> io::Result<bool> {
+        let allowed = self.limit.allow(Instant::now());
+        match message {
+            Message::Text(text) if allowed => {
+                self.on_request(&text).await?;
+                Ok(true)
+            }
+            Message::Text(_) => {
+                self.reply(&ServerMessage::error("rate limit exceeded; request ignored", None)).await?;
+                Ok(true)
+            }
+            Message::Binary(_) => {
+                self.close(close_code::UNSUPPORTED, "only text messages are accepted").await?;
+                Err(io::Error::new(io::ErrorKind::InvalidData, "binary message"))
+            }
+            // Pings are answered by the socket itself.
+            Message::Ping(_) | Message::Pong(_) if allowed => Ok(true),
+            Message::Ping(_) | Message::Pong(_) => {
+                self.close(close_code::POLICY, "rate limit exceeded").await?;
+                Err(io::Error::new(io::ErrorKind::InvalidData, "control frames over the rate limit"))
+            }
+            Message::Close(_) => {
+                // The socket echoes the close; reading on sends it and
+                // ends the stream.
+                while let Some(Ok(_)) = self.socket.recv().await {}
+                Ok(false)
+            }
+        }
+    }
+
+    async fn on_request(&mut self, text: &str) -> io::Result<()> {
+        let request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return self.reply(&ServerMessage::error(format!("invalid request: {}", e), None)).await,
+        };
+
+        match request {
+            ClientMessage::Ping => self.reply(&ServerMessage::Pong).await,
+            ClientMessage::Subscribe(topic) => self.subscribe(topic).await,
+            ClientMessage::Unsubscribe(topic) => match self.subscriptions.remove(&topic) {
+                Some(task) => {
+                    task.abort();
+                    self.reply(&ServerMessage::Unsubscribed(topic)).await
+                }
+                None => self.reply(&ServerMessage::error("not subscribed", Some(topic))).await,
+            },
+        }
+    }
+
+    async fn subscribe(&mut self, topic: Topic) -> io::Result<()> {
+        if let Err(message) = topic.check() {
+            return self.reply(&ServerMessage::error(message, Some(topic))).await;
+        }
+        // Trade subscriptions that fell behind have ended on their own.
+        self.subscriptions.retain(|_, task| !task.is_finished());
+        if self.subscriptions.contains_key(&topic) {
+            return self.reply(&ServerMessage::error("already subscribed", Some(topic))).await;
+        }
+        let limit = self.gateway.config.max_subscriptions;
+        if self.subscriptions.len() >= limit {
+            let message = format!("no more than {} subscriptions per connection", limit);
+            return self.reply(&ServerMessage::error(message, Some(topic))).await;
+        }
+
+        // Goes out before anything the subscription queues.
+        self.reply(&ServerMessage::Subscribed(topic.clone())).await?;
+        let task = tokio::spawn(stream_topic(Arc::clone(&self.gateway), topic.clone(), self.outbound.clone()));
+        self.subscriptions.insert(topic, task);
+        Ok(())
+    }
+
+    async fn reply(&mut self, message: &ServerMessage) -> io::Result<()> {
+        self.send(Message::Text(message.to_text())).await
+    }
+
+    async fn close(&mut self, code: u16, reason: &'static str) -> io::Result<()> {
+        let frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        self.send(Message::Close(Some(frame))).await
+    }
+
+    async fn send(&mut self, message: Message) -> io::Result<()> {
+        tokio::time::timeout(self.gateway.config.idle_timeout, self.socket.send(message))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "client is not reading"))?
+            .map_err(io::Error::other)
+    }
+}
+
+/// Feeds one subscription into the connection's outbound queue until the
+/// connection goes away or the subscription is aborted.
+async fn stream_topic(gateway: Arc<WebSocketGateway>, topic: Topic, tx: mpsc::Sender<String>) {
+    let instrument = gateway.engine.instrument(&topic.symbol);
+    let feeds = &gateway.feeds;
+    match (topic.channel, topic.interval) {
+        (Channel::Trades, _) => {
+            let mut trades = feeds.trades.subscribe(&topic.symbol);
+            loop {
+                let message = match trades.recv().await {
+                    Ok(public) => ServerMessage::Trade(TradeView::new(&public, &instrument)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("WebSocket trade subscriber on {} fell {} trades behind", topic.symbol, missed);
+                        let message = format!("fell {} trades behind; resubscribe", missed);
+                        let _ = tx.send(ServerMessage::error(message, Some(topic)).to_text()).await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if tx.send(message.to_text()).await.is_err() {
+                    return; // Client went away
+                }
+            }
+        }
+        (Channel::Depth, _) => {
+            let depth = feeds.depth.subscribe_conflated(&topic.symbol, ConflationMode::PerLevel);
+            forward_conflated(depth, tx, |batch| {
+                Some(match batch {
+                    DepthBatch::Snapshot(snapshot) => ServerMessage::DepthSnapshot(DepthView::new(&snapshot, &instrument)),
+                    DepthBatch::Levels {
+                        sequence,
+                        checksum,
+                        levels,
+                    } => ServerMessage::DepthUpdate(DepthUpdateView {
+                        symbol: topic.symbol.clone(),
+                        sequence,
+                        checksum,
+                        levels: levels.iter().map(|level| LevelChange::new(level, &instrument)).collect(),
+                    }),
+                })
+            })
+            .await
+        }
+        (Channel::Ticker, _) => {
+            let tickers = feeds.tickers.subscribe_conflated(&[topic.symbol.as_str()]);
+            forward_conflated(tickers, tx, |tickers| {
+                let ticker = tickers.last()?;
+                Some(ServerMessage::Ticker(TickerView::new(ticker, &instrument)))
+            })
+            .await
+        }
+        (Channel::Candles, interval) => {
+            let interval = interval.expect("checked on subscribe");
+            // The open candle is the whole state, so a subscription that
+            // falls behind starts again from it.
+            loop {
+                let (current, mut updates) = feeds.candles.subscribe(&topic.symbol, interval);
+                if let Some(candle) = current {
+                    if tx.send(ServerMessage::Candle(CandleView::new(&candle, &instrument)).to_text()).await.is_err() {
+                        return;
+                    }
+                }
+                loop {
+                    let candle = match updates.recv().await {
+                        Ok(candle) if candle.interval == interval => candle,
+                        Ok(_) => continue,
+                        Err(broadcast::error::RecvError::Lagged(_)) => break,
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    };
+                    if tx.send(ServerMessage::Candle(CandleView::new(&candle, &instrument)).to_text()).await.is_err() {
+                        return; // Client went away
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sends a conflated subscription one batch at a time, whenever the queue
+/// has room. Updates keep being taken in while it has none.
+async fn forward_conflated<S: Conflated>(
+    mut subscription: S,
+    tx: mpsc::Sender<String>,
+    render: impl Fn(S::Batch) -> Option<ServerMessage>,
+) {
+    loop {
+        let permit = loop {
+            tokio::select! {
+                permit = tx.reserve() => match permit {
+                    Ok(permit) => break permit,
+                    Err(_) => return, // Client went away
+                },
+                alive = subscription.changed() => if !alive { return },
+            }
+        };
+        while !subscription.has_pending() {
+            if !subscription.changed().await {
+                return;
+            }
+        }
+        if !subscription.pump() {
+            return;
+        }
+        if let Some(message) = subscription.flush().and_then(&render) {
+            permit.send(message.to_text());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candles::CandleBuilder;
+    use crate::depth::DepthPublisher;
+    use crate::engine::EngineConfig;
+    use crate::ticker::TickerPublisher;
+    use crate::trades::TradePublisher;
+    use crate::types::test_order;
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::{CloseCode, Data, OpCode};
+    use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    async fn start(config: WebSocketConfig) -> (SocketAddr, Arc<MatchingEngine>) {
+        let feeds = MarketDataFeeds {
+            trades: Arc::new(TradeFeed::new(64)),
+            depth: Arc::new(DepthFeed::new(64)),
+            tickers: Arc::new(TickerFeed::new(64)),
+            candles: Arc::new(CandleFeed::new(16, 64)),
+        };
+        let engine = Arc::new(MatchingEngine::start(
+            EngineConfig::default(),
+            vec![
+                Box::new(TradePublisher::new(Arc::clone(&feeds.trades))),
+                Box::new(DepthPublisher::new(Arc::clone(&feeds.depth))),
+                Box::new(TickerPublisher::new(Arc::clone(&feeds.tickers))),
+                Box::new(CandleBuilder::new(Arc::clone(&feeds.candles))),
+            ],
+        ));
+        engine.register_instrument(Instrument::new("BTC-USD", 2, 4));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let gateway = Arc::new(WebSocketGateway::new(Arc::clone(&engine), feeds, config));
+        tokio::spawn(crate::rest::serve(gateway.router(), listener));
+        (addr, engine)
+    }
+
+    struct Client {
+        stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    }
+
+    impl Client {
+        async fn connect(addr: SocketAddr) -> Self {
+            let (stream, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws")).await.unwrap();
+            Self { stream }
+        }
+
+        async fn send(&mut self, message: Value) {
+            self.stream.send(WsMessage::Text(message.to_string())).await.unwrap();
+        }
+
+        /// The next server message, or `None` once the server has closed.
+        async fn frame(&mut self) -> Option<WsMessage> {
+            let next = tokio::time::timeout(Duration::from_secs(5), self.stream.next());
+            next.await.expect("no message within 5s").map(Result::unwrap)
+        }
+
+        /// The next JSON message, skipping pings.
+        async fn message(&mut self) -> Value {
+            loop {
+                match self.frame().await.expect("server closed the connection") {
+                    WsMessage::Text(text) => return serde_json::from_str(&text).unwrap(),
+                    WsMessage::Ping(_) => continue,
+                    message => panic!("unexpected {message:?}"),
+                }
+            }
+        }
+
+        /// The code of the server's close, skipping anything before it.
+        async fn close_code(&mut self) -> CloseCode {
+            loop {
+                match self.frame().await.expect("server closed the connection") {
+                    WsMessage::Close(frame) => return frame.expect("close has a code").code,
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_follow_the_book() {
+        let (addr, engine) = start(WebSocketConfig::default()).await;
+        let mut client = Client::connect(addr).await;
+
+        for channel in ["depth", "trades"] {
+            client.send(json!({"op": "subscribe", "channel": channel, "symbol": "BTC-USD"})).await;
+            assert_eq!(client.message().await, json!({"type": "subscribed", "channel": channel, "symbol": "BTC-USD"}));
+            if channel == "depth" {
+                let snapshot = client.message().await;
+                assert_eq!((&snapshot["type"], &snapshot["bids"]), (&json!("depth_snapshot"), &json!([])));
+            }
+        }
+
+        engine.place_order_async(test_order("a", OrderSide::Sell, 10_050, 20_000)).await.unwrap();
+        let update = client.message().await;
+        assert_eq!(update["type"], "depth_update");
+        assert_eq!(update["levels"], json!([{"side": "Sell", "price": "100.5", "amount": "2", "order_count": 1}]));
+
+        engine.place_order_async(test_order("b", OrderSide::Buy, 10_050, 5_000)).await.unwrap();
+        let mut seen = HashMap::new();
+        while seen.len() < 2 {
+            let message = client.message().await;
+            seen.insert(message["type"].as_str().unwrap().to_string(), message);
+        }
+        assert_eq!((&seen["trade"]["price"], &seen["trade"]["amount"]), (&json!("100.5"), &json!("0.5")));
+        assert_eq!(seen["depth_update"]["levels"][0]["amount"], "1.5");
+
+        client.send(json!({"op": "unsubscribe", "channel": "trades", "symbol": "BTC-USD"})).await;
+        assert_eq!(client.message().await["type"], "unsubscribed");
+        client.send(json!({"op": "ping"})).await;
+        assert_eq!(client.message().await, json!({"type": "pong"}));
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests_and_rate_limit() {
+        let config = WebSocketConfig {
+            request_burst: 4,
+            requests_per_second: 1,
+            max_subscriptions: 1,
+            ..WebSocketConfig::default()
+        };
+        let (addr, _engine) = start(config).await;
+        let mut client = Client::connect(addr).await;
+
+        let requests = [
+            json!({"op": "subscribe", "channel": "candles", "symbol": "BTC-USD"}),
+            json!({"op": "subscribe", "channel": "ticker", "symbol": "BTC-USD"}),
+            json!({"op": "subscribe", "channel": "trades", "symbol": "BTC-USD"}),
+            json!({"op": "launch"}),
+            json!({"op": "ping"}),
+        ];
+        for request in requests {
+            client.send(request).await;
+        }
+        let mut replies = Vec::new();
+        while replies.len() < 5 {
+            let message = client.message().await;
+            if message["type"] != "ticker" {
+                replies.push(message);
+            }
+        }
+        assert_eq!(replies[0]["message"], "candles need an interval");
+        assert_eq!(replies[1]["type"], "subscribed");
+        assert_eq!(replies[2]["message"], "no more than 1 subscriptions per connection");
+        assert!(replies[3]["message"].as_str().unwrap().starts_with("invalid request"));
+        assert_eq!(replies[4]["message"], "rate limit exceeded; request ignored");
+    }
+
+    #[tokio::test]
+    async fn test_every_frame_counts_against_the_rate() {
+        let config = WebSocketConfig {
+            request_burst: 2,
+            requests_per_second: 1,
+            ..WebSocketConfig::default()
+        };
+        let (addr, _engine) = start(config).await;
+        let mut client = Client::connect(addr).await;
+
+        client.stream.send(WsMessage::Ping(b"hi".to_vec())).await.unwrap();
+        assert_eq!(client.frame().await, Some(WsMessage::Pong(b"hi".to_vec())));
+        client.send(json!({"op": "ping"})).await;
+        assert_eq!(client.message().await, json!({"type": "pong"}));
+        client.send(json!({"op": "ping"})).await;
+        assert_eq!(client.message().await["message"], "rate limit exceeded; request ignored");
+
+        client.stream.send(WsMessage::Ping(Vec::new())).await.unwrap();
+        assert_eq!(client.close_code().await, CloseCode::Policy);
+    }
+
+    #[tokio::test]
+    async fn test_fragmented_messages_are_reassembled() {
+        let config = WebSocketConfig {
+            max_message_len: 256,
+            ..WebSocketConfig::default()
+        };
+        let (addr, _engine) = start(config).await;
+        let mut client = Client::connect(addr).await;
+
+        let request = json!({"op": "subscribe", "channel": "trades", "symbol": "BTC-USD"}).to_string();
+        let (head, tail) = request.split_at(10);
+        let first = Frame::message(head.as_bytes().to_vec(), OpCode::Data(Data::Text), false);
+        let last = Frame::message(tail.as_bytes().to_vec(), OpCode::Data(Data::Continue), true);
+        client.stream.send(WsMessage::Frame(first)).await.unwrap();
+        client.stream.send(WsMessage::Frame(last)).await.unwrap();
+        assert_eq!(client.message().await["type"], "subscribed");
+
+        // Too long in all, though each fragment is within the limit.
+        for (data, fin) in [(Data::Text, false), (Data::Continue, true)] {
+            let frame = Frame::message(vec![b' '; 200], OpCode::Data(data), fin);
+            client.stream.send(WsMessage::Frame(frame)).await.unwrap();
+        }
+        while let Some(message) = client.stream.next().await {
+            assert!(!matches!(message, Ok(WsMessage::Text(_))), "{message:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pings_and_closes() {
+        let config = WebSocketConfig {
+            ping_interval: Duration::from_millis(20),
+            ..WebSocketConfig::default()
+        };
+        let (addr, _engine) = start(config).await;
+        let mut client = Client::connect(addr).await;
+        assert!(matches!(client.frame().await, Some(WsMessage::Ping(_))));
+
+        client.stream.close(None).await.unwrap();
+        while let Some(message) = client.frame().await {
+            assert!(matches!(message, WsMessage::Ping(_) | WsMessage::Close(_)), "{message:?}");
+        }
+
+        let mut rejected = TcpStream::connect(addr).await.unwrap();
+        rejected
+            .write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{response}");
+    }
+
+    #[test]
+    fn test_rate_limit_refills() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2, 2, start);
+        assert!(limit.allow(start) && limit.allow(start));
+        assert!(!limit.allow(start));
+        assert!(limit.allow(start + Duration::from_millis(500)));
+        assert!(!limit.allow(start + Duration::from_millis(600)));
+    }
+}